@@ -21,8 +21,14 @@ pub const FIELD_ACC_VOLATILE: u16      = 0x0040;
 pub const FIELD_ACC_TRANSIENT: u16     = 0x0080;
 
 // Method flags
-pub const METHOD_ACC_SYNCHRONIZED: u16 = 0x0100;
+pub const METHOD_ACC_SYNCHRONIZED: u16 = 0x0020;
+pub const METHOD_ACC_VARARGS: u16      = 0x0080;
 pub const METHOD_ACC_NATIVE: u16       = 0x0100;
+pub const METHOD_ACC_STRICT: u16       = 0x0800;
+
+// Supported classfile versions, up to Java 21
+pub const MIN_MAJOR_VERSION: u16 = 45;
+pub const MAX_MAJOR_VERSION: u16 = 65;
 
 pub fn bit_set(flags: u16, flag: u16) -> bool{
     return (flags & flag) == flag;
@@ -194,10 +200,12 @@ pub const OP_LXOR: u8                    = 131;
 pub const OP_IINC: u8                    = 132;
 
 pub const OP_GOTO: u8                    = 167;
-// jsr, ret, not supported
+pub const OP_JSR: u8                     = 168;
+pub const OP_RET: u8                     = 169;
 pub const OP_TABLE_SWITCH: u8            = 170;
 pub const OP_LOOKUP_SWITCH: u8           = 171;
 pub const OP_GOTO_W: u8                  = 200;
+pub const OP_JSR_W: u8                   = 201;
 
 pub const OP_LCMP: u8                    = 148;
 pub const OP_FCMPL: u8                   = 149;
@@ -304,5 +312,4 @@ pub fn system_init_phase_1() -> NameAndType{
 // Misc
 pub const BOOTSTRAP_LOADER_NAME: &str   = "java.lang.ClassLoader";
 
-pub const CLASS_DESC_FIELD_NAME: &str   = "JVM_DESCRIPTOR";
 pub const FOS_FD_FIELD_NAME:     &str   = "fd";
@@ -1,6 +1,8 @@
 #![feature(if_let_guard)]
 #![feature(let_chains)]
 
+use std::sync::Arc;
+
 use crate::runtime::interpreter::StackTrace;
 
 mod constants;
@@ -12,13 +14,15 @@ fn main() {
 
     match runtime::class::load_class("run/Basics".to_owned()){
         Ok(o) => {
-            for m in &o.methods{
+            let o = Arc::new(o);
+            for (idx, m) in o.methods.iter().enumerate(){
                 if m.name == "main2"{
-                    let result = runtime::interpreter::execute(&o, &m, vec![], StackTrace::new());
+                    let result = runtime::interpreter::execute_at(o.clone(), idx, vec![], StackTrace::new());
                     println!("got {:?}", result);
                 }
             }
         },
         Err(e) => println!("error: {}", e),
     }
+    runtime::threads::wait_for_non_daemon_threads();
 }
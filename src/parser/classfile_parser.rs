@@ -1,62 +1,97 @@
-use super::classfile_structs::*;
+use super::{classfile_structs::*, modified_utf8};
 use crate::constants;
 
+/// Parses a classfile, checking that it's well-formed (JVMS 4.8).
+/// Errors start with ClassFormatError, or UnsupportedClassVersionError for versions we can't run.
 pub fn parse(file: &mut Vec<u8>) -> Result<Classfile, String>{
+    return parse_classfile(file).map_err(|e| if e.starts_with("UnsupportedClassVersionError"){ e }else{ format!("ClassFormatError: {}", e) });
+}
+
+fn parse_classfile(file: &mut Vec<u8>) -> Result<Classfile, String>{
     if !expect_int(file, 0xCAFEBABE){
         return Err("Missing magic number!".to_owned())
     }
 
     let Some(minor_ver) = next_short(file) else { return Err("Missing minor version".to_owned()); };
     let Some(major_ver) = next_short(file) else { return Err("Missing major version".to_owned()); };
+    check_version(major_ver, minor_ver)?;
 
-    let Some(raw_constants) = parse_constants(file) else { return Err("Unable to parse constant pool".to_owned()); };
+    let raw_constants = parse_constants(file, major_ver)?;
     let constants = resolve_constants(raw_constants)?;
 
     let Some(flags) = next_short(file) else { return Err("Missing access flags".to_owned()); };
     check_class_flags(flags)?;
+    let is_interface = constants::bit_set(flags, constants::CLASS_ACC_INTERFACE);
 
-    let ConstantEntry::Class(this_class) = &constants[next_short_err(file)? as usize - 1]
+    let ConstantEntry::Class(this_class) = constant(&constants, next_short_err(file)?)?
         else { return Err("Unable to resolve this class's name".to_owned()); };
     let name: String = this_class.clone(); // own the string
+    if name.starts_with('['){
+        return Err(format!("Array type {} can't be declared by a classfile", name));
+    }
 
-    let super_idx = next_short_err(file)? as usize;
+    // only Object and module descriptors have no superclass, and interfaces always have Object
+    let super_idx = next_short_err(file)?;
     let super_class: Option<String>;
     if super_idx == 0{
+        if name != "java/lang/Object" && !constants::bit_set(flags, constants::CLASS_ACC_MODULE){
+            return Err(format!("Class {} has no superclass", name));
+        }
         super_class = None;
     }else{
-        let ConstantEntry::Class(super_class_name) = &constants[super_idx - 1]
+        let ConstantEntry::Class(super_class_name) = constant(&constants, super_idx)?
             else { return Err("Unable to resolve super class's name".to_owned()); };
+        if super_class_name.starts_with('['){
+            return Err(format!("Class {} can't extend array type {}", name, super_class_name));
+        }
+        if is_interface && super_class_name != "java/lang/Object"{
+            return Err(format!("Interface {} must extend java/lang/Object", name));
+        }
         super_class = Some(super_class_name.clone());
     }
 
     let Some(ifaces_count) = next_short(file) else { return Err("Missing interfaces count".to_owned()); };
     let mut interfaces: Vec<String> = Vec::with_capacity(ifaces_count as usize);
     for _ in 0..ifaces_count{
-        let ConstantEntry::Class(interface) = &constants[next_short_err(file)? as usize - 1]
-            else {
-                println!("{:?}", constants[next_short_err(file)? as usize - 1]);
-                return Err("Unable to resolve interface name".to_owned());
-            };
+        let ConstantEntry::Class(interface) = constant(&constants, next_short_err(file)?)?
+            else { return Err("Unable to resolve interface name".to_owned()); };
+        if interface.starts_with('['){
+            return Err(format!("Class {} can't implement array type {}", name, interface));
+        }
         interfaces.push(interface.clone());
     }
 
     let Some(field_count) = next_short(file) else { return Err("Missing field count".to_owned()); };
     let mut fields: Vec<FieldInfo> = Vec::with_capacity(field_count as usize);
     for _ in 0..field_count{
-        fields.push(parse_member(file, &constants,
-            |flags, name, desc, attributes| Ok(FieldInfo { flags, name, desc, attributes }))?);
+        let field = parse_member(file, &constants,
+            |flags, name, desc, attributes| Ok(FieldInfo { flags, name, desc, attributes }))?;
+        check_field(&field, is_interface)?;
+        if fields.iter().any(|f| f.name == field.name && f.desc == field.desc){
+            return Err(format!("Duplicate field {} {}", field.name, field.desc));
+        }
+        fields.push(field);
     }
 
     let Some(method_count) = next_short(file) else { return Err("Missing method count".to_owned()); };
     let mut methods: Vec<MethodInfo> = Vec::with_capacity(method_count as usize);
     for _ in 0..method_count{
-        methods.push(parse_member(file, &constants,
-            |flags, name, desc, attributes| Ok(MethodInfo { flags, name, desc: parse_method_descriptor(desc)?, attributes }))?);
+        let method = parse_member(file, &constants, |flags, name, desc, attributes| {
+            check_method(flags, &name, &desc, &attributes, is_interface, major_ver)?;
+            let parsed = parse_method_descriptor(desc.clone())?;
+            if methods.iter().any(|m| m.name == name && m.desc == parsed){
+                return Err(format!("Duplicate method {}{}", name, desc));
+            }
+            Ok(MethodInfo { flags, name, desc: parsed, attributes })
+        })?;
+        methods.push(method);
     }
 
     let attributes = parse_attributes(file, &constants)?;
 
-    // TODO: check EOF
+    if !file.is_empty(){
+        return Err(format!("{} extra bytes at the end of the classfile", file.len()));
+    }
 
     return Ok(Classfile{
         major_ver,
@@ -72,157 +107,219 @@ pub fn parse(file: &mut Vec<u8>) -> Result<Classfile, String>{
     });
 }
 
-fn parse_constants(file: &mut Vec<u8>) -> Option<Vec<RawConstantEntry>>{
+fn parse_constants(file: &mut Vec<u8>, major_ver: u16) -> Result<Vec<RawConstantEntry>, String>{
     let mut pool: Vec<RawConstantEntry> = Vec::new();
-    let count = next_short(file)?;
+    let count = next_short_err(file)?;
     let mut i = 0;
-    while i < count - 1 {
-        let tag = next_byte(file)?;
-        match tag{
-            1 => pool.push(RawConstantEntry::Utf8(parse_modified_utf8(file)?)),
-            3 => pool.push(RawConstantEntry::Integer(next_int(file)?)),
-            4 => pool.push(RawConstantEntry::Float(next_float(file)?)),
-            5 => {
-                i += 1;
-                pool.push(RawConstantEntry::Long(next_long(file)?));
-                pool.push(RawConstantEntry::LongSecond);
-            },
-            6 => {
-                i += 1;
-                pool.push(RawConstantEntry::Double(next_double(file)?));
-                pool.push(RawConstantEntry::LongSecond);
-            },
-            7 => pool.push(RawConstantEntry::Class(next_short(file)?)),
-            8 => pool.push(RawConstantEntry::StringConst(next_short(file)?)),
-            9 | 10 | 11 => pool.push(RawConstantEntry::MemberRef(tag, next_short(file)?, next_short(file)?)),
-            12 => pool.push(RawConstantEntry::NameAndType(next_short(file)?, next_short(file)?)),
-            15 => pool.push(RawConstantEntry::MethodHandle(next_byte(file)?, next_short(file)?)),
-            16 => pool.push(RawConstantEntry::MethodType(next_short(file)?)),
-            17 | 18 => pool.push(RawConstantEntry::Dynamic(tag, next_short(file)?, next_short(file)?)),
-            19 => pool.push(RawConstantEntry::Module(next_short(file)?)),
-            20 => pool.push(RawConstantEntry::Package(next_short(file)?)),
-            _ => {
-                panic!("Invalid tag: {}", tag);
-            }
+    while i + 1 < count {
+        let tag = next_byte_err(file)?;
+        // newer kinds of constants can't be used in older classfiles
+        let since = match tag{
+            1 | 3..=12 => 45,
+            15 | 16 | 18 => 51,
+            19 | 20 => 53,
+            17 => 55,
+            _ => return Err(format!("Invalid constant pool tag {}", tag))
         };
+        if major_ver < since{
+            return Err(format!("Constant pool tag {} isn't allowed before version {}", tag, since));
+        }
+        if tag == 1{
+            pool.push(RawConstantEntry::Utf8(parse_modified_utf8(file)?));
+        }else{
+            parse_constant(file, tag, &mut pool).ok_or("Unexpected end of file")?;
+        }
+        if matches!(tag, 5 | 6){
+            i += 1;
+        }
         i += 1;
     }
-    return Some(pool);
-}
-
-pub fn parse_modified_utf8(file: &mut Vec<u8>) -> Option<String>{
-    let len = next_short(file)?;
-    let mut buffer = next_vec(file, len as usize);
-    let mut current: String = String::with_capacity(len as usize);
-    while !buffer.is_empty(){
-        let next = next_byte(&mut buffer)?;
-        // :(
-        // TODO: parse 6-byte form
-        // the spec is ambiguous on that?
-        if next & 0b10000000 == 0{
-            // first bit = 0 -> single byte
-            current.push(char::from(next));
-        }else if next & 0b111_00000 == 0b110_00000{
-            // first three bits = 110 -> two bytes
-            let b = next_byte(&mut buffer)?;
-            current.push(char::from_u32(((next as u32 & 0x1f) << 6) + (b as u32 & 0x3f))?);
-        }else if next & 0b1111_0000 == 0b1110_0000{
-            // first four bits = 1110 -> three bytes
-            let b = next_byte(&mut buffer)?;
-            let c = next_byte(&mut buffer)?;
-            current.push(char::from_u32(((next as u32 & 0xf) << 12) + ((b as u32 & 0x3f) << 6) + (c as u32 & 0x3f))?);
-        }
+    if i + 1 > count.max(1){
+        return Err("Long or double constant takes the last constant pool slot".to_owned());
     }
-    return Some(current);
+    return Ok(pool);
+}
+
+fn parse_constant(file: &mut Vec<u8>, tag: u8, pool: &mut Vec<RawConstantEntry>) -> Option<()>{
+    match tag{
+        3 => pool.push(RawConstantEntry::Integer(next_int(file)?)),
+        4 => pool.push(RawConstantEntry::Float(next_float(file)?)),
+        5 => {
+            pool.push(RawConstantEntry::Long(next_long(file)?));
+            pool.push(RawConstantEntry::LongSecond);
+        },
+        6 => {
+            pool.push(RawConstantEntry::Double(next_double(file)?));
+            pool.push(RawConstantEntry::LongSecond);
+        },
+        7 => pool.push(RawConstantEntry::Class(next_short(file)?)),
+        8 => pool.push(RawConstantEntry::StringConst(next_short(file)?)),
+        9 | 10 | 11 => pool.push(RawConstantEntry::MemberRef(tag, next_short(file)?, next_short(file)?)),
+        12 => pool.push(RawConstantEntry::NameAndType(next_short(file)?, next_short(file)?)),
+        15 => pool.push(RawConstantEntry::MethodHandle(next_byte(file)?, next_short(file)?)),
+        16 => pool.push(RawConstantEntry::MethodType(next_short(file)?)),
+        17 | 18 => pool.push(RawConstantEntry::Dynamic(tag, next_short(file)?, next_short(file)?)),
+        19 => pool.push(RawConstantEntry::Module(next_short(file)?)),
+        20 => pool.push(RawConstantEntry::Package(next_short(file)?)),
+        _ => return None
+    };
+    return Some(());
+}
+
+pub fn parse_modified_utf8(file: &mut Vec<u8>) -> Result<Vec<u16>, String>{
+    let len = next_short_err(file)?;
+    return modified_utf8::decode(&next_vec(file, len as usize)?);
 }
 
 fn resolve_constants(raw_pool: Vec<RawConstantEntry>) -> Result<Vec<ConstantEntry>, String>{
     let mut ret: Vec<ConstantEntry> = Vec::with_capacity(raw_pool.len());
     for con in &raw_pool {
-        ret.push(match con {
+        let entry = match con {
             RawConstantEntry::LongSecond => ConstantEntry::LongSecond,
 
-            RawConstantEntry::Utf8(s) => ConstantEntry::Utf8(s.clone()),
+            RawConstantEntry::Utf8(chars) => ConstantEntry::Utf8(String::from_utf16_lossy(chars)),
             RawConstantEntry::Integer(i) => ConstantEntry::Integer(*i),
             RawConstantEntry::Float(f) => ConstantEntry::Float(*f),
             RawConstantEntry::Long(l) => ConstantEntry::Long(*l),
             RawConstantEntry::Double(d) => ConstantEntry::Double(*d),
 
-            RawConstantEntry::Class(idx) if let RawConstantEntry::Utf8(s) = &raw_pool[*idx as usize - 1]
-                => ConstantEntry::Class(s.clone()),
-            RawConstantEntry::StringConst(idx) if let RawConstantEntry::Utf8(s) = &raw_pool[*idx as usize - 1]
-                => ConstantEntry::StringConst(s.clone()),
-            RawConstantEntry::MethodType(idx) if let RawConstantEntry::Utf8(s) = &raw_pool[*idx as usize - 1]
-                => ConstantEntry::MethodType(s.clone()),
-            RawConstantEntry::Module(idx) if let RawConstantEntry::Utf8(s) = &raw_pool[*idx as usize - 1]
-                => ConstantEntry::Module(s.clone()),
-            RawConstantEntry::Package(idx) if let RawConstantEntry::Utf8(s) = &raw_pool[*idx as usize - 1]
-                => ConstantEntry::Package(s.clone()),
+            RawConstantEntry::Class(idx) if let Some(s) = raw_utf8(&raw_pool, *idx)
+                => ConstantEntry::Class(s),
+            RawConstantEntry::StringConst(idx) if let Some(RawConstantEntry::Utf8(chars)) = raw_constant(&raw_pool, *idx)
+                => ConstantEntry::StringConst(chars.clone()),
+            RawConstantEntry::MethodType(idx) if let Some(s) = raw_utf8(&raw_pool, *idx)
+                => ConstantEntry::MethodType(s),
+            RawConstantEntry::Module(idx) if let Some(s) = raw_utf8(&raw_pool, *idx)
+                => ConstantEntry::Module(s),
+            RawConstantEntry::Package(idx) if let Some(s) = raw_utf8(&raw_pool, *idx)
+                => ConstantEntry::Package(s),
 
             RawConstantEntry::MemberRef(tag, class_idx, name_and_type_idx) => {
                 // TODO: split up into functions so we don't need this
-                if let RawConstantEntry::Class(class_name_idx) = &raw_pool[*class_idx as usize - 1]
-                && let RawConstantEntry::NameAndType(name_idx, descriptor_idx) = &raw_pool[*name_and_type_idx as usize - 1]
-                && let RawConstantEntry::Utf8(class_name) = &raw_pool[*class_name_idx as usize - 1]
-                && let RawConstantEntry::Utf8(name) = &raw_pool[*name_idx as usize - 1]
-                && let RawConstantEntry::Utf8(descriptor) = &raw_pool[*descriptor_idx as usize - 1]{
+                if let Some(RawConstantEntry::Class(class_name_idx)) = raw_constant(&raw_pool, *class_idx)
+                && let Some(RawConstantEntry::NameAndType(name_idx, descriptor_idx)) = raw_constant(&raw_pool, *name_and_type_idx)
+                && let Some(class_name) = raw_utf8(&raw_pool, *class_name_idx)
+                && let Some(name) = raw_utf8(&raw_pool, *name_idx)
+                && let Some(descriptor) = raw_utf8(&raw_pool, *descriptor_idx){
                     ConstantEntry::MemberRef(MemberRef {
                         kind: tag_to_member_kind(tag)?,
-                        owner_name: class_name.clone(),
+                        owner_name: class_name,
                         name_and_type: NameAndType {
-                            name: name.clone(),
-                            descriptor: descriptor.clone(),
+                            name,
+                            descriptor,
                         },
                     })
                 }else{ return Err("Invalid MemberRef entry".to_owned()); }
             }
 
             RawConstantEntry::NameAndType(name_idx, descriptor_idx) => {
-                if let RawConstantEntry::Utf8(name) = &raw_pool[*name_idx as usize - 1]
-                && let RawConstantEntry::Utf8(descriptor) = &raw_pool[*descriptor_idx as usize - 1]{
+                if let Some(name) = raw_utf8(&raw_pool, *name_idx)
+                && let Some(descriptor) = raw_utf8(&raw_pool, *descriptor_idx){
                     ConstantEntry::NameAndType(NameAndType{
-                        name: name.clone(),
-                        descriptor: descriptor.clone(),
+                        name,
+                        descriptor,
                     })
                 }else{ return Err("Invalid NameAndType entry".to_owned()); }
             }
 
             RawConstantEntry::MethodHandle(dyn_ref_idx, member_ref_idx) => {
                 // also same here
-                if let RawConstantEntry::MemberRef(mtype, owner_class_idx, name_and_type_idx) = &raw_pool[*member_ref_idx as usize - 1]
-                && let RawConstantEntry::Class(class_name_idx) = &raw_pool[*owner_class_idx as usize - 1]
-                && let RawConstantEntry::Utf8(class_name) = &raw_pool[*class_name_idx as usize - 1]
-                && let RawConstantEntry::NameAndType(name_idx, desc_idx) = &raw_pool[*name_and_type_idx as usize - 1]
-                && let RawConstantEntry::Utf8(name) = &raw_pool[*name_idx as usize - 1]
-                && let RawConstantEntry::Utf8(desc) = &raw_pool[*desc_idx as usize - 1]{
+                if let Some(RawConstantEntry::MemberRef(mtype, owner_class_idx, name_and_type_idx)) = raw_constant(&raw_pool, *member_ref_idx)
+                && let Some(RawConstantEntry::Class(class_name_idx)) = raw_constant(&raw_pool, *owner_class_idx)
+                && let Some(class_name) = raw_utf8(&raw_pool, *class_name_idx)
+                && let Some(RawConstantEntry::NameAndType(name_idx, desc_idx)) = raw_constant(&raw_pool, *name_and_type_idx)
+                && let Some(name) = raw_utf8(&raw_pool, *name_idx)
+                && let Some(desc) = raw_utf8(&raw_pool, *desc_idx){
                     ConstantEntry::MethodHandle(
                         dyn_ref_index_to_type(dyn_ref_idx)?,
                         MemberRef{
                             kind: tag_to_member_kind(mtype)?,
-                            owner_name: class_name.clone(),
+                            owner_name: class_name,
                             name_and_type: NameAndType{
-                                name: name.clone(),
-                                descriptor: desc.clone(),
+                                name,
+                                descriptor: desc,
                             },
                         }
                     )
                 }else{ return Err("Invalid MethodHandle entry".to_owned()); }
             },
 
-            RawConstantEntry::Dynamic(_,_,_) => {
-                // TODO: resolve against bootstrap table later
-                ConstantEntry::Dynamic(Dynamic{
-                    bootstrap: NameAndType{ name: "".to_string(), descriptor: "".to_string() },
-                    value: NameAndType{ name: "".to_string(), descriptor: "".to_string() }
-                })
+            RawConstantEntry::Dynamic(tag, bootstrap, name_and_type_idx) => {
+                // the bootstrap method is looked up in the BootstrapMethods attribute when it's needed
+                if let Some(RawConstantEntry::NameAndType(name_idx, desc_idx)) = raw_constant(&raw_pool, *name_and_type_idx)
+                && let Some(name) = raw_utf8(&raw_pool, *name_idx)
+                && let Some(desc) = raw_utf8(&raw_pool, *desc_idx){
+                    let dynamic = Dynamic{
+                        bootstrap: *bootstrap,
+                        value: NameAndType{ name, descriptor: desc }
+                    };
+                    if *tag == 18{ ConstantEntry::InvokeDynamic(dynamic) }else{ ConstantEntry::Dynamic(dynamic) }
+                }else{ return Err("Invalid Dynamic entry".to_owned()); }
             }
 
-            _ => panic!("Bad conversion from {:?}", con)
-        });
+            _ => return Err(format!("Invalid constant pool entry {:?}", con))
+        };
+        check_constant(&entry)?;
+        ret.push(entry);
     }
     return Ok(ret);
 }
 
+/// Returns the constant at the given index of the constant pool, which starts at 1.
+fn constant(const_pool: &Vec<ConstantEntry>, idx: u16) -> Result<&ConstantEntry, String>{
+    return match const_pool.get((idx as usize).wrapping_sub(1)){
+        Some(ConstantEntry::LongSecond) | None => Err(format!("Invalid constant pool index {}", idx)),
+        Some(c) => Ok(c)
+    };
+}
+
+fn utf8_constant(const_pool: &Vec<ConstantEntry>, idx: u16) -> Result<String, String>{
+    let ConstantEntry::Utf8(s) = constant(const_pool, idx)? else { return Err(format!("Constant {} isn't a Utf8 entry", idx)) };
+    return Ok(s.clone());
+}
+
+fn class_constant(const_pool: &Vec<ConstantEntry>, idx: u16) -> Result<String, String>{
+    let ConstantEntry::Class(s) = constant(const_pool, idx)? else { return Err(format!("Constant {} isn't a Class entry", idx)) };
+    return Ok(s.clone());
+}
+
+fn module_constant(const_pool: &Vec<ConstantEntry>, idx: u16) -> Result<String, String>{
+    let ConstantEntry::Module(s) = constant(const_pool, idx)? else { return Err(format!("Constant {} isn't a Module entry", idx)) };
+    return Ok(s.clone());
+}
+
+/// Gets a constant with the given function, unless the index is 0, which means there isn't one.
+fn optional_constant<T>(idx: u16, get: impl FnOnce(u16) -> Result<T, String>) -> Result<Option<T>, String>{
+    return if idx == 0{ Ok(None) }else{ get(idx).map(Some) };
+}
+
+/// Returns the Utf8 entry at the given index as a string, for names and descriptors. Unlike string constants, these
+/// aren't kept as UTF-16, so any unpaired surrogates are replaced.
+fn raw_utf8(raw_pool: &Vec<RawConstantEntry>, idx: u16) -> Option<String>{
+    let Some(RawConstantEntry::Utf8(chars)) = raw_constant(raw_pool, idx) else { return None };
+    return Some(String::from_utf16_lossy(chars));
+}
+
+fn raw_constant(raw_pool: &Vec<RawConstantEntry>, idx: u16) -> Option<&RawConstantEntry>{
+    return raw_pool.get((idx as usize).wrapping_sub(1));
+}
+
+/// Returns a constant for ldc and ldc_w, or ldc2_w if wide, checking that it can be loaded by them (JVMS 4.4).
+fn loadable_constant(const_pool: &Vec<ConstantEntry>, idx: u16, wide: bool) -> Result<ConstantEntry, String>{
+    let c = constant(const_pool, idx)?;
+    let loadable = match c{
+        ConstantEntry::Long(_) | ConstantEntry::Double(_) => wide,
+        ConstantEntry::Integer(_) | ConstantEntry::Float(_) | ConstantEntry::StringConst(_)
+        | ConstantEntry::Class(_) | ConstantEntry::MethodType(_) | ConstantEntry::MethodHandle(..) => !wide,
+        ConstantEntry::Dynamic(d) => wide == matches!(d.value.descriptor.as_str(), "J" | "D"),
+        _ => false
+    };
+    if !loadable{
+        return Err(format!("Constant {:?} can't be loaded by {}", c, if wide{ "ldc2_w" }else{ "ldc" }));
+    }
+    return Ok(c.clone());
+}
+
 fn tag_to_member_kind(tag: &u8) -> Result<MemberKind, String>{
     return match tag {
         9 => Ok(MemberKind::Field),
@@ -247,8 +344,8 @@ fn dyn_ref_index_to_type(idx: &u8) -> Result<DynamicReferenceType, String>{
     }
 }
 
-fn newarray_operand_to_descriptor(op: u8) -> String{
-    return (match op{
+fn newarray_operand_to_descriptor(op: u8) -> Result<String, String>{
+    return Ok((match op{
         4 => "Z",
         5 => "C",
         6 => "F",
@@ -257,8 +354,8 @@ fn newarray_operand_to_descriptor(op: u8) -> String{
         9 => "S",
         10 => "I",
         11 => "J",
-        _ => panic!("Unknown newarray array type {}!", op)
-    }).to_owned();
+        _ => return Err(format!("Unknown newarray array type {}", op))
+    }).to_owned());
 }
 
 fn parse_attributes(file: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<Vec<Attribute>, String>{
@@ -266,10 +363,13 @@ fn parse_attributes(file: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Resu
     let mut ret: Vec<Attribute> = Vec::with_capacity(count as usize);
     for _ in 0..count{
         let Some(name_idx) = next_short(file) else { return Err("Missing attribute name".to_owned()); };
-        if let ConstantEntry::Utf8(name) = &const_pool[name_idx as usize - 1]{
+        if let ConstantEntry::Utf8(name) = constant(const_pool, name_idx)?{
             let Some(size) = next_uint(file) else { return Err("Missing attribute size".to_owned()); };
-            let attr_data = next_vec(file, size as usize);
-            if let Some(attr) = parse_attribute(attr_data, &const_pool, name)?{
+            let mut attr_data = next_vec(file, size as usize)?;
+            if let Some(attr) = parse_attribute(&mut attr_data, &const_pool, name)?{
+                if !attr_data.is_empty(){
+                    return Err(format!("{} attribute is longer than its contents", name));
+                }
                 ret.push(attr);
             }
         }else{
@@ -279,44 +379,253 @@ fn parse_attributes(file: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Resu
     return Ok(ret);
 }
 
-fn parse_attribute(mut attr: Vec<u8>, const_pool: &Vec<ConstantEntry>, name: &String) -> Result<Option<Attribute>, String>{
+fn parse_attribute(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>, name: &String) -> Result<Option<Attribute>, String>{
     let name: &str = name;
     match name{
         "SourceFile" => {
-            let ConstantEntry::Utf8(source) = &const_pool[next_short_err(&mut attr)? as usize - 1] else { return Err("Invalid SourceFile name index".to_owned()) };
+            let ConstantEntry::Utf8(source) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid SourceFile name index".to_owned()) };
             return Ok(Some(Attribute::SourceFile(source.clone())));
         }
-        
+        "SourceDebugExtension" => {
+            // not length-prefixed, unlike Utf8 constants
+            let debug = modified_utf8::decode_string(attr)?;
+            return Ok(Some(Attribute::SourceDebugExtension(debug)));
+        },
+        "InnerClasses" => {
+            let count = next_short_err(attr)?;
+            let mut classes = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                classes.push(InnerClassInfo{
+                    inner_class: class_constant(const_pool, next_short_err(attr)?)?,
+                    outer_class: optional_constant(next_short_err(attr)?, |idx| class_constant(const_pool, idx))?,
+                    name: optional_constant(next_short_err(attr)?, |idx| utf8_constant(const_pool, idx))?,
+                    flags: next_short_err(attr)?
+                });
+            }
+            return Ok(Some(Attribute::InnerClasses(classes)));
+        },
+        "EnclosingMethod" => {
+            let owner_class = class_constant(const_pool, next_short_err(attr)?)?;
+            let owner_method = optional_constant(next_short_err(attr)?, |idx| match constant(const_pool, idx)?{
+                ConstantEntry::NameAndType(nt) => Ok(nt.clone()),
+                _ => Err("Invalid EnclosingMethod method index".to_owned())
+            })?;
+            return Ok(Some(Attribute::EnclosingMethod{ owner_class, owner_method }));
+        },
+        "Signature" => return Ok(Some(Attribute::Signature(utf8_constant(const_pool, next_short_err(attr)?)?))),
+
+        "Module" => {
+            let ConstantEntry::Module(name) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid Module name index".to_owned()) };
+            let flags = next_short_err(attr)?;
+            let version = optional_constant(next_short_err(attr)?, |idx| utf8_constant(const_pool, idx))?;
+            let mut requires = Vec::new();
+            for _ in 0..next_short_err(attr)?{
+                requires.push(ModuleRequires{
+                    module: module_constant(const_pool, next_short_err(attr)?)?,
+                    flags: next_short_err(attr)?,
+                    version: optional_constant(next_short_err(attr)?, |idx| utf8_constant(const_pool, idx))?
+                });
+            }
+            let exports = parse_module_exports(attr, const_pool)?;
+            let opens = parse_module_exports(attr, const_pool)?;
+            let mut uses = Vec::new();
+            for _ in 0..next_short_err(attr)?{
+                uses.push(class_constant(const_pool, next_short_err(attr)?)?);
+            }
+            let mut provides = Vec::new();
+            for _ in 0..next_short_err(attr)?{
+                let service = class_constant(const_pool, next_short_err(attr)?)?;
+                let mut with = Vec::new();
+                for _ in 0..next_short_err(attr)?{
+                    with.push(class_constant(const_pool, next_short_err(attr)?)?);
+                }
+                provides.push(ModuleProvides{ service, with });
+            }
+            return Ok(Some(Attribute::Module(ModuleInfo{ name: name.clone(), flags, version, requires, exports, opens, uses, provides })));
+        },
+        "ModulePackages" => {
+            let count = next_short_err(attr)?;
+            let mut packages = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                let ConstantEntry::Package(package) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid ModulePackages package index".to_owned()) };
+                packages.push(package.clone());
+            }
+            return Ok(Some(Attribute::ModulePackages(packages)));
+        },
+        "ModuleMainClass" => return Ok(Some(Attribute::ModuleMainClass(class_constant(const_pool, next_short_err(attr)?)?))),
+
+        "NestHost" => {
+            let ConstantEntry::Class(host) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid NestHost class index".to_owned()) };
+            return Ok(Some(Attribute::NestHost(host.clone())));
+        },
+        "NestMembers" => {
+            let count = next_short_err(attr)?;
+            let mut members = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                let ConstantEntry::Class(member) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid NestMembers class index".to_owned()) };
+                members.push(member.clone());
+            }
+            return Ok(Some(Attribute::NestMembers(members)));
+        },
+
+        "BootstrapMethods" => {
+            let count = next_short_err(attr)?;
+            let mut entries = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                let ConstantEntry::MethodHandle(ref_type, method) = constant(const_pool, next_short_err(attr)?)?
+                    else { return Err("Invalid bootstrap method handle index".to_owned()) };
+                let arg_count = next_short_err(attr)?;
+                let mut args = Vec::with_capacity(arg_count as usize);
+                for _ in 0..arg_count{
+                    args.push(constant(const_pool, next_short_err(attr)?)?.clone());
+                }
+                entries.push(BootstrapEntry{ ref_type: ref_type.clone(), method: method.clone(), args });
+            }
+            return Ok(Some(Attribute::BootstrapMethods(entries)));
+        },
+        "Record" => {
+            let count = next_short_err(attr)?;
+            let mut components = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                let ConstantEntry::Utf8(name) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid record component name index".to_owned()) };
+                let ConstantEntry::Utf8(desc) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid record component descriptor index".to_owned()) };
+                let attributes = parse_attributes(attr, const_pool)?;
+                components.push(RecordComponentInfo{ name: name.clone(), desc: desc.clone(), attributes });
+            }
+            return Ok(Some(Attribute::Record(components)));
+        },
+        "PermittedSubclasses" => {
+            let count = next_short_err(attr)?;
+            let mut permitted = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                let ConstantEntry::Class(class) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid PermittedSubclasses class index".to_owned()) };
+                permitted.push(class.clone());
+            }
+            return Ok(Some(Attribute::PermittedSubclasses(permitted)));
+        },
+
+        "ConstantValue" => {
+            let value = constant(const_pool, next_short_err(attr)?)?;
+            if !matches!(value, ConstantEntry::Integer(_) | ConstantEntry::Float(_) | ConstantEntry::Long(_) | ConstantEntry::Double(_) | ConstantEntry::StringConst(_)){
+                return Err(format!("Invalid ConstantValue {:?}", value));
+            }
+            return Ok(Some(Attribute::ConstantValue(value.clone())));
+        },
+
+        "Exceptions" => {
+            let count = next_short_err(attr)?;
+            let mut exceptions = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                exceptions.push(class_constant(const_pool, next_short_err(attr)?)?);
+            }
+            return Ok(Some(Attribute::Exceptions(exceptions)));
+        },
+        "MethodParameters" => {
+            let count = next_byte_err(attr)?;
+            let mut parameters = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                parameters.push(ParameterInfo{
+                    name: optional_constant(next_short_err(attr)?, |idx| utf8_constant(const_pool, idx))?,
+                    flags: next_short_err(attr)?
+                });
+            }
+            return Ok(Some(Attribute::MethodParameters(parameters)));
+        },
+
+        "RuntimeVisibleAnnotations" => {
+            let raw = attr.clone();
+            return Ok(Some(Attribute::RuntimeVisibleAnnotations(parse_annotations(attr, const_pool)?, raw)));
+        },
+        "RuntimeInvisibleAnnotations" => return Ok(Some(Attribute::RuntimeInvisibleAnnotations(parse_annotations(attr, const_pool)?))),
+        "RuntimeVisibleParameterAnnotations" => {
+            let raw = attr.clone();
+            return Ok(Some(Attribute::RuntimeVisibleParameterAnnotations(parse_parameter_annotations(attr, const_pool)?, raw)));
+        },
+        "RuntimeInvisibleParameterAnnotations" => return Ok(Some(Attribute::RuntimeInvisibleParameterAnnotations(parse_parameter_annotations(attr, const_pool)?))),
+        "RuntimeVisibleTypeAnnotations" => {
+            let raw = attr.clone();
+            return Ok(Some(Attribute::RuntimeVisibleTypeAnnotations(parse_type_annotations(attr, const_pool)?, raw)));
+        },
+        "RuntimeInvisibleTypeAnnotations" => return Ok(Some(Attribute::RuntimeInvisibleTypeAnnotations(parse_type_annotations(attr, const_pool)?))),
+        "AnnotationDefault" => {
+            let raw = attr.clone();
+            return Ok(Some(Attribute::AnnotationDefault(parse_element_value(attr, const_pool)?, raw)));
+        },
+
         "Synthetic" => return Ok(Some(Attribute::Synthetic)),
         "Deprecated" => return Ok(Some(Attribute::Deprecated)),
 
         "LineNumberTable" => {
-            let entries = next_short_err(&mut attr)?;
+            let entries = next_short_err(attr)?;
             let mut table = Vec::with_capacity(entries as usize);
             for _ in 0..entries{
                 table.push(LineNumberMapping{
-                    bytecode_idx: next_short_err(&mut attr)?,
-                    line_number: next_short_err(&mut attr)?
+                    bytecode_idx: next_short_err(attr)?,
+                    line_number: next_short_err(attr)?
                 });
             }
             return Ok(Some(Attribute::LineNumberTable(table)));
         },
+        "LocalVariableTable" => return Ok(Some(Attribute::LocalVariableTable(parse_local_variables(attr, const_pool, false)?))),
+        "LocalVariableTypeTable" => return Ok(Some(Attribute::LocalVariableTypeTable(parse_local_variables(attr, const_pool, true)?))),
+
+        "StackMapTable" => {
+            let count = next_short_err(attr)?;
+            let mut frames = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                let Some(frame_type) = next_byte(attr) else { return Err("Unexpected end of file".to_owned()) };
+                frames.push(match frame_type{
+                    0..=63 => StackMapFrame::Same{ offset_delta: frame_type as u16 },
+                    64..=127 => StackMapFrame::SameLocals1StackItem{ offset_delta: frame_type as u16 - 64, stack: parse_verification_type(attr, const_pool)? },
+                    247 => StackMapFrame::SameLocals1StackItem{ offset_delta: next_short_err(attr)?, stack: parse_verification_type(attr, const_pool)? },
+                    248..=250 => StackMapFrame::Chop{ offset_delta: next_short_err(attr)?, count: 251 - frame_type },
+                    251 => StackMapFrame::Same{ offset_delta: next_short_err(attr)? },
+                    252..=254 => {
+                        let offset_delta = next_short_err(attr)?;
+                        let mut locals = Vec::with_capacity(frame_type as usize - 251);
+                        for _ in 251..frame_type{
+                            locals.push(parse_verification_type(attr, const_pool)?);
+                        }
+                        StackMapFrame::Append{ offset_delta, locals }
+                    },
+                    255 => {
+                        let offset_delta = next_short_err(attr)?;
+                        let mut locals = Vec::new();
+                        for _ in 0..next_short_err(attr)?{
+                            locals.push(parse_verification_type(attr, const_pool)?);
+                        }
+                        let mut stack = Vec::new();
+                        for _ in 0..next_short_err(attr)?{
+                            stack.push(parse_verification_type(attr, const_pool)?);
+                        }
+                        StackMapFrame::Full{ offset_delta, locals, stack }
+                    },
+                    _ => return Err(format!("Invalid stack map frame type {}", frame_type))
+                });
+            }
+            return Ok(Some(Attribute::StackMapTable(frames)));
+        },
 
         "Code" => {
-            let max_stack = next_short_err(&mut attr)?;
-            let max_locals = next_short_err(&mut attr)?;
+            let max_stack = next_short_err(attr)?;
+            let max_locals = next_short_err(attr)?;
 
-            let bytecode_length = next_uint_err(&mut attr)?;
-            let mut bytecode = next_vec(&mut attr, bytecode_length as usize);
+            let bytecode_length = next_uint_err(attr)?;
+            if bytecode_length == 0 || bytecode_length >= 65536{
+                return Err(format!("Invalid code length {}", bytecode_length));
+            }
+            let mut bytecode = next_vec(attr, bytecode_length as usize)?;
             let bytecode = parse_bytecode(&mut bytecode, const_pool)?;
 
-            let exception_handlers_count = next_short_err(&mut attr)?;
+            let exception_handlers_count = next_short_err(attr)?;
             let mut exception_handlers: Vec<ExceptionHandler> = Vec::with_capacity(exception_handlers_count as usize);
             for _ in 0..exception_handlers_count{
-                exception_handlers.push(parse_exception_handler(&mut attr, const_pool)?);
+                exception_handlers.push(parse_exception_handler(attr, const_pool)?);
             }
+            resolve_handler_ranges(&bytecode, &mut exception_handlers)?;
 
-            let attributes = parse_attributes(&mut attr, const_pool)?;
+            let mut attributes = parse_attributes(attr, const_pool)?;
+            match_local_variable_types(&mut attributes, bytecode_length as u16)?;
 
             return Ok(Some(Attribute::Code(Code{
                 max_stack,
@@ -335,10 +644,203 @@ fn parse_attribute(mut attr: Vec<u8>, const_pool: &Vec<ConstantEntry>, name: &St
     return Ok(None); // unknown attributes are valid
 } 
 
+fn parse_annotations(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<Vec<Annotation>, String>{
+    let count = next_short_err(attr)?;
+    let mut annotations = Vec::with_capacity(count as usize);
+    for _ in 0..count{
+        annotations.push(parse_annotation(attr, const_pool)?);
+    }
+    return Ok(annotations);
+}
+
+fn parse_parameter_annotations(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<Vec<Vec<Annotation>>, String>{
+    let count = next_byte_err(attr)?;
+    let mut parameters = Vec::with_capacity(count as usize);
+    for _ in 0..count{
+        parameters.push(parse_annotations(attr, const_pool)?);
+    }
+    return Ok(parameters);
+}
+
+fn parse_annotation(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<Annotation, String>{
+    let class = utf8_constant(const_pool, next_short_err(attr)?)?;
+    check_field_descriptor(&class)?;
+    let count = next_short_err(attr)?;
+    let mut elements = Vec::with_capacity(count as usize);
+    for _ in 0..count{
+        let name = utf8_constant(const_pool, next_short_err(attr)?)?;
+        elements.push((name, parse_element_value(attr, const_pool)?));
+    }
+    return Ok(Annotation{ class, elements });
+}
+
+fn parse_element_value(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<ElementValue, String>{
+    let tag = next_byte_err(attr)? as char;
+    return Ok(match tag{
+        'B' | 'C' | 'D' | 'F' | 'I' | 'J' | 'S' | 'Z' | 's' => {
+            let value = constant(const_pool, next_short_err(attr)?)?;
+            let matches = match value{
+                ConstantEntry::Integer(_) => matches!(tag, 'B' | 'C' | 'I' | 'S' | 'Z'),
+                ConstantEntry::Double(_) => tag == 'D',
+                ConstantEntry::Float(_) => tag == 'F',
+                ConstantEntry::Long(_) => tag == 'J',
+                ConstantEntry::Utf8(_) => tag == 's',
+                _ => false
+            };
+            if !matches{
+                return Err(format!("Invalid constant {:?} for annotation element of type {}", value, tag));
+            }
+            ElementValue::Const(tag, value.clone())
+        },
+        'e' => {
+            let type_desc = utf8_constant(const_pool, next_short_err(attr)?)?;
+            check_field_descriptor(&type_desc)?;
+            ElementValue::Enum{ type_desc, name: utf8_constant(const_pool, next_short_err(attr)?)? }
+        },
+        'c' => {
+            let class = utf8_constant(const_pool, next_short_err(attr)?)?;
+            if class != "V"{
+                check_field_descriptor(&class)?;
+            }
+            ElementValue::Class(class)
+        },
+        '@' => ElementValue::Annotation(parse_annotation(attr, const_pool)?),
+        '[' => {
+            let count = next_short_err(attr)?;
+            let mut values = Vec::with_capacity(count as usize);
+            for _ in 0..count{
+                values.push(parse_element_value(attr, const_pool)?);
+            }
+            ElementValue::Array(values)
+        },
+        _ => return Err(format!("Invalid annotation element tag {}", tag))
+    });
+}
+
+fn parse_type_annotations(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<Vec<TypeAnnotation>, String>{
+    let count = next_short_err(attr)?;
+    let mut annotations = Vec::with_capacity(count as usize);
+    for _ in 0..count{
+        let target_type = next_byte_err(attr)?;
+        let target = match target_type{
+            0x00 | 0x01 => TypeAnnotationTarget::TypeParameter(next_byte_err(attr)?),
+            0x10 => TypeAnnotationTarget::Supertype(next_short_err(attr)?),
+            0x11 | 0x12 => TypeAnnotationTarget::TypeParameterBound(next_byte_err(attr)?, next_byte_err(attr)?),
+            0x13..=0x15 => TypeAnnotationTarget::Empty,
+            0x16 => TypeAnnotationTarget::FormalParameter(next_byte_err(attr)?),
+            0x17 => TypeAnnotationTarget::Throws(next_short_err(attr)?),
+            0x40 | 0x41 => {
+                let mut ranges = Vec::new();
+                for _ in 0..next_short_err(attr)?{
+                    ranges.push((next_short_err(attr)?, next_short_err(attr)?, next_short_err(attr)?));
+                }
+                TypeAnnotationTarget::LocalVar(ranges)
+            },
+            0x42 => TypeAnnotationTarget::Catch(next_short_err(attr)?),
+            0x43..=0x46 => TypeAnnotationTarget::Offset(next_short_err(attr)?),
+            0x47..=0x4B => TypeAnnotationTarget::TypeArgument(next_short_err(attr)?, next_byte_err(attr)?),
+            _ => return Err(format!("Invalid type annotation target type {}", target_type))
+        };
+        let path_length = next_byte_err(attr)?;
+        let mut path = Vec::with_capacity(path_length as usize);
+        for _ in 0..path_length{
+            path.push((next_byte_err(attr)?, next_byte_err(attr)?));
+        }
+        annotations.push(TypeAnnotation{ target_type, target, path, annotation: parse_annotation(attr, const_pool)? });
+    }
+    return Ok(annotations);
+}
+
+fn parse_module_exports(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<Vec<ModuleExports>, String>{
+    let count = next_short_err(attr)?;
+    let mut exports = Vec::with_capacity(count as usize);
+    for _ in 0..count{
+        let ConstantEntry::Package(package) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid Module package index".to_owned()) };
+        let flags = next_short_err(attr)?;
+        let mut to = Vec::new();
+        for _ in 0..next_short_err(attr)?{
+            to.push(module_constant(const_pool, next_short_err(attr)?)?);
+        }
+        exports.push(ModuleExports{ package: package.clone(), flags, to });
+    }
+    return Ok(exports);
+}
+
+fn parse_local_variables(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>, types: bool) -> Result<Vec<LocalVariableEntry>, String>{
+    let count = next_short_err(attr)?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count{
+        let start_idx = next_short_err(attr)?;
+        let Some(end_idx) = start_idx.checked_add(next_short_err(attr)?) else { return Err("Local variable range is past the end of the code".to_owned()) };
+        let name = utf8_constant(const_pool, next_short_err(attr)?)?;
+        check_unqualified_name(&name, false)?;
+        // the type table has signatures in place of descriptors, which get filled in by `match_local_variable_types`
+        let desc_or_sig = utf8_constant(const_pool, next_short_err(attr)?)?;
+        let (desc, sig) = if types{ (String::new(), Some(desc_or_sig)) }else{ check_field_descriptor(&desc_or_sig)?; (desc_or_sig, None) };
+        entries.push(LocalVariableEntry{ start_idx, end_idx, name, desc, sig, lv_idx: next_short_err(attr)? });
+    }
+    return Ok(entries);
+}
+
+/// Fills in the signatures of LocalVariableTable entries, and the descriptors of LocalVariableTypeTable entries,
+/// from the entry for the same variable in the other table. Every type table entry needs one (JVMS 4.7.14).
+fn match_local_variable_types(attributes: &mut [Attribute], code_length: u16) -> Result<(), String>{
+    let same_var = |a: &LocalVariableEntry, b: &LocalVariableEntry|
+        a.start_idx == b.start_idx && a.end_idx == b.end_idx && a.name == b.name && a.lv_idx == b.lv_idx;
+    let mut typed: Vec<LocalVariableEntry> = Vec::new();
+    for attr in attributes.iter(){
+        if let Attribute::LocalVariableTypeTable(entries) = attr{
+            typed.extend(entries.iter().cloned());
+        }
+    }
+    let mut plain: Vec<LocalVariableEntry> = Vec::new();
+    for attr in attributes.iter_mut(){
+        if let Attribute::LocalVariableTable(entries) = attr{
+            for entry in entries.iter_mut(){
+                if entry.end_idx > code_length{
+                    return Err(format!("Local variable {} is past the end of the code", entry.name));
+                }
+                entry.sig = typed.iter().find(|t| same_var(t, entry)).and_then(|t| t.sig.clone());
+                plain.push(entry.clone());
+            }
+        }
+    }
+    for attr in attributes.iter_mut(){
+        if let Attribute::LocalVariableTypeTable(entries) = attr{
+            for entry in entries.iter_mut(){
+                let Some(var) = plain.iter().find(|p| same_var(p, entry)) else {
+                    return Err(format!("LocalVariableTypeTable entry {} doesn't match any LocalVariableTable entry", entry.name));
+                };
+                entry.desc = var.desc.clone();
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn parse_verification_type(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<VerificationType, String>{
+    return Ok(match next_byte(attr){
+        Some(0) => VerificationType::Top,
+        Some(1) => VerificationType::Integer,
+        Some(2) => VerificationType::Float,
+        Some(3) => VerificationType::Double,
+        Some(4) => VerificationType::Long,
+        Some(5) => VerificationType::Null,
+        Some(6) => VerificationType::UninitializedThis,
+        Some(7) => {
+            let ConstantEntry::Class(class) = constant(const_pool, next_short_err(attr)?)? else { return Err("Invalid verification type class index".to_owned()) };
+            VerificationType::Object(class.clone())
+        },
+        Some(8) => VerificationType::Uninitialized(next_short_err(attr)?),
+        Some(tag) => return Err(format!("Invalid verification type tag {}", tag)),
+        None => return Err("Unexpected end of file".to_owned())
+    });
+}
+
 fn parse_exception_handler(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Result<ExceptionHandler, String>{
-    let start_idx = next_short_err(attr)?;
-    let end_idx = next_short_err(attr)?;
-    let handler_idx = next_short_err(attr)?;
+    let start_idx = next_short_err(attr)? as usize;
+    let end_idx = next_short_err(attr)? as usize;
+    let handler_idx = next_short_err(attr)? as usize;
     let exception_type_idx = next_short_err(attr)?;
     return if exception_type_idx == 0 {
         Ok(ExceptionHandler {
@@ -348,7 +850,7 @@ fn parse_exception_handler(attr: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>)
             catch_type: None
         })
     } else {
-        if let ConstantEntry::Class(exception_name) = &const_pool[exception_type_idx as usize - 1] {
+        if let ConstantEntry::Class(exception_name) = constant(const_pool, exception_type_idx)? {
             Ok(ExceptionHandler {
                 start_idx,
                 end_idx,
@@ -369,7 +871,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
         let idx = start_len - bytecode.len();
         let opcode = bytecode.remove(0);
         match opcode{
-            constants::OP_NOP => { /* no-op */ },
+            constants::OP_NOP => result.push((idx, Instruction::Nop)),
 
             constants::OP_ACONST_NULL => result.push((idx, Instruction::AConstNull)),
             
@@ -405,29 +907,25 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_DCONST_0 => result.push((idx, Instruction::DConst(0.0))),
             constants::OP_DCONST_1 => result.push((idx, Instruction::DConst(1.0))),
 
-            // TODO: check constant types
             constants::OP_LDC => {
                 if let Some(it) = next_byte(bytecode){
-                    let c = &const_pool[it as usize - 1];
-                    result.push((idx, Instruction::Ldc(c.clone())));
+                    result.push((idx, Instruction::Ldc(loadable_constant(const_pool, it as u16, false)?)));
                 }else{
                     return Err("Missing byte operand of ldc".to_owned());
                 }
             }
             constants::OP_LDC_W => {
                 if let Some(it) = next_short(bytecode){
-                    let c = &const_pool[it as usize - 1];
-                    result.push((idx, Instruction::Ldc(c.clone())));
+                    result.push((idx, Instruction::Ldc(loadable_constant(const_pool, it, false)?)));
                 }else{
                     return Err("Missing short operand of ldc_w".to_owned());
                 }
             }
             constants::OP_LDC2_W => {
                 if let Some(it) = next_short(bytecode){
-                    let c = &const_pool[it as usize - 1];
-                    result.push((idx, Instruction::Ldc(c.clone())));
+                    result.push((idx, Instruction::Ldc(loadable_constant(const_pool, it, true)?)));
                 }else{
-                    return Err("Missing short operand of ldc_w".to_owned());
+                    return Err("Missing short operand of ldc2_w".to_owned());
                 }
             }
 
@@ -437,7 +935,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_ISTORE_3 => result.push((idx, Instruction::IStore(3))),
             constants::OP_ISTORE => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::IStore(it)));
+                    result.push((idx, Instruction::IStore(it as u16)));
                 }else{
                     return Err("Missing byte operand of istore".to_owned());
                 }
@@ -449,7 +947,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_LSTORE_3 => result.push((idx, Instruction::LStore(3))),
             constants::OP_LSTORE => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::LStore(it)));
+                    result.push((idx, Instruction::LStore(it as u16)));
                 }else{
                     return Err("Missing byte operand of lstore".to_owned());
                 }
@@ -461,7 +959,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_FSTORE_3 => result.push((idx, Instruction::FStore(3))),
             constants::OP_FSTORE => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::FStore(it)));
+                    result.push((idx, Instruction::FStore(it as u16)));
                 }else{
                     return Err("Missing byte operand of fstore".to_owned());
                 }
@@ -473,7 +971,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_DSTORE_3 => result.push((idx, Instruction::DStore(3))),
             constants::OP_DSTORE => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::DStore(it)));
+                    result.push((idx, Instruction::DStore(it as u16)));
                 }else{
                     return Err("Missing byte operand of dstore".to_owned());
                 }
@@ -485,7 +983,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_ASTORE_3 => result.push((idx, Instruction::AStore(3))),
             constants::OP_ASTORE => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::AStore(it)));
+                    result.push((idx, Instruction::AStore(it as u16)));
                 }else{
                     return Err("Missing byte operand of astore".to_owned());
                 }
@@ -506,7 +1004,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_ILOAD_3 => result.push((idx, Instruction::ILoad(3))),
             constants::OP_ILOAD => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::ILoad(it)));
+                    result.push((idx, Instruction::ILoad(it as u16)));
                 }else{
                     return Err("Missing byte operand of iload".to_owned());
                 }
@@ -518,7 +1016,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_LLOAD_3 => result.push((idx, Instruction::LLoad(3))),
             constants::OP_LLOAD => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::LLoad(it)));
+                    result.push((idx, Instruction::LLoad(it as u16)));
                 }else{
                     return Err("Missing byte operand of lload".to_owned());
                 }
@@ -530,7 +1028,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_FLOAD_3 => result.push((idx, Instruction::FLoad(3))),
             constants::OP_FLOAD => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::FLoad(it)));
+                    result.push((idx, Instruction::FLoad(it as u16)));
                 }else{
                     return Err("Missing byte operand of fload".to_owned());
                 }
@@ -542,7 +1040,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_DLOAD_3 => result.push((idx, Instruction::DLoad(3))),
             constants::OP_DLOAD => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::DLoad(it)));
+                    result.push((idx, Instruction::DLoad(it as u16)));
                 }else{
                     return Err("Missing byte operand of dload".to_owned());
                 }
@@ -554,7 +1052,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_ALOAD_3 => result.push((idx, Instruction::ALoad(3))),
             constants::OP_ALOAD => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::ALoad(it)));
+                    result.push((idx, Instruction::ALoad(it as u16)));
                 }else{
                     return Err("Missing byte operand of aload".to_owned());
                 }
@@ -582,7 +1080,7 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_IINC => {
                 if let Some(target) = next_byte(bytecode)
                 && let Some(offset) = next_sbyte(bytecode){
-                    result.push((idx, Instruction::IInc(target, offset)));
+                    result.push((idx, Instruction::IInc(target as u16, offset as i16)));
                 }else{
                     return Err("Missing byte operand(s) of iinc".to_owned());
                 }
@@ -634,52 +1132,82 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
 
             constants::OP_GOTO => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::Goto(it as i32)));
+                    result.push((idx, Instruction::Goto(branch_target(idx, it as i32)?)));
                 }else{
                     return Err("Missing short operand of goto".to_owned());
                 }
             }
             constants::OP_GOTO_W => {
                 if let Some(it) = next_int(bytecode){
-                    result.push((idx, Instruction::Goto(it)));
+                    result.push((idx, Instruction::Goto(branch_target(idx, it)?)));
                 }else{
                     return Err("Missing uint operand of goto_w".to_owned());
                 }
             },
+            constants::OP_JSR => {
+                if let Some(it) = next_sshort(bytecode){
+                    result.push((idx, Instruction::Jsr(branch_target(idx, it as i32)?)));
+                }else{
+                    return Err("Missing short operand of jsr".to_owned());
+                }
+            },
+            constants::OP_JSR_W => {
+                if let Some(it) = next_int(bytecode){
+                    result.push((idx, Instruction::Jsr(branch_target(idx, it)?)));
+                }else{
+                    return Err("Missing uint operand of jsr_w".to_owned());
+                }
+            },
+            constants::OP_RET => {
+                if let Some(it) = next_byte(bytecode){
+                    result.push((idx, Instruction::Ret(it as u16)));
+                }else{
+                    return Err("Missing byte operand of ret".to_owned());
+                }
+            },
 
             constants::OP_TABLE_SWITCH => {
                 let pad = (4 - ((idx + 1) % 4)) % 4; // amazing
-                for _ in 0..pad{
-                    bytecode.remove(0);
+                if next_vec(bytecode, pad).is_err(){
+                    return Err("Missing padding of switch".to_owned());
                 }
                 if let Some(default_idx) = next_int(bytecode)
                 && let Some(lo) = next_int(bytecode)
                 && let Some(hi) = next_int(bytecode){
-                    let n_jumps = (hi - lo + 1) as usize;
-                    let mut jumps: Vec<i32> = Vec::with_capacity(n_jumps);
+                    if hi < lo{
+                        return Err(format!("Empty tableswitch range {} to {}", lo, hi));
+                    }
+                    let n_jumps = (hi as i64 - lo as i64 + 1) as usize;
+                    if n_jumps > bytecode.len() / 4{
+                        return Err("Missing jump targets of tableswitch".to_owned());
+                    }
+                    let mut jumps: Vec<usize> = Vec::with_capacity(n_jumps);
                     for _ in 0..n_jumps{
                         if let Some(off) = next_int(bytecode){
-                            jumps.push(off);
+                            jumps.push(branch_target(idx, off)?);
                         }else{ return Err("Missing jump target of tableswitch".to_owned()); }
                     }
-                    result.push((idx, Instruction::TableSwitch(default_idx, lo, hi, jumps)));
+                    result.push((idx, Instruction::TableSwitch(branch_target(idx, default_idx)?, lo, hi, jumps)));
                 }else{ return Err("Missing initial int operands of tableswitch".to_owned()); }
             },
             constants::OP_LOOKUP_SWITCH => {
                 let pad = (4 - ((idx + 1) % 4)) % 4; // amazing
-                for _ in 0..pad{
-                    bytecode.remove(0);
+                if next_vec(bytecode, pad).is_err(){
+                    return Err("Missing padding of switch".to_owned());
                 }
                 if let Some(default_idx) = next_int(bytecode)
                 && let Some(n_pairs) = next_int(bytecode){
-                    let mut pairs: Vec<(i32, i32)> = Vec::with_capacity(n_pairs as usize);
+                    if n_pairs < 0 || n_pairs as usize > bytecode.len() / 8{
+                        return Err(format!("Invalid lookupswitch pair count {}", n_pairs));
+                    }
+                    let mut pairs: Vec<(i32, usize)> = Vec::with_capacity(n_pairs as usize);
                     for _ in 0..n_pairs{
                         if let Some(m) = next_int(bytecode)
                         && let Some(off) = next_int(bytecode){
-                            pairs.push((m, off));
+                            pairs.push((m, branch_target(idx, off)?));
                         }else{ return Err("Missing match-offset pair of lookupswitch".to_owned()); }
                     }
-                    result.push((idx, Instruction::LookupSwitch(default_idx, pairs)));
+                    result.push((idx, Instruction::LookupSwitch(branch_target(idx, default_idx)?, pairs)));
                 }else{ return Err("Missing initial int operands of lookupswitch".to_owned()); }
             },
 
@@ -691,84 +1219,84 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
 
             constants::OP_IF_EQ => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfEq(it as i32)));
+                    result.push((idx, Instruction::IfEq(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifeq".to_owned()); }
             },
             constants::OP_IF_NE => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfNe(it as i32)));
+                    result.push((idx, Instruction::IfNe(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifne".to_owned()); }
             },
             constants::OP_IF_LT => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfLt(it as i32)));
+                    result.push((idx, Instruction::IfLt(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of iflt".to_owned()); }
             },
             constants::OP_IF_GE => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfGe(it as i32)));
+                    result.push((idx, Instruction::IfGe(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifge".to_owned()); }
             },
             constants::OP_IF_GT => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfGt(it as i32)));
+                    result.push((idx, Instruction::IfGt(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifgt".to_owned()); }
             },
             constants::OP_IF_LE => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfLe(it as i32)));
+                    result.push((idx, Instruction::IfLe(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifle".to_owned()); }
             },
 
             constants::OP_IF_ICMP_EQ => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfICmpEq(it as i32)));
+                    result.push((idx, Instruction::IfICmpEq(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ificmpeq".to_owned()); }
             },
             constants::OP_IF_ICMP_NE => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfICmpNe(it as i32)));
+                    result.push((idx, Instruction::IfICmpNe(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ificmpne".to_owned()); }
             },
             constants::OP_IF_ICMP_LT => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfICmpLt(it as i32)));
+                    result.push((idx, Instruction::IfICmpLt(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ificmplt".to_owned()); }
             },
             constants::OP_IF_ICMP_GE => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfICmpGe(it as i32)));
+                    result.push((idx, Instruction::IfICmpGe(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ificmpge".to_owned()); }
             },
             constants::OP_IF_ICMP_GT => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfICmpGt(it as i32)));
+                    result.push((idx, Instruction::IfICmpGt(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ificmpgt".to_owned()); }
             },
             constants::OP_IF_ICMP_LE => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfICmpLe(it as i32)));
+                    result.push((idx, Instruction::IfICmpLe(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ificmple".to_owned()); }
             },
 
             constants::OP_IF_ACMP_EQ => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfACmpEq(it as i32)));
+                    result.push((idx, Instruction::IfACmpEq(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifacmpeq".to_owned()); }
             },
             constants::OP_IF_ACMP_NE => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfACmpNe(it as i32)));
+                    result.push((idx, Instruction::IfACmpNe(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifacmpne".to_owned()); }
             },
             constants::OP_IF_NULL => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfNull(it as i32)));
+                    result.push((idx, Instruction::IfNull(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifnull".to_owned()); }
             },
             constants::OP_IF_NONNULL => {
                 if let Some(it) = next_sshort(bytecode){
-                    result.push((idx, Instruction::IfNonnull(it as i32)));
+                    result.push((idx, Instruction::IfNonnull(branch_target(idx, it as i32)?)));
                 }else{ return Err("Missing short operand of ifnonnull".to_owned()); }
             },
 
@@ -804,47 +1332,55 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_GET_STATIC |
             constants::OP_GET_FIELD => {
                 if let Some(it) = next_short(bytecode)
-                && let ConstantEntry::MemberRef(m) = &const_pool[it as usize - 1]{
-                    result.push((idx, Instruction::GetField(m.clone())));
+                && let ConstantEntry::MemberRef(m) = constant(const_pool, it)?{
+                    result.push((idx, if opcode == constants::OP_GET_STATIC{
+                        Instruction::GetStatic(m.clone())
+                    }else{
+                        Instruction::GetField(m.clone())
+                    }));
                 }else{ return Err("Missing short operand of getstatic/getfield or invalid const pool index".to_owned()); }
             }
             constants::OP_PUT_STATIC |
             constants::OP_PUT_FIELD => {
                 if let Some(it) = next_short(bytecode)
-                && let ConstantEntry::MemberRef(m) = &const_pool[it as usize - 1]{
-                    result.push((idx, Instruction::PutField(m.clone())));
+                && let ConstantEntry::MemberRef(m) = constant(const_pool, it)?{
+                    result.push((idx, if opcode == constants::OP_PUT_STATIC{
+                        Instruction::PutStatic(m.clone())
+                    }else{
+                        Instruction::PutField(m.clone())
+                    }));
                 }else{ return Err("Missing short operand of putstatic/putfield or invalid const pool index".to_owned()); }
             }
 
             // TODO: cleanup (this whole thing :p)
             constants::OP_INVOKE_VIRTUAL => {
                 if let Some(it) = next_short(bytecode)
-                && let ConstantEntry::MemberRef(m) = &const_pool[it as usize - 1]{
+                && let ConstantEntry::MemberRef(m) = constant(const_pool, it)?{
                     result.push((idx, Instruction::InvokeVirtual(m.clone())));
                 }else{ return Err("Missing short operand of invokevirtual or invalid const pool index".to_owned()); }
             },
             constants::OP_INVOKE_SPECIAL => {
                 if let Some(it) = next_short(bytecode)
-                && let ConstantEntry::MemberRef(m) = &const_pool[it as usize - 1]{
+                && let ConstantEntry::MemberRef(m) = constant(const_pool, it)?{
                     result.push((idx, Instruction::InvokeSpecial(m.clone())));
                 }else{ return Err("Missing short operand of invokespecial or invalid const pool index".to_owned()); }
             },
             constants::OP_INVOKE_STATIC => {
                 if let Some(it) = next_short(bytecode)
-                && let ConstantEntry::MemberRef(m) = &const_pool[it as usize - 1]{
+                && let ConstantEntry::MemberRef(m) = constant(const_pool, it)?{
                     result.push((idx, Instruction::InvokeStatic(m.clone())));
                 }else{ return Err("Missing short operand of invokestatic or invalid const pool index".to_owned()); }
             },
             constants::OP_INVOKE_INTERFACE => {
                 if let Some(it) = next_short(bytecode)
-                && let ConstantEntry::MemberRef(m) = &const_pool[it as usize - 1]{
+                && let ConstantEntry::MemberRef(m) = constant(const_pool, it)?{
                     let _ = next_short(bytecode); // count, 0, both ignored
                     result.push((idx, Instruction::InvokeInterface(m.clone())));
                 }else{ return Err("Missing short operand of invokeinterface or invalid const pool index".to_owned()); }
             },
             constants::OP_INVOKE_DYNAMIC => {
                 if let Some(it) = next_short(bytecode)
-                && let ConstantEntry::Dynamic(d) = &const_pool[it as usize - 1]{
+                && let ConstantEntry::InvokeDynamic(d) = constant(const_pool, it)?{
                     expect_short(bytecode, 0);
                     result.push((idx, Instruction::InvokeDynamic(d.clone())));
                 }else{ return Err("Missing short operand of invokedynamic or invalid const pool index".to_owned()); }
@@ -854,31 +1390,33 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
 
             constants::OP_NEW => {
                 if let Some(it) = next_short(bytecode)
-                    && let ConstantEntry::Class(name) = &const_pool[it as usize - 1]{
+                    && let ConstantEntry::Class(name) = constant(const_pool, it)?{
                     result.push((idx, Instruction::New(name.clone())));
                 }else{ return Err("Missing short operand of new or invalid const pool index".to_owned()); }
             },
             constants::OP_NEWARRAY => {
                 if let Some(it) = next_byte(bytecode){
-                    result.push((idx, Instruction::NewArray(newarray_operand_to_descriptor(it))));
+                    result.push((idx, Instruction::NewArray(newarray_operand_to_descriptor(it)?)));
                 }else{ return Err("Missing byte operand of newarray".to_owned()); }
             },
             constants::OP_ANEWARRAY => {
                 if let Some(it) = next_short(bytecode)
-                    && let ConstantEntry::Class(name) = &const_pool[it as usize - 1]{
-                    result.push((idx, Instruction::NewArray(format!("L{};", name.clone()))));
+                    && let ConstantEntry::Class(name) = constant(const_pool, it)?{
+                    // the component type may itself be an array type, which is named by its descriptor
+                    let component = if name.starts_with("["){ name.clone() }else{ format!("L{};", name) };
+                    result.push((idx, Instruction::NewArray(component)));
                 }else{ return Err("Missing short operand of anewarray or invalid const pool index".to_owned()); }
             },
 
             constants::OP_CHECK_CAST => {
                 if let Some(it) = next_short(bytecode)
-                && let ConstantEntry::Class(name) = &const_pool[it as usize - 1]{
+                && let ConstantEntry::Class(name) = constant(const_pool, it)?{
                     result.push((idx, Instruction::CheckCast(name.clone())));
                 }else{ return Err("Missing short operand of checkcast or invalid const pool index".to_owned()); }
             },
             constants::OP_INSTANCE_OF => {
                 if let Some(it) = next_short(bytecode)
-                    && let ConstantEntry::Class(name) = &const_pool[it as usize - 1]{
+                    && let ConstantEntry::Class(name) = constant(const_pool, it)?{
                     result.push((idx, Instruction::InstanceOf(name.clone())));
                 }else{ return Err("Missing short operand of instanceof or invalid const pool index".to_owned()); }
             },
@@ -886,27 +1424,117 @@ fn parse_bytecode(bytecode: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>) -> Re
             constants::OP_MONITOR_ENTER => result.push((idx, Instruction::MonitorEnter)),
             constants::OP_MONITOR_EXIT => result.push((idx, Instruction::MonitorExit)),
 
-            constants::OP_WIDE | constants::OP_MULTI_ANEWARRAY => result.push((idx, Instruction::TODO)),
+            constants::OP_MULTI_ANEWARRAY => {
+                if let Some(it) = next_short(bytecode)
+                    && let ConstantEntry::Class(name) = constant(const_pool, it)?
+                    && let Some(dimensions) = next_byte(bytecode){
+                    result.push((idx, Instruction::MultiANewArray(name.clone(), dimensions)));
+                }else{ return Err("Missing operands of multianewarray or invalid const pool index".to_owned()); }
+            },
 
-            other => {
-                //return Err("");
-                // TODO: error, once all valid opcodes are handled
-                println!("what's a {}?", other);
-            }
+            // wide variants take a short local index (and a short increment, for iinc)
+            constants::OP_WIDE => {
+                let Some(op) = next_byte(bytecode) else { return Err("Missing opcode operand of wide".to_owned()) };
+                let Some(at) = next_short(bytecode) else { return Err("Missing short operand of wide".to_owned()) };
+                result.push((idx, match op{
+                    constants::OP_ILOAD => Instruction::ILoad(at),
+                    constants::OP_LLOAD => Instruction::LLoad(at),
+                    constants::OP_FLOAD => Instruction::FLoad(at),
+                    constants::OP_DLOAD => Instruction::DLoad(at),
+                    constants::OP_ALOAD => Instruction::ALoad(at),
+                    constants::OP_ISTORE => Instruction::IStore(at),
+                    constants::OP_LSTORE => Instruction::LStore(at),
+                    constants::OP_FSTORE => Instruction::FStore(at),
+                    constants::OP_DSTORE => Instruction::DStore(at),
+                    constants::OP_ASTORE => Instruction::AStore(at),
+                    constants::OP_RET => Instruction::Ret(at),
+                    constants::OP_IINC => {
+                        let Some(inc) = next_sshort(bytecode) else { return Err("Missing short operand of wide iinc".to_owned()) };
+                        Instruction::IInc(at, inc)
+                    },
+                    other => return Err(format!("Invalid opcode {} for wide", other))
+                }));
+            },
+
+            other => return Err(format!("Invalid opcode {}", other))
         }
     }
+    resolve_branch_targets(&mut result)?;
     return Ok(result);
 }
 
-fn parse_member<T>(file: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>, constr: fn(u16, String, String, Vec<Attribute>) -> Result<T, String>) -> Result<T, String>{
+/// Computes the bytecode offset a branch instruction at the given offset jumps to.
+fn branch_target(idx: usize, offset: i32) -> Result<usize, String>{
+    let target = idx as i64 + offset as i64;
+    if target < 0{
+        return Err(format!("Branch at {} jumps to negative offset {}", idx, target));
+    }
+    return Ok(target as usize);
+}
+
+/// Replaces the bytecode offsets in branch instructions with the indices of the instructions they point to.
+fn resolve_branch_targets(bytecode: &mut Vec<(usize, Instruction)>) -> Result<(), String>{
+    let offsets: Vec<usize> = bytecode.iter().map(|(idx, _)| *idx).collect();
+    let resolve = |target: &mut usize| -> Result<(), String>{
+        *target = instruction_index(&offsets, *target)?;
+        return Ok(());
+    };
+    for (_, instr) in bytecode.iter_mut(){
+        match instr{
+            Instruction::Goto(target) | Instruction::Jsr(target)
+            | Instruction::IfEq(target) | Instruction::IfNe(target) | Instruction::IfLt(target)
+            | Instruction::IfGe(target) | Instruction::IfGt(target) | Instruction::IfLe(target)
+            | Instruction::IfICmpEq(target) | Instruction::IfICmpNe(target) | Instruction::IfICmpLt(target)
+            | Instruction::IfICmpGe(target) | Instruction::IfICmpGt(target) | Instruction::IfICmpLe(target)
+            | Instruction::IfACmpEq(target) | Instruction::IfACmpNe(target)
+            | Instruction::IfNull(target) | Instruction::IfNonnull(target) => resolve(target)?,
+            Instruction::TableSwitch(default, _, _, jumps) => {
+                resolve(default)?;
+                for target in jumps{
+                    resolve(target)?;
+                }
+            },
+            Instruction::LookupSwitch(default, pairs) => {
+                resolve(default)?;
+                for (_, target) in pairs{
+                    resolve(target)?;
+                }
+            },
+            _ => {}
+        }
+    }
+    return Ok(());
+}
+
+/// Replaces the bytecode offsets in exception handlers with instruction indices.
+/// The end of a range is exclusive, and may be the end of the code.
+fn resolve_handler_ranges(bytecode: &Vec<(usize, Instruction)>, handlers: &mut Vec<ExceptionHandler>) -> Result<(), String>{
+    let offsets: Vec<usize> = bytecode.iter().map(|(idx, _)| *idx).collect();
+    for handler in handlers{
+        if handler.start_idx >= handler.end_idx{
+            return Err(format!("Empty exception handler range {} to {}", handler.start_idx, handler.end_idx));
+        }
+        handler.start_idx = instruction_index(&offsets, handler.start_idx)?;
+        handler.end_idx = offsets.partition_point(|idx| *idx < handler.end_idx);
+        handler.handler_idx = instruction_index(&offsets, handler.handler_idx)?;
+    }
+    return Ok(());
+}
+
+/// Returns the index of the instruction at the given bytecode offset.
+fn instruction_index(offsets: &Vec<usize>, target: usize) -> Result<usize, String>{
+    return offsets.binary_search(&target).map_err(|_| format!("Branch to offset {} is not at an instruction", target));
+}
+
+fn parse_member<T>(file: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>, constr: impl FnOnce(u16, String, String, Vec<Attribute>) -> Result<T, String>) -> Result<T, String>{
     let flags = next_short_err(file)?;
     
     let name_idx = next_short_err(file)?;
-    let ConstantEntry::Utf8(name) = &const_pool[name_idx as usize - 1] else { return Err("Invalid field name index".to_owned()); };
+    let ConstantEntry::Utf8(name) = constant(const_pool, name_idx)? else { return Err("Invalid field name index".to_owned()); };
     let name = name.clone();
     
     let desc_idx = next_short_err(file)?;
-    let ConstantEntry::Utf8(desc) = &const_pool[desc_idx as usize - 1] else { return Err("Invalid field descriptor index".to_owned()); };
+    let ConstantEntry::Utf8(desc) = constant(const_pool, desc_idx)? else { return Err("Invalid field descriptor index".to_owned()); };
     let desc = desc.clone();
     
     let attrs = parse_attributes(file, &const_pool)?;
@@ -914,7 +1542,7 @@ fn parse_member<T>(file: &mut Vec<u8>, const_pool: &Vec<ConstantEntry>, constr:
     return Ok(constr(flags, name, desc, attrs)?);
 }
 
-fn parse_method_descriptor(mut desc: String) -> Result<Vec<String>, String>{
+pub fn parse_method_descriptor(mut desc: String) -> Result<Vec<String>, String>{
     desc = desc.replace("(", ""); desc = desc.replace(")", ""); // don't *actually* matter
     let mut buffer = Vec::new();
     while desc.len() > 0{
@@ -924,6 +1552,9 @@ fn parse_method_descriptor(mut desc: String) -> Result<Vec<String>, String>{
 }
 
 fn next_descriptor(desc: &mut String) -> Result<String, String>{
+    if desc.is_empty(){
+        return Err("Unexpected end of descriptor".to_owned());
+    }
     let ch = desc.remove(0);
     match ch {
         'Z' | 'B' | 'S' | 'C' | 'I' | 'J' | 'F' | 'D' | 'V' => Ok(ch.to_string()),
@@ -946,6 +1577,243 @@ fn next_descriptor(desc: &mut String) -> Result<String, String>{
 
 // validation methods
 
+fn check_version(major_ver: u16, minor_ver: u16) -> Result<(), String>{
+    // from Java 12, minor versions are only used for preview features, which we don't support
+    if !(constants::MIN_MAJOR_VERSION..=constants::MAX_MAJOR_VERSION).contains(&major_ver) || (major_ver >= 56 && minor_ver != 0){
+        return Err(format!("UnsupportedClassVersionError: Classfile version {}.{} is not supported, only versions {} to {}.0",
+            major_ver, minor_ver, constants::MIN_MAJOR_VERSION, constants::MAX_MAJOR_VERSION));
+    }
+    return Ok(());
+}
+
+fn check_constant(c: &ConstantEntry) -> Result<(), String>{
+    match c{
+        ConstantEntry::Class(name) => check_class_name(name)?,
+        ConstantEntry::MethodType(desc) => check_method_descriptor(desc)?,
+        ConstantEntry::NameAndType(nt) => {
+            if nt.descriptor.starts_with('('){
+                check_unqualified_name(&nt.name, true)?;
+                check_method_descriptor(&nt.descriptor)?;
+            }else{
+                check_unqualified_name(&nt.name, false)?;
+                check_field_descriptor(&nt.descriptor)?;
+            }
+        },
+        ConstantEntry::MemberRef(member) => check_member_ref(member)?,
+        ConstantEntry::MethodHandle(kind, member) => {
+            let kind_matches = match kind{
+                DynamicReferenceType::GetField | DynamicReferenceType::GetStatic
+                | DynamicReferenceType::PutField | DynamicReferenceType::PutStatic => member.kind == MemberKind::Field,
+                DynamicReferenceType::InvokeVirtual | DynamicReferenceType::NewInvokeSpecial => member.kind == MemberKind::Method,
+                DynamicReferenceType::InvokeStatic | DynamicReferenceType::InvokeSpecial => member.kind != MemberKind::Field,
+                DynamicReferenceType::InvokeInterface => member.kind == MemberKind::InterfaceMethod
+            };
+            // only NewInvokeSpecial handles can (and must) refer to constructors
+            let is_init = member.name_and_type.name == "<init>";
+            if !kind_matches || is_init != (*kind == DynamicReferenceType::NewInvokeSpecial) || member.name_and_type.name == "<clinit>"{
+                return Err(format!("Invalid method handle {:?} to {}.{}", kind, member.owner_name, member.name_and_type.name));
+            }
+        },
+        ConstantEntry::Dynamic(dynamic) => {
+            check_unqualified_name(&dynamic.value.name, false)?;
+            check_field_descriptor(&dynamic.value.descriptor)?;
+        },
+        ConstantEntry::InvokeDynamic(dynamic) => {
+            check_unqualified_name(&dynamic.value.name, true)?;
+            check_method_descriptor(&dynamic.value.descriptor)?;
+        },
+        _ => {}
+    }
+    return Ok(());
+}
+
+fn check_member_ref(member: &MemberRef) -> Result<(), String>{
+    check_class_name(&member.owner_name)?;
+    let name = &member.name_and_type.name;
+    let desc = &member.name_and_type.descriptor;
+    if member.kind == MemberKind::Field{
+        check_unqualified_name(name, false)?;
+        return check_field_descriptor(desc);
+    }
+    check_method_descriptor(desc)?;
+    // the only special method that can be referred to is a constructor
+    if name.starts_with('<') && (name != "<init>" || member.kind != MemberKind::Method || !desc.ends_with(")V")){
+        return Err(format!("Invalid method reference {}.{}{}", member.owner_name, name, desc));
+    }
+    return check_unqualified_name(name, true);
+}
+
+/// Checks an unqualified name (JVMS 4.2.2); methods can also be initialization methods.
+fn check_unqualified_name(name: &str, method: bool) -> Result<(), String>{
+    if method && (name == "<init>" || name == "<clinit>"){
+        return Ok(());
+    }
+    if name.is_empty() || name.contains(['.', ';', '[', '/']) || (method && name.contains(['<', '>'])){
+        return Err(format!("Invalid name \"{}\"", name));
+    }
+    return Ok(());
+}
+
+/// Checks a class name in internal form, or an array descriptor (JVMS 4.2.1, 4.4.1).
+fn check_class_name(name: &str) -> Result<(), String>{
+    if name.starts_with('['){
+        return check_field_descriptor(name);
+    }
+    if name.split('/').any(|part| part.is_empty() || part.contains(['.', ';', '['])){
+        return Err(format!("Invalid class name \"{}\"", name));
+    }
+    return Ok(());
+}
+
+/// Returns the length of the field type at the start of a descriptor, if it's valid (JVMS 4.3.2).
+fn field_type_len(desc: &str) -> Option<usize>{
+    let dimensions = desc.bytes().take_while(|c| *c == b'[').count();
+    if dimensions > 255{
+        return None;
+    }
+    let component = &desc[dimensions..];
+    let len = match component.bytes().next()?{
+        b'Z' | b'B' | b'S' | b'C' | b'I' | b'J' | b'F' | b'D' => 1,
+        b'L' => {
+            let end = component.find(';')?;
+            let class = &component[1..end];
+            if class.starts_with('[') || check_class_name(class).is_err(){
+                return None;
+            }
+            end + 1
+        },
+        _ => return None
+    };
+    return Some(dimensions + len);
+}
+
+fn check_field_descriptor(desc: &str) -> Result<(), String>{
+    return match field_type_len(desc){
+        Some(len) if len == desc.len() => Ok(()),
+        _ => Err(format!("Invalid field descriptor \"{}\"", desc))
+    };
+}
+
+fn check_method_descriptor(desc: &str) -> Result<(), String>{
+    let invalid = || format!("Invalid method descriptor \"{}\"", desc);
+    let mut rest = desc.strip_prefix('(').ok_or_else(invalid)?;
+    let mut param_slots = 0;
+    while !rest.starts_with(')'){
+        let len = field_type_len(rest).ok_or_else(invalid)?;
+        param_slots += if matches!(&rest[..len], "J" | "D"){ 2 }else{ 1 };
+        rest = &rest[len..];
+    }
+    let ret = &rest[1..];
+    if param_slots > 255 || (ret != "V" && field_type_len(ret) != Some(ret.len())){
+        return Err(invalid());
+    }
+    return Ok(());
+}
+
+/// At most one access flag can be set.
+fn check_access_flags(flags: u16) -> Result<(), String>{
+    let access = [constants::ACC_PUBLIC, constants::ACC_PRIVATE, constants::ACC_PROTECTED];
+    if access.iter().filter(|flag| constants::bit_set(flags, **flag)).count() > 1{
+        return Err("Must have at most one of public, private and protected".to_owned());
+    }
+    return Ok(());
+}
+
+fn check_field(field: &FieldInfo, is_interface: bool) -> Result<(), String>{
+    check_unqualified_name(&field.name, false)?;
+    check_field_descriptor(&field.desc)?;
+    // non-static fields ignore their constant values (JVMS 4.7.2)
+    if constants::bit_set(field.flags, constants::ACC_STATIC){
+        for attr in &field.attributes{
+            if let Attribute::ConstantValue(value) = attr{
+                let matches = match value{
+                    ConstantEntry::Integer(_) => matches!(field.desc.as_str(), "I" | "S" | "C" | "B" | "Z"),
+                    ConstantEntry::Float(_) => field.desc == "F",
+                    ConstantEntry::Long(_) => field.desc == "J",
+                    ConstantEntry::Double(_) => field.desc == "D",
+                    _ => field.desc == "Ljava/lang/String;"
+                };
+                if !matches{
+                    return Err(format!("Constant value {:?} doesn't match the type of field {} {}", value, field.name, field.desc));
+                }
+            }
+        }
+    }
+    return check_field_flags(field.flags, is_interface).map_err(|e| format!("{} (field {})", e, field.name));
+}
+
+pub fn check_field_flags(flags: u16, is_interface: bool) -> Result<(), String>{
+    check_access_flags(flags)?;
+    if constants::bit_set(flags, constants::ACC_FINAL) && constants::bit_set(flags, constants::FIELD_ACC_VOLATILE){
+        return Err("Field cannot be both final and volatile".to_owned());
+    }
+    if is_interface{
+        let required = constants::ACC_PUBLIC | constants::ACC_STATIC | constants::ACC_FINAL;
+        if !constants::bit_set(flags, required) || flags & !(required | constants::ACC_SYNTHETIC) != 0{
+            return Err("Interface field must be public, static and final".to_owned());
+        }
+    }
+    return Ok(());
+}
+
+fn check_method(flags: u16, name: &str, desc: &str, attributes: &[Attribute], is_interface: bool, major_ver: u16) -> Result<(), String>{
+    check_unqualified_name(name, true)?;
+    check_method_descriptor(desc)?;
+    if (name == "<init>" || name == "<clinit>") && !desc.ends_with(")V"){
+        return Err(format!("Initialization method {} must return void", name));
+    }
+    // class initializers ignore most flags
+    if name == "<clinit>"{
+        if major_ver >= 51 && !constants::bit_set(flags, constants::ACC_STATIC){
+            return Err("Class initializer must be static".to_owned());
+        }
+    }else{
+        check_method_flags(flags, name, is_interface, major_ver).map_err(|e| format!("{} (method {}{})", e, name, desc))?;
+    }
+    let code_count = attributes.iter().filter(|a| matches!(a, Attribute::Code(_))).count();
+    let needs_code = !constants::bit_set(flags, constants::ACC_ABSTRACT) && !constants::bit_set(flags, constants::METHOD_ACC_NATIVE);
+    if code_count > 1 || needs_code != (code_count == 1){
+        return Err(format!("Method {}{} must have {} Code attribute", name, desc, if needs_code{ "one" }else{ "no" }));
+    }
+    return Ok(());
+}
+
+pub fn check_method_flags(flags: u16, name: &str, is_interface: bool, major_ver: u16) -> Result<(), String>{
+    check_access_flags(flags)?;
+    let public = constants::bit_set(flags, constants::ACC_PUBLIC);
+    if is_interface{
+        if name == "<init>"{
+            return Err("Interface cannot have a constructor".to_owned());
+        }
+        if major_ver < 52 && !(public && constants::bit_set(flags, constants::ACC_ABSTRACT)){
+            return Err("Interface method must be public and abstract".to_owned());
+        }
+        if public == constants::bit_set(flags, constants::ACC_PRIVATE){
+            return Err("Interface method must be either public or private".to_owned());
+        }
+        let forbidden = constants::ACC_PROTECTED | constants::ACC_FINAL | constants::METHOD_ACC_SYNCHRONIZED | constants::METHOD_ACC_NATIVE;
+        if flags & forbidden != 0{
+            return Err("Interface method must not be protected, final, synchronized or native".to_owned());
+        }
+    }
+    if constants::bit_set(flags, constants::ACC_ABSTRACT){
+        let forbidden = constants::ACC_PRIVATE | constants::ACC_STATIC | constants::ACC_FINAL
+            | constants::METHOD_ACC_SYNCHRONIZED | constants::METHOD_ACC_NATIVE;
+        let strict = (46..=60).contains(&major_ver) && constants::bit_set(flags, constants::METHOD_ACC_STRICT);
+        if flags & forbidden != 0 || strict{
+            return Err("Abstract method must not be private, static, final, synchronized, native or strict".to_owned());
+        }
+    }
+    if name == "<init>"{
+        let allowed = constants::ACC_PUBLIC | constants::ACC_PRIVATE | constants::ACC_PROTECTED
+            | constants::METHOD_ACC_VARARGS | constants::METHOD_ACC_STRICT | constants::ACC_SYNTHETIC;
+        if flags & !allowed != 0{
+            return Err("Constructor can only be an access level, varargs, strict or synthetic".to_owned());
+        }
+    }
+    return Ok(());
+}
+
 pub fn check_class_flags(flags: u16) -> Result<(), String>{
     if constants::bit_set(flags, constants::CLASS_ACC_INTERFACE){
         if !constants::bit_set(flags, constants::ACC_ABSTRACT){
@@ -971,7 +1839,9 @@ pub fn check_class_flags(flags: u16) -> Result<(), String>{
     if constants::bit_set(flags, constants::ACC_ABSTRACT) && constants::bit_set(flags, constants::ACC_FINAL){
         return Err("Class cannot be both abstract and final".to_owned());
     }
-    // TODO: check modules have no other flags
+    if constants::bit_set(flags, constants::CLASS_ACC_MODULE) && flags != constants::CLASS_ACC_MODULE{
+        return Err("Module info classfile must not have any other flags".to_owned());
+    }
     return Ok(());
 }
 
@@ -998,6 +1868,13 @@ fn next_short(stream: &mut Vec<u8>) -> Option<u16>{
     };
 }
 
+fn next_byte_err(stream: &mut Vec<u8>) -> Result<u8, String>{
+    return match next_byte(stream) {
+        Some(u) => Ok(u),
+        None => Err("Unexpected end of file".to_owned())
+    }
+}
+
 fn next_sshort(stream: &mut Vec<u8>) -> Option<i16>{
     return match (next_byte(stream), next_byte(stream)) {
         (Some(left), Some(right)) => Some(i16::from_be_bytes([left, right])),
@@ -1055,12 +1932,11 @@ fn next_double(stream: &mut Vec<u8>) -> Option<f64>{
     };
 }
 
-fn next_vec<T>(stream: &mut Vec<T>, amount: usize) -> Vec<T>{
-    let mut ret: Vec<T> = Vec::with_capacity(amount);
-    for _ in 0..amount{
-        ret.push(stream.remove(0));
+fn next_vec<T>(stream: &mut Vec<T>, amount: usize) -> Result<Vec<T>, String>{
+    if stream.len() < amount{
+        return Err("Unexpected end of file".to_owned());
     }
-    return ret;
+    return Ok(stream.drain(..amount).collect());
 }
 
 // expect data methods
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 #[derive(Debug, Clone, PartialEq)]
 pub struct Classfile{
     pub major_ver: u16,
@@ -21,7 +19,7 @@ pub struct Classfile{
 // Ordered by tag number
 #[derive(Debug, Clone)]
 pub enum RawConstantEntry {
-    Utf8(String),   // 1, u16 length + u8[len] modified utf8
+    Utf8(Vec<u16>), // 1, u16 length + u8[len] modified utf8, decoded to UTF-16
     Integer(i32),   // 3
     Float(f32),     // 4
     Long(i64),      // 5, ***uses two entries***
@@ -56,7 +54,7 @@ pub enum ConstantEntry{
     LongSecond,
 
     Class(String),
-    StringConst(String),
+    StringConst(Vec<u16>), // UTF-16, as it may hold unpaired surrogates
     MemberRef(MemberRef),
     NameAndType(NameAndType),
     MethodHandle(DynamicReferenceType, MemberRef),
@@ -82,7 +80,7 @@ pub struct NameAndType{ pub name: String, pub descriptor: String }
 #[derive(Debug, Clone, PartialEq)]
 pub struct MemberRef{ pub kind: MemberKind, pub owner_name: String, pub name_and_type: NameAndType }
 #[derive(Debug, Clone, PartialEq)]
-pub struct Dynamic{ pub bootstrap: NameAndType, pub value: NameAndType }
+pub struct Dynamic{ pub bootstrap: u16, pub value: NameAndType } // index into the class's bootstrap methods
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FieldInfo{
@@ -111,11 +109,11 @@ pub struct RecordComponentInfo{
 pub enum Attribute{ // ordered by location
     // Classfile attributes
     SourceFile(String),
-    InnerClasses{ /* TODO */ },
-    EnclosingMethod{ owner_class: String, owner_method: NameAndType },
+    InnerClasses(Vec<InnerClassInfo>),
+    EnclosingMethod{ owner_class: String, owner_method: Option<NameAndType> }, // no method for classes in initializers
     SourceDebugExtension(String),
     BootstrapMethods(Vec<BootstrapEntry>),
-    Module{ /* TODO */ }, ModulePackages(Vec<String>), ModuleMainClass(String),
+    Module(ModuleInfo), ModulePackages(Vec<String>), ModuleMainClass(String),
     NestHost(String), NestMembers(Vec<String>),
     Record(Vec<RecordComponentInfo>),
     PermittedSubclasses(Vec<String>),
@@ -126,36 +124,84 @@ pub enum Attribute{ // ordered by location
     // Method attributes
     Code(Code),
     Exceptions(Vec<String>),
-    RuntimeVisibleParameterAnnotations(Vec<Vec<Annotation>>),
+    RuntimeVisibleParameterAnnotations(Vec<Vec<Annotation>>, Vec<u8>),
     RuntimeInvisibleParameterAnnotations(Vec<Vec<Annotation>>),
-    AnnotationDefault{ /* TODO */ },
+    AnnotationDefault(ElementValue, Vec<u8>),
     MethodParameters(Vec<ParameterInfo>),
 
     // Code attributes
     LineNumberTable(Vec<LineNumberMapping>),
     LocalVariableTable(Vec<LocalVariableEntry>),
     LocalVariableTypeTable(Vec<LocalVariableEntry>),
-    StackMapTable{ /* TODO */ },
+    StackMapTable(Vec<StackMapFrame>),
 
     // Class, member attributes
     Synthetic, Deprecated, // zero-length
     // Class, member, record component attributes
     Signature(String),
     // Class, member, record component, code attributes
-    RuntimeVisibleAnnotations(Vec<Annotation>), RuntimeInvisibleAnnotations(Vec<Annotation>),
-    RuntimeVisibleTypeAnnotations{ /* TODO */ }, RuntimeInvisibleTypeAnnotations{ /* TODO */ },
+    // reflection parses visible annotations itself, so those also keep the attribute's bytes
+    RuntimeVisibleAnnotations(Vec<Annotation>, Vec<u8>), RuntimeInvisibleAnnotations(Vec<Annotation>),
+    RuntimeVisibleTypeAnnotations(Vec<TypeAnnotation>, Vec<u8>), RuntimeInvisibleTypeAnnotations(Vec<TypeAnnotation>),
+}
+
+// frames are as in the classfile, each relative to the previous one; see verifier for how they're expanded
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackMapFrame{
+    Same{ offset_delta: u16 },
+    SameLocals1StackItem{ offset_delta: u16, stack: VerificationType },
+    Chop{ offset_delta: u16, count: u8 },
+    Append{ offset_delta: u16, locals: Vec<VerificationType> },
+    Full{ offset_delta: u16, locals: Vec<VerificationType>, stack: Vec<VerificationType> }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationType{
+    Top, Integer, Float, Double, Long, Null, UninitializedThis,
+    Object(String),         // internal name, or descriptor for arrays
+    Uninitialized(u16)      // bytecode offset of the new instruction that made it
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InnerClassInfo{
+    pub inner_class: String,
+    pub outer_class: Option<String>, // None for local and anonymous classes
+    pub name: Option<String>,        // simple name; None for anonymous classes
+    pub flags: u16
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleInfo{
+    pub name: String,
+    pub flags: u16,
+    pub version: Option<String>,
+    pub requires: Vec<ModuleRequires>,
+    pub exports: Vec<ModuleExports>,
+    pub opens: Vec<ModuleExports>,
+    pub uses: Vec<String>,
+    pub provides: Vec<ModuleProvides>
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleRequires{ pub module: String, pub flags: u16, pub version: Option<String> }
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleExports{ pub package: String, pub flags: u16, pub to: Vec<String> } // exports and opens; empty `to` means unqualified
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleProvides{ pub service: String, pub with: Vec<String> }
+
+// bytecode offsets, like the classfile
 #[derive(Debug, Clone, PartialEq)]
 pub struct LineNumberMapping{
     pub bytecode_idx: u16,
     pub line_number: u16
 }
 
+// bytecode offsets, like the classfile; entries in the type table are matched up with the ones in the plain table,
+// so both have the descriptor and signature
 #[derive(Debug, Clone, PartialEq)]
 pub struct LocalVariableEntry{
     pub start_idx: u16,
-    pub end_idx: u16,
+    pub end_idx: u16,    // exclusive, up to the code length
     pub name: String,
     pub desc: String,
     pub sig: Option<String>,
@@ -170,8 +216,40 @@ pub struct ParameterInfo{
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Annotation{
-    pub class: String,
-    pub data: HashMap<String, Vec<u8>> // TODO: annotation parsing
+    pub class: String, // descriptor
+    pub elements: Vec<(String, ElementValue)>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementValue{
+    Const(char, ConstantEntry), // tag is a primitive descriptor, or 's' for strings, which are Utf8 entries
+    Enum{ type_desc: String, name: String },
+    Class(String),              // return descriptor, so V for void.class
+    Annotation(Annotation),
+    Array(Vec<ElementValue>)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeAnnotation{
+    pub target_type: u8,
+    pub target: TypeAnnotationTarget,
+    pub path: Vec<(u8, u8)>, // kind and type argument index of each step
+    pub annotation: Annotation
+}
+
+// by target_type (JVMS 4.7.20.1)
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeAnnotationTarget{
+    TypeParameter(u8),          // 0x00, 0x01
+    Supertype(u16),             // 0x10, index into interfaces or 65535 for the superclass
+    TypeParameterBound(u8, u8), // 0x11, 0x12
+    Empty,                      // 0x13-0x15, field, return or receiver type
+    FormalParameter(u8),        // 0x16
+    Throws(u16),                // 0x17
+    LocalVar(Vec<(u16, u16, u16)>), // 0x40, 0x41, start offset, length and local index of each range
+    Catch(u16),                 // 0x42, exception table index
+    Offset(u16),                // 0x43-0x46, bytecode offset
+    TypeArgument(u16, u8)       // 0x47-0x4B, bytecode offset and type argument index
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -181,11 +259,12 @@ pub struct BootstrapEntry{
     pub args: Vec<ConstantEntry>
 }
 
+// like branch targets, these are instruction indices, resolved when parsing
 #[derive(Debug, Clone, PartialEq)]
 pub struct ExceptionHandler{
-    pub start_idx: u16,
-    pub end_idx: u16,
-    pub handler_idx: u16,
+    pub start_idx: usize,
+    pub end_idx: usize,
+    pub handler_idx: usize,
     pub catch_type: Option<String>
 }
 
@@ -201,15 +280,17 @@ pub struct Code{
 #[derive(Debug, Clone, PartialEq)]
 // only supported ones rn
 pub enum Instruction{
+    Nop,
+
     IConst(i32), LConst(i64), FConst(f32), DConst(f64), AConstNull,
 
     Ldc(ConstantEntry), // copy it here for now
 
-    IStore(u8), LStore(u8), FStore(u8), DStore(u8), AStore(u8),
+    IStore(u16), LStore(u16), FStore(u16), DStore(u16), AStore(u16),
 
     IAStore, LAStore, FAStore, DAStore, AAStore, BAStore, CAStore, SAStore,
 
-    ILoad(u8), LLoad(u8), FLoad(u8), DLoad(u8), ALoad(u8),
+    ILoad(u16), LLoad(u16), FLoad(u16), DLoad(u16), ALoad(u16),
 
     IALoad, LALoad, FALoad, DALoad, AALoad, BALoad, CALoad, SALoad,
 
@@ -228,16 +309,18 @@ pub enum Instruction{
     IShl, LShl, IShr, LShr, IUshr, LUshr,
     IAnd, LAnd, IOr, LOr, IXor, LXor,
 
-    IInc(u8, i8),
+    IInc(u16, i16),
 
-    Goto(i32), GotoWide(i64),
-    TableSwitch(i32, i32, i32, Vec<i32>), LookupSwitch(i32, Vec<(i32, i32)>),
+    // branch targets are instruction indices, resolved when parsing
+    Goto(usize),
+    Jsr(usize), Ret(u16), // subroutines, only allowed before version 51
+    TableSwitch(usize, i32, i32, Vec<usize>), LookupSwitch(usize, Vec<(i32, usize)>),
 
     LCmp, FCmpL, FCmpG, DCmpL, DCmpG,
 
-    IfEq(i32), IfNe(i32), IfLt(i32), IfGe(i32), IfGt(i32), IfLe(i32),
-    IfICmpEq(i32), IfICmpNe(i32), IfICmpLt(i32), IfICmpGe(i32), IfICmpGt(i32), IfICmpLe(i32),
-    IfACmpEq(i32), IfACmpNe(i32), IfNull(i32), IfNonnull(i32),
+    IfEq(usize), IfNe(usize), IfLt(usize), IfGe(usize), IfGt(usize), IfLe(usize),
+    IfICmpEq(usize), IfICmpNe(usize), IfICmpLt(usize), IfICmpGe(usize), IfICmpGt(usize), IfICmpLe(usize),
+    IfACmpEq(usize), IfACmpNe(usize), IfNull(usize), IfNonnull(usize),
 
     I2L, I2F, I2D,
     L2I, L2F, L2D,
@@ -247,6 +330,8 @@ pub enum Instruction{
 
     IReturn, LReturn, FReturn, DReturn, AReturn, Return, AThrow,
 
+    GetStatic(MemberRef),
+    PutStatic(MemberRef),
     GetField(MemberRef),
     PutField(MemberRef),
 
@@ -258,11 +343,9 @@ pub enum Instruction{
 
     ArrayLength,
 
-    New(String), NewArray(String), MultiANewArray(String, u32),
+    New(String), NewArray(String), MultiANewArray(String, u8),
 
     CheckCast(String), InstanceOf(String),
 
-    MonitorEnter, MonitorExit,
-
-    TODO
+    MonitorEnter, MonitorExit
 }
\ No newline at end of file
@@ -1,2 +1,3 @@
 pub mod classfile_parser;
-pub mod classfile_structs;
\ No newline at end of file
+pub mod classfile_structs;
+pub mod modified_utf8;
\ No newline at end of file
@@ -0,0 +1,73 @@
+// Modified UTF-8, the encoding of strings in classfiles (JVMS 4.4.7).
+// It differs from UTF-8 in encoding NUL as two bytes, so encoded strings never contain a zero byte, and in encoding
+// supplementary characters as a surrogate pair of three-byte sequences (6 bytes) rather than as four bytes. Strings
+// are decoded to UTF-16 code units, since Java strings can hold unpaired surrogates that a Rust string can't.
+
+/// Decodes modified UTF-8 into UTF-16 code units, rejecting invalid sequences.
+pub fn decode(bytes: &[u8]) -> Result<Vec<u16>, String>{
+    let mut chars = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len(){
+        let first = bytes[idx] as u16;
+        let (unit, len) = match first{
+            0x01..=0x7F => (first, 1),
+            // 110xxxxx 10xxxxxx, for NUL and U+0080 to U+07FF
+            0xC0..=0xDF => {
+                let unit = ((first & 0x1F) << 6) | continuation(bytes, idx + 1)?;
+                if unit != 0 && unit < 0x80{
+                    return Err(format!("Overlong modified UTF-8 sequence at byte {}", idx));
+                }
+                (unit, 2)
+            },
+            // 1110xxxx 10xxxxxx 10xxxxxx, for U+0800 to U+FFFF, including each half of a surrogate pair
+            0xE0..=0xEF => {
+                let unit = ((first & 0x0F) << 12) | (continuation(bytes, idx + 1)? << 6) | continuation(bytes, idx + 2)?;
+                if unit < 0x800{
+                    return Err(format!("Overlong modified UTF-8 sequence at byte {}", idx));
+                }
+                (unit, 3)
+            },
+            // zero bytes, continuation bytes, and the 4 byte form of standard UTF-8 are never valid
+            _ => return Err(format!("Invalid modified UTF-8 byte {:#04x} at byte {}", first, idx))
+        };
+        chars.push(unit);
+        idx += len;
+    }
+    return Ok(chars);
+}
+
+/// Decodes modified UTF-8 into a string, replacing any unpaired surrogates with U+FFFD.
+pub fn decode_string(bytes: &[u8]) -> Result<String, String>{
+    return Ok(String::from_utf16_lossy(&decode(bytes)?));
+}
+
+// the encoder is for writing classfiles, which nothing does yet
+
+/// Encodes UTF-16 code units as modified UTF-8.
+#[allow(dead_code)]
+pub fn encode(chars: &[u16]) -> Vec<u8>{
+    let mut bytes = Vec::with_capacity(chars.len());
+    for &unit in chars{
+        match unit{
+            0x01..=0x7F => bytes.push(unit as u8),
+            0x00 | 0x80..=0x7FF => bytes.extend([0xC0 | (unit >> 6) as u8, 0x80 | (unit & 0x3F) as u8]),
+            _ => bytes.extend([0xE0 | (unit >> 12) as u8, 0x80 | ((unit >> 6) & 0x3F) as u8, 0x80 | (unit & 0x3F) as u8])
+        }
+    }
+    return bytes;
+}
+
+/// Encodes a string as modified UTF-8.
+#[allow(dead_code)]
+pub fn encode_string(string: &str) -> Vec<u8>{
+    return encode(&string.encode_utf16().collect::<Vec<u16>>());
+}
+
+/// Returns the low 6 bits of the continuation byte at the given index.
+fn continuation(bytes: &[u8], idx: usize) -> Result<u16, String>{
+    return match bytes.get(idx){
+        Some(byte) if byte & 0xC0 == 0x80 => Ok((byte & 0x3F) as u16),
+        Some(byte) => Err(format!("Invalid modified UTF-8 continuation byte {:#04x} at byte {}", byte, idx)),
+        None => Err("Truncated modified UTF-8 sequence".to_owned())
+    };
+}
@@ -0,0 +1,230 @@
+use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+use crate::constants;
+use crate::parser::{classfile_parser, classfile_structs::{Attribute, BootstrapEntry, Classfile, Code, ConstantEntry, Dynamic, DynamicReferenceType, Instruction, MemberKind, MemberRef, MethodInfo, NameAndType}};
+
+use super::{class::{self, Class, ClassRef}, classes, heap};
+
+// Bootstrap methods for invokedynamic (JVMS 5.4.3.6)
+// rather than running the bootstrap method, the VM implements the ones javac uses itself, by defining a class with a
+// static method that does what the call site should; the call site then calls that method with its arguments.
+// errors are the names of the exception to throw
+
+static DEFINED_CLASSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Links an invokedynamic call site in the given class, returning the static method it should call.
+pub fn link_call_site(call: &Dynamic, caller: &Class) -> Result<(ClassRef, usize), &'static str>{
+    let bootstrap = caller.bootstrap_methods.get(call.bootstrap as usize).ok_or("BootstrapMethodError")?;
+    let class = match (bootstrap.method.owner_name.as_str(), bootstrap.method.name_and_type.name.as_str()){
+        ("java/lang/runtime/ObjectMethods", "bootstrap") => object_method(call, bootstrap, caller)?,
+        _ => return Err("BootstrapMethodError")
+    };
+    // the classes we define have one static method, for the call site
+    let m_idx = class.methods.iter().position(|m| m.is_static).unwrap();
+    return Ok((class, m_idx));
+}
+
+// ObjectMethods: toString, equals and hashCode for records
+
+fn object_method(call: &Dynamic, bootstrap: &BootstrapEntry, caller: &Class) -> Result<ClassRef, &'static str>{
+    // the record class, its component names separated by ';', and getters for its fields
+    let [ConstantEntry::Class(record), ConstantEntry::StringConst(names), getters @ ..] = bootstrap.args.as_slice() else {
+        return Err("BootstrapMethodError");
+    };
+    let mut fields = Vec::with_capacity(getters.len());
+    for getter in getters{
+        let ConstantEntry::MethodHandle(DynamicReferenceType::GetField, field) = getter else { return Err("BootstrapMethodError") };
+        fields.push(field.clone());
+    }
+    let names = String::from_utf16_lossy(names);
+    let names: Vec<&str> = if names.is_empty(){ Vec::new() }else{ names.split(';').collect() };
+    if names.len() != fields.len(){
+        return Err("BootstrapMethodError");
+    }
+
+    let code = match call.value.name.as_str(){
+        "toString" => record_to_string(record, &names, &fields),
+        "equals" => record_equals(record, &fields),
+        "hashCode" => record_hash_code(&fields),
+        _ => return Err("BootstrapMethodError")
+    };
+    let method = method(constants::ACC_PUBLIC | constants::ACC_STATIC, &call.value.name, &call.value.descriptor, code, 3)?;
+    return define_class(caller, vec![method]);
+}
+
+/// `Simple[a=1, b=2]`, for a record `(LSimple;)Ljava/lang/String;`.
+fn record_to_string(record: &str, names: &[&str], fields: &[MemberRef]) -> Vec<Instruction>{
+    let builder = "java/lang/StringBuilder";
+    let mut code = vec![
+        Instruction::New(builder.to_owned()),
+        Instruction::Dup,
+        Instruction::InvokeSpecial(method_ref(builder, "<init>", "()V"))
+    ];
+    for (k, (name, field)) in names.iter().zip(fields).enumerate(){
+        let label = format!("{}{}=", if k == 0{ format!("{}[", simple_name(record)) }else{ ", ".to_owned() }, name);
+        code.push(Instruction::Ldc(ConstantEntry::StringConst(label.encode_utf16().collect())));
+        code.push(Instruction::InvokeVirtual(method_ref(builder, "append", "(Ljava/lang/String;)Ljava/lang/StringBuilder;")));
+        code.push(Instruction::ALoad(0));
+        code.push(Instruction::GetField(field.clone()));
+        let appended = match field.name_and_type.descriptor.as_str(){
+            "Z" => "Z", "C" => "C", "B" | "S" | "I" => "I", "J" => "J", "F" => "F", "D" => "D",
+            _ => "Ljava/lang/Object;"
+        };
+        code.push(Instruction::InvokeVirtual(method_ref(builder, "append", &format!("({})Ljava/lang/StringBuilder;", appended))));
+    }
+    let end = if fields.is_empty(){ format!("{}[]", simple_name(record)) }else{ "]".to_owned() };
+    code.push(Instruction::Ldc(ConstantEntry::StringConst(end.encode_utf16().collect())));
+    code.push(Instruction::InvokeVirtual(method_ref(builder, "append", "(Ljava/lang/String;)Ljava/lang/StringBuilder;")));
+    code.push(Instruction::InvokeVirtual(method_ref(builder, "toString", "()Ljava/lang/String;")));
+    code.push(Instruction::AReturn);
+    return code;
+}
+
+/// Whether the other object is a record of the same class with equal components, for `(LRecord;Ljava/lang/Object;)Z`.
+fn record_equals(record: &str, fields: &[MemberRef]) -> Vec<Instruction>{
+    let mut code = vec![
+        Instruction::ALoad(1),
+        Instruction::InstanceOf(record.to_owned()),
+        Instruction::IfEq(usize::MAX), // patched to go to the false branch below
+        Instruction::ALoad(1),
+        Instruction::CheckCast(record.to_owned()),
+        Instruction::AStore(2)
+    ];
+    let mut not_equal = vec![2];
+    for field in fields{
+        code.push(Instruction::ALoad(0));
+        code.push(Instruction::GetField(field.clone()));
+        code.push(Instruction::ALoad(2));
+        code.push(Instruction::GetField(field.clone()));
+        match field.name_and_type.descriptor.as_str(){
+            "Z" | "B" | "S" | "C" | "I" => {},
+            "J" => code.push(Instruction::LCmp),
+            // floats compare like their boxes do, so NaN equals itself
+            "F" => code.push(Instruction::InvokeStatic(method_ref("java/lang/Float", "compare", "(FF)I"))),
+            "D" => code.push(Instruction::InvokeStatic(method_ref("java/lang/Double", "compare", "(DD)I"))),
+            _ => {
+                code.push(Instruction::InvokeStatic(method_ref("java/util/Objects", "equals", "(Ljava/lang/Object;Ljava/lang/Object;)Z")));
+                code.push(Instruction::IConst(1));
+            }
+        }
+        not_equal.push(code.len());
+        code.push(match field.name_and_type.descriptor.as_str(){
+            "J" | "F" | "D" => Instruction::IfNe(usize::MAX),
+            _ => Instruction::IfICmpNe(usize::MAX)
+        });
+    }
+    code.push(Instruction::IConst(1));
+    code.push(Instruction::IReturn);
+    let false_branch = code.len();
+    code.push(Instruction::IConst(0));
+    code.push(Instruction::IReturn);
+    for idx in not_equal{
+        match &mut code[idx]{
+            Instruction::IfEq(target) | Instruction::IfNe(target) | Instruction::IfICmpNe(target) => *target = false_branch,
+            _ => unreachable!()
+        }
+    }
+    return code;
+}
+
+/// Combines the hashes of the components like `31 * h + c`, for `(LRecord;)I`.
+fn record_hash_code(fields: &[MemberRef]) -> Vec<Instruction>{
+    let mut code = vec![Instruction::IConst(0)];
+    for field in fields{
+        code.push(Instruction::IConst(31));
+        code.push(Instruction::IMul);
+        code.push(Instruction::ALoad(0));
+        code.push(Instruction::GetField(field.clone()));
+        let desc = field.name_and_type.descriptor.as_str();
+        code.push(Instruction::InvokeStatic(match box_class(desc){
+            Some(box_name) => method_ref(box_name, "hashCode", &format!("({})I", desc)),
+            None => method_ref("java/util/Objects", "hashCode", "(Ljava/lang/Object;)I")
+        }));
+        code.push(Instruction::IAdd);
+    }
+    code.push(Instruction::IReturn);
+    return code;
+}
+
+/// The simple name of a class from its internal name, as Class.getSimpleName gives for nested and local classes.
+fn simple_name(internal_name: &str) -> &str{
+    let name = internal_name.rsplit('/').next().unwrap();
+    let name = name.rsplit('$').next().unwrap();
+    return name.trim_start_matches(|c: char| c.is_ascii_digit());
+}
+
+// Defining classes
+
+/// Defines a class with the given methods in the bootstrap loader, in the same package and nest as the caller so it can reach its private members.
+fn define_class(caller: &Class, methods: Vec<MethodInfo>) -> Result<ClassRef, &'static str>{
+    let name = format!("{}$$Bootstrap${}", caller.name.replace('.', "/"), DEFINED_CLASSES.fetch_add(1, Ordering::Relaxed));
+    let classfile = Classfile{
+        major_ver: 61,
+        minor_ver: 0,
+        constants: Vec::new(),
+        flags: constants::ACC_FINAL | constants::CLASS_ACC_SUPER | constants::ACC_SYNTHETIC,
+        name,
+        super_class: Some("java/lang/Object".to_owned()),
+        interfaces: Vec::new(),
+        fields: Vec::new(),
+        methods,
+        attributes: Vec::new()
+    };
+    let class = class::link_class(classfile, Arc::new(classes::BOOTSTRAP_LOADER)).map_err(|_| "BootstrapMethodError")?;
+    let _ = class.nest_host.set(caller.nest_host().to_owned());
+    let descriptor = class.descriptor.clone();
+    heap::add_bt_class(class, true);
+    return heap::bt_class_by_desc(descriptor).ok_or("BootstrapMethodError");
+}
+
+/// A method with the given code; locals are the parameters plus `extra_locals`, and the stack is never deeper than the code is long.
+fn method(flags: u16, name: &str, desc: &str, code: Vec<Instruction>, extra_locals: u16) -> Result<MethodInfo, &'static str>{
+    let types = descriptor_types(desc)?;
+    let mut locals = types[..types.len() - 1].iter().map(|d| slot_size(d)).sum::<u16>() + extra_locals;
+    if !constants::bit_set(flags, constants::ACC_STATIC){
+        locals += 1;
+    }
+    return Ok(MethodInfo{
+        flags,
+        name: name.to_owned(),
+        desc: types,
+        attributes: vec![Attribute::Code(Code{
+            max_stack: code.len() as u16 * 2,
+            max_locals: locals,
+            bytecode: code.into_iter().enumerate().collect(),
+            exception_handlers: Vec::new(),
+            attributes: Vec::new()
+        })]
+    });
+}
+
+fn method_ref(owner: &str, name: &str, desc: &str) -> MemberRef{
+    return MemberRef{
+        kind: MemberKind::Method,
+        owner_name: owner.to_owned(),
+        name_and_type: NameAndType{ name: name.to_owned(), descriptor: desc.to_owned() }
+    };
+}
+
+fn descriptor_types(desc: &str) -> Result<Vec<String>, &'static str>{
+    return classfile_parser::parse_method_descriptor(desc.to_owned()).map_err(|_| "BootstrapMethodError");
+}
+
+/// The class primitives of the given type are boxed as.
+fn box_class(desc: &str) -> Option<&'static str>{
+    return match desc{
+        "Z" => Some("java/lang/Boolean"),
+        "B" => Some("java/lang/Byte"),
+        "S" => Some("java/lang/Short"),
+        "C" => Some("java/lang/Character"),
+        "I" => Some("java/lang/Integer"),
+        "J" => Some("java/lang/Long"),
+        "F" => Some("java/lang/Float"),
+        "D" => Some("java/lang/Double"),
+        _ => None
+    };
+}
+
+fn slot_size(desc: &str) -> u16{
+    return if desc == "J" || desc == "D"{ 2 }else{ 1 };
+}
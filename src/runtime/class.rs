@@ -1,6 +1,6 @@
-use std::sync::{Arc, RwLock};
-use crate::{parser::{classfile_structs::{Code, Classfile, NameAndType, FieldInfo, MethodInfo, Attribute, LineNumberMapping}, classfile_parser}, constants};
-use super::{classes::{ClassLoader, self}, jvalue::JValue, heap};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, RwLock};
+use crate::{parser::{classfile_structs::{Code, Classfile, ConstantEntry, NameAndType, MemberRef, MemberKind, FieldInfo, MethodInfo, Attribute, LineNumberMapping, BootstrapEntry, RecordComponentInfo, InnerClassInfo}, classfile_parser}, constants};
+use super::{classes::{ClassLoader, self}, jvalue::JValue, heap, native_impls, verifier};
 
 #[derive(Debug)]
 pub struct Class{
@@ -9,10 +9,26 @@ pub struct Class{
     pub super_class: Option<ClassRef>, // None for Object and primitives
     pub interfaces: Vec<ClassRef>,
     pub loader_name: String,
-    pub initialized: RwLock<bool>,
+    pub flags: u16,
+    pub init_state: Mutex<InitState>,
+    pub init_finished: Condvar, // signalled when another thread finishes initializing this class
     pub instance_fields: Vec<Field>,
+    pub instance_layout: Vec<FieldKey>, // the field in each slot of an object, starting with inherited ones
     pub static_fields: Vec<RwLock<(Field, JValue)>>,
-    pub methods: Vec<Method>
+    pub methods: Vec<Method>,
+    pub vtable: Vec<MethodSlot>,
+    pub itable: Vec<(ClassRef, Vec<Result<MethodSlot, &'static str>>)>, // indexed like the interface's methods
+    pub nest_host_name: Option<String>, // internal name from NestHost, if any
+    pub nest_members: Vec<String>,      // internal names from NestMembers
+    pub nest_host: OnceLock<String>,    // descriptor of the validated nest host, worked out on first use
+    pub bootstrap_methods: Vec<BootstrapEntry>,
+    pub record_components: Option<Vec<RecordComponentInfo>>, // None if it's not a record
+    pub permitted_subclasses: Option<Vec<String>>,           // internal names; None if it's not sealed
+    pub inner_classes: Vec<InnerClassInfo>,                  // from InnerClasses, including this class's own entry if it's nested
+    pub enclosing_method: Option<(String, Option<NameAndType>)>, // from EnclosingMethod, for local and anonymous classes
+    pub signature: Option<String>,                           // generic signature
+    pub annotations: RawAnnotations,
+    pub constants: Vec<ConstantEntry>                        // the classfile's constant pool, which annotations refer to
 }
 
 impl PartialEq for Class {
@@ -23,8 +39,8 @@ impl PartialEq for Class {
 }
 
 impl Class{
-    pub fn assignable_to(&self, to: &String) -> bool{
-        if &self.descriptor == to{
+    pub fn assignable_to(&self, to: &str) -> bool{
+        if self.descriptor == to{
             return true;
         }
         if let Some(sup) = &self.super_class && sup.assignable_to(to){
@@ -47,61 +63,423 @@ impl Class{
         return None;
     }
 
-    pub fn special_method(&self, target: &NameAndType, owner_int_name: &str) -> Option<(&Method, &Class)> {
-        if self.descriptor == format!("L{};", owner_int_name){
-            for method in &self.methods{
-                if !method.is_static && method.name == target.name && method.descriptor() == target.descriptor{
-                    return Some((method, self));
-                }
+    /// Returns the method with the given name and descriptor declared directly in this class, if any.
+    pub fn declared_method(&self, target: &NameAndType) -> Option<&Method>{
+        for method in &self.methods{
+            if method.name == target.name && method.descriptor() == target.descriptor{
+                return Some(method);
             }
         }
-        if let Some(c) = &self.super_class{
-            if let Some(ret) = c.special_method(target, owner_int_name){
-                return Some(ret);
-            }
+        return None;
+    }
+
+    // Field resolution (JVMS 5.4.3.2)
+
+    /// Resolves a field reference against this class, returning the class declaring the field and where it is.
+    pub fn resolve_field(&self, target: &NameAndType) -> Result<(&Class, FieldLocation), &'static str>{
+        if let Some(location) = self.declared_field(target){
+            return Ok((self, location));
         }
         for interface in &self.interfaces{
-            if let Some(m) = interface.special_method(target, owner_int_name){
-                return Some(m);
+            if let Ok(r) = interface.resolve_field(target){
+                return Ok(r);
             }
         }
-        return None;
+        return match &self.super_class{
+            Some(sc) => sc.resolve_field(target),
+            None => Err("NoSuchFieldError")
+        };
     }
 
-    pub fn virtual_method(&self, target: &NameAndType) -> Option<(&Method, &Class)>{
-        for method in &self.methods{
-            if method.name == target.name && method.descriptor() == target.descriptor{
-                return Some((method, self));
+    /// Returns the offset of the instance field with the given name that code in this class would see, for natives that access fields directly.
+    pub fn field_offset(&self, name: &str) -> Option<usize>{
+        return self.instance_layout.iter().rposition(|k| k.name == name);
+    }
+
+    /// Returns the index in the instance layout of the field with the given name declared by this class.
+    pub fn declared_field_offset(&self, name: &str) -> Option<usize>{
+        return self.instance_layout.iter().position(|k| k.owner == self.descriptor && k.name == name);
+    }
+
+    fn declared_field(&self, target: &NameAndType) -> Option<FieldLocation>{
+        if let Some(idx) = self.instance_fields.iter()
+            .position(|f| f.name == target.name && f.type_class.descriptor() == target.descriptor){
+            return Some(FieldLocation::Instance(idx));
+        }
+        return self.static_fields.iter()
+            .position(|f| {
+                let f = &f.read().unwrap().0;
+                f.name == target.name && f.type_class.descriptor() == target.descriptor
+            })
+            .map(FieldLocation::Static);
+    }
+
+    // Method resolution (JVMS 5.4.3.3, 5.4.3.4)
+    // errors are the names of the exception to throw
+
+    /// Resolves a symbolic method reference against this class, using interface method resolution for interface method refs.
+    pub fn resolve_method_ref(&self, target: &MemberRef) -> Result<(&Method, &Class), &'static str>{
+        return match target.kind{
+            MemberKind::InterfaceMethod => self.resolve_interface_method(&target.name_and_type),
+            _ => self.resolve_method(&target.name_and_type)
+        };
+    }
+
+    pub fn resolve_method(&self, target: &NameAndType) -> Result<(&Method, &Class), &'static str>{
+        if self.is_interface(){
+            return Err("IncompatibleClassChangeError");
+        }
+        let mut cur = Some(self);
+        while let Some(c) = cur{
+            if let Some(m) = c.declared_method(target){
+                return Ok((m, c));
             }
+            cur = c.super_class.as_deref();
         }
-        if let Some(c) = &self.super_class{
-            return c.virtual_method(target);
+        return self.resolve_from_superinterfaces(target);
+    }
+
+    pub fn resolve_interface_method(&self, target: &NameAndType) -> Result<(&Method, &Class), &'static str>{
+        if !self.is_interface(){
+            return Err("IncompatibleClassChangeError");
         }
-        return None;
+        if let Some(m) = self.declared_method(target){
+            return Ok((m, self));
+        }
+        // public instance methods of Object (an interface's superclass)
+        if let Some(object) = &self.super_class
+        && let Some(m) = object.declared_method(target)
+        && m.visibility == Visibility::Public && !m.is_static{
+            return Ok((m, object));
+        }
+        return self.resolve_from_superinterfaces(target);
     }
 
-    pub fn interface_method(&self, target: &NameAndType) -> Option<(&Method, &Class)>{
-        for method in &self.methods{
-            if method.name == target.name && method.descriptor() == target.descriptor{
-                return Some((method, self));
+    fn resolve_from_superinterfaces(&self, target: &NameAndType) -> Result<(&Method, &Class), &'static str>{
+        let candidates = self.maximally_specific_methods(target);
+        let concrete: Vec<_> = candidates.iter().filter(|(m, _)| !m.is_abstract()).collect();
+        if concrete.len() == 1{
+            return Ok(*concrete[0]);
+        }
+        // otherwise, any of them will do
+        return candidates.first().copied().ok_or("NoSuchMethodError");
+    }
+
+    // Method selection (JVMS 5.4.6)
+
+    /// Selects the method to invoke for invokevirtual or invokeinterface, with this class being the receiver's.
+    pub fn select_method<'a>(&'a self, resolved: &'a Method, resolved_owner: &'a Class) -> Result<(&'a Method, &'a Class), &'static str>{
+        if resolved.visibility == Visibility::Private{
+            return Ok((resolved, resolved_owner));
+        }
+        let mut cur = Some(self);
+        while let Some(c) = cur{
+            for method in &c.methods{
+                if method.overrides(c, resolved, resolved_owner){
+                    return if method.is_abstract(){ Err("AbstractMethodError") }else{ Ok((method, c)) };
+                }
             }
+            cur = c.super_class.as_deref();
         }
-        if let Some(c) = &self.super_class{
-            if let Some(m) = c.interface_method(target){
-                return Some(m);
+        return self.select_from_superinterfaces(&NameAndType{
+            name: resolved.name.clone(),
+            descriptor: resolved.descriptor()
+        });
+    }
+
+    /// Selects the method to invoke for invokespecial, with this class being the one to start searching from.
+    pub fn special_method(&self, target: &NameAndType) -> Result<(&Method, &Class), &'static str>{
+        let mut cur = Some(self);
+        while let Some(c) = cur{
+            if let Some(m) = c.declared_method(target) && !m.is_static{
+                return if m.is_abstract(){ Err("AbstractMethodError") }else{ Ok((m, c)) };
             }
+            // interfaces don't search their superclass, except for public methods of Object
+            cur = if c.is_interface(){ None }else{ c.super_class.as_deref() };
         }
+        if self.is_interface()
+        && let Some(object) = &self.super_class
+        && let Some(m) = object.declared_method(target)
+        && m.visibility == Visibility::Public && !m.is_static{
+            return Ok((m, object));
+        }
+        return self.select_from_superinterfaces(target);
+    }
+
+    fn select_from_superinterfaces(&self, target: &NameAndType) -> Result<(&Method, &Class), &'static str>{
+        let candidates = self.maximally_specific_methods(target);
+        let mut concrete = candidates.into_iter().filter(|(m, _)| !m.is_abstract());
+        return match (concrete.next(), concrete.next()){
+            (Some(m), None) => Ok(m),
+            (Some(_), Some(_)) => Err("IncompatibleClassChangeError"), // conflicting defaults
+            (None, _) => Err("AbstractMethodError")
+        };
+    }
+
+    /// Returns the maximally-specific superinterface methods of this class with the given name and descriptor.
+    pub fn maximally_specific_methods(&self, target: &NameAndType) -> Vec<(&Method, &Class)>{
+        let mut candidates = Vec::new();
+        for interface in self.superinterfaces(){
+            for method in &interface.methods{
+                if !method.is_static && method.visibility != Visibility::Private
+                && method.name == target.name && method.descriptor() == target.descriptor{
+                    candidates.push((method, &**interface));
+                }
+            }
+        }
+        // drop candidates declared in a superinterface of another candidate's interface
+        let owners: Vec<&Class> = candidates.iter().map(|(_, c)| *c).collect();
+        candidates.retain(|(_, c)| !owners.iter().any(|o| o.descriptor != c.descriptor && o.assignable_to(&c.descriptor)));
+        return candidates;
+    }
+
+    /// Returns every direct and indirect superinterface of this class, without duplicates.
+    pub fn superinterfaces(&self) -> Vec<&ClassRef>{
+        let mut ret = Vec::new();
+        self.collect_superinterfaces(&mut ret);
+        return ret;
+    }
+
+    fn collect_superinterfaces<'a>(&'a self, into: &mut Vec<&'a ClassRef>){
         for interface in &self.interfaces{
-            if let Some(m) = interface.interface_method(target){
-                return Some(m);
+            if !into.iter().any(|c| c.descriptor == interface.descriptor){
+                into.push(interface);
+                interface.collect_superinterfaces(into);
+            }
+        }
+        if let Some(sc) = &self.super_class{
+            sc.collect_superinterfaces(into);
+        }
+    }
+
+    // Dispatch tables
+
+    /// Returns the method in the given slot, and the class declaring it.
+    pub fn slot_method<'a>(&'a self, slot: &'a MethodSlot) -> (&'a Method, &'a Class){
+        let class = slot.owner.as_deref().unwrap_or(self);
+        return (&class.methods[slot.idx], class);
+    }
+
+    /// Returns the itable entry for the method at the given index in the given interface,
+    /// or None if this class doesn't implement it.
+    pub fn itable_method(&self, interface: &ClassRef, idx: usize) -> Option<&Result<MethodSlot, &'static str>>{
+        for (i, entries) in &self.itable{
+            if Arc::ptr_eq(i, interface){
+                return entries.get(idx);
             }
         }
         return None;
     }
+
+    /// Returns the reference to a superclass or superinterface of this class.
+    fn super_ref(&self, target: &Class) -> Option<ClassRef>{
+        let mut cur = &self.super_class;
+        while let Some(c) = cur{
+            if std::ptr::eq(&**c, target){
+                return Some(c.clone());
+            }
+            cur = &c.super_class;
+        }
+        return self.superinterfaces().into_iter().find(|i| std::ptr::eq(&***i, target)).cloned();
+    }
+
+    fn slot_for(&self, method: &Method, owner: &Class) -> MethodSlot{
+        let idx = owner.methods.iter().position(|m| std::ptr::eq(m, method)).expect("Method not in its owner");
+        return if std::ptr::eq(owner, self){
+            MethodSlot{ owner: None, idx }
+        }else{
+            MethodSlot{ owner: Some(self.super_ref(owner).expect("Method owner not a supertype")), idx }
+        };
+    }
+
+    /// Builds the vtable and itables of this class, and assigns vtable slots to its own methods.
+    fn build_dispatch_tables(&mut self){
+        // start with our superclass's, pointing its own entries back at it
+        let mut vtable: Vec<MethodSlot> = match &self.super_class{
+            Some(sc) => sc.vtable.iter().map(|slot| MethodSlot{
+                owner: Some(slot.owner.clone().unwrap_or_else(|| sc.clone())),
+                idx: slot.idx
+            }).collect(),
+            None => Vec::new()
+        };
+        // interface methods are dispatched through itables instead
+        let mut own_slots = vec![None; self.methods.len()];
+        if !self.is_interface(){
+            for (i, method) in self.methods.iter().enumerate(){
+                if method.is_static || method.visibility == Visibility::Private || method.name == "<init>"{
+                    continue;
+                }
+                // a method can override several inherited slots, with package-private methods
+                for (idx, slot) in vtable.iter_mut().enumerate(){
+                    let (inherited, inherited_owner) = self.slot_method(slot);
+                    if method.overrides(self, inherited, inherited_owner){
+                        *slot = MethodSlot{ owner: None, idx: i };
+                        own_slots[i].get_or_insert(idx);
+                    }
+                }
+                if own_slots[i].is_none(){
+                    own_slots[i] = Some(vtable.len());
+                    vtable.push(MethodSlot{ owner: None, idx: i });
+                }
+            }
+        }
+
+        let mut itable = Vec::new();
+        if !self.is_interface(){
+            for interface in self.superinterfaces(){
+                let entries = interface.methods.iter().map(|m| {
+                    if m.is_static || m.visibility == Visibility::Private{
+                        return Err("IncompatibleClassChangeError");
+                    }
+                    return self.select_method(m, interface).map(|(selected, owner)| self.slot_for(selected, owner));
+                }).collect();
+                itable.push((interface.clone(), entries));
+            }
+        }
+
+        for (method, slot) in self.methods.iter_mut().zip(own_slots){
+            method.vtable_idx = slot;
+        }
+        self.vtable = vtable;
+        self.itable = itable;
+    }
+
+    pub fn is_interface(&self) -> bool{
+        return constants::bit_set(self.flags, constants::CLASS_ACC_INTERFACE);
+    }
+
+    /// Returns whether the given class is a proper superclass of this class.
+    pub fn is_subclass_of(&self, other: &Class) -> bool{
+        let mut cur = self.super_class.as_deref();
+        while let Some(c) = cur{
+            if c.descriptor == other.descriptor{
+                return true;
+            }
+            cur = c.super_class.as_deref();
+        }
+        return false;
+    }
+
+    pub fn package_name(&self) -> &str{
+        return match self.name.rfind('.'){
+            Some(idx) => &self.name[..idx],
+            None => ""
+        };
+    }
+
+    /// Returns whether this class is in the same runtime package (loader and package name) as the given class.
+    pub fn same_runtime_package(&self, other: &Class) -> bool{
+        return self.loader_name == other.loader_name && self.package_name() == other.package_name();
+    }
+
+    // Access control (JVMS 5.4.4)
+
+    /// Returns whether code in the given class can refer to this class.
+    pub fn accessible_to(&self, accessor: &Class) -> bool{
+        // primitives are accessible everywhere
+        return !self.descriptor.starts_with('L')
+            || constants::bit_set(self.flags, constants::ACC_PUBLIC)
+            || self.same_runtime_package(accessor);
+    }
+
+    /// Returns whether code in the given class can access a member of this class with the given visibility, through a
+    /// reference to the member in `ref_owner`.
+    pub fn member_accessible_to(&self, visibility: &Visibility, is_static: bool, ref_owner: &Class, accessor: &Class) -> bool{
+        return match visibility{
+            Visibility::Public => true,
+            // protected instance members must be referred to through a class related to the accessor
+            Visibility::Protected => self.same_runtime_package(accessor)
+                || ((accessor == self || accessor.is_subclass_of(self))
+                    && (is_static || ref_owner == accessor || ref_owner.is_subclass_of(accessor) || accessor.is_subclass_of(ref_owner))),
+            Visibility::Local => self.same_runtime_package(accessor),
+            Visibility::Private => accessor == self || self.nestmate_of(accessor)
+        };
+    }
+
+    /// Returns whether a protected instance member of this class, accessed from the given class, can only be accessed
+    /// on objects of that class and its subclasses.
+    pub fn restricts_protected_receiver(&self, accessor: &Class) -> bool{
+        return !self.same_runtime_package(accessor) && accessor.is_subclass_of(self);
+    }
+
+    // Nests (JVMS 5.4.4)
+
+    /// Returns whether this class is in the same nest as the given class.
+    pub fn nestmate_of(&self, other: &Class) -> bool{
+        return self.nest_host() == other.nest_host();
+    }
+
+    /// Returns the descriptor of this class's nest host, validating its NestHost attribute the first time.
+    pub fn nest_host(&self) -> &str{
+        return self.nest_host.get_or_init(|| {
+            // the host has to load, be in the same runtime package, and list this class as a member
+            if let Some(host_name) = &self.nest_host_name
+            && let Ok(host) = heap::get_or_create_bt_class(format!("L{};", host_name))
+            && let Ok(host) = host.ensure_loaded()
+            && host.same_runtime_package(self)
+            && host.nest_members.iter().any(|m| m == &self.name.replace('.', "/")){
+                return host.descriptor.clone();
+            }
+            // otherwise it's its own nest host
+            return self.descriptor.clone();
+        });
+    }
 }
 
 pub type ClassRef = Arc<Class>;
 
+/// An entry in a vtable or itable, referring to a method by index in its declaring class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodSlot{
+    pub owner: Option<ClassRef>, // None for the class holding the table
+    pub idx: usize
+}
+
+/// Identifies an instance field of an object; a subclass can declare a field with the same name as one it inherits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldKey{
+    pub owner: String, // descriptor of the declaring class
+    pub name: String,
+    pub descriptor: String
+}
+
+/// Where a resolved field is stored in its declaring class.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldLocation{
+    Static(usize),  // index into static_fields
+    Instance(usize) // index into instance_fields
+}
+
+/// The quickened form of an instruction, with its symbolic references resolved on first execution.
+#[derive(Debug, PartialEq)]
+pub enum Quick{
+    Invoke(CallSite),
+    StaticField(ClassRef, usize, bool), // declaring class, index into its static fields, whether it's volatile
+    InstanceField(usize, bool, Option<ClassRef>), // offset into the object's slots, whether it's volatile, the class the object must be an instance of for protected fields
+    Class(ClassRef),                    // for new and [a]newarray
+    Type(String),                       // descriptor, for checkcast and instanceof
+    Constant(JValue)                    // for ldc of strings and classes
+}
+
+/// A method reference resolved at an invoke instruction.
+#[derive(Debug, PartialEq)]
+pub struct CallSite{
+    pub target: CallTarget,
+    pub arg_count: usize,
+    pub protected_receiver: Option<ClassRef> // the class the receiver must be an instance of, for protected methods
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CallTarget{
+    Static(ClassRef, usize),    // declaring class, method index
+    Special(ClassRef, usize),   // selected class, method index; also used for private methods
+    Virtual(usize),             // vtable slot
+    Interface(ClassRef, usize), // interface, method index in the interface
+    VarHandle(String, String),  // signature polymorphic access mode method, the return type of the call site
+    EnterContinuation(ClassRef, usize), // Continuation.enterSpecial; Continuation, index of its enter method
+    YieldContinuation           // Continuation.doYield
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum MaybeClass{
     Class(ClassRef),
@@ -109,12 +487,22 @@ pub enum MaybeClass{
     UnloadedArray(String)
 }
 
+/// How far a class is through initialization (JVMS 5.5).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InitState{
+    Uninitialized,
+    InProgress(u32), // by the thread with this monitor thread id
+    Initialized
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Field{
     pub name: String,
     pub type_class: MaybeClass, // TODO: does a field of the same type as the class create cycles?
     pub visibility: Visibility,
-    pub is_static: bool
+    pub is_static: bool,
+    pub flags: u16, // as in the classfile, for reflection
+    pub constant_value: Option<ConstantEntry> // from ConstantValue, set when the class is initialized
 }
 
 #[derive(Debug, PartialEq)]
@@ -124,8 +512,15 @@ pub struct Method{
     pub return_type: MaybeClass,
     pub visibility: Visibility,
     pub is_static: bool,
+    pub is_synchronized: bool,
+    pub flags: u16, // as in the classfile, for reflection
     pub line_number_table: Option<Vec<LineNumberMapping>>,
-    pub code: MethodImpl
+    pub exceptions: Vec<String>, // internal names from Exceptions, for reflection
+    pub signature: Option<String>,
+    pub annotations: RawAnnotations,
+    pub code: MethodImpl,
+    pub vtable_idx: Option<usize>, // slot in the declaring class's vtable, if overridable
+    pub quick: Vec<OnceLock<Result<Quick, &'static str>>> // by instruction index
 }
 
 impl MaybeClass{
@@ -147,6 +542,36 @@ impl MaybeClass{
 }
 
 impl Method{
+    pub fn is_abstract(&self) -> bool{
+        return self.code == MethodImpl::Abstract;
+    }
+
+    /// Returns whether this method, declared in `owner`, can override `other`, declared in `other_owner` (JVMS 5.4.5).
+    pub fn overrides(&self, owner: &Class, other: &Method, other_owner: &Class) -> bool{
+        if self.is_static || self.visibility == Visibility::Private
+        || self.name != other.name || self.descriptor() != other.descriptor(){
+            return false;
+        }
+        if match other.visibility{
+            Visibility::Public | Visibility::Protected => true,
+            Visibility::Local => owner.same_runtime_package(other_owner),
+            Visibility::Private => false
+        }{
+            return true;
+        }
+        // or transitively, through a method in between that overrides `other`
+        let mut cur = owner.super_class.as_deref();
+        while let Some(c) = cur && c.descriptor != other_owner.descriptor{
+            for method in &c.methods{
+                if self.overrides(owner, method, c) && method.overrides(c, other, other_owner){
+                    return true;
+                }
+            }
+            cur = c.super_class.as_deref();
+        }
+        return false;
+    }
+
     pub fn descriptor(&self) -> String{
         let mut desc = String::with_capacity(self.parameters.len() + 2);
         desc.push_str("(");
@@ -164,6 +589,15 @@ pub enum Visibility{
     Public, Local, Protected, Private
 }
 
+/// The contents of visible annotation attributes, which reflection parses itself using the class's constant pool.
+#[derive(Debug, PartialEq, Default)]
+pub struct RawAnnotations{
+    pub annotations: Option<Vec<u8>>,
+    pub parameter_annotations: Option<Vec<u8>>,
+    pub type_annotations: Option<Vec<u8>>,
+    pub annotation_default: Option<Vec<u8>>
+}
+
 #[derive(Debug, PartialEq)]
 pub enum MethodImpl{
     Bytecode(Code), Native, Abstract
@@ -182,7 +616,11 @@ pub fn load_class(classname: String) -> Result<Class, String>{
 
 /// Loads and links the class with the given name, provided by the given classloader.
 pub fn load_class_with(classname: String, loader: Arc<dyn ClassLoader>) -> Result<Class, String>{
-    return link_class(classfile_parser::parse(&mut loader.load(&classname))?, loader);
+    let classfile = classfile_parser::parse(&mut loader.load(&classname))?;
+    if !loader.is_trusted(&classname){
+        verifier::verify(&classfile)?;
+    }
+    return link_class(classfile, loader);
 }
 
 /// Links the classfile into a class, ascribing it to the given classloader.
@@ -210,33 +648,105 @@ pub fn link_class(classfile: Classfile, loader: Arc<dyn ClassLoader>) -> Result<
     for m in classfile.methods{
         all_methods.push(link_method(m, &loader)?);
     }
+    if loader.is_trusted(&classfile.name){
+        let name = binary_to_fq_name(classfile.name.clone());
+        for m in &mut all_methods{
+            if native_impls::replaces_bytecode(&name, &format!("{}{}", m.name, m.descriptor())){
+                m.code = MethodImpl::Native;
+                m.quick = Vec::new();
+            }
+        }
+    }
 
     let mut super_class = None;
     if let Some(super_name) = &classfile.super_class{
-        super_class = Some(heap::get_or_create_class(format!("L{};", super_name), &loader)
-            .expect("Could not find or parse super-class classfile")
-            .ensure_loaded()
-            .expect("Could not link superclass"));
+        super_class = Some(heap::get_or_create_class(format!("L{};", super_name), &loader)?.ensure_loaded()?);
     }
     let mut interfaces = Vec::with_capacity(classfile.interfaces.len());
     for interface in &classfile.interfaces {
-        interfaces.push(heap::get_or_create_class(format!("L{};", interface), &loader)
-            .expect("Could not find or parse super-class classfile")
-            .ensure_loaded()
-            .expect("Could not link superclass"));
+        interfaces.push(heap::get_or_create_class(format!("L{};", interface), &loader)?.ensure_loaded()?);
     }
 
-    return Ok(Class{
+    // objects of this class have the fields of the superclass first, so offsets into them stay valid for subclasses
+    let descriptor = format!("L{};", classfile.name.clone());
+    let mut instance_layout = super_class.as_ref().map_or(Vec::new(), |sc: &ClassRef| sc.instance_layout.clone());
+    for f in &instance_fields{
+        instance_layout.push(FieldKey{
+            owner: descriptor.clone(),
+            name: f.name.clone(),
+            descriptor: f.type_class.descriptor()
+        });
+    }
+
+    let mut nest_host_name = None;
+    let mut nest_members = Vec::new();
+    let mut bootstrap_methods = Vec::new();
+    let mut record_components = None;
+    let mut permitted_subclasses = None;
+    let mut inner_classes = Vec::new();
+    let mut enclosing_method = None;
+    let mut signature = None;
+    let mut annotations = RawAnnotations::default();
+    for attribute in classfile.attributes{
+        match attribute{
+            Attribute::NestHost(host) => nest_host_name = Some(host),
+            Attribute::NestMembers(members) => nest_members = members,
+            Attribute::BootstrapMethods(methods) => bootstrap_methods = methods,
+            Attribute::Record(components) => record_components = Some(components),
+            Attribute::PermittedSubclasses(permitted) => permitted_subclasses = Some(permitted),
+            Attribute::InnerClasses(classes) => inner_classes = classes,
+            Attribute::EnclosingMethod{ owner_class, owner_method } => enclosing_method = Some((owner_class, owner_method)),
+            Attribute::Signature(sig) => signature = Some(sig),
+            Attribute::RuntimeVisibleAnnotations(_, raw) => annotations.annotations = Some(raw),
+            Attribute::RuntimeVisibleTypeAnnotations(_, raw) => annotations.type_annotations = Some(raw),
+            _ => {}
+        }
+    }
+
+    let mut class = Class{
         name: binary_to_fq_name(classfile.name.clone()),
-        descriptor: format!("L{};", classfile.name.clone()),
+        descriptor,
         loader_name: loader.name(),
-        initialized: RwLock::new(false),
+        flags: classfile.flags,
+        init_state: Mutex::new(InitState::Uninitialized),
+        init_finished: Condvar::new(),
         instance_fields,
+        instance_layout,
         static_fields,
         methods: all_methods,
         super_class,
         interfaces,
-    });
+        vtable: Vec::new(),
+        itable: Vec::new(),
+        nest_host_name,
+        nest_members,
+        nest_host: OnceLock::new(),
+        bootstrap_methods,
+        record_components,
+        permitted_subclasses,
+        inner_classes,
+        enclosing_method,
+        signature,
+        annotations,
+        constants: classfile.constants
+    };
+    // sealed supertypes have to permit this class, and be in the same module; in the unnamed module,
+    // they also have to be in the same runtime package (JVMS 5.3.5)
+    let binary_name = class.name.replace('.', "/");
+    let in_java_base = classes::in_java_base(&binary_name);
+    for sup in class.super_class.iter().chain(&class.interfaces){
+        if let Some(permitted) = &sup.permitted_subclasses{
+            if !permitted.contains(&binary_name){
+                return Err(format!("IncompatibleClassChangeError: {} is not a permitted subtype of sealed {}", class.name, sup.name));
+            }
+            let same_module = classes::in_java_base(&sup.name.replace('.', "/")) == in_java_base;
+            if !same_module || (!in_java_base && !sup.same_runtime_package(&class)){
+                return Err(format!("IncompatibleClassChangeError: {} is not in the same module, or unnamed module package, as sealed {}", class.name, sup.name));
+            }
+        }
+    }
+    class.build_dispatch_tables();
+    return Ok(class);
 }
 
 fn binary_to_fq_name(binary_name: String) -> String{
@@ -247,7 +757,7 @@ fn flags_to_visibility(flags: u16) -> Visibility{
     return if constants::bit_set(flags, constants::ACC_PUBLIC){
         Visibility::Public
     }else if constants::bit_set(flags, constants::ACC_PROTECTED){
-        Visibility::Public
+        Visibility::Protected
     }else if constants::bit_set(flags, constants::ACC_PRIVATE){
         Visibility::Private
     }else{
@@ -256,11 +766,19 @@ fn flags_to_visibility(flags: u16) -> Visibility{
 }
 
 fn link_field(field: FieldInfo, loader: &Arc<dyn ClassLoader>) -> Result<Field, String>{
+    let is_static = constants::bit_set(field.flags, constants::ACC_STATIC);
+    // instance fields ignore their constant values (JVMS 4.7.2)
+    let constant_value = field.attributes.into_iter().find_map(|attr| match attr{
+        Attribute::ConstantValue(value) if is_static => Some(value),
+        _ => None
+    });
     return Ok(Field{
         name: field.name,
         type_class: heap::get_or_create_class(field.desc, loader)?,
         visibility: flags_to_visibility(field.flags),
-        is_static: constants::bit_set(field.flags, constants::ACC_STATIC)
+        is_static,
+        flags: field.flags,
+        constant_value
     });
 }
 
@@ -273,20 +791,32 @@ fn link_method(method: MethodInfo, loader: &Arc<dyn ClassLoader>) -> Result<Meth
         parameters.push(heap::get_or_create_class(d, loader)?);
     }
 
-    // TODO: check Code presence & flags
-    // (should be checked much earlier though)
+    // Code presence & flags are checked by the parser
     let mut code = MethodImpl::Abstract;
     let mut line_number_table = None;
+    let mut exceptions = Vec::new();
+    let mut signature = None;
+    let mut annotations = RawAnnotations::default();
     for attr in method.attributes{
-        if let Attribute::Code(c) = attr{
-            code = MethodImpl::Bytecode(c);
-        }else if let Attribute::LineNumberTable(table) = attr{
-            line_number_table = Some(table);
+        match attr{
+            Attribute::Code(c) => code = MethodImpl::Bytecode(c),
+            Attribute::LineNumberTable(table) => line_number_table = Some(table),
+            Attribute::Exceptions(e) => exceptions = e,
+            Attribute::Signature(sig) => signature = Some(sig),
+            Attribute::RuntimeVisibleAnnotations(_, raw) => annotations.annotations = Some(raw),
+            Attribute::RuntimeVisibleParameterAnnotations(_, raw) => annotations.parameter_annotations = Some(raw),
+            Attribute::RuntimeVisibleTypeAnnotations(_, raw) => annotations.type_annotations = Some(raw),
+            Attribute::AnnotationDefault(_, raw) => annotations.annotation_default = Some(raw),
+            _ => {}
         }
     }
     if constants::bit_set(method.flags, constants::METHOD_ACC_NATIVE){
         code = MethodImpl::Native;
     }
+    let quick = match &code{
+        MethodImpl::Bytecode(c) => c.bytecode.iter().map(|_| OnceLock::new()).collect(),
+        _ => Vec::new()
+    };
 
     return Ok(Method{
         name: method.name,
@@ -294,7 +824,14 @@ fn link_method(method: MethodInfo, loader: &Arc<dyn ClassLoader>) -> Result<Meth
         return_type,
         visibility: flags_to_visibility(method.flags),
         is_static: constants::bit_set(method.flags, constants::ACC_STATIC),
+        is_synchronized: constants::bit_set(method.flags, constants::METHOD_ACC_SYNCHRONIZED),
+        flags: method.flags,
         line_number_table,
+        exceptions,
+        signature,
+        annotations,
         code,
+        vtable_idx: None,
+        quick
     });
 }
\ No newline at end of file
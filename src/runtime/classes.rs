@@ -1,7 +1,7 @@
-use std::{path, fs, sync::RwLock, collections::HashMap, io::Read};
+use std::{path, fs, sync::{Condvar, Mutex, OnceLock, RwLock}, collections::HashMap, io::Read};
 use crate::constants;
 
-use super::{class::{ClassRef, Class}, heap::{JRef, self}};
+use super::{class::{ClassRef, Class, InitState, RawAnnotations}, heap::{JRef, self}};
 
 // Class loaders
 
@@ -11,6 +11,10 @@ pub trait ClassLoader{
     fn prev_loaded(&self) -> Vec<ClassRef>{
         return heap::classes_by_loader(self.name());
     }
+    /// Whether the given class can skip verification.
+    fn is_trusted(&self, _classname: &str) -> bool{
+        return false;
+    }
 }
 
 pub const BOOTSTRAP_LOADER: BootstrapLoader = BootstrapLoader{};
@@ -56,6 +60,16 @@ impl ClassLoader for BootstrapLoader{
         file.read_to_end(&mut buffer).expect("Could not read user class file data");
         return buffer;
     }
+    fn is_trusted(&self, classname: &str) -> bool{
+        // like HotSpot, platform classes aren't verified
+        return in_java_base(classname);
+    }
+}
+
+/// Returns whether the class with the given binary name is a platform class, in java.base.
+/// Modules aren't otherwise modelled, so every other class is in the unnamed module.
+pub fn in_java_base(classname: &str) -> bool{
+    return JAVA_BASE_CLASSES.read().unwrap().as_ref().is_some_and(|c| c.contains_key(classname));
 }
 
 pub fn find_java_home() -> Option<String>{
@@ -123,12 +137,28 @@ fn primitive_class(template: (&str, &str)) -> Class{
         name: template.0.to_owned(),
         descriptor: template.1.to_owned(),
         loader_name: constants::BOOTSTRAP_LOADER_NAME.to_owned(),
-        initialized: RwLock::new(true),
+        flags: constants::ACC_PUBLIC | constants::ACC_FINAL | constants::ACC_ABSTRACT,
+        init_state: Mutex::new(InitState::Initialized),
+        init_finished: Condvar::new(),
         instance_fields: vec![],
+        instance_layout: vec![],
         static_fields: vec![],
         methods: vec![],
         super_class: None,
         interfaces: vec![],
+        vtable: vec![],
+        itable: vec![],
+        nest_host_name: None,
+        nest_members: vec![],
+        nest_host: OnceLock::new(),
+        bootstrap_methods: vec![],
+        record_components: None,
+        permitted_subclasses: None,
+        inner_classes: vec![],
+        enclosing_method: None,
+        signature: None,
+        annotations: RawAnnotations::default(),
+        constants: vec![]
     };
 }
 
@@ -139,11 +169,27 @@ pub fn array_class(of: &ClassRef) -> Class{
         name: of.name.clone() + "[]",
         descriptor: "[".to_owned() + &of.descriptor,
         loader_name: constants::BOOTSTRAP_LOADER_NAME.to_owned(),
-        initialized: RwLock::new(true),
+        flags: constants::ACC_PUBLIC | constants::ACC_FINAL | constants::ACC_ABSTRACT,
+        init_state: Mutex::new(InitState::Initialized),
+        init_finished: Condvar::new(),
         instance_fields: vec![],
+        instance_layout: vec![],
         static_fields: vec![],
         methods: vec![],
         super_class: Some(of.clone()),
         interfaces: vec![],
+        vtable: vec![], // arrays dispatch through Object's
+        itable: vec![],
+        nest_host_name: None,
+        nest_members: vec![],
+        nest_host: OnceLock::new(),
+        bootstrap_methods: vec![],
+        record_components: None,
+        permitted_subclasses: None,
+        inner_classes: vec![],
+        enclosing_method: None,
+        signature: None,
+        annotations: RawAnnotations::default(),
+        constants: vec![]
     };
 }
\ No newline at end of file
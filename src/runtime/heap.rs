@@ -1,9 +1,8 @@
-use std::{sync::{RwLock, Arc}, collections::HashMap, hash::Hash};
-use std::fmt::Debug;
+use std::{sync::{RwLock, Arc, OnceLock, atomic::{AtomicPtr, AtomicUsize, Ordering}}, collections::HashMap, hash::Hash, cell::Cell, ptr};
 
-use crate::{constants, parser::{classfile_structs::Classfile, classfile_parser}};
+use crate::{constants, parser::{classfile_structs::{Classfile, ConstantEntry}, classfile_parser}};
 use crate::runtime::jvalue::JValue;
-use super::{jvalue::JObject, class::{ClassRef, Class, MaybeClass, self}, classes::{self, ClassLoader}, interpreter::{StackTrace, MethodResult}, interpreter};
+use super::{jvalue::JObject, class::{ClassRef, Class, MaybeClass, InitState, self}, classes::{self, ClassLoader}, interpreter::{StackTrace, MethodResult}, interpreter, monitors, objects};
 
 // TODO: use weak references everywhere (esp JRef and ClassRef)
 // and only keep objects and classes alive via the heaps
@@ -11,24 +10,37 @@ use super::{jvalue::JObject, class::{ClassRef, Class, MaybeClass, self}, classes
 
 // Heap shared between threads.
 
-// References are indexes into the "active" list.
-// On GC, reachable objects are moved to the "inactive", and the two lists are swapped.
+// References are indexes into a table of object slots. The table is split into segments, which are allocated when
+// first needed and never move, so dereferencing needs no locks. Threads claim runs of slots at once to allocate into
+// (like TLABs), so allocating only touches shared state once per run.
+// A moving collector would relocate objects between slots at a safepoint, updating references as it goes. There are no
+// safepoints yet, but borrowed objects can't outlive the reference they came from, so none can be stashed away past one.
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct JRef{
     heap_idx: usize // used in `get`
 }
 
 impl JRef {
-    pub fn deref(&self) -> Arc<JObject>{
+    /// Returns the referenced object, borrowed for as long as this reference is.
+    pub fn deref(&self) -> &JObject{
         return get(self);
     }
 }
 
-// Heaps must be mutable so that they can be setup at runtime
-// RwLocks are used for threadsafe addition to heaps and classloading
-static HEAP_ACTIVE: RwLock<Vec<Arc<JObject>>> = RwLock::new(Vec::new());
-static HEAP_INACTIVE: RwLock<Vec<Arc<JObject>>> = RwLock::new(Vec::new());
+const SEGMENT_SIZE: usize = 4096;
+const MAX_SEGMENTS: usize = 1 << 16;
+const TLAB_SIZE: usize = 256; // divides SEGMENT_SIZE, so a thread's run of slots is always in one segment
+
+// each entry points to the first of SEGMENT_SIZE slots, or is null if that segment hasn't been needed yet
+static SEGMENTS: [AtomicPtr<OnceLock<JObject>>; MAX_SEGMENTS] = [const { AtomicPtr::new(ptr::null_mut()) }; MAX_SEGMENTS];
+static NEXT_FREE_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local!{
+    /// The slots this thread has claimed to allocate into, as the next free slot and the end of the run.
+    static TLAB: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
 // Map of classloader name -> associated classes
 static CREATED_CLASSES: RwLock<Option<HashMap<String, Vec<Classfile>>>> = RwLock::new(None);
 static LOADED_CLASSES: RwLock<Option<HashMap<String, Vec<ClassRef>>>> = RwLock::new(None);
@@ -45,14 +57,40 @@ pub fn setup(){
 // Object handling
 
 pub fn add(obj: JObject) -> JRef{
-    let true_heap = &mut *(HEAP_ACTIVE.write().unwrap());
-    true_heap.push(Arc::new(obj));
-    return JRef{ heap_idx: true_heap.len() - 1 };
+    let (mut next, mut end) = TLAB.get();
+    if next == end{
+        next = NEXT_FREE_SLOT.fetch_add(TLAB_SIZE, Ordering::Relaxed);
+        end = next + TLAB_SIZE;
+        ensure_segment(next / SEGMENT_SIZE);
+    }
+    TLAB.set((next + 1, end));
+    if slot(next).set(obj).is_err(){
+        panic!("Allocated into an occupied heap slot");
+    }
+    return JRef{ heap_idx: next };
+}
+
+pub fn get(refs: &JRef) -> &JObject{
+    return slot(refs.heap_idx).get().expect("Dereferenced an empty heap slot");
 }
 
-pub fn get(refs: &JRef) -> Arc<JObject>{
-    let true_heap = &HEAP_ACTIVE.read().unwrap();
-    return true_heap[refs.heap_idx].clone();
+fn slot(idx: usize) -> &'static OnceLock<JObject>{
+    let segment = SEGMENTS[idx / SEGMENT_SIZE].load(Ordering::Acquire);
+    // segments are created before any slot in them is handed out, and are never freed
+    return unsafe{ &*segment.add(idx % SEGMENT_SIZE) };
+}
+
+fn ensure_segment(segment_idx: usize){
+    let entry = SEGMENTS.get(segment_idx).expect("Ran out of heap segments");
+    if !entry.load(Ordering::Acquire).is_null(){
+        return;
+    }
+    let segment: Box<[OnceLock<JObject>]> = (0..SEGMENT_SIZE).map(|_| OnceLock::new()).collect();
+    let segment = Box::into_raw(segment) as *mut OnceLock<JObject>;
+    if entry.compare_exchange(ptr::null_mut(), segment, Ordering::AcqRel, Ordering::Acquire).is_err(){
+        // another thread made it first
+        drop(unsafe{ Box::from_raw(ptr::slice_from_raw_parts_mut(segment, SEGMENT_SIZE)) });
+    }
 }
 
 pub fn add_ref(obj: JObject) -> JValue{
@@ -60,45 +98,41 @@ pub fn add_ref(obj: JObject) -> JValue{
 }
 
 pub fn gc(){
-    // Starting from GC roots, find all objects in "active" and move to "inactive",
-    // Then swap and clear.
-    // TODO: how do we update JRefs to match?
-    //   should I just use Arc directly? how to compare reachability that way?
+    // Starting from GC roots, find all reachable objects and compact them into the lowest slots,
+    // updating references to match.
+    // TODO: how do we find references on the stack?
 }
 
 // Class handling
 // TODO: move to classes.rs?
 
 /// Adds a loaded class under the given classloader, and optionally invokes its static initializer.
-pub fn add_class(mut class: Class, loader_name: String, initialize: bool){
+/// If another thread has added the same class first, that class is used instead, and is initialized if requested.
+pub fn add_class(class: Class, loader_name: String, initialize: bool){
     let class_desc = class.descriptor.clone();
-    if initialize{
-        // while we only actually initialize it later, its easier to set now and should not have an observable difference
-        class.initialized = RwLock::new(true);
-    }
-    add_to_map_list(loader_name.clone(), Arc::new(class), &LOADED_CLASSES);
+    let added = add_to_map_list(loader_name.clone(), Arc::new(class), &LOADED_CLASSES);
     
     // run client init
     // TODO: don't repeat this (get().unwrap().ensure().unwrap()) as much
     let class = get_or_create_bt_class(class_desc.clone()).unwrap().ensure_loaded().unwrap();
     if initialize{
-        if let Some(clinit) = class.static_method(&constants::clinit()){
-            match interpreter::execute(&class, clinit, Vec::new(), StackTrace::new()){
-                MethodResult::FinishWithValue(_) |
-                MethodResult::Finish => { /* good */ },
-                MethodResult::Throw(s, e) => panic!("clinit failed: {}\n{}", e, s),
-                MethodResult::MachineError(e) => panic!("clinit failed: {}", e),
-            }
-        }
+        // this waits for the initializer if another thread is already running it
+        initialize_class(&class);
+    }
+    if !added{
+        return;
     }
 
     // for java.lang.System: run initSystemPhase1
     if class_desc == "Ljava/lang/System;"{
+        // like hotspot, initialize Method before that; its superclass sets up the access reflection uses
+        get_or_create_bt_class("Ljava/lang/reflect/Method;".to_owned()).unwrap().ensure_initialized().unwrap();
         let init = class.static_method(&constants::system_init_phase_1()).unwrap();
         match interpreter::execute(&class, init, Vec::new(), StackTrace::new()){
             MethodResult::FinishWithValue(_) |
             MethodResult::Finish => { /* good */ },
             MethodResult::Throw(s, e) => panic!("System.initSystemPhase1 failed: {}\n{}", e, s),
+            MethodResult::ThrowObject(s, e) => panic!("System.initSystemPhase1 failed: {}\n{}", e.deref().class.name, s),
             MethodResult::MachineError(e) => panic!("System.initSystemPhase1 failed: {}", e),
         }
     }
@@ -180,31 +214,7 @@ pub fn ensure_loaded(class: &MaybeClass, initialize: bool) -> Result<ClassRef, S
     match class{
         MaybeClass::Class(c) => {
             if initialize{
-                let done_init = {
-                    let read = c.initialized.read();
-                    if let Ok(status) = read{
-                        *status
-                    }else{
-                        return Err("Could not access initialization status of class for loading".to_string())
-                    }
-                };
-                if !done_init{
-                    let write = c.initialized.write();
-                    if let Ok(mut acc) = write{
-                        *acc = true;
-                    }else{
-                        return Err("Could not write initialization status of class after loading".to_string())
-                    }
-                    // run clinit
-                    if let Some(clinit) = c.static_method(&constants::clinit()){
-                        match interpreter::execute(&*c, clinit, Vec::new(), StackTrace::new()){
-                            MethodResult::FinishWithValue(_) |
-                            MethodResult::Finish => { /* good */ },
-                            MethodResult::Throw(s, e) => panic!("clinit failed: {}\n{}", e, s),
-                            MethodResult::MachineError(e) => panic!("clinit failed: {}", e),
-                        }
-                    }
-                }
+                initialize_class(c);
             }
             Ok(c.clone())
         },
@@ -225,8 +235,71 @@ pub fn ensure_loaded(class: &MaybeClass, initialize: bool) -> Result<ClassRef, S
     }
 }
 
+/// Runs a class's static initializer, unless it's already been run or is being run by this thread (JVMS 5.5).
+/// If another thread is running it, waits for it to finish.
+fn initialize_class(class: &ClassRef){
+    let me = monitors::thread_id();
+    {
+        let mut state = class.init_state.lock().unwrap();
+        loop{
+            match *state{
+                InitState::Initialized => return,
+                InitState::InProgress(t) if t == me => return, // recursive request
+                InitState::InProgress(_) => state = class.init_finished.wait(state).unwrap(),
+                InitState::Uninitialized => break
+            }
+        }
+        *state = InitState::InProgress(me);
+    }
+    // static fields with constant values get them before anything else runs (JVMS 5.5)
+    for field in &class.static_fields{
+        let mut field = field.write().unwrap();
+        if let Some(value) = &field.0.constant_value{
+            field.1 = constant_value(value);
+        }
+    }
+    // a class's superclass, and superinterfaces with default methods, are initialized first (JVMS 5.5)
+    if !class.is_interface(){
+        if let Some(sc) = &class.super_class{
+            initialize_class(sc);
+        }
+        initialize_default_interfaces(&class.interfaces);
+    }
+    if let Some(clinit) = class.static_method(&constants::clinit()){
+        match interpreter::execute(&class, clinit, Vec::new(), StackTrace::new()){
+            MethodResult::FinishWithValue(_) |
+            MethodResult::Finish => { /* good */ },
+            MethodResult::Throw(s, e) => panic!("clinit failed: {}\n{}", e, s),
+            MethodResult::ThrowObject(s, e) => panic!("clinit failed: {}\n{}", e.deref().class.name, s),
+            MethodResult::MachineError(e) => panic!("clinit failed: {}", e),
+        }
+    }
+    *class.init_state.lock().unwrap() = InitState::Initialized;
+    class.init_finished.notify_all();
+}
+
+fn initialize_default_interfaces(interfaces: &[ClassRef]){
+    for interface in interfaces{
+        initialize_default_interfaces(&interface.interfaces);
+        if interface.methods.iter().any(|m| !m.is_static && m.code != class::MethodImpl::Abstract){
+            initialize_class(interface);
+        }
+    }
+}
+
 // implementation
 
+fn constant_value(constant: &ConstantEntry) -> JValue{
+    return match constant{
+        ConstantEntry::Integer(i) => JValue::Int(*i),
+        ConstantEntry::Long(l) => JValue::Long(*l),
+        ConstantEntry::Float(f) => JValue::Float(*f),
+        ConstantEntry::Double(d) => JValue::Double(*d),
+        ConstantEntry::StringConst(s) => objects::intern_chars(s.clone()),
+        _ => unreachable!("The parser only allows primitive and string constant values")
+    };
+}
+
 fn desc_to_name(desc: String) -> Result<String, String>{
     return if desc.starts_with("L") && desc.ends_with(";"){
         Ok(desc[1..desc.len() - 1].to_string())
@@ -250,7 +323,8 @@ fn desc_to_name(desc: String) -> Result<String, String>{
     }
 }
 
-fn add_to_map_list<K, V>(key: K, value: V, map_list: &RwLock<Option<HashMap<K, Vec<V>>>>) where K: Eq + Clone + Hash, V: PartialEq + Debug{
+/// Adds a value to the list under a key, returning false if it's already there (e.g. if two threads load a class at once).
+fn add_to_map_list<K, V>(key: K, value: V, map_list: &RwLock<Option<HashMap<K, Vec<V>>>>) -> bool where K: Eq + Clone + Hash, V: PartialEq{
     let lc_opt = &mut *map_list.write().unwrap();
     let loaded_classes = lc_opt.as_mut().unwrap();
     let loader_classes = if loaded_classes.contains_key(&key){
@@ -260,9 +334,10 @@ fn add_to_map_list<K, V>(key: K, value: V, map_list: &RwLock<Option<HashMap<K, V
         loaded_classes.get_mut(&key).unwrap()
     };
     if loader_classes.contains(&value){
-        panic!("Entry already exists: {:?}", &value);
+        return false;
     }
     loader_classes.push(value);
+    return true;
 }
 
 pub fn unwrap_map_list<K, V>(key: K, map_list: &RwLock<Option<HashMap<K, Vec<V>>>>) -> Vec<V> where K: Eq + Clone + Hash, V: Clone{
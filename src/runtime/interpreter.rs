@@ -1,19 +1,24 @@
-use std::collections::VecDeque;
-use crate::parser::classfile_structs::{Code, Instruction};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, atomic::{fence, Ordering}};
+
+use crate::parser::{classfile_parser, classfile_structs::{Code, Instruction}};
 use crate::runtime::jvalue::JValue;
-use crate::runtime::{native_impls, objects};
+use crate::runtime::{bootstraps, monitors, native_impls, objects};
 use crate::runtime::class::Class;
+use crate::constants;
 
-use crate::parser::classfile_structs::{ConstantEntry, MemberRef};
+use crate::parser::classfile_structs::{ConstantEntry, Dynamic, MemberRef, NameAndType};
 
-use super::{jvalue::JObjectData, class::{self, Method, MaybeClass}, heap::{self, JRef}};
+use super::{jvalue::{JObject, JObjectData}, class::{self, Method, MaybeClass, ClassRef}, heap::{self, JRef}};
 
 #[derive(Debug)]
 pub enum MethodResult{
     FinishWithValue(JValue),
     Finish,
     Throw(StackTrace, &'static str), // TODO: just use JRef
-    MachineError(&'static str) // TODO: replace with panics after classfile verification works
+    ThrowObject(StackTrace, JRef),
+    MachineError(&'static str) // bad bytecode; verified classes can't cause these, but platform and VM-defined classes aren't verified
 }
 
 // Stack traces
@@ -71,665 +76,950 @@ impl StackTraceEntry {
 
 pub fn execute(owner: &Class, method: &Method, args: Vec<JValue>, trace: StackTrace) -> MethodResult{
     match &method.code{
-        class::MethodImpl::Bytecode(bytecode) => interpret(owner, method, args, bytecode, trace),
+        class::MethodImpl::Bytecode(_) => {
+            let class = class_ref(owner);
+            let m_idx = method_index(&class, method);
+            execute_at(class, m_idx, args, trace)
+        },
         class::MethodImpl::Native => {
             let owner_name = &owner.name;
             let name_and_desc = &format!("{}{}", method.name, method.descriptor());
             let trace_argument = &trace;
-            match native_impls::builtin_native(owner_name, name_and_desc, trace_argument, args){
+            let lock = method.is_synchronized.then(|| sync_object(owner, method, &args));
+            if let Some(lock) = lock{
+                lock.deref().monitor.enter();
+            }
+            let result = native_impls::builtin_native(owner_name, name_and_desc, trace_argument, args);
+            if let Some(lock) = lock{
+                // entered above, so we still own it
+                lock.deref().monitor.exit().unwrap();
+            }
+            match result{
                 MethodResult::Throw(_, err) => MethodResult::Throw(update_trace(&trace, 0, method, &owner), err),
                 u => u
             }
         },
-        class::MethodImpl::Abstract => todo!(),
+        class::MethodImpl::Abstract => MethodResult::Throw(update_trace(&trace, 0, method, owner), "AbstractMethodError"),
+    }
+}
+
+/// Runs the method at the given index of a class, which doesn't need to be in the heap.
+pub fn execute_at(class: ClassRef, m_idx: usize, args: Vec<JValue>, trace: StackTrace) -> MethodResult{
+    if let class::MethodImpl::Bytecode(_) = &class.methods[m_idx].code{
+        return run(class, m_idx, args, trace);
     }
+    return execute(&class, &class.methods[m_idx], args, trace);
 }
 
-pub fn interpret(owner: &Class, method: &Method, args: Vec<JValue>, code: &Code, trace: StackTrace) -> MethodResult{
-    let mut i: usize = 0;
-    let mut stack: VecDeque<JValue> = VecDeque::with_capacity(code.max_stack as usize);
-    let mut locals: Vec<Option<JValue>> = Vec::with_capacity(code.max_locals as usize);
-
-    for arg in &args{
-        locals.push(Some(arg.clone()));
-        if let JValue::Long(_) = arg{
-            locals.push(Some(JValue::Second));
-        }else if let JValue::Double(_) = arg{
-            locals.push(Some(JValue::Second));
+/// How the top frame stopped running.
+enum Exit{
+    Invoke(ClassRef, usize, Vec<JValue>),
+    InvokeVarHandle(ClassRef, usize, Vec<JValue>, String), // access mode method, arguments, call site return descriptor
+    Return(Option<JValue>),
+    Throw(&'static str),
+    ThrowObject(JRef),
+    Error(&'static str),
+    EnterContinuation(ClassRef, usize, Vec<JValue>), // enter method, and the arguments to enterSpecial
+    YieldContinuation
+}
+
+/// Runs a bytecode method, and every bytecode method it calls, on an explicit stack of frames.
+/// Natives that call back into Java start a new run, on top of the same thread's stack.
+fn run(class: ClassRef, m_idx: usize, args: Vec<JValue>, trace: StackTrace) -> MethodResult{
+    let mut frames: Vec<Frame> = Vec::new();
+    RUNS.set(RUNS.get() + 1);
+    let result = match push_frame(&mut frames, class, m_idx, args){
+        Ok(()) => dispatch(&mut frames, &trace),
+        Err(soe) => MethodResult::ThrowObject(trace, soe)
+    };
+    while !frames.is_empty(){
+        pop_frame(&mut frames);
+    }
+    RUNS.set(RUNS.get() - 1);
+    return result;
+}
+
+fn dispatch(frames: &mut Vec<Frame>, outer: &StackTrace) -> MethodResult{
+    loop{
+        match step(frames.last_mut().unwrap()){
+            Exit::Invoke(class, m_idx, args) => if let Some(result) = invoke(frames, outer, class, m_idx, args, None){
+                return result;
+            },
+            Exit::InvokeVarHandle(class, m_idx, args, return_as) => if let Some(result) = invoke(frames, outer, class, m_idx, args, Some(return_as)){
+                return result;
+            },
+            Exit::Return(value) => {
+                let frame = frames.last().unwrap();
+                let value = match &frame.return_as{
+                    Some(return_as) => adapt_return(value, &frame.class.methods[frame.method], return_as),
+                    None => value
+                };
+                pop_frame(frames);
+                let Some(caller) = frames.last_mut() else {
+                    return match value{
+                        Some(v) => MethodResult::FinishWithValue(v),
+                        None => MethodResult::Finish
+                    };
+                };
+                if let Some(v) = value{
+                    caller.push(v);
+                }
+                caller.pc += 1;
+            },
+            Exit::Throw(e) => return MethodResult::Throw(frames_trace(outer, frames), e),
+            Exit::ThrowObject(exception) => if let Some(result) = throw(frames, outer, exception){
+                return result;
+            },
+            Exit::Error(e) => return MethodResult::MachineError(e),
+            Exit::EnterContinuation(class, m_idx, args) => {
+                if let Err(soe) = enter_continuation(frames, class, m_idx, args)
+                && let Some(result) = throw(frames, outer, soe){
+                    return result;
+                }
+            },
+            Exit::YieldContinuation => yield_continuation(frames)
         }
     }
-    locals.resize(code.max_locals as usize, None);
+}
+
+/// Calls a method for the top frame; bytecode methods get a frame of their own, and natives are run right away.
+/// If the call is from a VarHandle call site, its return descriptor is given, for adapting the result to.
+fn invoke(frames: &mut Vec<Frame>, outer: &StackTrace, class: ClassRef, m_idx: usize, args: Vec<JValue>, return_as: Option<String>) -> Option<MethodResult>{
+    let method = &class.methods[m_idx];
+    if let class::MethodImpl::Bytecode(_) = &method.code{
+        if let Err(soe) = push_frame(frames, class.clone(), m_idx, args){
+            return throw(frames, outer, soe);
+        }
+        frames.last_mut().unwrap().return_as = return_as;
+        return None;
+    }
+    let value = match execute(&class, method, args, frames_trace(outer, frames)){
+        MethodResult::FinishWithValue(v) => Some(v),
+        MethodResult::Finish => None,
+        MethodResult::ThrowObject(_, exception) => return throw(frames, outer, exception),
+        other => return Some(other)
+    };
+    let value = match &return_as{
+        Some(return_as) => adapt_return(value, method, return_as),
+        None => value
+    };
+    let caller = frames.last_mut().unwrap();
+    if let Some(v) = value{
+        caller.push(v);
+    }
+    caller.pc += 1;
+    return None;
+}
+
+/// Adapts the result of a VarHandle access mode method to its call site, which can discard it or take it boxed.
+fn adapt_return(value: Option<JValue>, method: &Method, return_as: &str) -> Option<JValue>{
+    if return_as == "V"{
+        return None;
+    }
+    let boxed = (return_as.starts_with('L') || return_as.starts_with('[')) && !matches!(value, Some(JValue::Reference(_)));
+    return if boxed{ value.map(|v| objects::box_primitive(v, &method.return_type.descriptor())) }else{ value };
+}
+
+/// Unwinds frames until one has a handler for the exception, which is jumped to.
+/// If none do, returns the result of the whole run.
+fn throw(frames: &mut Vec<Frame>, outer: &StackTrace, exception: JRef) -> Option<MethodResult>{
+    let trace = frames_trace(outer, frames);
+    let exception_obj = exception.deref();
+    while let Some(frame) = frames.last_mut(){
+        if let Some(handler) = frame.handler_for(&exception_obj){
+            frame.stack.clear();
+            frame.push(JValue::Reference(Some(exception)));
+            frame.pc = handler;
+            return None;
+        }
+        pop_frame(frames);
+    }
+    return Some(MethodResult::ThrowObject(trace, exception));
+}
+
+// Frames
+
+/// The default maximum size of a thread's stack, if not given by `-Xss`.
+const DEFAULT_STACK_SIZE: usize = 1024 * 1024;
+
+thread_local!{
+    /// The size of the frames on this thread's stack, across all runs.
+    static STACK_USED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns the maximum size of a thread's stack in bytes, as given by `-Xss` (e.g. `-Xss512k`).
+fn max_stack_size() -> usize{
+    static SIZE: OnceLock<usize> = OnceLock::new();
+    return *SIZE.get_or_init(|| {
+        let mut size = DEFAULT_STACK_SIZE;
+        for op in std::env::args(){
+            if let Some(arg) = op.strip_prefix("-Xss"){
+                size = parse_memory_size(arg).expect("Invalid stack size given by \"-Xss\"");
+            }
+        }
+        return size;
+    });
+}
+
+fn parse_memory_size(size: &str) -> Option<usize>{
+    let (digits, unit) = match size.chars().last()?{
+        'k' | 'K' => (&size[..size.len() - 1], 1024),
+        'm' | 'M' => (&size[..size.len() - 1], 1024 * 1024),
+        'g' | 'G' => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1)
+    };
+    return digits.parse::<usize>().ok()?.checked_mul(unit);
+}
+
+/// Pushes a frame for a bytecode method, or returns a StackOverflowError if there isn't room for it.
+fn push_frame(frames: &mut Vec<Frame>, class: ClassRef, m_idx: usize, args: Vec<JValue>) -> Result<(), JRef>{
+    let class::MethodImpl::Bytecode(code) = &class.methods[m_idx].code else { unreachable!() };
+    let size = Frame::size_for(code);
+    let used = STACK_USED.get() + size;
+    if used > max_stack_size(){
+        return Err(stack_overflow_error());
+    }
+    STACK_USED.set(used);
+    let mut frame = Frame::new(class.clone(), m_idx, code, &args);
+    if class.methods[m_idx].is_synchronized{
+        let lock = sync_object(&class, &class.methods[m_idx], &args);
+        lock.deref().monitor.enter();
+        frame.lock = Some(lock);
+    }
+    frames.push(frame);
+    return Ok(());
+}
+
+/// Pops the top frame, releasing its monitor if it's for a synchronized method, however it finished.
+fn pop_frame(frames: &mut Vec<Frame>){
+    let frame = frames.pop().unwrap();
+    STACK_USED.set(STACK_USED.get() - frame.size);
+    if let Some(lock) = frame.lock{
+        // unbalanced monitorexits could have released it already, which we don't check for
+        let _ = lock.deref().monitor.exit();
+    }
+    if frame.continuation_entry{
+        CONTINUATIONS.with_borrow_mut(|entries| entries.pop());
+    }
+}
+
+/// Returns the object whose monitor a synchronized method holds: the receiver, or the class object if it's static.
+fn sync_object(class: &Class, method: &Method, args: &Vec<JValue>) -> JRef{
+    let lock = if method.is_static{ objects::class_object(&class.descriptor) }else{ args[0] };
+    let JValue::Reference(Some(lock)) = lock else { panic!("Synchronized method called without a receiver") };
+    return lock;
+}
+
+fn stack_overflow_error() -> JRef{
+    let class = heap::get_or_create_bt_class("Ljava/lang/StackOverflowError;".to_owned())
+        .expect("Could not load StackOverflowError")
+        .ensure_initialized()
+        .expect("Could not initialize StackOverflowError");
+    let JValue::Reference(Some(r)) = objects::create_new(class) else { unreachable!() };
+    return r;
+}
+
+/// Builds a stack trace from the frames of a run, on top of the trace of whatever started it.
+fn frames_trace(outer: &StackTrace, frames: &Vec<Frame>) -> StackTrace{
+    let mut trace = outer.clone();
+    for frame in frames{
+        let method = &frame.class.methods[frame.method];
+        let class::MethodImpl::Bytecode(code) = &method.code else { unreachable!() };
+        let line_number = code.bytecode.get(frame.pc)
+            .and_then(|(idx, _)| bytecode_idx_to_line_number(*idx, method));
+        trace.push(StackTraceEntry::new(frame.class.name.clone(), method.name.clone(), line_number));
+    }
+    return trace;
+}
 
-    while i < code.bytecode.len(){
-        let mut was_jump = false;
-        let (idx, instr) = code.bytecode.get(i).unwrap();
+/// A bytecode method invocation: the method, the index of the instruction it's at, and its local variables and
+/// operand stack, preallocated from its Code attribute.
+/// Every value takes up one stack entry; longs and doubles take up two local variable slots, the second being unused.
+struct Frame{
+    class: ClassRef,
+    method: usize,
+    pc: usize,
+    size: usize,
+    lock: Option<JRef>, // the object whose monitor a synchronized method holds
+    continuation_entry: bool, // whether this is the bottom frame of a continuation, for Continuation.enter
+    return_as: Option<String>, // the return descriptor of the VarHandle call site that called this, if any
+    locals: Vec<JValue>,
+    stack: Vec<JValue>
+}
+
+impl Frame{
+    fn new(class: ClassRef, method: usize, code: &Code, args: &Vec<JValue>) -> Self{
+        let mut locals = vec![JValue::Reference(None); code.max_locals as usize];
+        let mut at = 0;
+        for arg in args{
+            locals[at] = *arg;
+            at += if arg.is_wide(){ 2 }else{ 1 };
+        }
+        return Frame{
+            class,
+            method,
+            pc: 0,
+            size: Frame::size_for(code),
+            lock: None,
+            continuation_entry: false,
+            return_as: None,
+            locals,
+            stack: Vec::with_capacity(code.max_stack as usize)
+        };
+    }
+
+    /// Returns how much of the stack a frame for the given code takes up.
+    fn size_for(code: &Code) -> usize{
+        return size_of::<Frame>() + (code.max_locals as usize + code.max_stack as usize) * size_of::<JValue>();
+    }
+
+    /// Returns the index of the instruction handling the given exception at the current instruction, if any.
+    fn handler_for(&self, exception: &JObject) -> Option<usize>{
+        let class::MethodImpl::Bytecode(code) = &self.class.methods[self.method].code else { unreachable!() };
+        for handler in &code.exception_handlers{
+            if self.pc >= handler.start_idx && self.pc < handler.end_idx
+            && handler.catch_type.as_ref().is_none_or(|t| exception.assignable_to(&internal_name_to_desc(t))){
+                return Some(handler.handler_idx);
+            }
+        }
+        return None;
+    }
+
+    fn push(&mut self, value: JValue){
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Option<JValue>{
+        return self.stack.pop();
+    }
+
+    fn pop_int(&mut self) -> Option<i32>{
+        return match self.stack.pop(){ Some(JValue::Int(v)) => Some(v), _ => None };
+    }
+
+    fn pop_long(&mut self) -> Option<i64>{
+        return match self.stack.pop(){ Some(JValue::Long(v)) => Some(v), _ => None };
+    }
+
+    fn pop_float(&mut self) -> Option<f32>{
+        return match self.stack.pop(){ Some(JValue::Float(v)) => Some(v), _ => None };
+    }
+
+    fn pop_double(&mut self) -> Option<f64>{
+        return match self.stack.pop(){ Some(JValue::Double(v)) => Some(v), _ => None };
+    }
+
+    fn pop_ref(&mut self) -> Option<Option<JRef>>{
+        return match self.stack.pop(){ Some(JValue::Reference(v)) => Some(v), _ => None };
+    }
+
+    /// Returns the value the given number of entries below the top of the stack.
+    fn peek(&self, depth: usize) -> Option<&JValue>{
+        return self.stack.len().checked_sub(depth + 1).map(|i| &self.stack[i]);
+    }
+
+    /// Inserts a value below the given number of entries from the top of the stack.
+    fn insert_below(&mut self, depth: usize, value: JValue){
+        self.stack.insert(self.stack.len() - depth, value);
+    }
+
+    /// Returns how many stack entries make up the given number of slots from the top of the stack, if they line up.
+    fn entries_for_slots(&self, slots: usize) -> Option<usize>{
+        let mut taken = 0;
+        let mut entries = 0;
+        while taken < slots{
+            taken += if self.peek(entries)?.is_wide(){ 2 }else{ 1 };
+            entries += 1;
+        }
+        return if taken == slots{ Some(entries) }else{ None };
+    }
+}
+
+/// Runs the top frame until it calls, returns, or throws.
+fn step(frame: &mut Frame) -> Exit{
+    let class = frame.class.clone();
+    let owner: &Class = &class;
+    let method = &owner.methods[frame.method];
+    let class::MethodImpl::Bytecode(code) = &method.code else { unreachable!() };
+    let mut i = frame.pc;
+
+    let exit = loop{
+        let Some((_, instr)) = code.bytecode.get(i) else {
+            break Exit::Error("Reached end of function without return!");
+        };
         match instr{
+            Instruction::Nop => {},
             Instruction::AConstNull => {
-                stack.push_front(JValue::Reference(None));
+                frame.push(JValue::Reference(None));
             },
 
             Instruction::IConst(it) => {
-                stack.push_front(JValue::Int(*it as i32));
+                frame.push(JValue::Int(*it as i32));
             },
             Instruction::LConst(it) => {
-                stack.push_front(JValue::Long(*it as i64));
-                stack.insert(1, JValue::Second);
+                frame.push(JValue::Long(*it as i64));
             },
             Instruction::FConst(it) => {
-                stack.push_front(JValue::Float(*it as f32));
+                frame.push(JValue::Float(*it as f32));
             },
             Instruction::DConst(it) => {
-                stack.push_front(JValue::Double(*it as f64));
-                stack.insert(1, JValue::Second);
+                frame.push(JValue::Double(*it as f64));
             },
 
             Instruction::Ldc(c) => match c{
                 ConstantEntry::Integer(i) => {
-                    stack.push_front(JValue::Int(*i));
+                    frame.push(JValue::Int(*i));
                 },
                 ConstantEntry::Long(l) => {
-                    stack.push_front(JValue::Long(*l));
-                    stack.insert(1, JValue::Second);
+                    frame.push(JValue::Long(*l));
                 },
                 ConstantEntry::Float(f) => {
-                    stack.push_front(JValue::Float(*f));
+                    frame.push(JValue::Float(*f));
                 },
                 ConstantEntry::Double(d) => {
-                    stack.push_front(JValue::Double(*d));
-                    stack.insert(1, JValue::Second);
+                    frame.push(JValue::Double(*d));
                 },
-                ConstantEntry::StringConst(s) => {
-                    stack.push_front(heap::add_ref(objects::synthesize_string(&s)));
-                },
-                ConstantEntry::Class(s) => {
-                    stack.push_front(heap::add_ref(objects::synthesize_class(&internal_name_to_desc(s))));
+                ConstantEntry::StringConst(_) | ConstantEntry::Class(_) => {
+                    match quicken(method, i, || resolve_constant(c, owner).map(class::Quick::Constant)){
+                        Ok(class::Quick::Constant(value)) => frame.push(*value),
+                        Err(e) => break Exit::Throw(e),
+                        _ => unreachable!()
+                    }
                 },
                 _ => { panic!("Possibly unhandled or invalid constant: {:?}", c) }
             }
 
             Instruction::IStore(at) => {
-                if let Some(JValue::Int(value)) = stack.get(0){
-                    let at = *at as usize;
-                    set_and_pad(&mut locals, at, Some(JValue::Int(*value)), None);
-                    stack.remove(0);
+                if let Some(value) = frame.pop_int(){
+                    frame.locals[*at as usize] = JValue::Int(value);
                 }else{
-                    return MethodResult::MachineError("Tried to execute istore without int on top of stack");
+                    break Exit::Error("Tried to execute istore without int on top of stack");
                 }
             },
             Instruction::LStore(at) => {
-                if let Some(JValue::Long(value)) = stack.get(0){
-                    let at = *at as usize;
-                    set_and_pad(&mut locals, at, Some(JValue::Long(*value)), None);
-                    set_and_pad(&mut locals, at + 1, Some(JValue::Second), None);
-                    stack.remove(0); stack.remove(0); // get rid of the Second too
+                if let Some(value) = frame.pop_long(){
+                    frame.locals[*at as usize] = JValue::Long(value);
                 }else{
-                    return MethodResult::MachineError("Tried to execute lstore without long on top of stack");
+                    break Exit::Error("Tried to execute lstore without long on top of stack");
                 }
             },
             Instruction::FStore(at) => {
-                if let Some(JValue::Float(value)) = stack.get(0){
-                    let at = *at as usize;
-                    set_and_pad(&mut locals, at, Some(JValue::Float(*value)), None);
-                    stack.remove(0);
+                if let Some(value) = frame.pop_float(){
+                    frame.locals[*at as usize] = JValue::Float(value);
                 }else{
-                    return MethodResult::MachineError("Tried to execute fstore without float on top of stack");
+                    break Exit::Error("Tried to execute fstore without float on top of stack");
                 }
             },
             Instruction::DStore(at) => {
-                if let Some(JValue::Double(value)) = stack.get(0){
-                    let at = *at as usize;
-                    set_and_pad(&mut locals, at, Some(JValue::Double(*value)), None);
-                    set_and_pad(&mut locals, at + 1, Some(JValue::Second), None);
-                    stack.remove(0); stack.remove(0); // get rid of the Second too
+                if let Some(value) = frame.pop_double(){
+                    frame.locals[*at as usize] = JValue::Double(value);
                 }else{
-                    return MethodResult::MachineError("Tried to execute dstore without double on top of stack");
+                    break Exit::Error("Tried to execute dstore without double on top of stack");
                 }
             },
             Instruction::AStore(at) => {
-                if let Some(JValue::Reference(value)) = stack.get(0){
-                    let at = *at as usize;
-                    set_and_pad(&mut locals, at, Some(JValue::Reference(*value)), None);
-                    stack.remove(0);
-                }else{
-                    return MethodResult::MachineError("Tried to execute astore without reference on top of stack");
+                // subroutines store their return address with astore
+                match frame.pop(){
+                    Some(value @ (JValue::Reference(_) | JValue::ReturnAddress(_))) => frame.locals[*at as usize] = value,
+                    _ => break Exit::Error("Tried to execute astore without reference on top of stack")
                 }
             },
 
-            Instruction::BAStore => {
-                if let Some(JValue::Reference(array_ref)) = stack.get(2)
-                && let Some(JValue::Int(array_idx)) = stack.get(1)
-                && let Some(JValue::Int(value)) = stack.get(0){
-                    let array_ref: &Option<JRef> = array_ref; // fix IDE highlighting
-                    if let Some(array_ref) = array_ref{
-                        let array = array_ref.deref();
-                        if let Ok(mut write) = array.data.write(){
-                            if let JObjectData::Array(size, values) = &mut *write{
-                                if *array_idx < 0 || *array_idx >= (*size as i32){
-                                    return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "index out of bounds");
-                                }
-                                let idx = *array_idx as usize;
-                                set_and_pad(values, idx, JValue::Int(to_byte(*value)), JValue::Int(0));
-                            }
-                        }; //ah. fun.
-                    }else{
-                        return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for BAStore");
-                    }
-
-                    stack.remove(0); stack.remove(0); stack.remove(0);
-                }else{
-                    return MethodResult::MachineError("Tried to execute bastore without array & index & value on top of stack");
-                }
-            },
-            Instruction::CAStore => {
-                if let Some(JValue::Reference(array_ref)) = stack.get(2)
-                && let Some(JValue::Int(array_idx)) = stack.get(1)
-                && let Some(JValue::Int(value)) = stack.get(0){
-                    let array_ref: &Option<JRef> = array_ref; // fix IDE highlighting
-                    if let Some(array_ref) = array_ref{
-                        let array = array_ref.deref();
-                        if let Ok(mut write) = array.data.write(){
-                            if let JObjectData::Array(size, values) = &mut *write{
-                                if *array_idx < 0 || *array_idx >= (*size as i32){
-                                    return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "index out of bounds");
-                                }
-                                let idx = *array_idx as usize;
-                                set_and_pad(values, idx, JValue::Int(to_char(*value)), JValue::Int(0));
-                            }
-                        };
-                    }else{
-                        return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for CAStore");
-                    }
-
-                    stack.remove(0); stack.remove(0); stack.remove(0);
-                }else{
-                    return MethodResult::MachineError("Tried to execute castore without array & index & value on top of stack");
-                }
-            },
-            Instruction::IAStore => {
-                if let Some(JValue::Reference(array_ref)) = stack.get(2)
-                && let Some(JValue::Int(array_idx)) = stack.get(1)
-                && let Some(JValue::Int(value)) = stack.get(0){
-                    let array_ref: &Option<JRef> = array_ref; // fix IDE highlighting
-                    if let Some(array_ref) = array_ref{
-                        let array = array_ref.deref();
-                        if let Ok(mut write) = array.data.write(){
-                            if let JObjectData::Array(size, values) = &mut *write{
-                                if *array_idx < 0 || *array_idx >= (*size as i32){
-                                    return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "index out of bounds");
-                                }
-                                let idx = *array_idx as usize;
-                                set_and_pad(values, idx, JValue::Int(*value), JValue::Int(0));
-                            }
-                        };
-                    }else{
-                        return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for IAstore");
+            Instruction::BAStore | Instruction::CAStore | Instruction::SAStore | Instruction::IAStore => {
+                if let Some(value) = frame.pop_int()
+                && let Some(array_idx) = frame.pop_int()
+                && let Some(array_ref) = frame.pop_ref(){
+                    // narrowed to the array's element type when stored
+                    if let Err(e) = array_store(array_ref, array_idx, JValue::Int(value), "NPE for _AStore"){
+                        break Exit::Throw(e);
                     }
-
-                    stack.remove(0); stack.remove(0); stack.remove(0);
                 }else{
-                    return MethodResult::MachineError("Tried to execute iastore without array & index & value on top of stack");
+                    break Exit::Error("Tried to execute _astore without array & index & int on top of stack");
                 }
             },
-            Instruction::AAStore => {
-                if let Some(JValue::Reference(array_ref)) = stack.get(2)
-                && let Some(JValue::Int(array_idx)) = stack.get(1)
-                && let Some(JValue::Reference(value)) = stack.get(0){
-                    let array_ref: &Option<JRef> = array_ref; // fix IDE highlighting
-                    if let Some(array_ref) = array_ref{
-                        let array = array_ref.deref();
-                        if let Ok(mut write) = array.data.write(){
-                            if let JObjectData::Array(size, values) = &mut *write{
-                                if *array_idx < 0 || *array_idx >= (*size as i32){
-                                    return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "index out of bounds");
-                                }
-                                let idx = *array_idx as usize;
-                                set_and_pad(values, idx, JValue::Reference(*value), JValue::Reference(None));
-                            }
-                        };
-                    }else{
-                        return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for AAStore");
+            Instruction::LAStore | Instruction::FAStore | Instruction::DAStore | Instruction::AAStore => {
+                if let Some(value) = frame.pop()
+                && let Some(array_idx) = frame.pop_int()
+                && let Some(array_ref) = frame.pop_ref(){
+                    if let Err(e) = array_store(array_ref, array_idx, value, "NPE for _AStore"){
+                        break Exit::Throw(e);
                     }
-
-                    stack.remove(0); stack.remove(0); stack.remove(0);
                 }else{
-                    return MethodResult::MachineError("Tried to execute aastore without array & index & value on top of stack");
+                    break Exit::Error("Tried to execute _astore without array & index & value on top of stack");
                 }
             },
 
             Instruction::ILoad(at) => {
-                if let Some(Some(JValue::Int(value))) = locals.get(*at as usize){
-                    stack.push_front(JValue::Int(*value));
+                if let JValue::Int(value) = frame.locals[*at as usize]{
+                    frame.push(JValue::Int(value));
                 }else{
-                    return MethodResult::MachineError("Tried to execute iload without int at local variable index");
+                    break Exit::Error("Tried to execute iload without int at local variable index");
                 }
             },
             Instruction::LLoad(at) => {
-                if let Some(Some(JValue::Long(value))) = locals.get(*at as usize){
-                    stack.push_front(JValue::Long(*value));
-                    stack.insert(1, JValue::Second);
+                if let JValue::Long(value) = frame.locals[*at as usize]{
+                    frame.push(JValue::Long(value));
                 }else{
-                    return MethodResult::MachineError("Tried to execute lload without long at local variable index");
+                    break Exit::Error("Tried to execute lload without long at local variable index");
                 }
             },
             Instruction::FLoad(at) => {
-                if let Some(Some(JValue::Float(value))) = locals.get(*at as usize){
-                    stack.push_front(JValue::Float(*value));
+                if let JValue::Float(value) = frame.locals[*at as usize]{
+                    frame.push(JValue::Float(value));
                 }else{
-                    return MethodResult::MachineError("Tried to execute fload without int at local variable index");
+                    break Exit::Error("Tried to execute fload without float at local variable index");
                 }
             },
             Instruction::DLoad(at) => {
-                if let Some(Some(JValue::Double(value))) = locals.get(*at as usize){
-                    stack.push_front(JValue::Double(*value));
-                    stack.insert(1, JValue::Second);
+                if let JValue::Double(value) = frame.locals[*at as usize]{
+                    frame.push(JValue::Double(value));
                 }else{
-                    return MethodResult::MachineError("Tried to execute dload without long at local variable index");
+                    break Exit::Error("Tried to execute dload without double at local variable index");
                 }
             },
             Instruction::ALoad(at) => {
-                if let Some(Some(JValue::Reference(value))) = locals.get(*at as usize){
-                    stack.push_front(JValue::Reference(*value));
+                if let JValue::Reference(value) = frame.locals[*at as usize]{
+                    frame.push(JValue::Reference(value));
                 }else{
-                    println!("locals: {:?}, in {}.{}, args: {:?}", &locals, &owner.name, &method.name, &args);
-                    return MethodResult::MachineError("Tried to execute aload without reference at local variable index");
+                    println!("locals: {:?}, in {}.{}", &frame.locals, &owner.name, &method.name);
+                    break Exit::Error("Tried to execute aload without reference at local variable index");
                 }
             },
 
             // TODO: validate array type
-            Instruction::BALoad | Instruction::AALoad | Instruction::CALoad => {
-                if let Some(JValue::Reference(array_ref)) = stack.get(1)
-                && let Some(JValue::Int(array_idx)) = stack.get(0){
-                    let array_ref: &Option<JRef> = array_ref; // fix IDE highlighting
-                    if let Some(array_ref) = array_ref{
-                        let array = array_ref.deref();
-                        if let Ok(read) = array.data.read(){
-                            if let JObjectData::Array(size, values) = &*read{
-                                if *array_idx < 0 || *array_idx >= (*size as i32){
-                                    return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "index out of bounds");
-                                }
-                                let idx = *array_idx as usize;
-                                stack.push_front(values[idx].clone());
-                            }
-                        };
-                    }else{
-                        return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for _ALoad");
+            Instruction::IALoad | Instruction::LALoad | Instruction::FALoad | Instruction::DALoad
+            | Instruction::AALoad | Instruction::BALoad | Instruction::CALoad | Instruction::SALoad => {
+                if let Some(array_idx) = frame.pop_int()
+                && let Some(array_ref) = frame.pop_ref(){
+                    match array_load(array_ref, array_idx, "NPE for _ALoad"){
+                        Ok(value) => frame.push(value),
+                        Err(e) => break Exit::Throw(e)
                     }
-
-                    stack.remove(1); stack.remove(1); // don't remove what we just loaded
                 }else{
-                    return MethodResult::MachineError("Tried to execute _aload without array & index on top of stack");
+                    break Exit::Error("Tried to execute _aload without array & index on top of stack");
                 }
             },
 
+            // stack manipulation works on slots, where longs and doubles take up two
             Instruction::Pop => {
-                stack.remove(0);
+                frame.pop();
+            },
+            Instruction::Pop2 => {
+                if let Some(entries) = frame.entries_for_slots(2){
+                    frame.stack.truncate(frame.stack.len() - entries);
+                }else{
+                    break Exit::Error("Tried to execute pop2 with insufficient stack");
+                }
             },
             Instruction::Dup => {
-                if let Some(value) = stack.get(0){
-                    stack.push_front(value.clone());
+                if let Some(value) = frame.peek(0){
+                    frame.push(*value);
                 }else{
-                    return MethodResult::MachineError("Tried to execute dup with empty stack");
+                    break Exit::Error("Tried to execute dup with empty stack");
                 }
             },
             Instruction::DupX1 => {
-                if let Some(value) = stack.get(0){
-                    stack.insert(2, value.clone());
+                if let Some(value) = frame.peek(0).copied()
+                && frame.stack.len() >= 2{
+                    frame.insert_below(2, value);
                 }else{
-                    return MethodResult::MachineError("Tried to execute dup_x1 with empty stack");
+                    break Exit::Error("Tried to execute dup_x1 with insufficient stack");
                 }
             },
             Instruction::DupX2 => {
-                if let Some(value) = stack.get(0){
-                    stack.insert(3, value.clone());
+                if let Some(value) = frame.peek(0).copied()
+                && let Some(below) = frame.entries_for_slots(3){
+                    frame.insert_below(below, value);
+                }else{
+                    break Exit::Error("Tried to execute dup_x2 with insufficient stack");
+                }
+            },
+            Instruction::Dup2 | Instruction::Dup2X1 | Instruction::Dup2X2 => {
+                let under = match instr{
+                    Instruction::Dup2 => 0,
+                    Instruction::Dup2X1 => 1,
+                    _ => 2
+                };
+                if let Some(entries) = frame.entries_for_slots(2)
+                && let Some(below) = frame.entries_for_slots(2 + under){
+                    let values: Vec<JValue> = frame.stack[frame.stack.len() - entries..].to_vec();
+                    let at = frame.stack.len() - below;
+                    frame.stack.splice(at..at, values);
                 }else{
-                    return MethodResult::MachineError("Tried to execute dup_x1 with empty stack");
+                    break Exit::Error("Tried to execute dup2* with insufficient stack");
                 }
             },
-            Instruction::Dup2 => {
-                if let Some(value1) = stack.get(0)
-                && let Some(value2) = stack.get(1){
-                    let v1 = value1.clone(); // let the immutable borrow end before mutably borrowing
-                    let v2 = value2.clone();
-                    stack.push_front(v2.clone());
-                    stack.push_front(v1.clone());
+            Instruction::Swap => {
+                let len = frame.stack.len();
+                if len >= 2{
+                    frame.stack.swap(len - 1, len - 2);
                 }else{
-                    return MethodResult::MachineError("Tried to execute dup2 with insufficient stack");
+                    break Exit::Error("Tried to execute swap with insufficient stack");
                 }
             },
 
             // TODO: merge into one match arm (instr?) and match on the instruction inside
             Instruction::IAdd => {
-                if let Some(JValue::Int(l)) = stack.get(0)
-                && let Some(JValue::Int(r)) = stack.get(1){
-                    let (val, _) = i32::overflowing_add(*l, *r);
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(value1.wrapping_add(value2)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute iadd without two ints on top of stack");
+                    break Exit::Error("Tried to execute iadd without two ints on top of stack");
                 }
             },
             Instruction::ISub => {
-                if let Some(JValue::Int(l)) = stack.get(0)
-                && let Some(JValue::Int(r)) = stack.get(1){
-                    let (val, _) = i32::overflowing_add(-*l, *r); // value2 - value1
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(value1.wrapping_sub(value2)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute isub without two ints on top of stack");
+                    break Exit::Error("Tried to execute isub without two ints on top of stack");
                 }
             },
             Instruction::IMul => {
-                if let Some(JValue::Int(l)) = stack.get(0)
-                && let Some(JValue::Int(r)) = stack.get(1){
-                    let (val, _) = i32::overflowing_mul(*l, *r);
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(value1.wrapping_mul(value2)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute imul without two ints on top of stack");
+                    break Exit::Error("Tried to execute imul without two ints on top of stack");
                 }
             },
             Instruction::IDiv => {
-                if let Some(JValue::Int(value2)) = stack.get(0)
-                && let Some(JValue::Int(value1)) = stack.get(1){
-                    let val = *value1 / *value2;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    if value2 == 0{
+                        break Exit::Throw("ArithmeticException");
+                    }
+                    frame.push(JValue::Int(value1.wrapping_div(value2)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute idiv without two ints on top of stack");
+                    break Exit::Error("Tried to execute idiv without two ints on top of stack");
                 }
             },
             Instruction::IRem => {
-                if let Some(JValue::Int(value2)) = stack.get(0)
-                && let Some(JValue::Int(value1)) = stack.get(1){
-                    let val = value1 - (value1 / value2) * value2; // JVMS
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    if value2 == 0{
+                        break Exit::Throw("ArithmeticException");
+                    }
+                    frame.push(JValue::Int(value1.wrapping_rem(value2)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute irem without two ints on top of stack");
+                    break Exit::Error("Tried to execute irem without two ints on top of stack");
                 }
             },
             Instruction::INeg => {
-                if let Some(JValue::Int(l)) = stack.get(0){
-                    let val = -*l;
-                    stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value) = frame.pop_int(){
+                    frame.push(JValue::Int(value.wrapping_neg()));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ineg without int on top of stack");
+                    break Exit::Error("Tried to execute ineg without int on top of stack");
                 }
             },
             Instruction::IShl => {
-                if let Some(JValue::Int(value2)) = stack.get(0)
-                && let Some(JValue::Int(value1)) = stack.get(1){
-                    let val = *value1 << (*value2 & 0b00011111);
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(value1 << (value2 & 0b00011111)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ishl without two ints on top of stack");
+                    break Exit::Error("Tried to execute ishl without two ints on top of stack");
                 }
             },
             Instruction::IShr => {
-                if let Some(JValue::Int(value2)) = stack.get(0)
-                && let Some(JValue::Int(value1)) = stack.get(1){
-                    let val = *value1 >> (*value2 & 0b00011111);
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(value1 >> (value2 & 0b00011111)));
                 }else{
-                    dbg!(&stack);
-                    return MethodResult::MachineError("Tried to execute ishr without two ints on top of stack");
+                    break Exit::Error("Tried to execute ishr without two ints on top of stack");
                 }
             },
             Instruction::IUshr => {
-                if let Some(JValue::Int(value2)) = stack.get(0)
-                && let Some(JValue::Int(value1)) = stack.get(1){
-                    let val = ((*value1 as u32) >> ((*value2 & 0b00011111) as u32)) as i32;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(((value1 as u32) >> ((value2 & 0b00011111) as u32)) as i32));
                 }else{
-                    return MethodResult::MachineError("Tried to execute iushr without two ints on top of stack");
+                    break Exit::Error("Tried to execute iushr without two ints on top of stack");
                 }
             },
             Instruction::IAnd => {
-                if let Some(JValue::Int(l)) = stack.get(0)
-                && let Some(JValue::Int(r)) = stack.get(1){
-                    let val = *l & *r;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(value1 & value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute iand without two ints on top of stack");
+                    break Exit::Error("Tried to execute iand without two ints on top of stack");
                 }
             },
             Instruction::IOr => {
-                if let Some(JValue::Int(l)) = stack.get(0)
-                && let Some(JValue::Int(r)) = stack.get(1){
-                    let val = *l | *r;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(value1 | value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ior without two ints on top of stack");
+                    break Exit::Error("Tried to execute ior without two ints on top of stack");
                 }
             },
             Instruction::IXor => {
-                if let Some(JValue::Int(l)) = stack.get(0)
-                && let Some(JValue::Int(r)) = stack.get(1){
-                    let val = *l ^ *r;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    frame.push(JValue::Int(value1 ^ value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ixor without two ints on top of stack");
+                    break Exit::Error("Tried to execute ixor without two ints on top of stack");
                 }
             },
 
             Instruction::LAdd => {
-                if let Some(JValue::Long(l)) = stack.get(0)
-                && let Some(JValue::Long(r)) = stack.get(2){
-                    let val = *l + *r;
-                    stack.remove(0); stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_long()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(value1.wrapping_add(value2)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ladd without two longs on top of stack");
+                    break Exit::Error("Tried to execute ladd without two longs on top of stack");
                 }
             },
             Instruction::LSub => {
-                if let Some(JValue::Long(value2)) = stack.get(0)
-                && let Some(JValue::Long(value1)) = stack.get(2){
-                    let val = *value1 - *value2;
-                    stack.remove(0); stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_long()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(value1.wrapping_sub(value2)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute lsub without two longs on top of stack");
+                    break Exit::Error("Tried to execute lsub without two longs on top of stack");
                 }
             },
             Instruction::LMul => {
-                if let Some(JValue::Long(value2)) = stack.get(0)
-                && let Some(JValue::Long(value1)) = stack.get(2){
-                    let (val, _) = i64::overflowing_mul(*value1, *value2);
-                    stack.remove(0); stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_long()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(value1.wrapping_mul(value2)));
+                }else{
+                    break Exit::Error("Tried to execute lmul without two longs on top of stack");
+                }
+            },
+            Instruction::LDiv => {
+                if let Some(value2) = frame.pop_long()
+                && let Some(value1) = frame.pop_long(){
+                    if value2 == 0{
+                        break Exit::Throw("ArithmeticException");
+                    }
+                    frame.push(JValue::Long(value1.wrapping_div(value2)));
+                }else{
+                    break Exit::Error("Tried to execute ldiv without two longs on top of stack");
+                }
+            },
+            Instruction::LRem => {
+                if let Some(value2) = frame.pop_long()
+                && let Some(value1) = frame.pop_long(){
+                    if value2 == 0{
+                        break Exit::Throw("ArithmeticException");
+                    }
+                    frame.push(JValue::Long(value1.wrapping_rem(value2)));
+                }else{
+                    break Exit::Error("Tried to execute lrem without two longs on top of stack");
+                }
+            },
+            Instruction::LNeg => {
+                if let Some(value) = frame.pop_long(){
+                    frame.push(JValue::Long(value.wrapping_neg()));
                 }else{
-                    return MethodResult::MachineError("Tried to execute lmul without two longs on top of stack");
+                    break Exit::Error("Tried to execute lneg without a long on top of stack");
                 }
             },
             Instruction::LShl => {
-                if let Some(JValue::Int(value2)) = stack.get(0)
-                && let Some(JValue::Long(value1)) = stack.get(1){
-                    let val = *value1 << (*value2 & 0b00111111);
-                    stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(value1 << (value2 & 0b00111111)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute lshl without int+long on top of stack");
+                    break Exit::Error("Tried to execute lshl without int+long on top of stack");
                 }
             },
             Instruction::LShr => {
-                if let Some(JValue::Int(value2)) = stack.get(0)
-                && let Some(JValue::Long(value1)) = stack.get(1){
-                    let val = *value1 >> (*value2 & 0b00111111);
-                    stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(value1 >> (value2 & 0b00111111)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute lshl without int+long on top of stack");
+                    break Exit::Error("Tried to execute lshr without int+long on top of stack");
                 }
             },
             Instruction::LUshr => {
-                if let Some(JValue::Int(value2)) = stack.get(0)
-                && let Some(JValue::Long(value1)) = stack.get(1){
-                    let val = ((*value1 as u64) >> ((*value2 & 0b00111111) as u64)) as i64;
-                    stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(((value1 as u64) >> ((value2 & 0b00111111) as u64)) as i64));
                 }else{
-                    return MethodResult::MachineError("Tried to execute lshl without int+long on top of stack");
+                    break Exit::Error("Tried to execute lushr without int+long on top of stack");
                 }
             },
             Instruction::LAnd => {
-                if let Some(JValue::Long(value2)) = stack.get(0)
-                && let Some(JValue::Long(value1)) = stack.get(2){
-                    let val = *value1 & *value2;
-                    stack.remove(0); stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_long()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(value1 & value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute land without two longs on top of stack");
+                    break Exit::Error("Tried to execute land without two longs on top of stack");
                 }
             },
             Instruction::LOr => {
-                if let Some(JValue::Long(value2)) = stack.get(0)
-                && let Some(JValue::Long(value1)) = stack.get(2){
-                    let val = *value1 | *value2;
-                    stack.remove(0); stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_long()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(value1 | value2));
+                }else{
+                    break Exit::Error("Tried to execute lor without two longs on top of stack");
+                }
+            },
+            Instruction::LXor => {
+                if let Some(value2) = frame.pop_long()
+                && let Some(value1) = frame.pop_long(){
+                    frame.push(JValue::Long(value1 ^ value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute land without two longs on top of stack");
+                    break Exit::Error("Tried to execute lxor without two longs on top of stack");
                 }
             },
 
             Instruction::FAdd => {
-                if let Some(JValue::Float(l)) = stack.get(0)
-                && let Some(JValue::Float(r)) = stack.get(1){
-                    let val = *l + *r;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Float(val));
+                if let Some(value2) = frame.pop_float()
+                && let Some(value1) = frame.pop_float(){
+                    frame.push(JValue::Float(value1 + value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute fadd without two floats on top of stack");
+                    break Exit::Error("Tried to execute fadd without two floats on top of stack");
                 }
             },
             Instruction::FMul => {
-                if let Some(JValue::Float(l)) = stack.get(0)
-                && let Some(JValue::Float(r)) = stack.get(1){
-                    let val = *l * *r;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Float(val));
+                if let Some(value2) = frame.pop_float()
+                && let Some(value1) = frame.pop_float(){
+                    frame.push(JValue::Float(value1 * value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute fmul without two floats on top of stack");
+                    break Exit::Error("Tried to execute fmul without two floats on top of stack");
                 }
             },
             Instruction::FSub => {
-                if let Some(JValue::Float(value2)) = stack.get(0)
-                && let Some(JValue::Float(value1)) = stack.get(1){
-                    let val = *value1 - *value2;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Float(val));
+                if let Some(value2) = frame.pop_float()
+                && let Some(value1) = frame.pop_float(){
+                    frame.push(JValue::Float(value1 - value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute fdiv without two floats on top of stack");
+                    break Exit::Error("Tried to execute fsub without two floats on top of stack");
                 }
             },
             Instruction::FDiv => {
-                if let Some(JValue::Float(value2)) = stack.get(0)
-                && let Some(JValue::Float(value1)) = stack.get(1){
-                    let val = *value1 / *value2;
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Float(val));
+                if let Some(value2) = frame.pop_float()
+                && let Some(value1) = frame.pop_float(){
+                    frame.push(JValue::Float(value1 / value2));
+                }else{
+                    break Exit::Error("Tried to execute fdiv without two floats on top of stack");
+                }
+            },
+            Instruction::FRem => {
+                if let Some(value2) = frame.pop_float()
+                && let Some(value1) = frame.pop_float(){
+                    frame.push(JValue::Float(value1 % value2));
+                }else{
+                    break Exit::Error("Tried to execute frem without two floats on top of stack");
+                }
+            },
+            Instruction::FNeg => {
+                if let Some(value) = frame.pop_float(){
+                    frame.push(JValue::Float(-value));
                 }else{
-                    return MethodResult::MachineError("Tried to execute fdiv without two floats on top of stack");
+                    break Exit::Error("Tried to execute fneg without a float on top of stack");
                 }
             },
 
             Instruction::DAdd => {
-                if let Some(JValue::Double(l)) = stack.get(0)
-                && let Some(JValue::Double(r)) = stack.get(2){
-                    let val = *l + *r;
-                    stack.remove(0); stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Double(val));
-                    stack.insert(1, JValue::Second);
+                if let Some(value2) = frame.pop_double()
+                && let Some(value1) = frame.pop_double(){
+                    frame.push(JValue::Double(value1 + value2));
                 }else{
-                    return MethodResult::MachineError("Tried to execute dadd without two doubles on top of stack");
+                    break Exit::Error("Tried to execute dadd without two doubles on top of stack");
+                }
+            },
+            Instruction::DSub => {
+                if let Some(value2) = frame.pop_double()
+                && let Some(value1) = frame.pop_double(){
+                    frame.push(JValue::Double(value1 - value2));
+                }else{
+                    break Exit::Error("Tried to execute dsub without two doubles on top of stack");
+                }
+            },
+            Instruction::DMul => {
+                if let Some(value2) = frame.pop_double()
+                && let Some(value1) = frame.pop_double(){
+                    frame.push(JValue::Double(value1 * value2));
+                }else{
+                    break Exit::Error("Tried to execute dmul without two doubles on top of stack");
+                }
+            },
+            Instruction::DDiv => {
+                if let Some(value2) = frame.pop_double()
+                && let Some(value1) = frame.pop_double(){
+                    frame.push(JValue::Double(value1 / value2));
+                }else{
+                    break Exit::Error("Tried to execute ddiv without two doubles on top of stack");
+                }
+            },
+            Instruction::DRem => {
+                if let Some(value2) = frame.pop_double()
+                && let Some(value1) = frame.pop_double(){
+                    frame.push(JValue::Double(value1 % value2));
+                }else{
+                    break Exit::Error("Tried to execute drem without two doubles on top of stack");
+                }
+            },
+            Instruction::DNeg => {
+                if let Some(value) = frame.pop_double(){
+                    frame.push(JValue::Double(-value));
+                }else{
+                    break Exit::Error("Tried to execute dneg without a double on top of stack");
                 }
             },
 
             Instruction::IInc(at, inc) => {
-                if let Some(Some(JValue::Int(value))) = locals.get(*at as usize){
-                    let at = *at as usize;
-                    let new_value = *value + *inc as i32;
-                    set_and_pad(&mut locals, at, Some(JValue::Int(new_value)), None);
+                if let JValue::Int(value) = frame.locals[*at as usize]{
+                    frame.locals[*at as usize] = JValue::Int(value.wrapping_add(*inc as i32));
                 }
             }
 
-            Instruction::Goto(offset) => {
-                let target = (*idx as isize) + (*offset as isize);
-                if target < 0{
-                    panic!("Bad goto offset");
+            Instruction::Goto(target) => {
+                i = *target;
+                continue;
+            },
+            Instruction::Jsr(target) => {
+                frame.push(JValue::ReturnAddress(i + 1));
+                i = *target;
+                continue;
+            },
+            Instruction::Ret(at) => {
+                if let JValue::ReturnAddress(ret) = frame.locals[*at as usize]{
+                    i = ret;
+                    continue;
                 }
-                i = bytecode_idx_to_instr_idx(target as usize, code);
-                was_jump = true;
+                break Exit::Error("Tried to execute ret without return address at local variable index");
             },
 
-            Instruction::LookupSwitch(default, offsets) => {
-                let val = stack.pop_front();
-                if let Some(JValue::Int(selector)) = val{
-                    let mut target_offset = default;
-                    for (value, offset) in offsets{
-                        if *value == selector{
-                            target_offset = offset;
-                        }
-                    }
-                    let target = (*idx as isize) + (*target_offset as isize);
-                    if target < 0{
-                        panic!("Bad goto offset");
-                    }
-                    i = bytecode_idx_to_instr_idx(target as usize, code);
-                    was_jump = true;
+            Instruction::LookupSwitch(default, targets) => {
+                if let Some(selector) = frame.pop_int(){
+                    i = targets.iter()
+                        .find(|(value, _)| *value == selector)
+                        .map(|(_, target)| *target)
+                        .unwrap_or(*default);
+                    continue;
                 }else{
-                    return MethodResult::MachineError("Tried to execute lookupswitch without int on top of stack!");
+                    break Exit::Error("Tried to execute lookupswitch without int on top of stack!");
                 }
             },
-            Instruction::TableSwitch(default, lo, hi, jumps) => {
-                let val = stack.pop_front();
-                if let Some(JValue::Int(selector)) = val{
-                    let mut target_offset = *default;
-                    if selector >= *lo && selector <= *hi{
-                        target_offset = jumps[(selector - *lo) as usize];
-                    }
-                    let target = (*idx as isize) + (target_offset as isize);
-                    if target < 0{
-                        panic!("Bad goto offset");
-                    }
-                    i = bytecode_idx_to_instr_idx(target as usize, code);
-                    was_jump = true;
+            Instruction::TableSwitch(default, lo, hi, targets) => {
+                if let Some(selector) = frame.pop_int(){
+                    i = if selector >= *lo && selector <= *hi{
+                        targets[(selector - *lo) as usize]
+                    }else{
+                        *default
+                    };
+                    continue;
                 }else{
-                    return MethodResult::MachineError("Tried to execute tableswitch without int on top of stack!");
+                    break Exit::Error("Tried to execute tableswitch without int on top of stack!");
                 }
             }
-            
+
             Instruction::LCmp => {
-                if let Some(JValue::Long(val2)) = stack.get(0)
-                && let Some(JValue::Long(val1)) = stack.get(2){
+                if let Some(val2) = frame.pop_long()
+                && let Some(val1) = frame.pop_long(){
                     let val = if val1 == val2{ 0 }
                         else if val1 > val2{ 1 }
                         else{ -1 };
-                    stack.remove(0); stack.remove(0); stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+                    frame.push(JValue::Int(val));
                 }else{
-                    return MethodResult::MachineError("Tried to execute lcmp without two longs on top of stack");
+                    break Exit::Error("Tried to execute lcmp without two longs on top of stack");
                 }
             },
             Instruction::FCmpL | Instruction::FCmpG => {
-                if let Some(JValue::Float(val2)) = stack.get(0)
-                && let Some(JValue::Float(val1)) = stack.get(1){
+                if let Some(val2) = frame.pop_float()
+                && let Some(val1) = frame.pop_float(){
                     let val = if val1 == val2{ 0 }
                         else if val1 > val2{ 1 }
                         else if val1 < val2{ -1 }
@@ -737,734 +1027,492 @@ pub fn interpret(owner: &Class, method: &Method, args: Vec<JValue>, code: &Code,
                             if *instr == Instruction::FCmpG{ 1 }
                             else{ -1 }
                         };
-                    stack.remove(0); stack.remove(0);
-                    stack.push_front(JValue::Int(val));
-                }else{
-                    return MethodResult::MachineError("Tried to execute fcmp* without two floats on top of stack");
-                }
-            },
-            
-            Instruction::IfEq(offset) => {
-                if let Some(JValue::Int(value)) = stack.remove(0){
-                    if value == 0{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
-                }else{
-                    return MethodResult::MachineError("Tried to execute ifeq without int on top of stack");
-                }
-            },
-            Instruction::IfNe(offset) => {
-                if let Some(JValue::Int(value)) = stack.remove(0){
-                    if value != 0{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
-                }else{
-                    return MethodResult::MachineError("Tried to execute ifeq without int on top of stack");
-                }
-            },
-            Instruction::IfLt(offset) => {
-                if let Some(JValue::Int(value)) = stack.remove(0){
-                    if value < 0{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
-                }else{
-                    return MethodResult::MachineError("Tried to execute iflt without int on top of stack");
-                }
-            },
-            Instruction::IfGe(offset) => {
-                if let Some(JValue::Int(value)) = stack.remove(0){
-                    if value >= 0{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
+                    frame.push(JValue::Int(val));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ifge without int on top of stack");
+                    break Exit::Error("Tried to execute fcmp* without two floats on top of stack");
                 }
             },
-            Instruction::IfGt(offset) => {
-                if let Some(JValue::Int(value)) = stack.remove(0){
-                    if value > 0{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
-                }else{
-                    return MethodResult::MachineError("Tried to execute ifgt without int on top of stack");
-                }
-            },
-            Instruction::IfLe(offset) => {
-                if let Some(JValue::Int(value)) = stack.remove(0){
-                    if value <= 0{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
+            Instruction::DCmpL | Instruction::DCmpG => {
+                if let Some(val2) = frame.pop_double()
+                && let Some(val1) = frame.pop_double(){
+                    let val = if val1 == val2{ 0 }
+                        else if val1 > val2{ 1 }
+                        else if val1 < val2{ -1 }
+                        else{
+                            if *instr == Instruction::DCmpG{ 1 }
+                            else{ -1 }
+                        };
+                    frame.push(JValue::Int(val));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ifle without int on top of stack");
+                    break Exit::Error("Tried to execute dcmp* without two doubles on top of stack");
                 }
             },
 
-            Instruction::IfICmpEq(offset) => {
-                if let Some(JValue::Int(value2)) = stack.remove(0)
-                && let Some(JValue::Int(value1)) = stack.remove(0){
-                    if value1 == value2{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
+            Instruction::IfEq(target) | Instruction::IfNe(target) | Instruction::IfLt(target)
+            | Instruction::IfGe(target) | Instruction::IfGt(target) | Instruction::IfLe(target) => {
+                if let Some(value) = frame.pop_int(){
+                    let jump = match instr{
+                        Instruction::IfEq(_) => value == 0,
+                        Instruction::IfNe(_) => value != 0,
+                        Instruction::IfLt(_) => value < 0,
+                        Instruction::IfGe(_) => value >= 0,
+                        Instruction::IfGt(_) => value > 0,
+                        _ => value <= 0
+                    };
+                    if jump{
+                        i = *target;
+                        continue;
                     }
                 }else{
-                    return MethodResult::MachineError("Tried to execute if_icmpeq without int on top of stack");
-                }
-            },
-            Instruction::IfICmpNe(offset) => {
-                if let Some(JValue::Int(value2)) = stack.remove(0)
-                && let Some(JValue::Int(value1)) = stack.remove(0){
-                    if value1 != value2{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
+                    break Exit::Error("Tried to execute if<cond> without int on top of stack");
+                }
+            },
+            Instruction::IfICmpEq(target) | Instruction::IfICmpNe(target) | Instruction::IfICmpLt(target)
+            | Instruction::IfICmpGe(target) | Instruction::IfICmpGt(target) | Instruction::IfICmpLe(target) => {
+                if let Some(value2) = frame.pop_int()
+                && let Some(value1) = frame.pop_int(){
+                    let jump = match instr{
+                        Instruction::IfICmpEq(_) => value1 == value2,
+                        Instruction::IfICmpNe(_) => value1 != value2,
+                        Instruction::IfICmpLt(_) => value1 < value2,
+                        Instruction::IfICmpGe(_) => value1 >= value2,
+                        Instruction::IfICmpGt(_) => value1 > value2,
+                        _ => value1 <= value2
+                    };
+                    if jump{
+                        i = *target;
+                        continue;
                     }
                 }else{
-                    return MethodResult::MachineError("Tried to execute if_icmpne without int on top of stack");
+                    break Exit::Error("Tried to execute if_icmp<cond> without two ints on top of stack");
                 }
             },
-            Instruction::IfICmpLt(offset) => {
-                if let Some(JValue::Int(value2)) = stack.remove(0)
-                && let Some(JValue::Int(value1)) = stack.remove(0){
-                    if value1 < value2{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
+            Instruction::IfACmpEq(target) | Instruction::IfACmpNe(target) => {
+                if let Some(value2) = frame.pop_ref()
+                && let Some(value1) = frame.pop_ref(){
+                    let jump = if let Instruction::IfACmpEq(_) = instr{ value1 == value2 }else{ value1 != value2 };
+                    if jump{
+                        i = *target;
+                        continue;
                     }
                 }else{
-                    return MethodResult::MachineError("Tried to execute if_icmpeq without int on top of stack");
+                    break Exit::Error("Tried to execute if_acmp<cond> without two refs on top of stack");
                 }
             },
-            Instruction::IfICmpGe(offset) => {
-                if let Some(JValue::Int(value2)) = stack.remove(0)
-                && let Some(JValue::Int(value1)) = stack.remove(0){
-                    if value1 >= value2{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
+            Instruction::IfNull(target) | Instruction::IfNonnull(target) => {
+                if let Some(r) = frame.pop_ref(){
+                    let jump = if let Instruction::IfNull(_) = instr{ r.is_none() }else{ r.is_some() };
+                    if jump{
+                        i = *target;
+                        continue;
                     }
                 }else{
-                    return MethodResult::MachineError("Tried to execute if_icmpge without int on top of stack");
+                    break Exit::Error("Tried to execute ifnull/ifnonnull without reference on top of stack");
                 }
             },
-            Instruction::IfICmpGt(offset) => {
-                if let Some(JValue::Int(value2)) = stack.remove(0)
-                && let Some(JValue::Int(value1)) = stack.remove(0){
-                    if value1 > value2{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
+
+            Instruction::I2L => {
+                if let Some(value) = frame.pop_int(){
+                    frame.push(JValue::Long(value as i64));
                 }else{
-                    return MethodResult::MachineError("Tried to execute if_icmpeq without int on top of stack");
+                    break Exit::Error("Tried to execute i2l without int on top of stack");
                 }
             },
-            Instruction::IfICmpLe(offset) => {
-                if let Some(JValue::Int(value2)) = stack.remove(0)
-                && let Some(JValue::Int(value1)) = stack.remove(0){
-                    if value1 <= value2{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
+            Instruction::I2F => {
+                if let Some(value) = frame.pop_int(){
+                    frame.push(JValue::Float(value as f32));
                 }else{
-                    return MethodResult::MachineError("Tried to execute if_icmpeq without int on top of stack");
+                    break Exit::Error("Tried to execute i2f without int on top of stack");
                 }
             },
-
-            Instruction::IfACmpEq(offset) => {
-                if let Some(JValue::Reference(value2)) = stack.remove(0)
-                && let Some(JValue::Reference(value1)) = stack.remove(0){
-                    if value1 == value2{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
+            Instruction::I2D => {
+                if let Some(value) = frame.pop_int(){
+                    frame.push(JValue::Double(value as f64));
                 }else{
-                    return MethodResult::MachineError("Tried to execute if_acmpeq without two refs on top of stack");
+                    break Exit::Error("Tried to execute i2d without an int on top of stack");
                 }
             },
-            Instruction::IfACmpNe(offset) => {
-                if let Some(JValue::Reference(value2)) = stack.remove(0)
-                && let Some(JValue::Reference(value1)) = stack.remove(0){
-                    if value1 != value2{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
+            Instruction::L2I => {
+                if let Some(value) = frame.pop_long(){
+                    frame.push(JValue::Int(value as i32));
                 }else{
-                    return MethodResult::MachineError("Tried to execute if_acmpne without two refs on top of stack");
+                    break Exit::Error("Tried to execute l2i without long on top of stack");
                 }
             },
-
-            Instruction::IfNull(offset) => {
-                if let Some(JValue::Reference(r)) = stack.remove(0){
-                    if let None = r{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
+            Instruction::L2F => {
+                if let Some(value) = frame.pop_long(){
+                    frame.push(JValue::Float(value as f32));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ifnull without reference on top of stack");
+                    break Exit::Error("Tried to execute l2f without long on top of stack");
                 }
             },
-            Instruction::IfNonnull(offset) => {
-                if let Some(JValue::Reference(r)) = stack.remove(0){
-                    if let Some(_) = r{
-                        let target = (*idx as isize) + (*offset as isize);
-                        if target < 0{
-                            panic!("Bad goto offset");
-                        }
-                        i = bytecode_idx_to_instr_idx(target as usize, code);
-                        was_jump = true;
-                    }
+            Instruction::L2D => {
+                if let Some(value) = frame.pop_long(){
+                    frame.push(JValue::Double(value as f64));
                 }else{
-                    return MethodResult::MachineError("Tried to execute ifnonnull without reference on top of stack");
+                    break Exit::Error("Tried to execute l2d without a long on top of stack");
                 }
             },
-
-            Instruction::I2L => {
-                if let Some(JValue::Int(i)) = stack.remove(0){
-                    let val = i as i64;
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+            Instruction::F2I => {
+                if let Some(value) = frame.pop_float(){
+                    frame.push(JValue::Int(value as i32));
                 }else{
-                    return MethodResult::MachineError("Tried to execute i2l without int on top of stack");
+                    break Exit::Error("Tried to execute f2i without float on top of stack");
                 }
             },
-            Instruction::I2F => {
-                if let Some(JValue::Int(i)) = stack.remove(0){
-                    let val = i as f32;
-                    stack.push_front(JValue::Float(val));
+            Instruction::F2L => {
+                if let Some(value) = frame.pop_float(){
+                    frame.push(JValue::Long(value as i64));
                 }else{
-                    return MethodResult::MachineError("Tried to execute i2f without int on top of stack");
+                    break Exit::Error("Tried to execute f2l without a float on top of stack");
                 }
             },
-            Instruction::L2I => {
-                if let Some(JValue::Long(l)) = stack.remove(0){
-                    let val = l as i32;
-                    stack.remove(0);
-                    stack.push_front(JValue::Int(val));
+            Instruction::F2D => {
+                if let Some(value) = frame.pop_float(){
+                    frame.push(JValue::Double(value as f64));
                 }else{
-                    return MethodResult::MachineError("Tried to execute l2i without long on top of stack");
+                    break Exit::Error("Tried to execute f2d without float on top of stack");
                 }
             },
-            Instruction::L2F => {
-                if let Some(JValue::Long(l)) = stack.remove(0){
-                    let val = l as f32;
-                    stack.remove(0);
-                    stack.push_front(JValue::Float(val));
+            Instruction::D2L => {
+                if let Some(value) = frame.pop_double(){
+                    frame.push(JValue::Long(value as i64));
                 }else{
-                    return MethodResult::MachineError("Tried to execute l2f without long on top of stack");
+                    break Exit::Error("Tried to execute d2l without double on top of stack");
                 }
             },
-            Instruction::F2I => {
-                if let Some(JValue::Float(l)) = stack.remove(0){
-                    let val = l as i32;
-                    stack.push_front(JValue::Int(val));
+            Instruction::D2I => {
+                if let Some(value) = frame.pop_double(){
+                    frame.push(JValue::Int(value as i32));
                 }else{
-                    return MethodResult::MachineError("Tried to execute f2i without float on top of stack");
+                    break Exit::Error("Tried to execute d2i without a double on top of stack");
                 }
             },
-            Instruction::F2D => {
-                if let Some(JValue::Float(l)) = stack.remove(0){
-                    let val = l as f64;
-                    stack.push_front(JValue::Double(val));
-                    stack.insert(1, JValue::Second);
+            Instruction::D2F => {
+                if let Some(value) = frame.pop_double(){
+                    frame.push(JValue::Float(value as f32));
                 }else{
-                    return MethodResult::MachineError("Tried to execute f2d without float on top of stack");
+                    break Exit::Error("Tried to execute d2f without a double on top of stack");
                 }
             },
-            Instruction::D2L => {
-                if let Some(JValue::Double(l)) = stack.remove(0){
-                    let val = l as i64;
-                    stack.remove(0);
-                    stack.push_front(JValue::Long(val));
-                    stack.insert(1, JValue::Second);
+            Instruction::I2C => {
+                if let Some(value) = frame.pop_int(){
+                    frame.push(JValue::Int(to_char(value)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute d2l without float on top of stack");
+                    break Exit::Error("Tried to execute i2c without int on top of stack");
                 }
             },
-            Instruction::I2C => {
-                if let Some(JValue::Int(i)) = stack.remove(0){
-                    stack.push_front(JValue::Int(to_char(i)));
+            Instruction::I2B => {
+                if let Some(value) = frame.pop_int(){
+                    frame.push(JValue::Int(to_byte(value)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute i2c without int on top of stack");
+                    break Exit::Error("Tried to execute i2b without int on top of stack");
                 }
             },
-            Instruction::I2B => {
-                if let Some(JValue::Int(i)) = stack.remove(0){
-                    stack.push_front(JValue::Int(to_byte(i)));
+            Instruction::I2S => {
+                if let Some(value) = frame.pop_int(){
+                    frame.push(JValue::Int(to_short(value)));
                 }else{
-                    return MethodResult::MachineError("Tried to execute i2b without int on top of stack");
+                    break Exit::Error("Tried to execute i2s without an int on top of stack");
                 }
             },
 
             Instruction::IReturn => {
-                return if let Some(JValue::Int(ret)) = stack.get(0){
-                    MethodResult::FinishWithValue(JValue::Int(*ret))
+                break if let Some(ret) = frame.pop_int(){
+                    Exit::Return(Some(JValue::Int(ret)))
                 }else{
-                    MethodResult::MachineError("Tried to execute ireturn without int on top of stack")
+                    Exit::Error("Tried to execute ireturn without int on top of stack")
                 }
             },
             Instruction::LReturn => {
-                return if let Some(JValue::Long(ret)) = stack.get(0){
-                    MethodResult::FinishWithValue(JValue::Long(*ret))
+                break if let Some(ret) = frame.pop_long(){
+                    Exit::Return(Some(JValue::Long(ret)))
                 }else{
-                    MethodResult::MachineError("Tried to execute lreturn without long on top of stack")
+                    Exit::Error("Tried to execute lreturn without long on top of stack")
                 }
             },
             Instruction::FReturn => {
-                return if let Some(JValue::Float(ret)) = stack.get(0){
-                    MethodResult::FinishWithValue(JValue::Float(*ret))
+                break if let Some(ret) = frame.pop_float(){
+                    Exit::Return(Some(JValue::Float(ret)))
                 }else{
-                    MethodResult::MachineError("Tried to execute freturn without float on top of stack")
+                    Exit::Error("Tried to execute freturn without float on top of stack")
                 }
             },
             Instruction::DReturn => {
-                return if let Some(JValue::Double(ret)) = stack.get(0){
-                    MethodResult::FinishWithValue(JValue::Double(*ret))
+                break if let Some(ret) = frame.pop_double(){
+                    Exit::Return(Some(JValue::Double(ret)))
                 }else{
-                    MethodResult::MachineError("Tried to execute dreturn without double on top of stack")
+                    Exit::Error("Tried to execute dreturn without double on top of stack")
                 }
             },
             Instruction::AReturn => {
-                return if let Some(JValue::Reference(ret)) = stack.get(0){
-                    MethodResult::FinishWithValue(JValue::Reference(*ret))
+                break if let Some(ret) = frame.pop_ref(){
+                    Exit::Return(Some(JValue::Reference(ret)))
                 }else{
-                    MethodResult::MachineError("Tried to execute areturn without reference on top of stack")
+                    Exit::Error("Tried to execute areturn without reference on top of stack")
                 }
             },
-            Instruction::Return => return MethodResult::Finish,
+            Instruction::Return => break Exit::Return(None),
 
             Instruction::AThrow => {
-                let tr = update_trace(&trace, *idx, method, &owner);
-                return MethodResult::Throw(tr, "athrow");
+                match frame.pop_ref(){
+                    Some(Some(exception)) => break Exit::ThrowObject(exception),
+                    Some(None) => break Exit::Throw("NPE for athrow"),
+                    None => break Exit::Error("Tried to execute athrow without reference on top of stack")
+                }
             },
 
+            Instruction::GetStatic(target) | Instruction::PutStatic(target) => {
+                let (class, f_idx, volatile) = match quicken(method, i, || resolve_static_field(target, owner)){
+                    Ok(class::Quick::StaticField(class, f_idx, volatile)) => (class, *f_idx, *volatile),
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                let field = &class.static_fields[f_idx];
+                if let Instruction::GetStatic(_) = instr{
+                    frame.push(field.read().unwrap().1);
+                    volatile_load_fence(volatile);
+                }else if let Some(value) = frame.pop(){
+                    volatile_store_fence(volatile, || field.write().unwrap().1 = value);
+                }else{
+                    break Exit::Error("Tried to execute putstatic with empty stack!");
+                }
+            },
             Instruction::GetField(target) => {
-                let owner = heap::get_or_create_bt_class(format!("L{};", target.owner_name.clone()))
-                    .expect("Could not load field owner")
-                    .ensure_initialized()
-                    .expect("Could not load field owner");
-                let mut was_static = false;
-                let mut cur = &owner;
-                // also get statics from superclasses, because ByteBuffer
-                'st: while let Some(sc) = &cur.super_class{
-                    MaybeClass::Class(cur.clone()).ensure_initialized().expect("Failed to initialize superclass for get*");
-                    for f in &cur.static_fields{
-                        let f = f.read().unwrap();
-                        if f.0.name == target.name_and_type.name{
-                            let j_value = f.1.clone();
-                            stack.push_front(j_value);
-                            if let JValue::Long(_) = j_value{
-                                stack.insert(1, JValue::Second);
-                            }else if let JValue::Double(_) = j_value{
-                                stack.insert(1, JValue::Second);
-                            }
-                            was_static = true;
-                            break 'st;
+                let (offset, volatile, protected_receiver) = match quicken(method, i, || resolve_instance_field(target, owner)){
+                    Ok(class::Quick::InstanceField(offset, volatile, protected_receiver)) => (*offset, *volatile, protected_receiver),
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                if let Some(r) = frame.pop_ref(){
+                    if let Some(r) = r{
+                        let obj = r.deref();
+                        if !receiver_allowed(obj, protected_receiver){
+                            break Exit::Throw("IllegalAccessError");
                         }
-                    }
-                    cur = sc;
-                }
-                if !was_static{
-                    if let Some(JValue::Reference(r)) = stack.remove(0){
-                        if let Some(r) = r{
-                            let obj = r.deref();
-                            if let JObjectData::Fields(f) = &*obj.data.read().unwrap(){
-                                let mut pushed = false;
-                                for (name, value) in f{
-                                    if &target.name_and_type.name == name{
-                                        stack.push_front(value.clone());
-                                        if let JValue::Long(_) = value{
-                                            stack.insert(1, JValue::Second);
-                                        }else if let JValue::Double(_) = value{
-                                            stack.insert(1, JValue::Second);
-                                        }
-                                        pushed = true;
-                                        break;
-                                    }
-                                }
-                                if !pushed{
-                                    // field declared in class but not present in actual fields
-                                    // can happen if object is badly made (like `Class`es currently)
-                                    stack.push_front(JValue::default_value_for(&target.name_and_type.descriptor));
-                                }
-                            }else{
-                                return MethodResult::MachineError("Tried to execute getfield on array reference!");
-                            };
+                        if let JObjectData::Fields(f) = &*obj.data.read().unwrap(){
+                            frame.push(f[offset]);
+                            volatile_load_fence(volatile);
                         }else{
-                            return MethodResult::Throw(update_trace(&trace, *idx, method, &owner), "NPE for getfield");
-                        }
+                            break Exit::Error("Tried to execute getfield on array reference!");
+                        };
                     }else{
-                        eprintln!("Expected reference, got {:?}!", stack.get(0));
-                        return MethodResult::MachineError("Tried to execute getfield without reference on stack!");
+                        break Exit::Throw("NPE for getfield");
                     }
+                }else{
+                    break Exit::Error("Tried to execute getfield without reference on stack!");
                 }
             },
             Instruction::PutField(target) => {
-                let field_owner = heap::get_or_create_bt_class(format!("L{};", target.owner_name.clone()))
-                    .expect("Could not load field owner")
-                    .ensure_initialized()
-                    .expect("Could not load field owner");
-                let mut was_static = false;
-                let value = stack.remove(0).unwrap();
-                if let Some(JValue::Second) = stack.get(0) {
-                    stack.remove(0);
-                }
-                for f in &field_owner.static_fields{
-                    let mut f = f.write().unwrap();
-                    if f.0.name == target.name_and_type.name{
-                        f.1 = value;
-                        was_static = true;
-                        break;
-                    }
-                }
-                if !was_static{
-                    let object_ref = stack.remove(0);
-                    for f in &field_owner.instance_fields{
-                        if f.name == target.name_and_type.name{
-                            if let Some(JValue::Reference(Some(r))) = object_ref{
-                                let object = r.deref();
-                                let mut data = object.data.write().unwrap();
-                                if let JObjectData::Fields(fields) = &mut *data{
-                                    fields.insert(f.name.clone(), value);
-                                }else{
-                                    return MethodResult::MachineError("Tried to execute putfield on an array reference!");
-                                }
-                            }else if let Some(JValue::Reference(None)) = object_ref{
-                                return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for putfield");
-                            }else{
-                                return MethodResult::MachineError("Tried to execute putfield with non-reference on stack!")
-                            }
+                let (offset, volatile, protected_receiver) = match quicken(method, i, || resolve_instance_field(target, owner)){
+                    Ok(class::Quick::InstanceField(offset, volatile, protected_receiver)) => (*offset, *volatile, protected_receiver),
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                let value = frame.pop().unwrap();
+                match frame.pop(){
+                    Some(JValue::Reference(Some(r))) => {
+                        let object = r.deref();
+                        if !receiver_allowed(object, protected_receiver){
+                            break Exit::Throw("IllegalAccessError");
                         }
-                    }
+                        let mut data = object.data.write().unwrap();
+                        if let JObjectData::Fields(fields) = &mut *data{
+                            volatile_store_fence(volatile, || fields[offset] = value);
+                        }else{
+                            break Exit::Error("Tried to execute putfield on an array reference!");
+                        }
+                    },
+                    Some(JValue::Reference(None)) => break Exit::Throw("NPE for putfield"),
+                    _ => break Exit::Error("Tried to execute putfield with non-reference on stack!")
                 }
             },
             
-            Instruction::InvokeVirtual(target) => {
-                let params = resolve_signature(&target);
-                let mut args = Vec::with_capacity(params.len() + 1);
-                let mut i = 0;
-                while i < params.len(){
-                    let val = stack.remove(0).unwrap();
-                    if val != JValue::Second{
-                        args.insert(0, val);
-                        i += 1;
-                    }
-                }
-                // TODO: doesn't seem right? rework & simplify different invokes
-                if let Some(JValue::Second) = stack.get(0){
-                    stack.remove(0); // param 0 was a double/long
-                }
-                let receiver = stack.remove(0).unwrap();
-                args.insert(0, receiver.clone());
-
-                if let JValue::Reference(Some(r)) = receiver{
-                    let receiver_class = &r.deref().class;
-                    let (target, class) = receiver_class.virtual_method(&target.name_and_type)
-                        .expect(format!("Tried to execute invokevirtual for method with {:?} that doesn't exist on receiver of type {} inside {}.{}{}", &target, &r.deref().class.name, &owner.name, &method.name, &method.descriptor()).as_str());
-                    let result = execute(&*class, &target, args, update_trace(&trace, *idx, method, owner));
-                    // TODO: exception handling
-                    match result{
-                        MethodResult::FinishWithValue(v) => {
-                            stack.push_front(v);
-                            match v{
-                                JValue::Long(_) | JValue::Double(_) => stack.insert(1, JValue::Second),
-                                _ => {}
-                            }
-                        },
-                        MethodResult::Finish => {},
-                        MethodResult::Throw(s, e) => return MethodResult::Throw(s, e),
-                        MethodResult::MachineError(e) => return MethodResult::MachineError(e),
-                    }
-                }else if let JValue::Reference(None) = receiver{
-                    return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for invokevirtual");
-                }else{
-                    return MethodResult::MachineError("Tried to execute invokevirtual without object on stack");
-                }
-            },
-            Instruction::InvokeInterface(target) => {
-                let params = resolve_signature(&target);
-                let mut args = Vec::with_capacity(params.len() + 1);
-                let mut i = 0;
-                while i < params.len(){
-                    let val = stack.remove(0).unwrap();
-                    if val != JValue::Second{
-                        args.insert(0, val);
-                        i += 1;
-                    }
-                }
-                if let Some(JValue::Second) = stack.get(0){
-                    stack.remove(0); // param 0 was a double/long
-                }
-                let receiver = stack.remove(0).unwrap();
-                args.insert(0, receiver.clone());
-
-                if let JValue::Reference(Some(r)) = receiver{
-                    let receiver_class = &r.deref().class;
-                    let (target, class) = receiver_class.interface_method(&target.name_and_type)
-                        .expect(format!("Tried to execute invokeinterface for method with {:?} that doesn't exist on receiver of type {} inside {}.{}{}", &target, &r.deref().class.name, &owner.name, &method.name, &method.descriptor()).as_str());
-                    let result = execute(&*class, &target, args, update_trace(&trace, *idx, method, owner));
-                    // TODO: exception handling
-                    match result{
-                        MethodResult::FinishWithValue(v) => {
-                            stack.push_front(v);
-                            match v{
-                                JValue::Long(_) | JValue::Double(_) => stack.insert(1, JValue::Second),
-                                _ => {}
-                            }
-                        },
-                        MethodResult::Finish => {},
-                        MethodResult::Throw(s, e) => return MethodResult::Throw(s, e),
-                        MethodResult::MachineError(e) => return MethodResult::MachineError(e),
-                    }
-                }else if let JValue::Reference(None) = receiver{
-                    return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for invokeinterface");
-                }else{
-                    return MethodResult::MachineError("Tried to execute invokeinterface without object on stack");
-                }
-            },
-            Instruction::InvokeStatic(s) => {
-                let owner_name = &s.owner_name;
-                let class = heap::get_or_create_bt_class(format!("L{};", owner_name)).unwrap().ensure_initialized().unwrap();
-                if let Some(target) = class.static_method(&s.name_and_type){
-                    // TODO: dedup code
-                    let num_params = target.parameters.len();
-                    let mut args = Vec::with_capacity(num_params);
-                    let mut i = 0;
-                    while i < num_params{
-                        let val = stack.remove(0).unwrap();
-                        if val != JValue::Second{
-                            args.insert(0, val);
-                            i += 1;
-                        }
-                    }
-                    if let Some(JValue::Second) = stack.get(0){
-                        stack.remove(0); // param 0 was a double/long
-                    }
-                    let result = execute(&*class, &target, args, update_trace(&trace, *idx, method, owner));
-                    match result{
-                        MethodResult::FinishWithValue(v) => {
-                            stack.push_front(v);
-                            match v{
-                                JValue::Long(_) | JValue::Double(_) => stack.insert(1, JValue::Second),
-                                _ => {}
-                            }
-                        },
-                        MethodResult::Finish => {},
-                        MethodResult::Throw(s, e) => return MethodResult::Throw(s, e),
-                        MethodResult::MachineError(e) => return MethodResult::MachineError(e),
-                    }
-                }else{
-                    // TODO: should throw instead?
-                    return MethodResult::MachineError("Tried to execute invokestatic for a method that doesn't exist");
-                }
+            Instruction::InvokeDynamic(call) => {
+                let site = match quicken(method, i, || resolve_dynamic_call_site(call, owner).map(class::Quick::Invoke)){
+                    Ok(class::Quick::Invoke(site)) => site,
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                let args = frame.stack.split_off(frame.stack.len() - site.arg_count);
+                let class::CallTarget::Static(class, m_idx) = &site.target else { unreachable!() };
+                break Exit::Invoke(class.clone(), *m_idx, args);
             },
-            Instruction::InvokeSpecial(target) => {
-                let params = resolve_signature(&target);
-                let mut args = Vec::with_capacity(params.len() + 1);
-                let mut i = 0;
-                while i < params.len(){
-                    let val = stack.remove(0).unwrap();
-                    if val != JValue::Second{
-                        args.insert(0, val);
-                        i += 1;
-                    }
-                }
-                if let Some(JValue::Second) = stack.get(0){
-                    stack.remove(0); // param 0 was a double/long
-                }
-                let receiver = stack.remove(0).unwrap();
-                args.insert(0, receiver.clone());
-
-                if let JValue::Reference(Some(r)) = receiver{
-                    let class = &r.deref().class;
-                    let (target, owner) = class.special_method(&target.name_and_type, target.owner_name.as_str())
-                        .expect(format!("Tried to execute invokespecial for method with {:?} for {} that doesn't exist on receiver", &target.name_and_type, &target.owner_name.clone()).as_str());
-                    let result = execute(owner, &target, args, update_trace(&trace, *idx, method, owner));
-                    // TODO: exception handling
-                    match result{
-                        MethodResult::FinishWithValue(v) => {
-                            stack.push_front(v);
-                            match v{
-                                JValue::Long(_) | JValue::Double(_) => stack.insert(1, JValue::Second),
-                                _ => {}
-                            }
-                        },
-                        MethodResult::Finish => {},
-                        MethodResult::Throw(s, e) => return MethodResult::Throw(s, e),
-                        MethodResult::MachineError(e) => return MethodResult::MachineError(e),
-                    }
-                }else if let JValue::Reference(None) = receiver{
-                    return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for invokespecial");
-                }else{
-                    return MethodResult::MachineError("Tried to execute invokespecial without object on stack");
+
+            Instruction::InvokeVirtual(target) | Instruction::InvokeInterface(target)
+            | Instruction::InvokeStatic(target) | Instruction::InvokeSpecial(target) => {
+                let site = match quicken(method, i, || resolve_call_site(instr, target, owner).map(class::Quick::Invoke)){
+                    Ok(class::Quick::Invoke(site)) => site,
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                let mut args = frame.stack.split_off(frame.stack.len() - site.arg_count);
+
+                // the call itself happens in the dispatch loop; we come back to this instruction when it's done
+                match &site.target{
+                    class::CallTarget::Static(class, m_idx) => break Exit::Invoke(class.clone(), *m_idx, args),
+                    class::CallTarget::EnterContinuation(class, m_idx) => break Exit::EnterContinuation(class.clone(), *m_idx, args),
+                    class::CallTarget::YieldContinuation => break Exit::YieldContinuation,
+                    _ => {}
+                }
+                let r = match frame.pop(){
+                    Some(JValue::Reference(Some(r))) => r,
+                    Some(JValue::Reference(None)) => break Exit::Throw("NPE for invoke"),
+                    _ => break Exit::Error("Tried to execute invoke without object on stack")
+                };
+                if !receiver_allowed(r.deref(), &site.protected_receiver){
+                    break Exit::Throw("IllegalAccessError");
+                }
+                args.insert(0, JValue::Reference(Some(r)));
+                match &site.target{
+                    class::CallTarget::Special(class, m_idx) => break Exit::Invoke(class.clone(), *m_idx, args),
+                    class::CallTarget::Virtual(slot) => {
+                        let receiver_class = receiver_class(&r);
+                        let slot = &receiver_class.vtable[*slot];
+                        break Exit::Invoke(slot.owner.clone().unwrap_or_else(|| receiver_class.clone()), slot.idx, args);
+                    },
+                    class::CallTarget::Interface(interface, m_idx) => {
+                        let receiver_class = receiver_class(&r);
+                        let slot = match receiver_class.itable_method(interface, *m_idx){
+                            Some(Ok(slot)) => slot,
+                            Some(Err(e)) => break Exit::Throw(e),
+                            None => break Exit::Throw("IncompatibleClassChangeError")
+                        };
+                        break Exit::Invoke(slot.owner.clone().unwrap_or_else(|| receiver_class.clone()), slot.idx, args);
+                    },
+                    class::CallTarget::VarHandle(access_mode, return_type) => {
+                        let Some((class, m_idx)) = var_handle_method(&r, access_mode) else { break Exit::Throw("UnsupportedOperationException") };
+                        break Exit::InvokeVarHandle(class, m_idx, args, return_type.clone());
+                    },
+                    class::CallTarget::Static(..) |
+                    class::CallTarget::EnterContinuation(..) |
+                    class::CallTarget::YieldContinuation => unreachable!()
                 }
             },
 
             Instruction::ArrayLength => {
-                if let Some(JValue::Reference(array_ref)) = stack.get(0){
+                if let Some(array_ref) = frame.pop_ref(){
                     if let Some(array_ref) = array_ref{
                         let array = array_ref.deref();
                         if let Ok(read) = array.data.read(){
-                            if let JObjectData::Array(size, _) = &*read{
-                                stack.push_front(JValue::Int(*size as i32));
+                            if let JObjectData::Array(values) = &*read{
+                                frame.push(JValue::Int(values.len() as i32));
                             }else{
-                                return MethodResult::MachineError("Tried to execute arraylength on non-array reference!");
+                                break Exit::Error("Tried to execute arraylength on non-array reference!");
                             }
                         }else{
-                            return MethodResult::MachineError("Could not read object data for arraylength");
+                            break Exit::Error("Could not read object data for arraylength");
                         };
                     }else{
-                        return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "NPE for arraylength");
+                        break Exit::Throw("NPE for arraylength");
                     }
-
-                    stack.remove(1); // 0 is the length we just pushed
                 }else{
-                    return MethodResult::MachineError("Tried to execute arraylength without reference on top of stack");
+                    break Exit::Error("Tried to execute arraylength without reference on top of stack");
                 }
             },
 
             Instruction::InstanceOf(to) => {
-                if let Some(JValue::Reference(f)) = stack.remove(0){
+                let to = match quicken(method, i, || resolve_type(to, owner)){
+                    Ok(class::Quick::Type(to)) => to,
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                if let Some(f) = frame.pop_ref(){
                     if let Some(r) = f{
                         let obj = r.deref();
-                        let to = internal_name_to_desc(to).to_string();
-                        let assignable = obj.assignable_to(&to);
-                        stack.push_front(JValue::Int(if assignable { 1 } else { 0 }));
+                        let assignable = obj.assignable_to(to);
+                        frame.push(JValue::Int(if assignable { 1 } else { 0 }));
                     }else{
-                        stack.push_front(JValue::Int(0));
+                        frame.push(JValue::Int(0));
                     }
                 }else{
-                    return MethodResult::MachineError("Tried to execute instanceof without reference on top of stack");
+                    break Exit::Error("Tried to execute instanceof without reference on top of stack");
                 }
             },
 
             Instruction::New(class_name) => {
-                let class = heap::get_or_create_bt_class(format!("L{};", class_name))
-                    .expect("Could not parse class for new instruction!")
-                    .ensure_loaded()
-                    .expect("Could not link class for new instruction!");
-                stack.push_front(objects::create_new(class));
+                let class = match quicken(method, i, || resolve_class(format!("L{};", class_name), owner)){
+                    Ok(class::Quick::Class(class)) => class,
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                frame.push(objects::create_new(class.clone()));
             },
             Instruction::NewArray(class_name) => {
                 // TODO: check everywhere else too for linking VS initializing
-                let class = heap::get_or_create_bt_class(class_name.clone())
-                    .expect("Could not parse class for [a]newarray instruction!")
-                    .ensure_loaded()
-                    .expect("Could not link class for [a]newarray instruction!");
-                if let Some(JValue::Int(l)) = stack.remove(0){
+                let class = match quicken(method, i, || resolve_class(class_name.clone(), owner)){
+                    Ok(class::Quick::Class(class)) => class,
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                if let Some(l) = frame.pop_int(){
                     if l < 0{
                         // TODO: synthesize NegativeArraySizeException
-                        return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "negativearraysize for newarray");
+                        break Exit::Throw("negativearraysize for newarray");
                     }
                     let l = l as usize;
-                    stack.push_front(objects::create_new_array(class, l));
+                    frame.push(objects::create_new_array(class.clone(), l));
                 }
             },
+            Instruction::MultiANewArray(array_desc, dimensions) => {
+                if let Err(e) = quicken(method, i, || resolve_type(array_desc, owner)){
+                    break Exit::Throw(e);
+                }
+                // the outermost length is deepest on the stack
+                let Some(mut lengths) = (0..*dimensions).map(|_| frame.pop_int()).collect::<Option<Vec<i32>>>() else {
+                    break Exit::Error("Tried to execute multianewarray without int lengths on stack!");
+                };
+                lengths.reverse();
+                if lengths.iter().any(|l| *l < 0){
+                    // TODO: synthesize NegativeArraySizeException
+                    break Exit::Throw("negativearraysize for multianewarray");
+                }
+                frame.push(create_multi_array(array_desc, &lengths));
+            },
 
             Instruction::CheckCast(to) => {
-                if let Some(v) = stack.get(0){
+                let to = match quicken(method, i, || resolve_type(to, owner)){
+                    Ok(class::Quick::Type(to)) => to,
+                    Err(e) => break Exit::Throw(e),
+                    _ => unreachable!()
+                };
+                if let Some(v) = frame.peek(0){
                     if let JValue::Reference(r) = v{
                         if let Some(r) = r{
                             let obj = r.deref();
-                            let to = internal_name_to_desc(to);
-                            if !obj.assignable_to(&to){
+                            if !obj.assignable_to(to){
                                 println!("cannot assign {} to {}!", &obj.class.descriptor, &to);
-                                return MethodResult::Throw(update_trace(&trace, *idx, method, owner), "non-assignable for checkcast");
+                                break Exit::Throw("non-assignable for checkcast");
                             }
                         }
                     }else{
-                        return MethodResult::MachineError("Tried to execute checkcast with non-reference on stack!");
+                        break Exit::Error("Tried to execute checkcast with non-reference on stack!");
                     }
                 }else{
-                    return MethodResult::MachineError("Tried to execute checkcast with nothing on stack!");
+                    break Exit::Error("Tried to execute checkcast with nothing on stack!");
                 }
             },
-            Instruction::MonitorEnter | Instruction::MonitorExit => {
-                // TODO: synchronization
-                stack.pop_front();
-            }
-
-            other => {
-                panic!("Unhandled instruction: {:?}", other);
+            Instruction::MonitorEnter => {
+                match frame.pop(){
+                    Some(JValue::Reference(Some(r))) => r.deref().monitor.enter(),
+                    Some(JValue::Reference(None)) => break Exit::Throw("NPE for monitorenter"),
+                    _ => break Exit::Error("Tried to execute monitorenter without reference on top of stack")
+                }
+            },
+            Instruction::MonitorExit => {
+                match frame.pop(){
+                    Some(JValue::Reference(Some(r))) => if let Err(e) = r.deref().monitor.exit(){
+                        break Exit::Throw(e);
+                    },
+                    Some(JValue::Reference(None)) => break Exit::Throw("NPE for monitorexit"),
+                    _ => break Exit::Error("Tried to execute monitorexit without reference on top of stack")
+                }
             }
         };
-        if !was_jump{
-            i += 1;
-        }
-    }
-    return MethodResult::MachineError("Reached end of function without return!");
-}
-
-fn bytecode_idx_to_instr_idx(bytecode_idx: usize, code: &Code) -> usize{
-    let mut i = 0;
-    for (t_bidx, _) in &code.bytecode{
-        if *t_bidx == bytecode_idx{
-            return i;
-        }
         i += 1;
-    }
-    panic!("invalid bytecode offset {}", bytecode_idx);
+    };
+    frame.pc = i;
+    return exit;
 }
 
 fn bytecode_idx_to_line_number(bytecode_idx: usize, method: &Method) -> Option<u16>{
@@ -1486,39 +1534,445 @@ fn update_trace(trace: &StackTrace, bytecode_idx: usize, method: &Method, owner:
     return trace;
 }
 
-fn set_and_pad<T>(list: &mut Vec<T>, idx: usize, value: T, default: T) where T: Clone{
-    if list.len() <= idx{
-        for _ in 0..(idx - list.len()){
-            list.push(default.clone());
+// Continuations
+// A continuation's frames run on top of the frames of whoever enters it, in the same run, starting with an entry frame
+// for Continuation.enter. Yielding moves its frames out of the run, to be pushed back on top of the frames of whoever
+// continues it, possibly on another thread; either way, enterSpecial then returns to its caller.
+
+/// A continuation that's running on this thread.
+struct ContinuationEntry{
+    continuation: JRef,
+    run: usize,      // which of the thread's nested runs its frames are in
+    frame: usize,    // the index of its entry frame in that run's frames
+    monitors: usize, // how many monitor entries the thread held when it was entered
+    pins: u16        // how many critical sections it's in, from Continuation.pin
+}
+
+thread_local!{
+    /// The continuations running on this thread, innermost last.
+    static CONTINUATIONS: RefCell<Vec<ContinuationEntry>> = const { RefCell::new(Vec::new()) };
+    /// How many runs are nested on this thread, from natives calling back into Java.
+    static RUNS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The frames of each suspended continuation.
+static SUSPENDED: Mutex<Option<HashMap<JRef, Vec<Frame>>>> = Mutex::new(None);
+
+// why a continuation can't yield, as returned by doYield and isPinned0 (Continuation.pinnedReason)
+const PINNED_CRITICAL_SECTION: i32 = 2;
+const PINNED_NATIVE: i32 = 3;
+const PINNED_MONITOR: i32 = 4;
+
+/// Runs a continuation on top of the frames of the caller of enterSpecial, either starting it with its enter method
+/// or pushing back the frames it yielded from.
+fn enter_continuation(frames: &mut Vec<Frame>, class: ClassRef, m_idx: usize, args: Vec<JValue>) -> Result<(), JRef>{
+    // Continuation, boolean isContinue, boolean isVirtualThread
+    let JValue::Reference(Some(continuation)) = args[0] else { panic!("enterSpecial called without a continuation") };
+    let entry = ContinuationEntry{ continuation, run: RUNS.get(), frame: frames.len(), monitors: monitors::entries_held(), pins: 0 };
+    let suspended = SUSPENDED.lock().unwrap().as_mut().and_then(|s| s.remove(&continuation));
+    match suspended{
+        Some(suspended) => {
+            STACK_USED.set(STACK_USED.get() + suspended.iter().map(|f| f.size).sum::<usize>());
+            frames.extend(suspended);
+            // the frame that yielded sees doYield succeed
+            let top = frames.last_mut().unwrap();
+            top.push(JValue::Int(0));
+            top.pc += 1;
+        },
+        None => {
+            push_frame(frames, class, m_idx, args[..2].to_vec())?;
+            frames.last_mut().unwrap().continuation_entry = true;
+        }
+    }
+    CONTINUATIONS.with_borrow_mut(|entries| entries.push(entry));
+    return Ok(());
+}
+
+/// Suspends the innermost continuation, moving its frames out of the run and returning from its enterSpecial,
+/// unless it's pinned, which doYield returns the reason for.
+fn yield_continuation(frames: &mut Vec<Frame>){
+    let entry = CONTINUATIONS.with_borrow_mut(|entries| {
+        let reason = entries.last().map_or(PINNED_NATIVE, pinned_reason);
+        return if reason == 0{ entries.pop().ok_or(reason) }else{ Err(reason) };
+    });
+    match entry{
+        Ok(entry) => {
+            let suspended = frames.split_off(entry.frame);
+            STACK_USED.set(STACK_USED.get() - suspended.iter().map(|f| f.size).sum::<usize>());
+            SUSPENDED.lock().unwrap().get_or_insert_with(HashMap::new).insert(entry.continuation, suspended);
+            frames.last_mut().unwrap().pc += 1;
+        },
+        Err(reason) => {
+            let top = frames.last_mut().unwrap();
+            top.push(JValue::Int(reason));
+            top.pc += 1;
         }
-        list.push(value);
+    }
+}
+
+/// Returns why a running continuation couldn't yield now, or 0 if it could.
+fn pinned_reason(entry: &ContinuationEntry) -> i32{
+    return if entry.pins > 0{
+        PINNED_CRITICAL_SECTION
+    }else if entry.run != RUNS.get(){
+        // a native called back into Java after it was entered, and we can't suspend the native
+        PINNED_NATIVE
+    }else if monitors::entries_held() > entry.monitors{
+        // monitors are owned by OS threads, so can't move with it
+        PINNED_MONITOR
     }else{
-        list.remove(idx);
-        list.insert(idx, value);
+        0
+    };
+}
+
+/// Returns why yielding to a scope would pin, for Continuation.isPinned0, checking every continuation up to the
+/// innermost one of that scope.
+pub fn continuation_pinned_reason(scope: JRef) -> i32{
+    let scope_offset = objects::force_init_class("Ljdk/internal/vm/Continuation;").declared_field_offset("scope");
+    return CONTINUATIONS.with_borrow(|entries| {
+        for entry in entries.iter().rev(){
+            let reason = pinned_reason(entry);
+            if reason != 0{
+                return reason;
+            }
+            if let Some(offset) = scope_offset
+            && let JObjectData::Fields(fields) = &*entry.continuation.deref().data.read().unwrap()
+            && fields[offset] == JValue::Reference(Some(scope)){
+                break;
+            }
+        }
+        return 0;
+    });
+}
+
+/// Enters or exits a critical section of the innermost continuation, in which it can't yield. Errors are the names of
+/// the exception to throw.
+pub fn pin_continuation(pin: bool) -> Result<(), &'static str>{
+    return CONTINUATIONS.with_borrow_mut(|entries| {
+        // not being in a continuation is as good as being pinned
+        let Some(entry) = entries.last_mut() else { return Ok(()) };
+        entry.pins = if pin{ entry.pins.checked_add(1) }else{ entry.pins.checked_sub(1) }.ok_or("IllegalStateException")?;
+        return Ok(());
+    });
+}
+
+// Memory model
+
+// every field access takes a lock, of the object or the static field, which already makes it atomic and orders it with
+// other accesses to that field; volatile accesses also fence, so that they're ordered with every other access as the
+// JMM requires (JLS 17.4.4) without relying on how the locks are implemented
+
+fn volatile_load_fence(volatile: bool){
+    if volatile{
+        fence(Ordering::Acquire);
     }
 }
 
-fn resolve_signature(target: &MemberRef) -> Vec<MaybeClass>{
-    let owner = heap::get_or_create_bt_class(format!("L{};", target.owner_name.clone()))
-        .expect("Could not load field owner")
-        .ensure_initialized()
-        .expect("Could not load field owner");
-    return owner.virtual_method(&target.name_and_type)
-        .expect("Tried to invoke method that does not exist")
-        .0.parameters
-        .clone();
+fn volatile_store_fence(volatile: bool, store: impl FnOnce()){
+    if volatile{
+        fence(Ordering::Release);
+    }
+    store();
+    if volatile{
+        fence(Ordering::SeqCst);
+    }
 }
 
-fn to_short(v: i32) -> i32{
-    return v.clamp(i16::MIN as i32, i16::MAX as i32);
+// Resolution
+
+/// Returns the quickened form of the instruction at the given index, resolving it on first execution.
+fn quicken(method: &Method, i: usize, resolve: impl FnOnce() -> Result<class::Quick, &'static str>) -> &Result<class::Quick, &'static str>{
+    // not get_or_init, as resolving can run clinit, which can come back here
+    let cell = &method.quick[i];
+    if cell.get().is_none(){
+        let _ = cell.set(resolve());
+    }
+    return cell.get().unwrap();
+}
+
+/// Loads the class named by a field or method reference, to resolve the member against.
+fn member_ref_owner(target: &MemberRef, current: &Class) -> Result<ClassRef, &'static str>{
+    // methods of array types (e.g. clone) are those of Object, though the array type itself must be accessible
+    if target.owner_name.starts_with("["){
+        resolve_type(&target.owner_name, current)?;
+    }
+    let owner = if target.owner_name.starts_with("["){ "java/lang/Object" }else{ &target.owner_name };
+    let class = load_class(format!("L{};", owner), true)?;
+    return if class.accessible_to(current){ Ok(class) }else{ Err("IllegalAccessError") };
+}
+
+/// Loads a class being resolved, turning a failure to load or link it into the error to throw.
+fn load_class(desc: String, initialize: bool) -> Result<ClassRef, &'static str>{
+    let class = heap::get_or_create_bt_class(desc).map_err(linkage_error)?;
+    return if initialize{ class.ensure_initialized() }else{ class.ensure_loaded() }.map_err(linkage_error);
+}
+
+/// Returns the error to throw for a class that couldn't be loaded or linked, for errors that start with its name.
+fn linkage_error(e: String) -> &'static str{
+    if e.starts_with("IncompatibleClassChangeError"){
+        return "IncompatibleClassChangeError";
+    }
+    if e.starts_with("VerifyError"){
+        return "VerifyError";
+    }
+    if e.starts_with("UnsupportedClassVersionError"){
+        return "UnsupportedClassVersionError";
+    }
+    if e.starts_with("ClassFormatError"){
+        return "ClassFormatError";
+    }
+    panic!("Could not load class: {}", e);
+}
+
+/// Loads the class with the given descriptor for new or [a]newarray, checking that the current class can refer to it.
+fn resolve_class(desc: String, current: &Class) -> Result<class::Quick, &'static str>{
+    let class = load_class(desc, false)?;
+    return if class.accessible_to(current){ Ok(class::Quick::Class(class)) }else{ Err("IllegalAccessError") };
+}
+
+/// Resolves the class or array type named by a checkcast, instanceof, or multianewarray instruction, checking that
+/// the current class can refer to it; array types are accessible if their element type is.
+fn resolve_type(name: &str, current: &Class) -> Result<class::Quick, &'static str>{
+    let desc = internal_name_to_desc(name);
+    let element = desc.trim_start_matches('[');
+    if element.starts_with('L'){
+        let class = load_class(element.to_owned(), false)?;
+        if !class.accessible_to(current){
+            return Err("IllegalAccessError");
+        }
+    }
+    return Ok(class::Quick::Type(desc));
+}
+
+/// Creates the object for a class constant, or finds the interned string for a string constant.
+fn resolve_constant(constant: &ConstantEntry, current: &Class) -> Result<JValue, &'static str>{
+    return match constant{
+        ConstantEntry::StringConst(s) => Ok(objects::intern_chars(s.clone())),
+        ConstantEntry::Class(s) => {
+            let class::Quick::Type(desc) = resolve_type(s, current)? else { unreachable!() };
+            Ok(objects::class_object(&desc))
+        },
+        _ => panic!("Not an object constant: {:?}", constant)
+    };
+}
+
+/// Returns whether an object can be the receiver of a protected member, given the class it must be an instance of.
+fn receiver_allowed(obj: &JObject, protected_receiver: &Option<ClassRef>) -> bool{
+    return protected_receiver.as_ref().is_none_or(|d| obj.assignable_to(&d.descriptor));
+}
+
+/// Works out the class the receiver of a protected instance member must be an instance of, if any (JVMS 4.10.1.8).
+fn protected_receiver(declaring: &Class, visibility: &class::Visibility, target: &MemberRef, current: &Class) -> Option<ClassRef>{
+    // arrays can be cloned anywhere, and constructors are only called on new objects
+    if *visibility != class::Visibility::Protected || target.owner_name.starts_with("[") || target.name_and_type.name == "<init>"
+    || !declaring.restricts_protected_receiver(current){
+        return None;
+    }
+    return Some(class_ref(current));
+}
+
+/// Resolves the field referenced by a getstatic or putstatic instruction, and initializes its declaring class.
+fn resolve_static_field(target: &MemberRef, current: &Class) -> Result<class::Quick, &'static str>{
+    let ref_owner = member_ref_owner(target, current)?;
+    let (declaring, location) = ref_owner.resolve_field(&target.name_and_type)?;
+    let class::FieldLocation::Static(f_idx) = location else { return Err("IncompatibleClassChangeError"); };
+    // not held while initializing the class, which sets it
+    let (accessible, volatile) = {
+        let field = &declaring.static_fields[f_idx].read().unwrap().0;
+        (declaring.member_accessible_to(&field.visibility, true, &ref_owner, current), constants::bit_set(field.flags, constants::FIELD_ACC_VOLATILE))
+    };
+    if !accessible{
+        return Err("IllegalAccessError");
+    }
+    let class = MaybeClass::Class(class_ref(declaring)).ensure_initialized().expect("Could not initialize field owner");
+    return Ok(class::Quick::StaticField(class, f_idx, volatile));
+}
+
+/// Resolves the field referenced by a getfield or putfield instruction, which may be declared by a superclass.
+fn resolve_instance_field(target: &MemberRef, current: &Class) -> Result<class::Quick, &'static str>{
+    let ref_owner = member_ref_owner(target, current)?;
+    let (declaring, location) = ref_owner.resolve_field(&target.name_and_type)?;
+    let class::FieldLocation::Instance(f_idx) = location else { return Err("IncompatibleClassChangeError"); };
+    let field = &declaring.instance_fields[f_idx];
+    if !declaring.member_accessible_to(&field.visibility, false, &ref_owner, current){
+        return Err("IllegalAccessError");
+    }
+    let volatile = constants::bit_set(field.flags, constants::FIELD_ACC_VOLATILE);
+    // a field has the same offset in every subclass of its declaring class
+    let offset = declaring.instance_layout.iter()
+        .position(|k| k.owner == declaring.descriptor && k.name == target.name_and_type.name && k.descriptor == target.name_and_type.descriptor)
+        .expect("Resolved field missing from its declaring class's layout");
+    return Ok(class::Quick::InstanceField(offset, volatile, protected_receiver(declaring, &field.visibility, target, current)));
+}
+
+/// Links an invokedynamic call site to a static method implementing it.
+fn resolve_dynamic_call_site(call: &Dynamic, current: &Class) -> Result<class::CallSite, &'static str>{
+    let (class, m_idx) = bootstraps::link_call_site(call, current)?;
+    let arg_count = class.methods[m_idx].parameters.len();
+    return Ok(class::CallSite{ target: class::CallTarget::Static(class, m_idx), arg_count, protected_receiver: None });
+}
+
+/// Resolves the method referenced by an invoke instruction, and works out how to dispatch it.
+fn resolve_call_site(instr: &Instruction, target: &MemberRef, current: &Class) -> Result<class::CallSite, &'static str>{
+    let ref_owner = member_ref_owner(target, current)?;
+    // VarHandle access modes are signature polymorphic (JVMS 2.9.3), taking and returning whatever the call site says
+    if let Instruction::InvokeVirtual(_) = instr
+    && ref_owner.descriptor == "Ljava/lang/invoke/VarHandle;"
+    && ref_owner.methods.iter().any(|m| m.name == target.name_and_type.name && m.code == class::MethodImpl::Native){
+        let types = classfile_parser::parse_method_descriptor(target.name_and_type.descriptor.clone())
+            .expect("Invalid descriptor for VarHandle call");
+        return Ok(class::CallSite{
+            target: class::CallTarget::VarHandle(target.name_and_type.name.clone(), types.last().unwrap().clone()),
+            arg_count: types.len() - 1,
+            protected_receiver: None
+        });
+    }
+    // continuations run on the interpreter's own stack of frames, which natives can't reach
+    if let Instruction::InvokeStatic(_) = instr
+    && ref_owner.descriptor == "Ljdk/internal/vm/Continuation;"{
+        if target.name_and_type.name == "enterSpecial"{
+            let enter = NameAndType{ name: "enter".to_string(), descriptor: "(Ljdk/internal/vm/Continuation;Z)V".to_string() };
+            let method = ref_owner.static_method(&enter).ok_or("NoSuchMethodError")?;
+            let m_idx = method_index(&ref_owner, method);
+            return Ok(class::CallSite{ target: class::CallTarget::EnterContinuation(ref_owner.clone(), m_idx), arg_count: 3, protected_receiver: None });
+        }else if target.name_and_type.name == "doYield"{
+            return Ok(class::CallSite{ target: class::CallTarget::YieldContinuation, arg_count: 0, protected_receiver: None });
+        }
+    }
+    let (resolved, resolved_owner) = ref_owner.resolve_method_ref(target)?;
+    let is_static = matches!(instr, Instruction::InvokeStatic(_));
+    if resolved.is_static != is_static{
+        return Err("IncompatibleClassChangeError");
+    }
+    if !resolved_owner.member_accessible_to(&resolved.visibility, resolved.is_static, &ref_owner, current){
+        return Err("IllegalAccessError");
+    }
+    let protected_receiver = if is_static{ None }else{ protected_receiver(resolved_owner, &resolved.visibility, target, current) };
+    let target = match instr{
+        Instruction::InvokeStatic(_) => {
+            // the declaring class may be a superclass, which has to be initialized too
+            let class = MaybeClass::Class(class_ref(resolved_owner)).ensure_initialized().expect("Could not initialize method owner");
+            let m_idx = method_index(&class, resolved);
+            class::CallTarget::Static(class, m_idx)
+        },
+        Instruction::InvokeSpecial(_) => {
+            // calls to superclass methods start from our direct superclass (ACC_SUPER is implied since Java 8)
+            let start: &Class = if resolved.name != "<init>" && !ref_owner.is_interface() && current.is_subclass_of(&ref_owner)
+                && let Some(sc) = &current.super_class{ sc }else{ &ref_owner };
+            let (selected, selected_owner) = start.special_method(&target.name_and_type)?;
+            let class = class_ref(selected_owner);
+            let m_idx = method_index(&class, selected);
+            class::CallTarget::Special(class, m_idx)
+        },
+        _ => if resolved.visibility == class::Visibility::Private{
+            let class = class_ref(resolved_owner);
+            let m_idx = method_index(&class, resolved);
+            class::CallTarget::Special(class, m_idx)
+        }else if resolved_owner.is_interface(){
+            let class = class_ref(resolved_owner);
+            let m_idx = method_index(&class, resolved);
+            class::CallTarget::Interface(class, m_idx)
+        }else{
+            class::CallTarget::Virtual(resolved.vtable_idx.expect("Overridable method without a vtable slot"))
+        }
+    };
+    return Ok(class::CallSite{
+        target,
+        arg_count: resolved.parameters.len(),
+        protected_receiver
+    });
+}
+
+/// Finds the static method implementing an access mode for a VarHandle, which takes the handle and then the arguments
+/// of the call; each kind of handle declares (or inherits) one for each access mode it supports.
+fn var_handle_method(handle: &JRef, access_mode: &str) -> Option<(ClassRef, usize)>{
+    let mut cur = Some(&handle.deref().class);
+    while let Some(c) = cur{
+        if let Some(m_idx) = c.methods.iter().position(|m| m.is_static && m.name == access_mode
+            && m.parameters.first().is_some_and(|p| p.descriptor() == "Ljava/lang/invoke/VarHandle;")){
+            return Some((c.clone(), m_idx));
+        }
+        cur = c.super_class.as_ref();
+    }
+    return None;
+}
+
+fn class_ref(class: &Class) -> ClassRef{
+    return heap::get_or_create_bt_class(class.descriptor.clone())
+        .expect("Could not load member owner")
+        .ensure_loaded()
+        .expect("Could not load member owner");
+}
+
+fn method_index(class: &Class, method: &Method) -> usize{
+    return class.methods.iter()
+        .position(|m| m.name == method.name && m.descriptor() == method.descriptor())
+        .expect("Method not found in its owner");
+}
+
+/// Returns the class to select methods from for the given receiver; arrays only have the methods of Object.
+fn receiver_class(r: &JRef) -> ClassRef{
+    let obj = r.deref();
+    if let JObjectData::Array(_) = &*obj.data.read().unwrap(){
+        return heap::bt_class_by_desc("Ljava/lang/Object;".to_owned()).unwrap();
+    }
+    return obj.class.clone();
+}
+
+/// Stores a value into an array, returning the exception to throw if it can't be.
+fn array_store(array_ref: Option<JRef>, array_idx: i32, value: JValue, npe: &'static str) -> Result<(), &'static str>{
+    let Some(array_ref) = array_ref else { return Err(npe); };
+    let array = array_ref.deref();
+    let mut write = array.data.write().unwrap();
+    let JObjectData::Array(values) = &mut *write else { panic!("Tried to store into non-array reference!") };
+    if array_idx < 0 || array_idx as usize >= values.len(){
+        return Err("index out of bounds");
+    }
+    // boolean arrays share the representation of byte arrays, but only keep the lowest bit
+    let value = match value{
+        JValue::Int(v) if array.class.descriptor == "Z" => JValue::Int(v & 1),
+        _ => value
+    };
+    values.store(array_idx as usize, value);
+    return Ok(());
+}
+
+/// Loads a value from an array, returning the exception to throw if it can't be.
+fn array_load(array_ref: Option<JRef>, array_idx: i32, npe: &'static str) -> Result<JValue, &'static str>{
+    let Some(array_ref) = array_ref else { return Err(npe); };
+    let array = array_ref.deref();
+    let read = array.data.read().unwrap();
+    let JObjectData::Array(values) = &*read else { panic!("Tried to load from non-array reference!") };
+    if array_idx < 0 || array_idx as usize >= values.len(){
+        return Err("index out of bounds");
+    }
+    return Ok(values.load(array_idx as usize));
+}
+
+/// Creates a multidimensional array of the given array descriptor, with an array for every dimension given a length.
+fn create_multi_array(array_desc: &str, lengths: &[i32]) -> JValue{
+    let component_desc = &array_desc[1..];
+    let component = heap::get_or_create_bt_class(component_desc.to_owned())
+        .expect("Could not parse class for multianewarray instruction!")
+        .ensure_loaded()
+        .expect("Could not link class for multianewarray instruction!");
+    if lengths.len() == 1{
+        return objects::create_new_array(component, lengths[0] as usize);
+    }
+    let elements = (0..lengths[0]).map(|_| create_multi_array(component_desc, &lengths[1..])).collect();
+    return objects::create_new_array_of(component, elements);
 }
 
 fn to_char(v: i32) -> i32{
-    return v.clamp(u16::MIN as i32, u16::MAX as i32);
+    return v as u16 as i32;
 }
 
 fn to_byte(v: i32) -> i32{
-    return v.clamp(i8::MIN as i32, i8::MAX as i32);
+    return v as i8 as i32;
+}
+
+fn to_short(v: i32) -> i32{
+    return v as i16 as i32;
 }
 
 fn internal_name_to_desc(iname: &str) -> String{
@@ -1,8 +1,8 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::sync::RwLock;
 use rand::random;
 use crate::runtime::heap;
 
-use super::{heap::JRef, class::ClassRef};
+use super::{heap::JRef, class::ClassRef, monitors::Monitor};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum JValue{
@@ -11,15 +11,16 @@ pub enum JValue{
     Float(f32),
     Double(f64),
 
-    Second,
+    Reference(Option<JRef>), // None = null
 
-    Reference(Option<JRef>) // None = null
+    ReturnAddress(usize) // pushed by jsr, the index of the instruction to return to
 }
 
 #[derive(Debug)]
 pub struct JObject{
     pub class: ClassRef,
     pub identity_hash: i32, // just a random number
+    pub monitor: Monitor,
     pub data: RwLock<JObjectData>
 }
 
@@ -28,6 +29,7 @@ impl JObject{
         return JObject{
             class,
             identity_hash: random(),
+            monitor: Monitor::new(),
             data: RwLock::new(data)
         };
     }
@@ -35,13 +37,16 @@ impl JObject{
     /// Returns the descriptor of the type of this object.
     pub fn descriptor(&self) -> String{
         return match &*self.data.read().unwrap(){
-            JObjectData::Array(_, _) => format!("[{}", &self.class.descriptor),
+            JObjectData::Array(_) => format!("[{}", &self.class.descriptor),
             JObjectData::Fields(_) => self.class.descriptor.clone()
         };
     }
 
     /// Returns whether this object is assignable to the target descriptor, accounting for array objects.
     pub fn assignable_to(&self, desc: &str) -> bool{
+        if let JObjectData::Fields(_) = &*self.data.read().unwrap(){
+            return self.class.assignable_to(desc);
+        }
         return JObject::assignable_to_rec(self.descriptor(), desc.to_owned());
     }
 
@@ -60,10 +65,135 @@ impl JObject{
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum JObjectData{
-    Fields(HashMap<String, JValue>),
-    Array(usize, Vec<JValue>)
+    Fields(Vec<JValue>), // indexed by offset in the class's instance layout
+    Array(ArrayData)
+}
+
+/// The elements of an array, stored as their component type.
+#[derive(Debug, Clone)]
+pub enum ArrayData{
+    Byte(Vec<i8>), // and boolean
+    Char(Vec<u16>),
+    Short(Vec<i16>),
+    Int(Vec<i32>),
+    Long(Vec<i64>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    Reference(Vec<Option<JRef>>)
+}
+
+impl ArrayData{
+    /// Creates an array of the given component type and length, filled with default values.
+    pub fn new(component_desc: &str, length: usize) -> ArrayData{
+        return match component_desc{
+            "Z" | "B" => ArrayData::Byte(vec![0; length]),
+            "C" => ArrayData::Char(vec![0; length]),
+            "S" => ArrayData::Short(vec![0; length]),
+            "I" => ArrayData::Int(vec![0; length]),
+            "J" => ArrayData::Long(vec![0; length]),
+            "F" => ArrayData::Float(vec![0.0; length]),
+            "D" => ArrayData::Double(vec![0.0; length]),
+            _ => ArrayData::Reference(vec![None; length])
+        };
+    }
+
+    /// Creates an array of the given component type holding the given values.
+    pub fn of(component_desc: &str, values: Vec<JValue>) -> ArrayData{
+        let mut array = ArrayData::new(component_desc, values.len());
+        for (idx, value) in values.into_iter().enumerate(){
+            array.store(idx, value);
+        }
+        return array;
+    }
+
+    pub fn len(&self) -> usize{
+        return match self{
+            ArrayData::Byte(v) => v.len(),
+            ArrayData::Char(v) => v.len(),
+            ArrayData::Short(v) => v.len(),
+            ArrayData::Int(v) => v.len(),
+            ArrayData::Long(v) => v.len(),
+            ArrayData::Float(v) => v.len(),
+            ArrayData::Double(v) => v.len(),
+            ArrayData::Reference(v) => v.len()
+        };
+    }
+
+    /// Returns the element at the given index, which must be in bounds, widening bytes, chars, and shorts to ints.
+    pub fn load(&self, idx: usize) -> JValue{
+        return match self{
+            ArrayData::Byte(v) => JValue::Int(v[idx] as i32),
+            ArrayData::Char(v) => JValue::Int(v[idx] as i32),
+            ArrayData::Short(v) => JValue::Int(v[idx] as i32),
+            ArrayData::Int(v) => JValue::Int(v[idx]),
+            ArrayData::Long(v) => JValue::Long(v[idx]),
+            ArrayData::Float(v) => JValue::Float(v[idx]),
+            ArrayData::Double(v) => JValue::Double(v[idx]),
+            ArrayData::Reference(v) => JValue::Reference(v[idx])
+        };
+    }
+
+    /// Sets the element at the given index, which must be in bounds, truncating ints to bytes, chars, and shorts.
+    pub fn store(&mut self, idx: usize, value: JValue){
+        match (self, value){
+            (ArrayData::Byte(v), JValue::Int(i)) => v[idx] = i as i8,
+            (ArrayData::Char(v), JValue::Int(i)) => v[idx] = i as u16,
+            (ArrayData::Short(v), JValue::Int(i)) => v[idx] = i as i16,
+            (ArrayData::Int(v), JValue::Int(i)) => v[idx] = i,
+            (ArrayData::Long(v), JValue::Long(l)) => v[idx] = l,
+            (ArrayData::Float(v), JValue::Float(f)) => v[idx] = f,
+            (ArrayData::Double(v), JValue::Double(d)) => v[idx] = d,
+            (ArrayData::Reference(v), JValue::Reference(r)) => v[idx] = r,
+            (array, value) => panic!("Tried to store {:?} into {}", value, array.type_name())
+        }
+    }
+
+    /// Copies elements between two ranges of this array, as if through a temporary array.
+    pub fn copy_within(&mut self, src: usize, dest: usize, length: usize){
+        let range = src..src + length;
+        match self{
+            ArrayData::Byte(v) => v.copy_within(range, dest),
+            ArrayData::Char(v) => v.copy_within(range, dest),
+            ArrayData::Short(v) => v.copy_within(range, dest),
+            ArrayData::Int(v) => v.copy_within(range, dest),
+            ArrayData::Long(v) => v.copy_within(range, dest),
+            ArrayData::Float(v) => v.copy_within(range, dest),
+            ArrayData::Double(v) => v.copy_within(range, dest),
+            ArrayData::Reference(v) => v.copy_within(range, dest)
+        }
+    }
+
+    /// Copies elements from another array of the same representation, returning false if they differ.
+    pub fn copy_from(&mut self, from: &ArrayData, src: usize, dest: usize, length: usize) -> bool{
+        let (to_range, from_range) = (dest..dest + length, src..src + length);
+        match (self, from){
+            (ArrayData::Byte(to), ArrayData::Byte(from)) => to[to_range].copy_from_slice(&from[from_range]),
+            (ArrayData::Char(to), ArrayData::Char(from)) => to[to_range].copy_from_slice(&from[from_range]),
+            (ArrayData::Short(to), ArrayData::Short(from)) => to[to_range].copy_from_slice(&from[from_range]),
+            (ArrayData::Int(to), ArrayData::Int(from)) => to[to_range].copy_from_slice(&from[from_range]),
+            (ArrayData::Long(to), ArrayData::Long(from)) => to[to_range].copy_from_slice(&from[from_range]),
+            (ArrayData::Float(to), ArrayData::Float(from)) => to[to_range].copy_from_slice(&from[from_range]),
+            (ArrayData::Double(to), ArrayData::Double(from)) => to[to_range].copy_from_slice(&from[from_range]),
+            (ArrayData::Reference(to), ArrayData::Reference(from)) => to[to_range].copy_from_slice(&from[from_range]),
+            _ => return false
+        }
+        return true;
+    }
+
+    fn type_name(&self) -> &'static str{
+        return match self{
+            ArrayData::Byte(_) => "byte[]",
+            ArrayData::Char(_) => "char[]",
+            ArrayData::Short(_) => "short[]",
+            ArrayData::Int(_) => "int[]",
+            ArrayData::Long(_) => "long[]",
+            ArrayData::Float(_) => "float[]",
+            ArrayData::Double(_) => "double[]",
+            ArrayData::Reference(_) => "reference array"
+        };
+    }
 }
 
 impl JValue{
@@ -73,22 +203,15 @@ impl JValue{
             JValue::Long(_) => to.descriptor == "J",
             JValue::Float(_) => to.descriptor == "F",
             JValue::Double(_) => to.descriptor == "D",
-            JValue::Second => false,
             JValue::Reference(None) => to.descriptor.len() > 0, // any non-primitive
             JValue::Reference(Some(r)) => r.deref().class.assignable_to(&to.descriptor),
+            JValue::ReturnAddress(_) => false
         };
     }
 
-    pub fn class(&self) -> ClassRef{
-        return match self{
-            JValue::Int(_) => heap::bt_class_by_desc("I".to_owned()).unwrap(),
-            JValue::Long(_) => heap::bt_class_by_desc("J".to_owned()).unwrap(),
-            JValue::Float(_) => heap::bt_class_by_desc("F".to_owned()).unwrap(),
-            JValue::Double(_) => heap::bt_class_by_desc("D".to_owned()).unwrap(),
-            JValue::Second => { panic!("Tried to get the class of a long second value!") },
-            JValue::Reference(None) => heap::bt_class_by_desc("Ljava/lang/Object;".to_owned()).unwrap(),
-            JValue::Reference(Some(r)) => r.deref().class.clone(),
-        };
+    /// Returns whether this value takes up two local variable slots (longs and doubles).
+    pub fn is_wide(&self) -> bool{
+        return matches!(self, JValue::Long(_) | JValue::Double(_));
     }
 
     pub fn default_value_for(desc: &str) -> JValue{
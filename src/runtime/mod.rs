@@ -5,5 +5,9 @@ pub mod jvalue;
 pub mod classes;
 pub mod class;
 pub mod objects;
+pub mod monitors;
+pub mod threads;
+pub mod bootstraps;
+pub mod verifier;
 
 pub mod native_impls;
\ No newline at end of file
@@ -0,0 +1,261 @@
+// Object monitors, for synchronized blocks and methods, and Object.wait/notify.
+
+// A monitor starts as a thin lock: one word holding the owning thread and how many times it's entered the monitor,
+// which is updated with CAS. When another thread contends for it (or the owner waits on it), it's inflated into a
+// mutex and condition variables, which it then stays as. The inflated state is boxed, so objects whose monitors
+// are never contended only pay for the word.
+// Errors are the names of the exception to throw.
+
+use std::{cell::Cell, collections::VecDeque, sync::{Mutex, Condvar, OnceLock, atomic::{AtomicU32, AtomicU64, Ordering}}, time::{Duration, Instant}};
+
+const INFLATED: u64 = 1 << 63;
+
+static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1); // 0 = unowned
+
+thread_local!{
+    static THREAD_ID: u32 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    /// How many times this thread has entered monitors without exiting them, counting reentry.
+    static ENTRIES_HELD: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns the id identifying this thread as the owner of monitors.
+pub fn thread_id() -> u32{
+    return THREAD_ID.with(|id| *id);
+}
+
+/// Returns how many monitor entries this thread holds, for continuations, which can't yield while holding one.
+pub fn entries_held() -> usize{
+    return ENTRIES_HELD.get();
+}
+
+#[derive(Debug)]
+pub struct Monitor{
+    word: AtomicU64, // if not inflated, the owner's thread id in the upper half and the entry count in the lower half
+    inflated: OnceLock<Box<Inflated>>
+}
+
+#[derive(Debug)]
+struct Inflated{
+    state: Mutex<InflatedState>,
+    entry: Condvar, // signalled when the monitor is released
+    waiting: Condvar // signalled when waiters are notified, who each check if it was them
+}
+
+#[derive(Debug)]
+struct InflatedState{
+    owner: u32,
+    count: u32,
+    wait_set: VecDeque<u64>, // tickets of the threads waiting to be notified, in the order they started waiting
+    next_ticket: u64
+}
+
+fn thin(owner: u32, count: u32) -> u64{
+    return ((owner as u64) << 32) | count as u64;
+}
+
+fn thin_owner(word: u64) -> u32{
+    return ((word & !INFLATED) >> 32) as u32;
+}
+
+impl Monitor{
+    pub fn new() -> Self{
+        return Monitor{ word: AtomicU64::new(0), inflated: OnceLock::new() };
+    }
+
+    /// Enters the monitor, blocking until no other thread owns it.
+    pub fn enter(&self){
+        self.acquire();
+        ENTRIES_HELD.set(ENTRIES_HELD.get() + 1);
+    }
+
+    /// Exits the monitor once, releasing it if that was the last entry.
+    pub fn exit(&self) -> Result<(), &'static str>{
+        self.release()?;
+        ENTRIES_HELD.set(ENTRIES_HELD.get() - 1);
+        return Ok(());
+    }
+
+    fn acquire(&self){
+        let me = thread_id();
+        loop{
+            let word = self.word.load(Ordering::Acquire);
+            if word & INFLATED != 0{
+                return self.inflated.get().unwrap().enter(me);
+            }
+            let entered = if word == 0{
+                thin(me, 1)
+            }else if thin_owner(word) == me{
+                word + 1
+            }else{
+                self.inflate(word);
+                continue;
+            };
+            if self.word.compare_exchange(word, entered, Ordering::Acquire, Ordering::Relaxed).is_ok(){
+                return;
+            }
+        }
+    }
+
+    fn release(&self) -> Result<(), &'static str>{
+        let me = thread_id();
+        loop{
+            let word = self.word.load(Ordering::Acquire);
+            if word & INFLATED != 0{
+                return self.inflated.get().unwrap().exit(me);
+            }
+            if thin_owner(word) != me{
+                return Err("IllegalMonitorStateException");
+            }
+            let exited = if word == thin(me, 1){ 0 }else{ word - 1 };
+            // fails if another thread is inflating it
+            if self.word.compare_exchange(word, exited, Ordering::Release, Ordering::Relaxed).is_ok(){
+                return Ok(());
+            }
+        }
+    }
+
+    /// Releases the monitor and waits until notified, interrupted, or the timeout passes, then enters it again as
+    /// many times as before. Spurious wakeups can happen, as Java allows.
+    pub fn wait(&self, timeout: Option<Duration>, interrupted: impl Fn() -> bool) -> Result<(), &'static str>{
+        return self.owned_inflated()?.wait(thread_id(), timeout, interrupted);
+    }
+
+    /// Wakes up every thread waiting on this monitor without notifying them, so they can check if they've been
+    /// interrupted.
+    pub fn wake_waiters(&self){
+        if let Some(inflated) = self.inflated.get(){
+            let _state = inflated.state.lock().unwrap();
+            inflated.waiting.notify_all();
+        }
+    }
+
+    /// Returns whether the current thread owns this monitor.
+    pub fn held_by_current_thread(&self) -> bool{
+        let word = self.word.load(Ordering::Acquire);
+        if word & INFLATED != 0{
+            return self.inflated.get().unwrap().state.lock().unwrap().owner == thread_id();
+        }
+        return thin_owner(word) == thread_id();
+    }
+
+    /// Wakes up one or all of the threads waiting on this monitor.
+    pub fn notify(&self, all: bool) -> Result<(), &'static str>{
+        let word = self.word.load(Ordering::Acquire);
+        if word & INFLATED == 0{
+            // only an inflated monitor can have waiters
+            return if thin_owner(word) == thread_id(){ Ok(()) }else{ Err("IllegalMonitorStateException") };
+        }
+        return self.inflated.get().unwrap().notify(thread_id(), all);
+    }
+
+    /// Inflates the monitor if it's still in the given thin state; the current owner keeps it.
+    fn inflate(&self, word: u64){
+        let inflated = self.inflated.get_or_init(|| Box::new(Inflated::new()));
+        // the owner has to take this lock to release an inflated monitor, so it can't until we're done
+        let mut state = inflated.state.lock().unwrap();
+        if self.word.compare_exchange(word, word | INFLATED, Ordering::AcqRel, Ordering::Relaxed).is_ok(){
+            state.owner = thin_owner(word);
+            state.count = word as u32;
+        }
+    }
+
+    /// Inflates the monitor if the current thread owns it, to wait on it.
+    fn owned_inflated(&self) -> Result<&Inflated, &'static str>{
+        loop{
+            let word = self.word.load(Ordering::Acquire);
+            if word & INFLATED != 0{
+                return Ok(self.inflated.get().unwrap());
+            }
+            if thin_owner(word) != thread_id(){
+                return Err("IllegalMonitorStateException");
+            }
+            self.inflate(word);
+        }
+    }
+}
+
+impl Inflated{
+    fn new() -> Self{
+        let state = InflatedState{ owner: 0, count: 0, wait_set: VecDeque::new(), next_ticket: 0 };
+        return Inflated{ state: Mutex::new(state), entry: Condvar::new(), waiting: Condvar::new() };
+    }
+
+    fn enter(&self, me: u32){
+        let mut state = self.state.lock().unwrap();
+        if state.owner == me{
+            state.count += 1;
+            return;
+        }
+        while state.owner != 0{
+            state = self.entry.wait(state).unwrap();
+        }
+        state.owner = me;
+        state.count = 1;
+    }
+
+    fn exit(&self, me: u32) -> Result<(), &'static str>{
+        let mut state = self.state.lock().unwrap();
+        if state.owner != me{
+            return Err("IllegalMonitorStateException");
+        }
+        state.count -= 1;
+        if state.count == 0{
+            state.owner = 0;
+            self.entry.notify_one();
+        }
+        return Ok(());
+    }
+
+    fn wait(&self, me: u32, timeout: Option<Duration>, interrupted: impl Fn() -> bool) -> Result<(), &'static str>{
+        let mut state = self.state.lock().unwrap();
+        if state.owner != me{
+            return Err("IllegalMonitorStateException");
+        }
+        let count = state.count;
+        state.owner = 0;
+        state.count = 0;
+        self.entry.notify_one();
+
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.wait_set.push_back(ticket);
+        let deadline = timeout.map(|t| Instant::now() + t);
+        // being notified takes our ticket out of the wait set
+        while state.wait_set.contains(&ticket) && !interrupted(){
+            match deadline{
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline{
+                        break;
+                    }
+                    state = self.waiting.wait_timeout(state, deadline - now).unwrap().0;
+                },
+                None => state = self.waiting.wait(state).unwrap()
+            }
+        }
+        // if we timed out or were interrupted, we leave without being notified
+        state.wait_set.retain(|t| *t != ticket);
+
+        while state.owner != 0{
+            state = self.entry.wait(state).unwrap();
+        }
+        state.owner = me;
+        state.count = count;
+        return Ok(());
+    }
+
+    fn notify(&self, me: u32, all: bool) -> Result<(), &'static str>{
+        let mut state = self.state.lock().unwrap();
+        if state.owner != me{
+            return Err("IllegalMonitorStateException");
+        }
+        if all{
+            state.wait_set.clear();
+        }else{
+            state.wait_set.pop_front();
+        }
+        // any waiter could be woken by notify_one, so they all check whether they were the one notified
+        self.waiting.notify_all();
+        return Ok(());
+    }
+}
@@ -1,9 +1,9 @@
 use std::ffi::c_void;
 use std::ops::Deref;
-use libc::{c_char, c_uint};
+use libc::c_uint;
 use crate::constants;
-use crate::runtime::interpreter::MethodResult;
-use crate::runtime::jvalue::{JObjectData, JValue};
+use crate::runtime::interpreter::{MethodResult, StackTrace};
+use crate::runtime::jvalue::{ArrayData, JObjectData, JValue};
 use crate::runtime::objects;
 
 pub fn builtin_file_input_stream_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
@@ -36,26 +36,24 @@ fn write_bytes(args: Vec<JValue>) -> MethodResult{
     let obj = this.deref();
     let data = obj.data.read();
     let JObjectData::Fields(f) = &*data.unwrap() else { panic!("Expected this to have fields for writeBytes") };
-    let Some(JValue::Reference(Some(fd_ref))) = f.get(constants::FOS_FD_FIELD_NAME) else { panic!("Expected this to have fd for writeBytes") };
+    let Some(JValue::Reference(Some(fd_ref))) = obj.class.field_offset(constants::FOS_FD_FIELD_NAME).and_then(|o| f.get(o)) else { panic!("Expected this to have fd for writeBytes") };
     // ... but this is a reference to a FileDescriptor, with its own field `fd`...
     let fd_obj = fd_ref.deref();
     let fd_data = fd_obj.data.read();
     let JObjectData::Fields(fd_f) = &*fd_data.unwrap() else { panic!("Expected FieldDescriptor to have fields for writeBytes!") };
-    let Some(JValue::Int(fd)) = fd_f.get(constants::FOS_FD_FIELD_NAME) else { panic!("Expected FD int for writeBytes!") };
+    let Some(JValue::Int(fd)) = fd_obj.class.field_offset(constants::FOS_FD_FIELD_NAME).and_then(|o| fd_f.get(o)) else { panic!("Expected FD int for writeBytes!") };
 
     let bytes_obj = bytes.deref();
-    let JObjectData::Array(_, arr) = &*bytes_obj.data.read().unwrap() else { panic!("a") };
-    // ...but we want a direct array of bytes...
-    let mut buf: Vec<c_char> = Vec::new();
-    for value in arr {
-        let JValue::Int(v) = &value else { panic!("aa") };
-        buf.push(*v as c_char);
+    let JObjectData::Array(ArrayData::Byte(arr)) = &*bytes_obj.data.read().unwrap() else { panic!("Expected byte[] for writeBytes") };
+    // ...and then we can write the array's bytes directly
+    if off < 0 || len < 0 || (off + len) as usize > arr.len(){
+        return MethodResult::Throw(StackTrace::new(), "index out of bounds");
     }
 
     unsafe{
         //let mode = OsString::from("");
         //let file = libc::fdopen(fd, mode.raw);
-        libc::write(*fd, (buf.as_ptr() as *const c_void).add(off as usize), len as c_uint);
+        libc::write(*fd, arr.as_ptr().add(off as usize) as *const c_void, len as c_uint);
     }
 
     return MethodResult::Finish;
@@ -1,5 +1,6 @@
 use crate::constants;
-use crate::runtime::{jvalue::{JValue, JObjectData}, interpreter::{MethodResult, StackTrace}, objects, heap};
+use crate::parser::classfile_structs::{Attribute, NameAndType, InnerClassInfo};
+use crate::runtime::{jvalue::{JValue, JObjectData}, interpreter::{MethodResult, StackTrace}, objects, heap, class::{ClassRef, RawAnnotations, Visibility}};
 
 pub fn builtin_class_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
     return match name_and_desc{
@@ -8,6 +9,28 @@ pub fn builtin_class_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodRes
         "desiredAssertionStatus0(Ljava/lang/Class;)Z" => const_1_i,
         "isArray()Z" => is_array_z,
         "isPrimitive()Z" => is_primitive_z,
+        "initClassName()Ljava/lang/String;" => init_class_name_str,
+        "isInstance(Ljava/lang/Object;)Z" => is_instance_z,
+        "forName0(Ljava/lang/String;ZLjava/lang/ClassLoader;Ljava/lang/Class;)Ljava/lang/Class;" => for_name_class,
+        "isInterface()Z" => is_interface_z,
+        "isHidden()Z" => const_0_i,
+        "getModifiers()I" => get_modifiers_i,
+        "getSuperclass()Ljava/lang/Class;" => get_superclass_class,
+        "getNestHost0()Ljava/lang/Class;" => get_nest_host_class,
+        "getNestMembers0()[Ljava/lang/Class;" => get_nest_members_arr,
+        "getInterfaces0()[Ljava/lang/Class;" => get_interfaces_arr,
+        "isRecord0()Z" => is_record_z,
+        "getRecordComponents0()[Ljava/lang/reflect/RecordComponent;" => get_record_components_arr,
+        "getPermittedSubclasses0()[Ljava/lang/Class;" => get_permitted_subclasses_arr,
+        "getPermittedSubclasses()[Ljava/lang/Class;" => get_direct_permitted_subclasses_arr,
+        "getDeclaredMethods0(Z)[Ljava/lang/reflect/Method;" => get_declared_methods_arr,
+        "getDeclaringClass0()Ljava/lang/Class;" => get_declaring_class_class,
+        "getEnclosingMethod0()[Ljava/lang/Object;" => get_enclosing_method_arr,
+        "getSimpleBinaryName0()Ljava/lang/String;" => get_simple_binary_name_str,
+        "getGenericSignature0()Ljava/lang/String;" => get_generic_signature_str,
+        "getConstantPool()Ljdk/internal/reflect/ConstantPool;" => get_constant_pool,
+        "getRawAnnotations()[B" => get_raw_annotations_arr,
+        "getRawTypeAnnotations()[B" => get_raw_type_annotations_arr,
         _ => panic!("Unknown java.lang.Class native: {}", name_and_desc)
     };
 }
@@ -32,7 +55,11 @@ fn get_primitive_class_str_class(params: Vec<JValue>) -> MethodResult{
         "void" => "V",
         other => panic!("Tried to create primitive class for {}!", other)
     };
-    return MethodResult::FinishWithValue(heap::add_ref(objects::synthesize_class(&desc.to_string())));
+    return MethodResult::FinishWithValue(objects::class_object(&desc.to_string()));
+}
+
+fn const_0_i(_: Vec<JValue>) -> MethodResult{
+    return MethodResult::FinishWithValue(JValue::Int(0));
 }
 
 fn const_1_i(_: Vec<JValue>) -> MethodResult{
@@ -57,8 +84,285 @@ fn is_primitive_z(p: Vec<JValue>) -> MethodResult{
     }
 }
 
+fn is_instance_z(p: Vec<JValue>) -> MethodResult{
+    let Some(desc) = get_desc_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class descriptor in Class::isInstance") };
+    return match p[1]{
+        JValue::Reference(Some(obj)) => MethodResult::FinishWithValue(JValue::Int(obj.deref().assignable_to(&desc) as i32)),
+        _ => MethodResult::FinishWithValue(JValue::Int(0))
+    };
+}
+
+fn for_name_class(p: Vec<JValue>) -> MethodResult{
+    // String name, boolean initialize, ClassLoader, Class caller
+    // TODO: use the given loader, and throw ClassNotFoundException instead of panicking in the bootstrap loader
+    let name = objects::java_string_to_rust_string(p[0]).replace(".", "/");
+    let desc = if name.starts_with("["){ name }else{ format!("L{};", name) };
+    let class = heap::get_or_create_bt_class(desc.clone()).expect("Could not load class for Class::forName0");
+    let linked = if p[1] == JValue::Int(1){ class.ensure_initialized() }else{ class.ensure_loaded() };
+    if linked.is_err(){
+        return MethodResult::Throw(StackTrace::new(), "LinkageError");
+    }
+    return MethodResult::FinishWithValue(objects::class_object(&desc));
+}
+
+fn is_interface_z(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::isInterface") };
+    return MethodResult::FinishWithValue(JValue::Int(class.is_interface() as i32));
+}
+
+fn get_modifiers_i(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getModifiers") };
+    // ACC_SUPER isn't a modifier
+    return MethodResult::FinishWithValue(JValue::Int((class.flags & !constants::CLASS_ACC_SUPER) as i32));
+}
+
+fn get_superclass_class(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getSuperclass") };
+    // interfaces and primitives have none, and array classes here have their component as super class
+    if class.is_interface() || class.descriptor.len() == 1{
+        return MethodResult::FinishWithValue(JValue::Reference(None));
+    }
+    if class.descriptor.starts_with("["){
+        return MethodResult::FinishWithValue(objects::class_object(&"Ljava/lang/Object;".to_string()));
+    }
+    return MethodResult::FinishWithValue(match &class.super_class{
+        Some(sc) => objects::class_object(&sc.descriptor),
+        None => JValue::Reference(None)
+    });
+}
+
+fn get_nest_host_class(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getNestHost0") };
+    return MethodResult::FinishWithValue(objects::class_object(&class.nest_host().to_string()));
+}
+
+fn get_nest_members_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getNestMembers0") };
+    // the host, then the members it lists that agree it's their host
+    let host_desc = class.nest_host().to_string();
+    let Some(host) = heap::get_or_create_bt_class(host_desc.clone()).ok().and_then(|h| h.ensure_loaded().ok()) else {
+        return MethodResult::Throw(StackTrace::new(), "Could not load nest host in Class::getNestMembers0");
+    };
+    let mut members = vec![objects::class_object(&host_desc)];
+    for member in &host.nest_members{
+        let desc = format!("L{};", member);
+        if let Ok(member) = heap::get_or_create_bt_class(desc.clone())
+        && let Ok(member) = member.ensure_loaded()
+        && member.nest_host() == host_desc{
+            members.push(objects::class_object(&desc));
+        }
+    }
+    return MethodResult::FinishWithValue(objects::create_new_array_of(objects::class_class(), members));
+}
+
+fn get_interfaces_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getInterfaces0") };
+    let interfaces = class.interfaces.iter().map(|i| objects::class_object(&i.descriptor)).collect();
+    return MethodResult::FinishWithValue(objects::create_new_array_of(objects::class_class(), interfaces));
+}
+
+fn is_record_z(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::isRecord0") };
+    return MethodResult::FinishWithValue(JValue::Int(class.record_components.is_some() as i32));
+}
+
+fn get_record_components_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getRecordComponents0") };
+    let Some(components) = &class.record_components else { return MethodResult::FinishWithValue(JValue::Reference(None)) };
+    let component_class = objects::force_init_class("Ljava/lang/reflect/RecordComponent;");
+    let mut created = Vec::with_capacity(components.len());
+    for component in components{
+        // the accessor is the public method with the component's name and type
+        let accessor = NameAndType{ name: component.name.clone(), descriptor: format!("(){}", component.desc) };
+        let accessor = class.methods.iter().position(|m| m.name == accessor.name && m.descriptor() == accessor.descriptor)
+            .map_or(JValue::Reference(None), |idx| method_object(&class, idx));
+        let mut annotations = RawAnnotations::default();
+        for attr in &component.attributes{
+            match attr{
+                Attribute::RuntimeVisibleAnnotations(_, raw) => annotations.annotations = Some(raw.clone()),
+                Attribute::RuntimeVisibleTypeAnnotations(_, raw) => annotations.type_annotations = Some(raw.clone()),
+                _ => {}
+            }
+        }
+        let object = objects::create_new(component_class.clone());
+        set_fields(object, &component_class, &[
+            ("clazz", objects::class_object(&class.descriptor)),
+            ("name", objects::intern_string(&component.name)),
+            ("type", objects::class_object(&component.desc)),
+            ("accessor", accessor),
+            ("annotations", raw_bytes(&annotations.annotations)),
+            ("typeAnnotations", raw_bytes(&annotations.type_annotations))
+        ]);
+        created.push(object);
+    }
+    return MethodResult::FinishWithValue(objects::create_new_array_of(component_class, created));
+}
+
+fn get_permitted_subclasses_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getPermittedSubclasses0") };
+    let Some(permitted) = &class.permitted_subclasses else { return MethodResult::FinishWithValue(JValue::Reference(None)) };
+    // Class checks that they really are subclasses, which loads them
+    let permitted = permitted.iter().map(|p| objects::class_object(&format!("L{};", p))).collect();
+    return MethodResult::FinishWithValue(objects::create_new_array_of(objects::class_class(), permitted));
+}
+
+// implemented in place of Class.getPermittedSubclasses, which filters with a lambda
+fn get_direct_permitted_subclasses_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getPermittedSubclasses") };
+    let Some(permitted) = &class.permitted_subclasses else { return MethodResult::FinishWithValue(JValue::Reference(None)) };
+    // like Class, leave out any that can't be loaded or don't directly extend this class
+    let subclasses = permitted.iter()
+        .filter_map(|p| heap::get_or_create_bt_class(format!("L{};", p)).ok()?.ensure_loaded().ok())
+        .filter(|sub| sub.super_class.iter().chain(&sub.interfaces).any(|sup| sup.name == class.name))
+        .map(|sub| objects::class_object(&sub.descriptor))
+        .collect();
+    return MethodResult::FinishWithValue(objects::create_new_array_of(objects::class_class(), subclasses));
+}
+
+fn get_declared_methods_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getDeclaredMethods0") };
+    let public_only = p[1] == JValue::Int(1);
+    let methods = class.methods.iter().enumerate()
+        .filter(|(_, m)| m.name != "<init>" && m.name != "<clinit>")
+        .filter(|(_, m)| !public_only || m.visibility == Visibility::Public)
+        .map(|(idx, _)| method_object(&class, idx))
+        .collect();
+    let method_class = objects::force_init_class("Ljava/lang/reflect/Method;");
+    return MethodResult::FinishWithValue(objects::create_new_array_of(method_class, methods));
+}
+
+fn get_declaring_class_class(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getDeclaringClass0") };
+    // local and anonymous classes have no declaring class, only an enclosing one
+    return MethodResult::FinishWithValue(match own_inner_class_info(&class).and_then(|info| info.outer_class.as_ref()){
+        Some(outer) => objects::class_object(&format!("L{};", outer)),
+        None => JValue::Reference(None)
+    });
+}
+
+fn get_enclosing_method_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getEnclosingMethod0") };
+    let Some((owner, method)) = &class.enclosing_method else { return MethodResult::FinishWithValue(JValue::Reference(None)) };
+    // the enclosing class, and the method's name and descriptor if it's in one
+    let (name, desc) = match method{
+        Some(method) => (objects::intern_string(&method.name), objects::intern_string(&method.descriptor)),
+        None => (JValue::Reference(None), JValue::Reference(None))
+    };
+    let info = vec![objects::class_object(&format!("L{};", owner)), name, desc];
+    return MethodResult::FinishWithValue(objects::create_new_array_of(objects::force_init_class("Ljava/lang/Object;"), info));
+}
+
+fn get_simple_binary_name_str(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getSimpleBinaryName0") };
+    return MethodResult::FinishWithValue(match own_inner_class_info(&class).and_then(|info| info.name.as_ref()){
+        Some(name) => objects::intern_string(name),
+        None => JValue::Reference(None)
+    });
+}
+
+fn get_generic_signature_str(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getGenericSignature0") };
+    return MethodResult::FinishWithValue(class.signature.as_ref().map_or(JValue::Reference(None), objects::intern_string));
+}
+
+fn get_raw_annotations_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getRawAnnotations") };
+    return MethodResult::FinishWithValue(raw_bytes(&class.annotations.annotations));
+}
+
+fn get_raw_type_annotations_arr(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_class_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in Class::getRawTypeAnnotations") };
+    return MethodResult::FinishWithValue(raw_bytes(&class.annotations.type_annotations));
+}
+
+fn init_class_name_str(p: Vec<JValue>) -> MethodResult{
+    let Some(desc) = get_desc_first(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class descriptor in Class::initClassName") };
+    let name = objects::intern_string(&binary_name(&desc));
+    // Class.getName caches it in the name field, which it expects this to set
+    if let JValue::Reference(Some(this)) = &p[0]
+    && let Some(offset) = objects::class_class().declared_field_offset("name")
+    && let JObjectData::Fields(fields) = &mut *this.deref().data.write().unwrap(){
+        fields[offset] = name;
+    }
+    return MethodResult::FinishWithValue(name);
+}
+
 // impl
 
+/// Returns the name of a class as given by Class.getName, e.g. `java.lang.String`, `int`, or `[Ljava.lang.String;`.
+fn binary_name(desc: &str) -> String{
+    let name = match desc{
+        "Z" => "boolean",
+        "B" => "byte",
+        "S" => "short",
+        "C" => "char",
+        "I" => "int",
+        "J" => "long",
+        "F" => "float",
+        "D" => "double",
+        "V" => "void",
+        _ if desc.starts_with("[") => desc,
+        _ => &desc[1..desc.len() - 1]
+    };
+    return name.replace("/", ".");
+}
+
+/// Returns a byte array of an attribute's contents, or null if there isn't one.
+pub fn raw_bytes(bytes: &Option<Vec<u8>>) -> JValue{
+    return match bytes{
+        Some(bytes) => objects::create_byte_array(bytes.iter().map(|b| *b as i8).collect()),
+        None => JValue::Reference(None)
+    };
+}
+
+/// Returns the InnerClasses entry describing the class itself, which it has if it's nested.
+fn own_inner_class_info(class: &ClassRef) -> Option<&InnerClassInfo>{
+    return class.inner_classes.iter().find(|info| format!("L{};", info.inner_class) == class.descriptor);
+}
+
+fn get_constant_pool(p: Vec<JValue>) -> MethodResult{
+    // the pool is identified by the class object that owns it
+    let pool_class = objects::force_init_class("Ljdk/internal/reflect/ConstantPool;");
+    let pool = objects::create_new(pool_class.clone());
+    set_fields(pool, &pool_class, &[("constantPoolOop", p[0])]);
+    return MethodResult::FinishWithValue(pool);
+}
+
+/// Creates a java.lang.reflect.Method for the method at the given index, which is its slot.
+fn method_object(class: &ClassRef, idx: usize) -> JValue{
+    let method = &class.methods[idx];
+    let method_class = objects::force_init_class("Ljava/lang/reflect/Method;");
+    let parameters = method.parameters.iter().map(|p| objects::class_object(&p.descriptor())).collect();
+    let exceptions = method.exceptions.iter().map(|e| objects::class_object(&format!("L{};", e))).collect();
+    let object = objects::create_new(method_class.clone());
+    set_fields(object, &method_class, &[
+        ("clazz", objects::class_object(&class.descriptor)),
+        ("name", objects::intern_string(&method.name)),
+        ("parameterTypes", objects::create_new_array_of(objects::class_class(), parameters)),
+        ("returnType", objects::class_object(&method.return_type.descriptor())),
+        ("exceptionTypes", objects::create_new_array_of(objects::class_class(), exceptions)),
+        ("modifiers", JValue::Int(method.flags as i32)),
+        ("slot", JValue::Int(idx as i32)),
+        ("signature", method.signature.as_ref().map_or(JValue::Reference(None), objects::intern_string)),
+        ("annotations", raw_bytes(&method.annotations.annotations)),
+        ("parameterAnnotations", raw_bytes(&method.annotations.parameter_annotations)),
+        ("annotationDefault", raw_bytes(&method.annotations.annotation_default))
+    ]);
+    return object;
+}
+
+fn set_fields(object: JValue, class: &ClassRef, values: &[(&str, JValue)]){
+    let JValue::Reference(Some(object)) = object else { return };
+    let JObjectData::Fields(fields) = &mut *object.deref().data.write().unwrap() else { return };
+    for (name, value) in values{
+        fields[class.field_offset(name).unwrap()] = *value;
+    }
+}
+
+fn get_class_first(p: &Vec<JValue>) -> Option<ClassRef>{
+    return heap::get_or_create_bt_class(get_desc_first(p)?).ok()?.ensure_loaded().ok();
+}
+
 pub fn get_desc_first(p: &Vec<JValue>) -> Option<String>{
     return get_class_desc(&p[0]);
 }
@@ -68,7 +372,7 @@ pub fn get_class_desc(this: &JValue) -> Option<String>{
         let obj = this.deref();
         let data = obj.data.read();
         if let JObjectData::Fields(f) = &*data.unwrap(){
-            let class_desc = f.get(constants::CLASS_DESC_FIELD_NAME);
+            let class_desc = f.get(objects::class_desc_offset());
             if let Some(desc) = class_desc{
                 let as_str = objects::java_string_to_rust_string(*desc);
                 return Some(as_str);
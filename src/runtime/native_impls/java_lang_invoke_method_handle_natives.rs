@@ -0,0 +1,150 @@
+use crate::parser::classfile_structs::NameAndType;
+use crate::runtime::{heap, objects};
+use crate::runtime::class::{Class, ClassRef, FieldLocation};
+use crate::runtime::heap::JRef;
+use crate::runtime::native_impls::{java_lang_class, jdk_internal_misc_unsafe};
+use crate::runtime::{jvalue::{JValue, JObjectData}, interpreter::{MethodResult, StackTrace}};
+
+// MemberName.flags, as in MethodHandleNatives.Constants
+const MN_IS_FIELD: i32 = 0x00040000;
+const MN_TRUSTED_FINAL: i32 = 0x00200000;
+const MN_REFERENCE_KIND_SHIFT: i32 = 24;
+const MN_REFERENCE_KIND_MASK: i32 = 0x0F;
+// public, private, protected, static, final, volatile, transient, synthetic, enum
+const RECOGNIZED_FIELD_MODIFIERS: i32 = 0x50DF;
+
+// reference kinds (JVMS 5.4.3.5)
+const REF_GET_FIELD: i32 = 1;
+const REF_GET_STATIC: i32 = 2;
+const REF_PUT_FIELD: i32 = 3;
+const REF_PUT_STATIC: i32 = 4;
+
+// only fields can be resolved so far, which is enough for VarHandles
+
+pub fn builtin_method_handle_natives_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "registerNatives()V" => no_op_v,
+        "getNamedCon(I[Ljava/lang/Object;)I" => get_named_con_i,
+        "resolve(Ljava/lang/invoke/MemberName;Ljava/lang/Class;IZ)Ljava/lang/invoke/MemberName;" => resolve_member_name,
+        "objectFieldOffset(Ljava/lang/invoke/MemberName;)J" => object_field_offset_j,
+        "staticFieldBase(Ljava/lang/invoke/MemberName;)Ljava/lang/Object;" => static_field_base_obj,
+        "staticFieldOffset(Ljava/lang/invoke/MemberName;)J" => static_field_offset_j,
+        "getMemberVMInfo(Ljava/lang/invoke/MemberName;)Ljava/lang/Object;" => get_member_vm_info_obj,
+        _ => panic!("Unknown java.lang.invoke.MethodHandleNatives native: {}", name_and_desc)
+    };
+}
+
+fn no_op_v(_: Vec<JValue>) -> MethodResult{
+    return MethodResult::Finish;
+}
+
+fn get_named_con_i(_: Vec<JValue>) -> MethodResult{
+    // we have no constants for MethodHandleNatives to check against its own, so leave the name unset
+    return MethodResult::FinishWithValue(JValue::Int(0));
+}
+
+fn resolve_member_name(args: Vec<JValue>) -> MethodResult{
+    // MemberName, Class caller, int lookup mode, boolean speculative
+    let JValue::Reference(Some(member)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in MethodHandleNatives::resolve") };
+    let speculative = args[3] == JValue::Int(1);
+    let JValue::Int(flags) = get_member_field(member, "flags") else { return MethodResult::MachineError("expected int flags for MemberName") };
+    if flags & MN_IS_FIELD == 0{
+        return MethodResult::Throw(StackTrace::new(), "UnsupportedOperationException");
+    }
+    let Some((owner, location)) = member_field(member) else {
+        return if speculative{
+            MethodResult::FinishWithValue(JValue::Reference(None))
+        }else{
+            MethodResult::Throw(StackTrace::new(), "NoSuchFieldError")
+        };
+    };
+    let (field_flags, is_static) = match location{
+        FieldLocation::Instance(idx) => (owner.instance_fields[idx].flags as i32, false),
+        FieldLocation::Static(idx) => (owner.static_fields[idx].read().unwrap().0.flags as i32, true)
+    };
+    let old_kind = (flags >> MN_REFERENCE_KIND_SHIFT) & MN_REFERENCE_KIND_MASK;
+    let mut kind = if is_static{ REF_GET_STATIC }else{ REF_GET_FIELD };
+    if old_kind == REF_PUT_FIELD || old_kind == REF_PUT_STATIC{
+        kind += REF_PUT_FIELD - REF_GET_FIELD;
+    }
+    let mut resolved = (field_flags & RECOGNIZED_FIELD_MODIFIERS) | MN_IS_FIELD | (kind << MN_REFERENCE_KIND_SHIFT);
+    // static finals can't be changed through reflection
+    if is_static && field_flags & crate::constants::ACC_FINAL as i32 != 0{
+        resolved |= MN_TRUSTED_FINAL;
+    }
+    set_member_field(member, "flags", JValue::Int(resolved));
+    set_member_field(member, "clazz", objects::class_object(&owner.descriptor));
+    return MethodResult::FinishWithValue(args[0]);
+}
+
+fn object_field_offset_j(args: Vec<JValue>) -> MethodResult{
+    // MemberName
+    let JValue::Reference(Some(member)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in MethodHandleNatives::objectFieldOffset") };
+    return match member_offset(member){
+        Some(offset) => MethodResult::FinishWithValue(JValue::Long(offset)),
+        None => MethodResult::Throw(StackTrace::new(), "InternalError")
+    };
+}
+
+fn static_field_base_obj(args: Vec<JValue>) -> MethodResult{
+    // MemberName
+    let JValue::Reference(Some(member)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in MethodHandleNatives::staticFieldBase") };
+    let Some((owner, _)) = member_field(member) else { return MethodResult::Throw(StackTrace::new(), "InternalError") };
+    return MethodResult::FinishWithValue(objects::class_object(&owner.descriptor));
+}
+
+fn static_field_offset_j(args: Vec<JValue>) -> MethodResult{
+    // MemberName
+    return object_field_offset_j(args);
+}
+
+fn get_member_vm_info_obj(args: Vec<JValue>) -> MethodResult{
+    // MemberName
+    // used in assertions, as { Long vmindex, Object vmtarget }; for fields, the offset and the declaring class
+    let JValue::Reference(Some(member)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in MethodHandleNatives::getMemberVMInfo") };
+    let Some(offset) = member_offset(member) else { return MethodResult::Throw(StackTrace::new(), "InternalError") };
+    let object_class = objects::force_init_class("Ljava/lang/Object;");
+    return MethodResult::FinishWithValue(objects::create_new_array_of(object_class, vec![objects::box_primitive(JValue::Long(offset), "J"), get_member_field(member, "clazz")]));
+}
+
+// impl
+
+fn member_name_class() -> ClassRef{
+    return objects::force_init_class("Ljava/lang/invoke/MemberName;");
+}
+
+fn get_member_field(member: JRef, name: &str) -> JValue{
+    let offset = member_name_class().declared_field_offset(name).expect("MemberName field missing");
+    let JObjectData::Fields(fields) = &*member.deref().data.read().unwrap() else { unreachable!() };
+    return fields[offset];
+}
+
+fn set_member_field(member: JRef, name: &str, value: JValue){
+    let offset = member_name_class().declared_field_offset(name).expect("MemberName field missing");
+    if let JObjectData::Fields(fields) = &mut *member.deref().data.write().unwrap(){
+        fields[offset] = value;
+    }
+}
+
+/// Finds the field a MemberName refers to, and the class declaring it.
+fn member_field(member: JRef) -> Option<(ClassRef, FieldLocation)>{
+    let class_desc = java_lang_class::get_class_desc(&get_member_field(member, "clazz"))?;
+    let name = objects::java_string_to_rust_string(get_member_field(member, "name"));
+    let type_desc = java_lang_class::get_class_desc(&get_member_field(member, "type"))?;
+    let class = heap::get_or_create_bt_class(class_desc).ok()?.ensure_loaded().ok()?;
+    let (owner, location) = class.resolve_field(&NameAndType{ name, descriptor: type_desc }).ok()?;
+    return Some((class_ref(owner), location));
+}
+
+/// Returns the Unsafe offset of the field a MemberName refers to, static or not.
+fn member_offset(member: JRef) -> Option<i64>{
+    let (owner, location) = member_field(member)?;
+    return match location{
+        FieldLocation::Instance(idx) => owner.declared_field_offset(&owner.instance_fields[idx].name).map(jdk_internal_misc_unsafe::instance_field_offset),
+        FieldLocation::Static(idx) => Some(jdk_internal_misc_unsafe::static_field_offset(idx))
+    };
+}
+
+fn class_ref(class: &Class) -> ClassRef{
+    return heap::bt_class_by_desc(class.descriptor.clone()).expect("Field owner not loaded");
+}
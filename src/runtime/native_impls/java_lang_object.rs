@@ -1,12 +1,18 @@
-use crate::runtime::{heap, objects};
-use crate::runtime::{jvalue::JValue, interpreter::{MethodResult, StackTrace}};
+use std::time::Duration;
+
+use crate::runtime::{heap, objects, threads};
+use crate::runtime::{jvalue::{JValue, JObject, JObjectData}, interpreter::{MethodResult, StackTrace}};
 
 pub fn builtin_object_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
     return match name_and_desc{
-        "registerNatives()V" |
-        "notifyAll()V" => no_op_v,
+        "registerNatives()V" => no_op_v,
         "hashCode()I" => hash_code_i,
+        "wait(J)V" |
+        "wait0(J)V" => wait_v,
+        "notify()V" => notify_v,
+        "notifyAll()V" => notify_all_v,
         "getClass()Ljava/lang/Class;" => get_class,
+        "clone()Ljava/lang/Object;" => clone_obj,
         _ => panic!("Unknown java.lang.Object native: {}", name_and_desc)
     };
 }
@@ -25,11 +31,54 @@ fn hash_code_i(args: Vec<JValue>) -> MethodResult{
     }
 }
 
+fn clone_obj(args: Vec<JValue>) -> MethodResult{
+    let JValue::Reference(Some(this)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in Object::clone") };
+    let obj = this.deref();
+    // arrays are always cloneable
+    let data = obj.data.read().unwrap().clone();
+    if let JObjectData::Fields(_) = data
+    && !obj.class.assignable_to("Ljava/lang/Cloneable;"){
+        return MethodResult::Throw(StackTrace::new(), "CloneNotSupportedException");
+    }
+    // a shallow copy, with its own identity and monitor
+    return MethodResult::FinishWithValue(heap::add_ref(JObject::new(obj.class.clone(), data)));
+}
+
 fn get_class(args: Vec<JValue>) -> MethodResult{
     let this = args[0];
     return if let JValue::Reference(Some(this)) = this{
-        MethodResult::FinishWithValue(heap::add_ref(objects::synthesize_class(&this.deref().class.descriptor)))
+        MethodResult::FinishWithValue(objects::class_object(&this.deref().descriptor()))
     }else{
         MethodResult::Throw(StackTrace::new(), "NPE in Object::getClass")
     }
-}
\ No newline at end of file
+}
+fn wait_v(args: Vec<JValue>) -> MethodResult{
+    // Object, long timeout in millis (0 = none)
+    let JValue::Reference(Some(this)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in Object::wait") };
+    let JValue::Long(millis) = args[1] else { return MethodResult::MachineError("expected long for Object::wait") };
+    if millis < 0{
+        return MethodResult::Throw(StackTrace::new(), "IllegalArgumentException");
+    }
+    let timeout = if millis == 0{ None }else{ Some(Duration::from_millis(millis as u64)) };
+    return match threads::wait(this, timeout){
+        Ok(()) => MethodResult::Finish,
+        Err("InterruptedException") => MethodResult::ThrowObject(StackTrace::new(), threads::interrupted_exception()),
+        Err(e) => MethodResult::Throw(StackTrace::new(), e)
+    };
+}
+
+fn notify_v(args: Vec<JValue>) -> MethodResult{
+    return notify(args, false);
+}
+
+fn notify_all_v(args: Vec<JValue>) -> MethodResult{
+    return notify(args, true);
+}
+
+fn notify(args: Vec<JValue>, all: bool) -> MethodResult{
+    let JValue::Reference(Some(this)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in Object::notify") };
+    return match this.deref().monitor.notify(all){
+        Ok(()) => MethodResult::Finish,
+        Err(e) => MethodResult::Throw(StackTrace::new(), e)
+    };
+}
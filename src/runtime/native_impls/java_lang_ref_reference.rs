@@ -0,0 +1,48 @@
+use crate::runtime::objects;
+use crate::runtime::{jvalue::{JValue, JObjectData}, interpreter::{MethodResult, StackTrace}};
+
+// there's no GC, so references are never cleared or enqueued by the VM, and there are never pending references
+
+pub fn builtin_reference_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "waitForReferencePendingList()V" => wait_for_pending_v,
+        "hasReferencePendingList()Z" => const_0_i,
+        "getAndClearReferencePendingList()Ljava/lang/ref/Reference;" => const_null,
+        "refersTo0(Ljava/lang/Object;)Z" => refers_to_z,
+        "clear0()V" => clear_v,
+        _ => panic!("Unknown java.lang.ref.Reference native: {}", name_and_desc)
+    };
+}
+
+fn wait_for_pending_v(_: Vec<JValue>) -> MethodResult{
+    // blocks the reference handler thread forever
+    loop{
+        std::thread::park();
+    }
+}
+
+fn const_0_i(_: Vec<JValue>) -> MethodResult{
+    return MethodResult::FinishWithValue(JValue::Int(0));
+}
+
+fn const_null(_: Vec<JValue>) -> MethodResult{
+    return MethodResult::FinishWithValue(JValue::Reference(None));
+}
+
+fn refers_to_z(args: Vec<JValue>) -> MethodResult{
+    // Reference, Object
+    let JValue::Reference(Some(this)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in Reference::refersTo0") };
+    let offset = objects::force_init_class("Ljava/lang/ref/Reference;").declared_field_offset("referent").unwrap();
+    let JObjectData::Fields(fields) = &*this.deref().data.read().unwrap() else { unreachable!() };
+    return MethodResult::FinishWithValue(JValue::Int((fields[offset] == args[1]) as i32));
+}
+
+fn clear_v(args: Vec<JValue>) -> MethodResult{
+    // Reference
+    let JValue::Reference(Some(this)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in Reference::clear0") };
+    let offset = objects::force_init_class("Ljava/lang/ref/Reference;").declared_field_offset("referent").unwrap();
+    if let JObjectData::Fields(fields) = &mut *this.deref().data.write().unwrap(){
+        fields[offset] = JValue::Reference(None);
+    }
+    return MethodResult::Finish;
+}
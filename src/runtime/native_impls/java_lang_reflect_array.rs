@@ -0,0 +1,26 @@
+use crate::runtime::native_impls::java_lang_class;
+use crate::runtime::{heap, objects};
+use crate::runtime::{jvalue::JValue, interpreter::{MethodResult, StackTrace}};
+
+pub fn builtin_array_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "newArray(Ljava/lang/Class;I)Ljava/lang/Object;" => new_array_obj,
+        _ => panic!("Unknown java.lang.reflect.Array native: {}", name_and_desc)
+    };
+}
+
+fn new_array_obj(args: Vec<JValue>) -> MethodResult{
+    // Class<?> component type, int length
+    let Some(component_desc) = java_lang_class::get_class_desc(&args[0]) else {
+        return MethodResult::Throw(StackTrace::new(), "NPE in Array::newArray");
+    };
+    let JValue::Int(length) = args[1] else { return MethodResult::MachineError("expected int for Array::newArray") };
+    if length < 0{
+        return MethodResult::Throw(StackTrace::new(), "NegativeArraySizeException");
+    }
+    let component = heap::get_or_create_bt_class(component_desc)
+        .unwrap()
+        .ensure_loaded()
+        .unwrap();
+    return MethodResult::FinishWithValue(objects::create_new_array(component, length as usize));
+}
@@ -0,0 +1,23 @@
+use crate::runtime::{heap, jvalue::JValue, objects, interpreter::{MethodResult, StackTrace}};
+use crate::runtime::native_impls::{java_lang_class, jdk_internal_reflect_native_method_accessor_impl::field};
+
+pub fn builtin_executable_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "getTypeAnnotationBytes0()[B" => get_type_annotation_bytes_arr,
+        _ => panic!("Unknown java.lang.reflect.Executable native: {}", name_and_desc)
+    };
+}
+
+fn get_type_annotation_bytes_arr(p: Vec<JValue>) -> MethodResult{
+    // methods are the only executables we create
+    let method_class = objects::force_init_class("Ljava/lang/reflect/Method;");
+    let (Some(clazz), Some(JValue::Int(slot))) = (field(p[0], &method_class, "clazz"), field(p[0], &method_class, "slot")) else {
+        return MethodResult::Throw(StackTrace::new(), "NPE in Executable::getTypeAnnotationBytes0");
+    };
+    let Some(class) = java_lang_class::get_class_desc(&clazz)
+        .and_then(|d| heap::get_or_create_bt_class(d).ok())
+        .and_then(|c| c.ensure_loaded().ok()) else {
+        return MethodResult::Throw(StackTrace::new(), "Could not load class in Executable::getTypeAnnotationBytes0");
+    };
+    return MethodResult::FinishWithValue(java_lang_class::raw_bytes(&class.methods[slot as usize].annotations.type_annotations));
+}
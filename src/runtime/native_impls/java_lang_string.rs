@@ -1,7 +1,14 @@
-use crate::runtime::{jvalue::JValue, interpreter::MethodResult};
+use crate::runtime::{jvalue::JValue, interpreter::MethodResult, objects};
 
 // plus StringUTF16
 
+pub fn builtin_string_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "intern()Ljava/lang/String;" => intern_obj,
+        _ => panic!("Unknown java.lang.String native: {}", name_and_desc)
+    };
+}
+
 pub fn builtin_string_utf16_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
     return match name_and_desc{
         "isBigEndian()Z" => const_1_i,
@@ -9,6 +16,12 @@ pub fn builtin_string_utf16_native(name_and_desc: &str) -> fn(Vec<JValue>) -> Me
     };
 }
 
+fn intern_obj(params: Vec<JValue>) -> MethodResult{
+    // String
+    let JValue::Reference(Some(this)) = params[0] else { return MethodResult::MachineError("expected string for intern") };
+    return MethodResult::FinishWithValue(JValue::Reference(Some(objects::intern_existing(this))));
+}
+
 fn const_1_i(_: Vec<JValue>) -> MethodResult{
     return MethodResult::FinishWithValue(JValue::Int(1));
-}
\ No newline at end of file
+}
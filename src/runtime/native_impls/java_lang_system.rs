@@ -1,8 +1,7 @@
-use std::sync::Arc;
 use std::time::Instant;
 use crate::runtime::heap;
 use crate::runtime::interpreter::{MethodResult, StackTrace};
-use crate::runtime::jvalue::{JObject, JObjectData, JValue};
+use crate::runtime::jvalue::{ArrayData, JObjectData, JValue};
 
 static mut START: Option<Instant> = None;
 
@@ -33,31 +32,63 @@ fn nano_time_j(_: Vec<JValue>) -> MethodResult{
 
 fn arraycopy_v(args: Vec<JValue>) -> MethodResult{
     // TODO: proper exceptions
-    let src_array = args[0];
-    let src_idx = args[1];
-    let dest_array = args[2];
-    let dest_idx = args[3];
-    let length = args[4];
-    if let JValue::Reference(Some(src_ptr)) = src_array
-    && let JValue::Reference(Some(dest_ptr)) = dest_array
-    && let JValue::Int(src_idx) = src_idx
-    && let JValue::Int(dest_idx) = dest_idx
-    && let JValue::Int(length) = length
-    && src_idx >= 0 && dest_idx >= 0 && length >= 0{
-        let src_obj: Arc<JObject> = src_ptr.deref();
-        let dest_obj = dest_ptr.deref();
-        let src_idx = src_idx as usize; let desc_idx = dest_idx as usize; let length = length as usize;
-        let mut write_lock = dest_obj.data.write().unwrap();
-        if let JObjectData::Array(_, src_values) = &*src_obj.data.read().unwrap()
-        && let JObjectData::Array(_, dest_values) = &mut *write_lock{
-            for i in 0..length{
-                dest_values[desc_idx + i] = src_values[src_idx + i].clone();
+    let JValue::Reference(src_array) = args[0] else { return MethodResult::MachineError("expected array for arraycopy") };
+    let JValue::Int(src_idx) = args[1] else { return MethodResult::MachineError("expected int for arraycopy") };
+    let JValue::Reference(dest_array) = args[2] else { return MethodResult::MachineError("expected array for arraycopy") };
+    let JValue::Int(dest_idx) = args[3] else { return MethodResult::MachineError("expected int for arraycopy") };
+    let JValue::Int(length) = args[4] else { return MethodResult::MachineError("expected int for arraycopy") };
+    let (Some(src_ptr), Some(dest_ptr)) = (src_array, dest_array) else { return MethodResult::Throw(StackTrace::new(), "NPE for arraycopy") };
+    if src_idx < 0 || dest_idx < 0 || length < 0{
+        return MethodResult::Throw(StackTrace::new(), "index out of bounds");
+    }
+    let (src_idx, dest_idx, length) = (src_idx as usize, dest_idx as usize, length as usize);
+
+    // copying within an array has to work like going through a temporary array
+    if src_ptr == dest_ptr{
+        let obj = src_ptr.deref();
+        let JObjectData::Array(values) = &mut *obj.data.write().unwrap() else { return MethodResult::Throw(StackTrace::new(), "ArrayStoreException") };
+        if src_idx + length > values.len() || dest_idx + length > values.len(){
+            return MethodResult::Throw(StackTrace::new(), "index out of bounds");
+        }
+        values.copy_within(src_idx, dest_idx, length);
+        return MethodResult::Finish;
+    }
+
+    let src_obj = src_ptr.deref();
+    let dest_obj = dest_ptr.deref();
+    // both are locked in heap order, so copies between the same arrays in opposite directions can't deadlock
+    let (src_read, mut dest_write) = if src_ptr < dest_ptr{
+        let src_read = src_obj.data.read().unwrap();
+        (src_read, dest_obj.data.write().unwrap())
+    }else{
+        let dest_write = dest_obj.data.write().unwrap();
+        (src_obj.data.read().unwrap(), dest_write)
+    };
+    let JObjectData::Array(src_values) = &*src_read else { return MethodResult::Throw(StackTrace::new(), "ArrayStoreException") };
+    if src_idx + length > src_values.len(){
+        return MethodResult::Throw(StackTrace::new(), "index out of bounds");
+    }
+    // primitive arrays need exactly the same type, references need each element to fit in the destination
+    let (src_component, dest_component) = (&src_obj.class.descriptor, &dest_obj.class.descriptor);
+    if let ArrayData::Reference(elements) = src_values{
+        if !src_obj.class.assignable_to(dest_component){
+            for element in &elements[src_idx..src_idx + length]{
+                if let Some(element) = element && !element.deref().assignable_to(dest_component){
+                    return MethodResult::Throw(StackTrace::new(), "ArrayStoreException");
+                }
             }
-            return MethodResult::Finish;
-        };
+        }
+    }else if src_component != dest_component{
+        return MethodResult::Throw(StackTrace::new(), "ArrayStoreException");
+    }
+    let JObjectData::Array(dest_values) = &mut *dest_write else { return MethodResult::Throw(StackTrace::new(), "ArrayStoreException") };
+    if dest_idx + length > dest_values.len(){
+        return MethodResult::Throw(StackTrace::new(), "index out of bounds");
     }
-    println!("src is {:?}, dest is {:?}, src idx is {:?}, dest idx is {:?}, length is {:?}", src_array, src_idx, dest_array, dest_idx, length);
-    return MethodResult::Throw(StackTrace::new(), "bad arraycopy args");
+    if !dest_values.copy_from(src_values, src_idx, dest_idx, length){
+        return MethodResult::Throw(StackTrace::new(), "ArrayStoreException");
+    }
+    return MethodResult::Finish;
 }
 
 fn set_in_v(args: Vec<JValue>) -> MethodResult{
@@ -1,42 +1,156 @@
-use std::sync::RwLock;
+use std::time::Duration;
 use crate::parser::classfile_structs::NameAndType;
-use crate::runtime::{heap, interpreter, objects};
-use crate::runtime::{jvalue::JValue, interpreter::MethodResult};
-
-// static DEFAULT_THREAD: OnceLock<JValue> = OnceLock::new();
-static DEFAULT_THREAD: RwLock<Option<JValue>> = RwLock::new(None);
+use crate::runtime::{heap, interpreter, objects, threads};
+use crate::runtime::{jvalue::{JValue, JObjectData}, interpreter::{MethodResult, StackTrace}};
 
 pub fn builtin_thread_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
     return match name_and_desc{
-        "registerNatives()V" => no_op_v,
+        "registerNatives()V" |
+        "setPriority0(I)V" | // OS thread priorities are left alone
+        "clearInterruptEvent()V" | // interrupts are seen through Thread.interrupted
+        "setNativeName(Ljava/lang/String;)V" | // OS threads are named when started
+        "ensureMaterializedForStackWalk(Ljava/lang/Object;)V" => no_op_v,
         "currentThread()Ljava/lang/Thread;" => current_thread_thread,
+        "currentCarrierThread()Ljava/lang/Thread;" => current_carrier_thread_thread,
+        "setCurrentThread(Ljava/lang/Thread;)V" => set_current_thread_v,
+        "scopedValueCache()[Ljava/lang/Object;" => scoped_value_cache_arr,
+        "setScopedValueCache([Ljava/lang/Object;)V" => set_scoped_value_cache_v,
+        "start0()V" => start_v,
+        "isAlive()Z" => is_alive_z,
+        "holdsLock(Ljava/lang/Object;)Z" => holds_lock_z,
+        "yield()V" |
+        "yield0()V" => yield_v,
+        "sleep(J)V" => sleep_millis_v,
+        "sleep0(J)V" => sleep_nanos_v,
+        "interrupt0()V" => interrupt_v,
+        "getThreads()[Ljava/lang/Thread;" => get_threads_arr,
         _ => panic!("Unknown java.lang.Thread native: {}", name_and_desc)
     };
 }
 
+pub fn builtin_virtual_thread_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        // there's no JVMTI to tell
+        "registerNatives()V" |
+        "notifyJvmtiStart()V" |
+        "notifyJvmtiEnd()V" |
+        "notifyJvmtiMount(Z)V" |
+        "notifyJvmtiUnmount(Z)V" |
+        "notifyJvmtiHideFrames(Z)V" => no_op_v,
+        _ => panic!("Unknown java.lang.VirtualThread native: {}", name_and_desc)
+    };
+}
+
 fn no_op_v(_: Vec<JValue>) -> MethodResult{
     return MethodResult::Finish;
 }
 
-fn current_thread_thread(_: Vec<JValue>) -> MethodResult{
-    // check if set...
-    {
-        let read = DEFAULT_THREAD.read().unwrap();
-        if let Some(thread) = &* read{ // if so, just pass it along
-            return MethodResult::FinishWithValue(thread.clone());
-        }
+fn current_thread_thread(args: Vec<JValue>) -> MethodResult{
+    if let Some(thread) = threads::mounted_thread(){
+        return MethodResult::FinishWithValue(JValue::Reference(Some(thread)));
+    }
+    return current_carrier_thread_thread(args);
+}
+
+fn current_carrier_thread_thread(_: Vec<JValue>) -> MethodResult{
+    // started threads are set when they start, so this is the main thread
+    if let Some(thread) = threads::current_thread(){
+        return MethodResult::FinishWithValue(JValue::Reference(Some(thread)));
+    }
+    // System.initPhase1 adds the main thread to its group, so it can't be the one to initialize System;
+    // if System isn't initialized, this creates the main thread from initPhase1 instead
+    objects::force_init_class("Ljava/lang/System;");
+    if let Some(thread) = threads::current_thread(){
+        return MethodResult::FinishWithValue(JValue::Reference(Some(thread)));
     }
     // otherwise, create and set it
     let thread = synthesize_default_thread();
-    {
-        let mut write = DEFAULT_THREAD.write().unwrap();
-        *write = Some(thread.clone());
-    }
+    let JValue::Reference(Some(thread_ref)) = thread else { unreachable!() };
+    threads::set_current_thread(thread_ref);
     // initialize it after, so the getCurrentThreadCall() in Thread::new isn't recursive
     initialize_default_thread(thread);
+    threads::register(thread_ref);
     return MethodResult::FinishWithValue(thread);
 }
 
+fn set_current_thread_v(args: Vec<JValue>) -> MethodResult{
+    // Thread (the carrier), Thread
+    let JValue::Reference(Some(thread)) = args[1] else { return MethodResult::Throw(StackTrace::new(), "NPE in Thread::setCurrentThread") };
+    // virtual threads unmount by setting the carrier back
+    threads::set_mounted_thread(if threads::current_thread() == Some(thread){ None }else{ Some(thread) });
+    return MethodResult::Finish;
+}
+
+fn scoped_value_cache_arr(_: Vec<JValue>) -> MethodResult{
+    return MethodResult::FinishWithValue(JValue::Reference(threads::scoped_value_cache()));
+}
+
+fn set_scoped_value_cache_v(args: Vec<JValue>) -> MethodResult{
+    // Object[]
+    let JValue::Reference(cache) = args[0] else { return MethodResult::MachineError("expected array for setScopedValueCache") };
+    threads::set_scoped_value_cache(cache);
+    return MethodResult::Finish;
+}
+
+fn start_v(args: Vec<JValue>) -> MethodResult{
+    // Thread
+    let JValue::Reference(Some(this)) = args[0] else { return MethodResult::MachineError("expected thread for start0") };
+    threads::start(this);
+    return MethodResult::Finish;
+}
+
+fn is_alive_z(args: Vec<JValue>) -> MethodResult{
+    // Thread
+    let JValue::Reference(Some(this)) = args[0] else { return MethodResult::MachineError("expected thread for isAlive") };
+    return MethodResult::FinishWithValue(JValue::Int(threads::is_alive(this) as i32));
+}
+
+fn holds_lock_z(args: Vec<JValue>) -> MethodResult{
+    // Object
+    let JValue::Reference(Some(obj)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in Thread::holdsLock") };
+    return MethodResult::FinishWithValue(JValue::Int(obj.deref().monitor.held_by_current_thread() as i32));
+}
+
+fn yield_v(_: Vec<JValue>) -> MethodResult{
+    std::thread::yield_now();
+    return MethodResult::Finish;
+}
+
+fn sleep_millis_v(args: Vec<JValue>) -> MethodResult{
+    // long millis
+    let JValue::Long(millis) = args[0] else { return MethodResult::MachineError("expected long for sleep") };
+    if millis < 0{
+        return MethodResult::Throw(StackTrace::new(), "IllegalArgumentException");
+    }
+    return sleep(Duration::from_millis(millis as u64));
+}
+
+fn sleep_nanos_v(args: Vec<JValue>) -> MethodResult{
+    // long nanos
+    let JValue::Long(nanos) = args[0] else { return MethodResult::MachineError("expected long for sleep0") };
+    return sleep(Duration::from_nanos(nanos.max(0) as u64));
+}
+
+fn sleep(duration: Duration) -> MethodResult{
+    if threads::sleep(duration){
+        return MethodResult::Finish;
+    }
+    return MethodResult::ThrowObject(StackTrace::new(), threads::interrupted_exception());
+}
+
+fn interrupt_v(args: Vec<JValue>) -> MethodResult{
+    // Thread
+    let JValue::Reference(Some(this)) = args[0] else { return MethodResult::MachineError("expected thread for interrupt0") };
+    threads::interrupt(this);
+    return MethodResult::Finish;
+}
+
+fn get_threads_arr(_: Vec<JValue>) -> MethodResult{
+    let class = objects::force_init_class("Ljava/lang/Thread;");
+    let threads = threads::live_threads().into_iter().map(|t| JValue::Reference(Some(t))).collect();
+    return MethodResult::FinishWithValue(objects::create_new_array_of(class, threads));
+}
+
 // Constructors for the default Thread and ThreadGroup
 
 fn synthesize_default_thread_group() -> JValue{
@@ -45,14 +159,21 @@ fn synthesize_default_thread_group() -> JValue{
     let (init, owner) = class.special_method(&NameAndType{
         name: "<init>".to_string(),
         descriptor: "()V".to_string()
-    }, "java/lang/ThreadGroup").unwrap();
+    }).unwrap();
     interpreter::execute(owner, init, vec![obj], interpreter::StackTrace::new());
     return obj;
 }
 
 fn synthesize_default_thread() -> JValue{
     let class = objects::force_init_class("Ljava/lang/Thread;");
-    return objects::create_new(class.clone());
+    let thread = objects::create_new(class.clone());
+    // the constructor copies the priority of the current thread (this one), which threads started later copy
+    if let JValue::Reference(Some(r)) = &thread
+    && let Some(offset) = class.declared_field_offset("priority")
+    && let JObjectData::Fields(fields) = &mut *r.deref().data.write().unwrap(){
+        fields[offset] = JValue::Int(5); // NORM_PRIORITY
+    }
+    return thread;
 }
 
 fn initialize_default_thread(obj: JValue){
@@ -63,6 +184,6 @@ fn initialize_default_thread(obj: JValue){
     let (init, owner) = class.special_method(&NameAndType{
         name: "<init>".to_string(),
         descriptor: "(Ljava/lang/ThreadGroup;Ljava/lang/String;)V".to_string()
-    }, "java/lang/Thread").unwrap();
+    }).unwrap();
     interpreter::execute(owner, init, vec![obj, group, name], interpreter::StackTrace::new());
-}
\ No newline at end of file
+}
@@ -0,0 +1,13 @@
+use crate::runtime::{jvalue::JValue, interpreter::MethodResult};
+
+pub fn builtin_boot_loader_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        // every class is loaded by the bootstrap loader, which doesn't keep track of modules
+        "setBootLoaderUnnamedModule0(Ljava/lang/Module;)V" => no_op_v,
+        _ => panic!("Unknown jdk.internal.loader.BootLoader native: {}", name_and_desc)
+    };
+}
+
+fn no_op_v(_: Vec<JValue>) -> MethodResult{
+    return MethodResult::Finish;
+}
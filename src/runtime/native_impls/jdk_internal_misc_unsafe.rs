@@ -1,23 +1,71 @@
 use crate::runtime::native_impls::java_lang_class;
-use crate::runtime::{heap, objects};
-use crate::runtime::jvalue::JObjectData;
+use std::{sync::atomic::{fence, Ordering}, time::Duration};
+use crate::runtime::{heap, objects, threads::{self, ParkTimeout}};
+use crate::runtime::{class::InitState, jvalue::{JObjectData, ArrayData}};
 use crate::runtime::{jvalue::JValue, interpreter::MethodResult};
 
 pub fn builtin_unsafe_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
     return match name_and_desc{
-        "registerNatives()V" |
-        "storeFence()V" => no_op_v,
+        "registerNatives()V" => no_op_v,
         "arrayBaseOffset0(Ljava/lang/Class;)I" => const_0_i,
         "arrayIndexScale0(Ljava/lang/Class;)I" => const_1_i,
         "addressSize0()I" => address_size_i,
-        "isBigEndian0()Z" |
+        // little-endian, so that the int containing a sub-word field has it in its low bits
+        "isBigEndian0()Z" => const_0_i,
         "unalignedAccess0()Z" => const_1_i,
         "objectFieldOffset1(Ljava/lang/Class;Ljava/lang/String;)J" => object_field_offset_by_name_j,
-        "compareAndSetInt(Ljava/lang/Object;JII)Z" => compare_and_set_int_z,
-        "compareAndSetLong(Ljava/lang/Object;JJJ)Z" => compare_and_set_long_z,
-        "getReferenceVolatile(Ljava/lang/Object;J)Ljava/lang/Object;" => get_reference_volatile_obj,
-        "getIntVolatile(Ljava/lang/Object;J)I" => get_int_volatile_i,
-        "compareAndSetReference(Ljava/lang/Object;JLjava/lang/Object;Ljava/lang/Object;)Z" => compare_and_set_reference_z,
+        // every access is as strong as a volatile one, so plain accesses are done the same way
+        "getBoolean(Ljava/lang/Object;J)Z" |
+        "getByte(Ljava/lang/Object;J)B" |
+        "getBooleanVolatile(Ljava/lang/Object;J)Z" |
+        "getByteVolatile(Ljava/lang/Object;J)B" => get_b,
+        "getShort(Ljava/lang/Object;J)S" |
+        "getShortVolatile(Ljava/lang/Object;J)S" => get_s,
+        "getChar(Ljava/lang/Object;J)C" |
+        "getCharVolatile(Ljava/lang/Object;J)C" => get_c,
+        "getInt(Ljava/lang/Object;J)I" |
+        "getIntVolatile(Ljava/lang/Object;J)I" => get_i,
+        "getLong(Ljava/lang/Object;J)J" |
+        "getLongVolatile(Ljava/lang/Object;J)J" => get_j,
+        "getFloat(Ljava/lang/Object;J)F" |
+        "getFloatVolatile(Ljava/lang/Object;J)F" => get_f,
+        "getDouble(Ljava/lang/Object;J)D" |
+        "getDoubleVolatile(Ljava/lang/Object;J)D" => get_d,
+        "getReference(Ljava/lang/Object;J)Ljava/lang/Object;" |
+        "getReferenceVolatile(Ljava/lang/Object;J)Ljava/lang/Object;" => get_obj,
+        "putFloat(Ljava/lang/Object;JF)V" |
+        "putDouble(Ljava/lang/Object;JD)V" |
+        "putBoolean(Ljava/lang/Object;JZ)V" |
+        "putByte(Ljava/lang/Object;JB)V" |
+        "putReference(Ljava/lang/Object;JLjava/lang/Object;)V" |
+        "putFloatVolatile(Ljava/lang/Object;JF)V" |
+        "putDoubleVolatile(Ljava/lang/Object;JD)V" |
+        "putBooleanVolatile(Ljava/lang/Object;JZ)V" |
+        "putByteVolatile(Ljava/lang/Object;JB)V" |
+        "putReferenceVolatile(Ljava/lang/Object;JLjava/lang/Object;)V" => put_v,
+        "putShort(Ljava/lang/Object;JS)V" |
+        "putChar(Ljava/lang/Object;JC)V" |
+        "putShortVolatile(Ljava/lang/Object;JS)V" |
+        "putCharVolatile(Ljava/lang/Object;JC)V" => put_short_v,
+        "putInt(Ljava/lang/Object;JI)V" |
+        "putIntVolatile(Ljava/lang/Object;JI)V" => put_int_v,
+        "putLong(Ljava/lang/Object;JJ)V" |
+        "putLongVolatile(Ljava/lang/Object;JJ)V" => put_long_v,
+        // the other atomic operations (weak CAS, getAndAdd, getAndSet, getAndBitwise...) and the acquire, release and
+        // opaque accesses are written in Java in terms of these
+        "compareAndSetInt(Ljava/lang/Object;JII)Z" |
+        "compareAndSetLong(Ljava/lang/Object;JJJ)Z" |
+        "compareAndSetReference(Ljava/lang/Object;JLjava/lang/Object;Ljava/lang/Object;)Z" => compare_and_set_z,
+        "compareAndExchangeInt(Ljava/lang/Object;JII)I" |
+        "compareAndExchangeLong(Ljava/lang/Object;JJJ)J" |
+        "compareAndExchangeReference(Ljava/lang/Object;JLjava/lang/Object;Ljava/lang/Object;)Ljava/lang/Object;" => compare_and_exchange_value,
+        "loadFence()V" => load_fence_v,
+        "storeFence()V" => store_fence_v,
+        "fullFence()V" => full_fence_v,
+        "shouldBeInitialized0(Ljava/lang/Class;)Z" => should_be_initialized_z,
+        "ensureClassInitialized0(Ljava/lang/Class;)V" => ensure_class_initialized_v,
+        "park(ZJ)V" => park_v,
+        "unpark(Ljava/lang/Object;)V" => unpark_v,
         _ => panic!("Unknown jdk.internal.misc.Unsafe native: {}", name_and_desc)
     };
 }
@@ -42,7 +90,7 @@ fn address_size_i(_: Vec<JValue>) -> MethodResult{
     return MethodResult::FinishWithValue(JValue::Int(8));
 }
 
-// offsets are just field indexes in our impl
+// offsets are from slot indexes in our impl, which are the same for subclasses
 fn object_field_offset_by_name_j(params: Vec<JValue>) -> MethodResult{
     // Unsafe, Class<?>, String
     let class_desc = java_lang_class::get_class_desc(&params[1]);
@@ -52,170 +100,281 @@ fn object_field_offset_by_name_j(params: Vec<JValue>) -> MethodResult{
         .ensure_loaded()
         .unwrap();
 
-    // TODO: panic if not present?
-    let mut i = 0;
-    for field in &class.instance_fields{
-        if field.name == name{
-            break;
-        }
-        i += 1;
-    }
-    return MethodResult::FinishWithValue(JValue::Long(i));
-}
-
-// TODO: extract similarities
-// leave until after JObject rework?
-fn compare_and_set_int_z(params: Vec<JValue>) -> MethodResult{
-    // Unsafe, Object to modify, long offset, int expected, int value to set
-    let JValue::Long(idx) = params[2] else { return MethodResult::MachineError("expected long for compareAndSetInt") };
-    let JValue::Int(expected) = params[3] else { return MethodResult::MachineError("expected int for compareAndSetInt") };
-    let JValue::Int(to_set) = params[4] else { return MethodResult::MachineError("expected int for compareAndSetInt") };
-    if let JValue::Reference(Some(r)) = params[1]{
-        if let JObjectData::Fields(fields) = &mut *r.deref().data.write().unwrap(){
-            let mut i = 0;
-            let mut name: Option<String> = None;
-            for field in &r.deref().class.instance_fields{
-                if i == idx{
-                    name = Some(field.name.clone());
-                    break;
-                }
-                i += 1;
-            }
-            if let Some(f) = name{
-                if let JValue::Int(v) = fields[&f]{
-                    if v == expected{
-                        fields.insert(f, JValue::Int(to_set));
-                        return MethodResult::FinishWithValue(JValue::Int(1)); // true
-                    }
-                }
-            }
-        }
+    let offset = class.declared_field_offset(&name).expect("Unknown field for objectFieldOffset1");
+    return MethodResult::FinishWithValue(JValue::Long(instance_field_offset(offset)));
+}
+
+/// Field offsets are a multiple of this, as if every field took up an int. Java does CAS on boolean, byte, short and
+/// char fields with a CAS on the aligned int containing them, which is then just the field.
+const FIELD_OFFSET_SCALE: i64 = 4;
+
+/// Static fields are accessed with their class object as the base, at offsets past those of any instance field.
+const STATIC_FIELD_OFFSET: i64 = 1 << 32;
+
+/// Returns the offset of the instance field in the given slot.
+pub fn instance_field_offset(slot: usize) -> i64{
+    return slot as i64 * FIELD_OFFSET_SCALE;
+}
+
+/// Returns the offset of the static field at the given index in its class's static fields.
+pub fn static_field_offset(index: usize) -> i64{
+    return STATIC_FIELD_OFFSET + index as i64 * FIELD_OFFSET_SCALE;
+}
+
+/// Runs a function on the field or array element at an offset of an object, under its write lock.
+/// Array offsets are element indexes. Accesses `width` bytes wide to a byte array see those bytes as one value, in
+/// little-endian order; ByteBuffer reads and writes wider values this way.
+fn with_slot<R>(obj: JValue, offset: i64, width: usize, f: impl FnOnce(&mut JValue) -> R) -> Option<R>{
+    let JValue::Reference(Some(r)) = obj else { return None };
+    if offset >= STATIC_FIELD_OFFSET && r.deref().class.descriptor == "Ljava/lang/Class;"{
+        let class = heap::bt_class_by_desc(java_lang_class::get_class_desc(&obj)?)?;
+        let index = field_slot(offset - STATIC_FIELD_OFFSET)?;
+        let mut field = class.static_fields.get(index)?.write().unwrap();
+        let result = f(&mut field.1);
+        field.1 = narrow(field.1, &field.0.type_class.descriptor());
+        return Some(result);
     }
-    return MethodResult::FinishWithValue(JValue::Int(0)); // false
-}
-
-fn compare_and_set_reference_z(params: Vec<JValue>) -> MethodResult{
-    // Unsafe, Object to modify, long offset, Object expected, Object value to set
-    let JValue::Long(idx) = params[2] else { return MethodResult::MachineError("expected long for compareAndSetReference") };
-    let JValue::Reference(expected) = params[3] else { return MethodResult::MachineError("expected int for compareAndSetReference") };
-    let JValue::Reference(to_set) = params[4] else { return MethodResult::MachineError("expected int for compareAndSetReference") };
-    if let JValue::Reference(Some(r)) = params[1]{
-        match &mut *r.deref().data.write().unwrap(){
-            JObjectData::Fields(fields) => {
-                let mut i = 0;
-                let mut name: Option<String> = None;
-                for field in &r.deref().class.instance_fields{
-                    if i == idx{
-                        name = Some(field.name.clone());
-                        break;
-                    }
-                    i += 1;
-                }
-                if let Some(f) = name{
-                    if let JValue::Reference(v) = fields[&f]{
-                        if v == expected{
-                            fields.insert(f, JValue::Reference(to_set));
-                            return MethodResult::FinishWithValue(JValue::Int(1)); // true
-                        }
-                    }
-                }
-            }
-            JObjectData::Array(_, values) => {
-                if let Some(JValue::Reference(v)) = values.get(idx as usize){
-                    if *v == expected{
-                        values[idx as usize] = JValue::Reference(to_set);
-                        return MethodResult::FinishWithValue(JValue::Int(1)); // true
-                    }
-                }
+    let obj = r.deref();
+    return match &mut *obj.data.write().unwrap(){
+        JObjectData::Fields(fields) => {
+            let slot = field_slot(offset)?;
+            let value = fields.get_mut(slot)?;
+            let result = f(value);
+            *value = narrow(*value, &obj.class.instance_layout[slot].descriptor);
+            Some(result)
+        },
+        JObjectData::Array(ArrayData::Byte(bytes)) if width > 1 => {
+            let start = usize::try_from(offset).ok()?;
+            let bytes = bytes.get_mut(start..start.checked_add(width)?)?;
+            let mut bits = [0; 8];
+            for (bit, byte) in bits.iter_mut().zip(bytes.iter()){
+                *bit = *byte as u8;
             }
-        }
-    }
-    return MethodResult::FinishWithValue(JValue::Int(0)); // false
-}
-
-fn compare_and_set_long_z(params: Vec<JValue>) -> MethodResult{
-    // Unsafe, Object to modify, long offset, long expected, long value to set
-    let JValue::Long(idx) = params[2] else { return MethodResult::MachineError("expected long for compareAndSetLong") };
-    let JValue::Long(expected) = params[3] else { return MethodResult::MachineError("expected long for compareAndSetLong") };
-    let JValue::Long(to_set) = params[4] else { return MethodResult::MachineError("expected long for compareAndSetLong") };
-    if let JValue::Reference(Some(r)) = params[1]{
-        if let JObjectData::Fields(fields) = &mut *r.deref().data.write().unwrap(){
-            let mut i = 0;
-            let mut name: Option<String> = None;
-            for field in &r.deref().class.instance_fields{
-                if i == idx{
-                    name = Some(field.name.clone());
-                    break;
-                }
-                i += 1;
+            let bits = i64::from_le_bytes(bits);
+            let mut value = if width == 8{ JValue::Long(bits) }else{ JValue::Int(bits as i32) };
+            let result = f(&mut value);
+            let bits = match value{
+                JValue::Int(i) => i as i64,
+                JValue::Long(l) => l,
+                _ => return None
+            };
+            for (byte, bit) in bytes.iter_mut().zip(bits.to_le_bytes()){
+                *byte = bit as i8;
             }
-            if let Some(f) = name{
-                if let JValue::Long(v) = fields[&f]{
-                    if v == expected{
-                        fields.insert(f, JValue::Long(to_set));
-                        return MethodResult::FinishWithValue(JValue::Int(1)); // true
-                    }
-                }
+            Some(result)
+        },
+        JObjectData::Array(values) => {
+            if offset < 0 || offset as usize >= values.len(){
+                return None;
             }
+            let mut value = values.load(offset as usize);
+            let result = f(&mut value);
+            values.store(offset as usize, value);
+            Some(result)
         }
-    }
-    return MethodResult::FinishWithValue(JValue::Int(0)); // false
+    };
+}
+
+// every access takes the lock of the object or static field it accesses, which makes it atomic and ordered with other
+// accesses to it, and fences as volatile field instructions do
+
+fn get_b(params: Vec<JValue>) -> MethodResult{
+    return get_as(params, "B");
+}
+
+fn get_s(params: Vec<JValue>) -> MethodResult{
+    return get_as(params, "S");
 }
 
-fn get_reference_volatile_obj(params: Vec<JValue>) -> MethodResult{
+fn get_c(params: Vec<JValue>) -> MethodResult{
+    return get_as(params, "C");
+}
+
+fn get_i(params: Vec<JValue>) -> MethodResult{
+    return get_as(params, "I");
+}
+
+fn get_j(params: Vec<JValue>) -> MethodResult{
+    return get_as(params, "J");
+}
+
+fn get_f(params: Vec<JValue>) -> MethodResult{
+    return get_as(params, "F");
+}
+
+fn get_d(params: Vec<JValue>) -> MethodResult{
+    return get_as(params, "D");
+}
+
+fn get_obj(params: Vec<JValue>) -> MethodResult{
+    return get_as(params, "Ljava/lang/Object;");
+}
+
+/// Reads a slot as a value of the given type.
+fn get_as(params: Vec<JValue>, desc: &str) -> MethodResult{
     // Unsafe, Object to access, long offset
-    let JValue::Long(idx) = params[2] else { return MethodResult::MachineError("expected long for getReferenceVolatile") };
-    if let JValue::Reference(Some(r)) = params[1]{
-        match &mut *r.deref().data.write().unwrap(){
-            JObjectData::Fields(fields) => {
-                let mut i = 0;
-                let mut name: Option<String> = None;
-                for field in &r.deref().class.instance_fields{
-                    if i == idx{
-                        name = Some(field.name.clone());
-                        break;
-                    }
-                    i += 1;
-                }
-                if let Some(f) = name{
-                    if let JValue::Reference(r) = fields[&f]{
-                        return MethodResult::FinishWithValue(JValue::Reference(r));
-                    }
-                }
-            }
-            JObjectData::Array(_, values) => {
-                return MethodResult::FinishWithValue(values[idx as usize]);
-            }
+    let JValue::Long(offset) = params[2] else { return MethodResult::MachineError("expected long offset for get") };
+    let like = JValue::default_value_for(desc);
+    let value = with_slot(params[1], offset, access_width(desc), |v| narrow(reinterpret(*v, like), desc));
+    fence(Ordering::Acquire);
+    return match value{
+        Some(value) => MethodResult::FinishWithValue(value),
+        None => MethodResult::MachineError("invalid object or offset for get")
+    };
+}
+
+fn put_v(params: Vec<JValue>) -> MethodResult{
+    return put_as(params, 1);
+}
+
+fn put_short_v(params: Vec<JValue>) -> MethodResult{
+    return put_as(params, access_width("S"));
+}
+
+fn put_int_v(params: Vec<JValue>) -> MethodResult{
+    return put_as(params, access_width("I"));
+}
+
+fn put_long_v(params: Vec<JValue>) -> MethodResult{
+    return put_as(params, access_width("J"));
+}
+
+/// Writes a slot, accessing `width` bytes if it's in a byte array.
+fn put_as(params: Vec<JValue>, width: usize) -> MethodResult{
+    // Unsafe, Object to modify, long offset, value to set
+    let JValue::Long(offset) = params[2] else { return MethodResult::MachineError("expected long offset for put") };
+    fence(Ordering::Release);
+    let result = with_slot(params[1], offset, width, |v| *v = reinterpret(params[3], *v));
+    fence(Ordering::SeqCst);
+    return match result{
+        Some(()) => MethodResult::Finish,
+        None => MethodResult::MachineError("invalid object or offset for put")
+    };
+}
+
+fn compare_and_set_z(params: Vec<JValue>) -> MethodResult{
+    // Unsafe, Object to modify, long offset, expected value, value to set
+    return match compare_and_exchange(&params){
+        Some(witness) => MethodResult::FinishWithValue(JValue::Int((witness == params[3]) as i32)),
+        None => MethodResult::MachineError("invalid object or offset for compareAndSet")
+    };
+}
+
+fn compare_and_exchange_value(params: Vec<JValue>) -> MethodResult{
+    // Unsafe, Object to modify, long offset, expected value, value to set
+    return match compare_and_exchange(&params){
+        Some(witness) => MethodResult::FinishWithValue(witness),
+        None => MethodResult::MachineError("invalid object or offset for compareAndExchange")
+    };
+}
+
+fn load_fence_v(_: Vec<JValue>) -> MethodResult{
+    fence(Ordering::Acquire);
+    return MethodResult::Finish;
+}
+
+fn store_fence_v(_: Vec<JValue>) -> MethodResult{
+    fence(Ordering::Release);
+    return MethodResult::Finish;
+}
+
+fn full_fence_v(_: Vec<JValue>) -> MethodResult{
+    fence(Ordering::SeqCst);
+    return MethodResult::Finish;
+}
+
+/// Atomically sets a slot to a new value if it holds the expected value, returning the value it held.
+fn compare_and_exchange(params: &[JValue]) -> Option<JValue>{
+    // Unsafe, Object to modify, long offset, expected value, value to set
+    let JValue::Long(offset) = params[2] else { return None };
+    fence(Ordering::SeqCst);
+    let width = access_width(if params[3].is_wide(){ "J" }else{ "I" });
+    let witness = with_slot(params[1], offset, width, |v| {
+        let witness = reinterpret(*v, params[3]);
+        if witness == params[3]{
+            *v = reinterpret(params[4], *v);
         }
+        return witness;
+    });
+    fence(Ordering::SeqCst);
+    return witness;
+}
+
+/// Returns how many bytes an access of the given type reads or writes.
+fn access_width(desc: &str) -> usize{
+    return match desc{
+        "S" | "C" => 2,
+        "I" | "F" => 4,
+        "J" | "D" => 8,
+        _ => 1
+    };
+}
+
+fn field_slot(offset: i64) -> Option<usize>{
+    if offset < 0 || offset % FIELD_OFFSET_SCALE != 0{
+        return None;
     }
-    return MethodResult::FinishWithValue(JValue::Reference(None));
+    return Some((offset / FIELD_OFFSET_SCALE) as usize);
 }
 
-fn get_int_volatile_i(params: Vec<JValue>) -> MethodResult{
-    // Unsafe, Object to access, long offset
-    let JValue::Long(idx) = params[2] else { return MethodResult::MachineError("expected long for getIntVolatile") };
-    if let JValue::Reference(Some(r)) = params[1]{
-        match &mut *r.deref().data.write().unwrap(){
-            JObjectData::Fields(fields) => {
-                let mut i = 0;
-                let mut name: Option<String> = None;
-                for field in &r.deref().class.instance_fields{
-                    if i == idx{
-                        name = Some(field.name.clone());
-                        break;
-                    }
-                    i += 1;
-                }
-                if let Some(f) = name{
-                    if let JValue::Int(ix) = fields[&f]{
-                        return MethodResult::FinishWithValue(JValue::Int(ix));
-                    }
-                }
-            }
-            JObjectData::Array(_, values) => {
-                return MethodResult::FinishWithValue(values[idx as usize]);
-            }
-        }
+/// Truncates an int to the type of a boolean, byte, short, or char field, as putfield does; Java's CAS on the int
+/// containing one can leave the upper bits set.
+fn narrow(value: JValue, desc: &str) -> JValue{
+    let JValue::Int(i) = value else { return value };
+    return JValue::Int(match desc{
+        "Z" => i & 1,
+        "B" => i as i8 as i32,
+        "S" => i as i16 as i32,
+        "C" => i as u16 as i32,
+        _ => i
+    });
+}
+
+/// Reinterprets a value as the same kind as another. Java accesses float and double slots by their bits as ints and
+/// longs (e.g. for CAS), and Unsafe can't tell us which kind of slot it's accessing.
+fn reinterpret(value: JValue, like: JValue) -> JValue{
+    return match (value, like){
+        (JValue::Int(bits), JValue::Float(_)) => JValue::Float(f32::from_bits(bits as u32)),
+        (JValue::Float(f), JValue::Int(_)) => JValue::Int(f.to_bits() as i32),
+        (JValue::Long(bits), JValue::Double(_)) => JValue::Double(f64::from_bits(bits as u64)),
+        (JValue::Double(d), JValue::Long(_)) => JValue::Long(d.to_bits() as i64),
+        _ => value
+    };
+}
+
+fn should_be_initialized_z(params: Vec<JValue>) -> MethodResult{
+    // Unsafe, Class
+    let Some(desc) = java_lang_class::get_class_desc(&params[1]) else { return MethodResult::MachineError("expected class for shouldBeInitialized0") };
+    let initialized = heap::bt_class_by_desc(desc).is_some_and(|c| *c.init_state.lock().unwrap() == InitState::Initialized);
+    return MethodResult::FinishWithValue(JValue::Int(!initialized as i32));
+}
+
+fn ensure_class_initialized_v(params: Vec<JValue>) -> MethodResult{
+    // Unsafe, Class
+    let Some(desc) = java_lang_class::get_class_desc(&params[1]) else { return MethodResult::MachineError("expected class for ensureClassInitialized0") };
+    objects::force_init_class(&desc);
+    return MethodResult::Finish;
+}
+
+fn park_v(params: Vec<JValue>) -> MethodResult{
+    // Unsafe, boolean isAbsolute, long time (millis since the epoch if absolute, otherwise nanos, with 0 = forever)
+    let JValue::Int(is_absolute) = params[1] else { return MethodResult::MachineError("expected boolean for park") };
+    let JValue::Long(time) = params[2] else { return MethodResult::MachineError("expected long for park") };
+    let timeout = if is_absolute != 0{
+        ParkTimeout::At(threads::epoch_millis(time))
+    }else if time == 0{
+        ParkTimeout::Never
+    }else{
+        ParkTimeout::After(Duration::from_nanos(time.max(0) as u64))
+    };
+    threads::park(timeout);
+    return MethodResult::Finish;
+}
+
+fn unpark_v(params: Vec<JValue>) -> MethodResult{
+    // Unsafe, Thread to unpark
+    if let JValue::Reference(Some(thread)) = params[1]{
+        threads::unpark(thread);
     }
-    return MethodResult::FinishWithValue(JValue::Int(0));
-}
\ No newline at end of file
+    return MethodResult::Finish;
+}
@@ -0,0 +1,112 @@
+use crate::parser::classfile_structs::{ConstantEntry, MemberKind, NameAndType};
+use crate::runtime::{jvalue::JValue, interpreter::{self, MethodResult, StackTrace}, objects, heap, class::ClassRef};
+use crate::runtime::native_impls::java_lang_class;
+
+pub fn builtin_constant_pool_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "getSize0(Ljava/lang/Object;)I" => get_size_i,
+        "getTagAt0(Ljava/lang/Object;I)B" => get_tag_at_b,
+        "getIntAt0(Ljava/lang/Object;I)I" => get_int_at_i,
+        "getLongAt0(Ljava/lang/Object;I)J" => get_long_at_j,
+        "getFloatAt0(Ljava/lang/Object;I)F" => get_float_at_f,
+        "getDoubleAt0(Ljava/lang/Object;I)D" => get_double_at_d,
+        "getStringAt0(Ljava/lang/Object;I)Ljava/lang/String;" => get_string_at_str,
+        "getUTF8At0(Ljava/lang/Object;I)Ljava/lang/String;" => get_utf8_at_str,
+        _ => panic!("Unknown jdk.internal.reflect.ConstantPool native: {}", name_and_desc)
+    };
+}
+
+fn get_size_i(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_pool_class(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in ConstantPool::getSize0") };
+    // entries are numbered from 1
+    return MethodResult::FinishWithValue(JValue::Int(class.constants.len() as i32 + 1));
+}
+
+fn get_tag_at_b(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_pool_class(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in ConstantPool::getTagAt0") };
+    let Some(constant) = get_constant(&class, &p) else { return wrong_type() };
+    let tag = match constant{
+        ConstantEntry::Utf8(_) => 1,
+        ConstantEntry::Integer(_) => 3,
+        ConstantEntry::Float(_) => 4,
+        ConstantEntry::Long(_) => 5,
+        ConstantEntry::Double(_) => 6,
+        ConstantEntry::LongSecond => 0,
+        ConstantEntry::Class(_) => 7,
+        ConstantEntry::StringConst(_) => 8,
+        ConstantEntry::MemberRef(m) => match m.kind{
+            MemberKind::Field => 9,
+            MemberKind::Method => 10,
+            MemberKind::InterfaceMethod => 11
+        },
+        ConstantEntry::NameAndType(_) => 12,
+        ConstantEntry::MethodHandle(_, _) => 15,
+        ConstantEntry::MethodType(_) => 16,
+        ConstantEntry::Dynamic(_) => 17,
+        ConstantEntry::InvokeDynamic(_) => 18,
+        ConstantEntry::Module(_) => 19,
+        ConstantEntry::Package(_) => 20
+    };
+    return MethodResult::FinishWithValue(JValue::Int(tag));
+}
+
+fn get_int_at_i(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_pool_class(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in ConstantPool::getIntAt0") };
+    let Some(ConstantEntry::Integer(i)) = get_constant(&class, &p) else { return wrong_type() };
+    return MethodResult::FinishWithValue(JValue::Int(*i));
+}
+
+fn get_long_at_j(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_pool_class(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in ConstantPool::getLongAt0") };
+    let Some(ConstantEntry::Long(l)) = get_constant(&class, &p) else { return wrong_type() };
+    return MethodResult::FinishWithValue(JValue::Long(*l));
+}
+
+fn get_float_at_f(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_pool_class(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in ConstantPool::getFloatAt0") };
+    let Some(ConstantEntry::Float(f)) = get_constant(&class, &p) else { return wrong_type() };
+    return MethodResult::FinishWithValue(JValue::Float(*f));
+}
+
+fn get_double_at_d(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_pool_class(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in ConstantPool::getDoubleAt0") };
+    let Some(ConstantEntry::Double(d)) = get_constant(&class, &p) else { return wrong_type() };
+    return MethodResult::FinishWithValue(JValue::Double(*d));
+}
+
+fn get_string_at_str(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_pool_class(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in ConstantPool::getStringAt0") };
+    let Some(ConstantEntry::StringConst(s)) = get_constant(&class, &p) else { return wrong_type() };
+    return MethodResult::FinishWithValue(objects::intern_chars(s.clone()));
+}
+
+fn get_utf8_at_str(p: Vec<JValue>) -> MethodResult{
+    let Some(class) = get_pool_class(&p) else { return MethodResult::Throw(StackTrace::new(), "Could not get class in ConstantPool::getUTF8At0") };
+    let Some(ConstantEntry::Utf8(s)) = get_constant(&class, &p) else { return wrong_type() };
+    return MethodResult::FinishWithValue(objects::intern_string(s));
+}
+
+/// Returns the class whose constant pool is being read, which the pool identifies by its class object.
+fn get_pool_class(p: &Vec<JValue>) -> Option<ClassRef>{
+    let desc = java_lang_class::get_class_desc(&p[1])?;
+    return heap::get_or_create_bt_class(desc).ok()?.ensure_loaded().ok();
+}
+
+fn get_constant<'a>(class: &'a ClassRef, p: &Vec<JValue>) -> Option<&'a ConstantEntry>{
+    let JValue::Int(idx) = p[2] else { return None };
+    return class.constants.get((idx as usize).wrapping_sub(1));
+}
+
+/// Throws an IllegalArgumentException for a missing entry or one of the wrong type, which AnnotationParser catches.
+fn wrong_type() -> MethodResult{
+    let class = objects::force_init_class("Ljava/lang/IllegalArgumentException;");
+    let init = NameAndType{ name: "<init>".to_owned(), descriptor: "()V".to_owned() };
+    let Some(m_idx) = class.methods.iter().position(|m| m.name == init.name && m.descriptor() == init.descriptor) else {
+        return MethodResult::Throw(StackTrace::new(), "IllegalArgumentException");
+    };
+    let exception = objects::create_new(class.clone());
+    return match (interpreter::execute_at(class.clone(), m_idx, vec![exception], StackTrace::new()), exception){
+        (MethodResult::Finish, JValue::Reference(Some(exception))) => MethodResult::ThrowObject(StackTrace::new(), exception),
+        (result, _) => result
+    };
+}
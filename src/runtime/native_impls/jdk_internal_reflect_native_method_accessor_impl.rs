@@ -0,0 +1,120 @@
+use crate::parser::classfile_structs::NameAndType;
+use crate::runtime::interpreter::{self, MethodResult, StackTrace};
+use crate::runtime::{heap, jvalue::{JValue, JObjectData}, objects, class::ClassRef};
+use crate::runtime::native_impls::java_lang_class;
+
+pub fn builtin_native_method_accessor_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "invoke0(Ljava/lang/reflect/Method;Ljava/lang/Object;[Ljava/lang/Object;)Ljava/lang/Object;" => invoke0_obj,
+        _ => panic!("Unknown jdk.internal.reflect.NativeMethodAccessorImpl native: {}", name_and_desc)
+    };
+}
+
+fn invoke0_obj(p: Vec<JValue>) -> MethodResult{
+    // Method, Object receiver, Object[] args; Method has checked access and the argument count
+    let method_class = objects::force_init_class("Ljava/lang/reflect/Method;");
+    let (Some(clazz), Some(JValue::Int(slot))) = (field(p[0], &method_class, "clazz"), field(p[0], &method_class, "slot")) else {
+        return MethodResult::Throw(StackTrace::new(), "NPE in NativeMethodAccessorImpl::invoke0");
+    };
+    let Some(class) = java_lang_class::get_class_desc(&clazz)
+        .and_then(|d| heap::get_or_create_bt_class(d).ok())
+        .and_then(|c| c.ensure_initialized().ok()) else {
+        return MethodResult::Throw(StackTrace::new(), "Could not load class in NativeMethodAccessorImpl::invoke0");
+    };
+    let slot = slot as usize;
+    let method = &class.methods[slot];
+
+    let given = match p[2]{
+        JValue::Reference(Some(r)) => match &*r.deref().data.read().unwrap(){
+            JObjectData::Array(values) => (0..values.len()).map(|i| values.load(i)).collect(),
+            _ => Vec::new()
+        },
+        _ => Vec::new()
+    };
+    if given.len() != method.parameters.len(){
+        return MethodResult::Throw(StackTrace::new(), "IllegalArgumentException");
+    }
+    let mut args = Vec::with_capacity(given.len() + 1);
+    for (value, param) in given.into_iter().zip(&method.parameters){
+        match unbox(value, &param.descriptor()){
+            Some(v) => args.push(v),
+            None => return MethodResult::Throw(StackTrace::new(), "IllegalArgumentException")
+        }
+    }
+
+    // instance methods are selected by the receiver's class, like invokevirtual and invokeinterface
+    let (target, idx) = if method.is_static{
+        (class.clone(), slot)
+    }else{
+        let JValue::Reference(Some(receiver)) = p[1] else { return MethodResult::Throw(StackTrace::new(), "NullPointerException") };
+        if !receiver.deref().assignable_to(&class.descriptor){
+            return MethodResult::Throw(StackTrace::new(), "IllegalArgumentException");
+        }
+        args.insert(0, p[1]);
+        let receiver_class = receiver.deref().class.clone();
+        let selected = if class.is_interface(){
+            receiver_class.itable_method(&class, slot).and_then(|s| s.as_ref().ok())
+        }else{
+            method.vtable_idx.and_then(|v| receiver_class.vtable.get(v))
+        };
+        match selected{
+            Some(s) => (s.owner.clone().unwrap_or_else(|| receiver_class.clone()), s.idx),
+            None => (class.clone(), slot)
+        }
+    };
+
+    let return_type = target.methods[idx].return_type.descriptor();
+    return match interpreter::execute_at(target, idx, args, StackTrace::new()){
+        MethodResult::FinishWithValue(v) => MethodResult::FinishWithValue(objects::box_primitive(v, &return_type)),
+        MethodResult::Finish => MethodResult::FinishWithValue(JValue::Reference(None)),
+        // exceptions thrown by the method are wrapped
+        MethodResult::ThrowObject(trace, e) => match invocation_target_exception(JValue::Reference(Some(e))){
+            Some(wrapped) => MethodResult::ThrowObject(trace, wrapped),
+            None => MethodResult::ThrowObject(trace, e)
+        },
+        other => other
+    };
+}
+
+pub fn field(object: JValue, class: &ClassRef, name: &str) -> Option<JValue>{
+    let JValue::Reference(Some(r)) = object else { return None };
+    if let JObjectData::Fields(fields) = &*r.deref().data.read().unwrap(){
+        return Some(fields[class.field_offset(name)?]);
+    }
+    return None;
+}
+
+/// Unboxes an argument for a parameter of the given type, or returns None if it's the wrong type.
+fn unbox(value: JValue, desc: &str) -> Option<JValue>{
+    let wrapper = match desc{
+        "Z" => "Ljava/lang/Boolean;",
+        "B" => "Ljava/lang/Byte;",
+        "S" => "Ljava/lang/Short;",
+        "C" => "Ljava/lang/Character;",
+        "I" => "Ljava/lang/Integer;",
+        "J" => "Ljava/lang/Long;",
+        "F" => "Ljava/lang/Float;",
+        "D" => "Ljava/lang/Double;",
+        _ => return match value{
+            JValue::Reference(Some(r)) if !r.deref().assignable_to(desc) => None,
+            _ => Some(value)
+        }
+    };
+    let JValue::Reference(Some(r)) = value else { return None };
+    let class = r.deref().class.clone();
+    if class.descriptor != wrapper{
+        return None;
+    }
+    return field(value, &class, "value");
+}
+
+fn invocation_target_exception(cause: JValue) -> Option<heap::JRef>{
+    let class = objects::force_init_class("Ljava/lang/reflect/InvocationTargetException;");
+    let init = NameAndType{ name: "<init>".to_owned(), descriptor: "(Ljava/lang/Throwable;)V".to_owned() };
+    let m_idx = class.methods.iter().position(|m| m.name == init.name && m.descriptor() == init.descriptor)?;
+    let JValue::Reference(Some(exception)) = objects::create_new(class.clone()) else { return None };
+    return match interpreter::execute_at(class.clone(), m_idx, vec![JValue::Reference(Some(exception)), cause], StackTrace::new()){
+        MethodResult::Finish => Some(exception),
+        _ => None
+    };
+}
@@ -1,16 +1,27 @@
 use crate::runtime::interpreter::{MethodResult, StackTrace};
-use crate::runtime::{heap, objects};
+use crate::runtime::{heap, jvalue::JValue, objects};
+use crate::runtime::native_impls::java_lang_class;
 
-pub fn run_reflection_native(name_and_desc: &str, trace: &StackTrace) -> MethodResult{
+pub fn run_reflection_native(name_and_desc: &str, trace: &StackTrace, args: Vec<JValue>) -> MethodResult{
     return match name_and_desc{
         "getCallerClass()Ljava/lang/Class;" => get_caller_class(trace),
+        "getClassAccessFlags(Ljava/lang/Class;)I" => get_class_access_flags(args),
         _ => panic!("Unknown jdk.internal.reflection.Reflect native: {}", name_and_desc)
     };
 }
 
 fn get_caller_class(trace: &StackTrace) -> MethodResult{
-    let caller = &trace[1]; // no frame for getCallerClass created & skipping direct caller
+    // traces start from the outermost frame; there's no frame for getCallerClass, and we skip its direct caller
+    let caller = &trace[trace.len() - 2];
     let caller_name = &caller.class_name;
     let as_descriptor = format!("L{};", caller_name.clone().replace(".", "/"));
-    return MethodResult::FinishWithValue(heap::add_ref(objects::synthesize_class(&as_descriptor)));
-}
\ No newline at end of file
+    return MethodResult::FinishWithValue(objects::class_object(&as_descriptor));
+}
+
+fn get_class_access_flags(args: Vec<JValue>) -> MethodResult{
+    // Class
+    let Some(desc) = java_lang_class::get_class_desc(&args[0]) else { return MethodResult::Throw(StackTrace::new(), "NPE in Reflection::getClassAccessFlags") };
+    let class = heap::get_or_create_bt_class(desc).expect("Could not load class for getClassAccessFlags");
+    let class = class.ensure_loaded().expect("Could not link class for getClassAccessFlags");
+    return MethodResult::FinishWithValue(JValue::Int(class.flags as i32));
+}
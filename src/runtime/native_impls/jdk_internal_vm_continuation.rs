@@ -0,0 +1,47 @@
+use crate::runtime::{interpreter::{self, MethodResult, StackTrace}, jvalue::JValue};
+
+pub fn builtin_continuation_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "registerNatives()V" => no_op_v,
+        "pin()V" => pin_v,
+        "unpin()V" => unpin_v,
+        "isPinned0(Ljdk/internal/vm/ContinuationScope;)I" => is_pinned_i,
+        // enterSpecial and doYield are handled by the interpreter
+        _ => panic!("Unknown jdk.internal.vm.Continuation native: {}", name_and_desc)
+    };
+}
+
+pub fn builtin_continuation_support_native(name_and_desc: &str) -> fn(Vec<JValue>) -> MethodResult{
+    return match name_and_desc{
+        "isSupported0()Z" => const_1_i,
+        _ => panic!("Unknown jdk.internal.vm.ContinuationSupport native: {}", name_and_desc)
+    };
+}
+
+fn no_op_v(_: Vec<JValue>) -> MethodResult{
+    return MethodResult::Finish;
+}
+
+fn const_1_i(_: Vec<JValue>) -> MethodResult{
+    return MethodResult::FinishWithValue(JValue::Int(1));
+}
+
+fn pin_v(_: Vec<JValue>) -> MethodResult{
+    return match interpreter::pin_continuation(true){
+        Ok(()) => MethodResult::Finish,
+        Err(e) => MethodResult::Throw(StackTrace::new(), e)
+    };
+}
+
+fn unpin_v(_: Vec<JValue>) -> MethodResult{
+    return match interpreter::pin_continuation(false){
+        Ok(()) => MethodResult::Finish,
+        Err(e) => MethodResult::Throw(StackTrace::new(), e)
+    };
+}
+
+fn is_pinned_i(args: Vec<JValue>) -> MethodResult{
+    // ContinuationScope
+    let JValue::Reference(Some(scope)) = args[0] else { return MethodResult::Throw(StackTrace::new(), "NPE in Continuation::isPinned0") };
+    return MethodResult::FinishWithValue(JValue::Int(interpreter::continuation_pinned_reason(scope)));
+}
@@ -10,31 +10,55 @@ mod java_lang_string;
 mod java_lang_throwable;
 mod java_lang_number;
 mod java_lang_thread;
+mod java_lang_reflect_array;
+mod java_lang_reflect_executable;
+mod java_lang_invoke_method_handle_natives;
+mod java_lang_ref_reference;
 
 mod java_io_file_descriptor;
 mod java_io_file_io_stream;
 
+mod jdk_internal_loader_boot_loader;
 mod jdk_internal_misc_unsafe;
 mod jdk_internal_misc_cds;
 mod jdk_internal_misc_vm;
 mod jdk_internal_misc_sma;
 mod jdk_internal_misc_signal;
 mod jdk_internal_reflect_reflection;
+mod jdk_internal_reflect_constant_pool;
+mod jdk_internal_reflect_native_method_accessor_impl;
 mod jdk_internal_util_system_props;
+mod jdk_internal_vm_continuation;
 
 mod java_security_access_controller;
 
+mod java_util_concurrent_atomic_atomic_long;
+
+/// Returns whether the VM implements the given platform method itself instead of running its bytecode,
+/// for methods whose bytecode needs something the VM doesn't support yet.
+pub fn replaces_bytecode(owner: &str, name_and_desc: &str) -> bool{
+    return matches!((owner, name_and_desc),
+        ("java.lang.Class", "getPermittedSubclasses()[Ljava/lang/Class;") // filters with a lambda
+    );
+}
+
 pub fn builtin_native(owner: &String, name_and_desc: &String, trace: &StackTrace, args: Vec<JValue>) -> MethodResult{
     return match owner as &str{
         "java.lang.Object" => java_lang_object::builtin_object_native(name_and_desc)(args),
         "java.lang.System" => java_lang_system::builtin_system_native(name_and_desc)(args),
         "java.lang.Runtime" => java_lang_runtime::builtin_runtime_native(name_and_desc)(args),
         "java.lang.Class" => java_lang_class::builtin_class_native(name_and_desc)(args),
+        "java.lang.String" => java_lang_string::builtin_string_native(name_and_desc)(args),
         "java.lang.StringUTF16" => java_lang_string::builtin_string_utf16_native(name_and_desc)(args),
         "java.lang.Throwable" => java_lang_throwable::builtin_throwable_native(name_and_desc)(args),
         "java.lang.Float" => java_lang_number::builtin_float_native(name_and_desc)(args),
         "java.lang.Double" => java_lang_number::builtin_double_native(name_and_desc)(args),
         "java.lang.Thread" => java_lang_thread::builtin_thread_native(name_and_desc)(args),
+        "java.lang.VirtualThread" => java_lang_thread::builtin_virtual_thread_native(name_and_desc)(args),
+        "java.lang.reflect.Array" => java_lang_reflect_array::builtin_array_native(name_and_desc)(args),
+        "java.lang.reflect.Executable" => java_lang_reflect_executable::builtin_executable_native(name_and_desc)(args),
+        "java.lang.ref.Reference" => java_lang_ref_reference::builtin_reference_native(name_and_desc)(args),
+        "java.lang.invoke.MethodHandleNatives" => java_lang_invoke_method_handle_natives::builtin_method_handle_natives_native(name_and_desc)(args),
 
         "java.io.FileDescriptor" => java_io_file_descriptor::builtin_file_descriptor_native(name_and_desc)(args),
         "java.io.FileInputStream" => java_io_file_io_stream::builtin_file_input_stream_native(name_and_desc)(args),
@@ -42,7 +66,13 @@ pub fn builtin_native(owner: &String, name_and_desc: &String, trace: &StackTrace
 
         "java.security.AccessController" => java_security_access_controller::builtin_access_controller_native(name_and_desc)(args),
 
-        "jdk.internal.reflect.Reflection" => jdk_internal_reflect_reflection::run_reflection_native(name_and_desc, trace),
+        "java.util.concurrent.atomic.AtomicLong" => java_util_concurrent_atomic_atomic_long::builtin_atomic_long_native(name_and_desc)(args),
+
+        "jdk.internal.reflect.Reflection" => jdk_internal_reflect_reflection::run_reflection_native(name_and_desc, trace, args),
+        "jdk.internal.reflect.ConstantPool" => jdk_internal_reflect_constant_pool::builtin_constant_pool_native(name_and_desc)(args),
+        "jdk.internal.reflect.NativeMethodAccessorImpl" => jdk_internal_reflect_native_method_accessor_impl::builtin_native_method_accessor_native(name_and_desc)(args),
+
+        "jdk.internal.loader.BootLoader" => jdk_internal_loader_boot_loader::builtin_boot_loader_native(name_and_desc)(args),
 
         "jdk.internal.misc.Unsafe" => jdk_internal_misc_unsafe::builtin_unsafe_native(name_and_desc)(args),
         "jdk.internal.misc.CDS" => jdk_internal_misc_cds::builtin_cds_native(name_and_desc)(args),
@@ -52,6 +82,9 @@ pub fn builtin_native(owner: &String, name_and_desc: &String, trace: &StackTrace
 
         "jdk.internal.util.SystemProps$Raw" => jdk_internal_util_system_props::builtin_raw_system_props_native(name_and_desc)(args),
 
+        "jdk.internal.vm.Continuation" => jdk_internal_vm_continuation::builtin_continuation_native(name_and_desc)(args),
+        "jdk.internal.vm.ContinuationSupport" => jdk_internal_vm_continuation::builtin_continuation_support_native(name_and_desc)(args),
+
         _ => panic!("Unknown builtin native owner {} for method {}", owner, name_and_desc)
     }
 }
\ No newline at end of file
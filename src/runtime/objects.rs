@@ -1,65 +1,179 @@
 // methods for building java objects (e.g. string constants)
 
-use std::collections::HashMap;
-use std::sync::Arc;
-use crate::runtime::{jvalue::{JObject, JObjectData, JValue}, class::ClassRef, classes, heap};
+use std::{collections::HashMap, sync::RwLock};
+use crate::parser::classfile_structs::NameAndType;
+use crate::runtime::{jvalue::{JObject, JObjectData, JValue, ArrayData}, class::ClassRef, heap::{self, JRef}};
+use crate::runtime::interpreter::{self, MethodResult, StackTrace};
 
-use crate::constants;
+// values of String.coder
+const LATIN1: i32 = 0;
+const UTF16: i32 = 1;
+
+// Map of string contents -> the interned string with those contents
+// TODO: hold these weakly once there's a GC
+static INTERNED_STRINGS: RwLock<Option<HashMap<Vec<u16>, JRef>>> = RwLock::new(None);
+// Map of class descriptor -> the class object for it
+static CLASS_OBJECTS: RwLock<Option<HashMap<String, JRef>>> = RwLock::new(None);
 
 pub fn create_new(of: ClassRef) -> JValue{
-    let mut fields = HashMap::with_capacity(of.instance_fields.len());
-    for f in &of.instance_fields{
-        fields.insert(f.name.clone(), JValue::default_value_for(&f.type_class.descriptor()));
-    }
+    let fields = default_fields(&of);
     return heap::add_ref(JObject::new(of, JObjectData::Fields(fields)));
 }
 
+/// Returns the slots of a new object of the given class, including inherited fields, set to their default values.
+fn default_fields(of: &ClassRef) -> Vec<JValue>{
+    return of.instance_layout.iter()
+        .map(|key| JValue::default_value_for(&key.descriptor))
+        .collect();
+}
+
 pub fn create_new_array(of: ClassRef, length: usize) -> JValue{
-    let mut elements = Vec::with_capacity(length);
-    for _ in 0..length{
-        elements.push(JValue::default_value_for(&of.descriptor));
-    }
-    return heap::add_ref(JObject::new(of, JObjectData::Array(length, elements)));
+    let elements = ArrayData::new(&of.descriptor, length);
+    return heap::add_ref(JObject::new(of, JObjectData::Array(elements)));
 }
 
 pub fn create_new_array_of(of: ClassRef, values: Vec<JValue>) -> JValue{
-    return heap::add_ref(JObject::new(of, JObjectData::Array(values.len(), values)));
+    let elements = ArrayData::of(&of.descriptor, values);
+    return heap::add_ref(JObject::new(of, JObjectData::Array(elements)));
+}
+
+/// Create a new Java byte array holding the given bytes.
+pub fn create_byte_array(bytes: Vec<i8>) -> JValue{
+    let class = heap::bt_class_by_desc("B".to_owned()).unwrap();
+    return heap::add_ref(JObject::new(class, JObjectData::Array(ArrayData::Byte(bytes))));
+}
+
+/// Boxes a value of the primitive type with the given descriptor, with its wrapper's `valueOf`.
+pub fn box_primitive(value: JValue, desc: &str) -> JValue{
+    let wrapper = match desc{
+        "Z" => "Ljava/lang/Boolean;",
+        "B" => "Ljava/lang/Byte;",
+        "S" => "Ljava/lang/Short;",
+        "C" => "Ljava/lang/Character;",
+        "I" => "Ljava/lang/Integer;",
+        "J" => "Ljava/lang/Long;",
+        "F" => "Ljava/lang/Float;",
+        "D" => "Ljava/lang/Double;",
+        _ => return value
+    };
+    let class = force_init_class(wrapper);
+    let value_of = NameAndType{ name: "valueOf".to_string(), descriptor: format!("({}){}", desc, wrapper) };
+    let method = class.static_method(&value_of).expect("Wrapper class has no valueOf");
+    return match interpreter::execute(&class, method, vec![value], StackTrace::new()){
+        MethodResult::FinishWithValue(boxed) => boxed,
+        _ => panic!("Could not box {}", desc)
+    };
 }
 
 /// Create a new Java string object with the given text.
 pub fn synthesize_string(string: &String) -> JObject{
-    let mut fields = HashMap::with_capacity(4);
-    fields.insert("value".to_owned(), array_of(wrap_bytes(as_utf16(string))));
-    fields.insert("coder".to_owned(), JValue::Int(1)); // always UTF16
-    fields.insert("hash".to_owned(), JValue::Int(0)); // let java figure it out; these are default values
-    fields.insert("hashIsZero".to_owned(), JValue::Int(0));
-    return JObject::new(string_class(), JObjectData::Fields(fields));
+    let chars: Vec<u16> = string.encode_utf16().collect();
+    return synthesize_string_from_chars(&chars);
+}
+
+/// Create a new Java string object with the given UTF-16 code units, using Latin-1 if they all fit.
+fn synthesize_string_from_chars(chars: &[u16]) -> JObject{
+    let class = string_class();
+    let (value, coder) = if compact_strings() && chars.iter().all(|c| *c <= 0xFF){
+        (chars.iter().map(|c| *c as u8 as i8).collect(), LATIN1)
+    }else{
+        // big-endian, as StringUTF16.isBigEndian says
+        (chars.iter().flat_map(|c| c.to_be_bytes()).map(|b| b as i8).collect(), UTF16)
+    };
+    // hash and hashIsZero are left at their default values, for java to figure out
+    let mut fields = default_fields(&class);
+    fields[class.field_offset("value").unwrap()] = create_byte_array(value);
+    fields[class.field_offset("coder").unwrap()] = JValue::Int(coder);
+    return JObject::new(class, JObjectData::Fields(fields));
+}
+
+/// Returns whether java.lang.String stores strings as Latin-1 where it can, which it decides in its static initializer.
+fn compact_strings() -> bool{
+    for field in &string_class().static_fields{
+        let field = field.read().unwrap();
+        if field.0.name == "COMPACT_STRINGS"{
+            return field.1 == JValue::Int(1);
+        }
+    }
+    return false;
+}
+
+/// Returns the interned string with the given text, creating it if needed.
+pub fn intern_string(string: &String) -> JValue{
+    return intern_chars(string.encode_utf16().collect());
+}
+
+/// Returns the interned string with the given UTF-16 code units, creating it if needed; used for string constants.
+pub fn intern_chars(chars: Vec<u16>) -> JValue{
+    if let Some(r) = INTERNED_STRINGS.read().unwrap().as_ref().and_then(|t| t.get(&chars)){
+        return JValue::Reference(Some(*r));
+    }
+    // creating the string can initialize String, which has its own string constants
+    let created = heap::add(synthesize_string_from_chars(&chars));
+    return JValue::Reference(Some(intern(chars, created)));
+}
+
+/// Returns the interned string with the same text as the given string, which becomes it if there isn't one yet.
+pub fn intern_existing(jstring: JRef) -> JRef{
+    return intern(java_string_chars(JValue::Reference(Some(jstring))), jstring);
+}
+
+fn intern(chars: Vec<u16>, string: JRef) -> JRef{
+    let mut table = INTERNED_STRINGS.write().unwrap();
+    return *table.get_or_insert_with(HashMap::new).entry(chars).or_insert(string);
+}
+
+/// Returns the Java class object for the given descriptor, creating it if needed.
+pub fn class_object(descriptor: &String) -> JValue{
+    if let Some(r) = CLASS_OBJECTS.read().unwrap().as_ref().and_then(|t| t.get(descriptor)){
+        return JValue::Reference(Some(*r));
+    }
+    // creating it can run Class's static initializer
+    let created = heap::add(synthesize_class(descriptor));
+    let mut table = CLASS_OBJECTS.write().unwrap();
+    let r = *table.get_or_insert_with(HashMap::new).entry(descriptor.clone()).or_insert(created);
+    return JValue::Reference(Some(r));
 }
 
-// TODO!: cache class objects for ==
 /// Create a new Java class object with the given descriptor.
-/// The descriptor is stored in an undeclared field `JVM_DESCRIPTOR`.
-pub fn synthesize_class(descriptor: &String) -> JObject{
-    let mut fields = HashMap::with_capacity(7 + 1);
-    fields.insert(constants::CLASS_DESC_FIELD_NAME.to_owned(), heap::add_ref(synthesize_string(descriptor)));
-    return JObject::new(class_class(), JObjectData::Fields(fields));
+/// The descriptor is stored in an undeclared slot, after the declared fields.
+fn synthesize_class(descriptor: &String) -> JObject{
+    let class = class_class();
+    let mut fields = default_fields(&class);
+    if let Some(component) = descriptor.strip_prefix("["){
+        fields[class.field_offset("componentType").unwrap()] = class_object(&component.to_string());
+    }
+    fields.push(heap::add_ref(synthesize_string(descriptor)));
+    return JObject::new(class, JObjectData::Fields(fields));
+}
+
+/// Returns the offset of the undeclared field holding the descriptor of a synthesized class object.
+pub fn class_desc_offset() -> usize{
+    return class_class().instance_layout.len();
 }
 
 pub fn java_string_to_rust_string(jstring: JValue) -> String{
+    // java strings can hold unpaired surrogates
+    return String::from_utf16_lossy(&java_string_chars(jstring));
+}
+
+/// Returns the UTF-16 code units of a Java string, whichever coder it uses.
+pub fn java_string_chars(jstring: JValue) -> Vec<u16>{
     if let JValue::Reference(Some(r)) = jstring{
         let obj = r.deref();
         if let JObjectData::Fields(f) = &*obj.data.read().unwrap(){
-            let value = f["value"];
+            let value = f[obj.class.field_offset("value").unwrap()];
+            let coder = f[obj.class.field_offset("coder").unwrap()];
             if let JValue::Reference(Some(r)) = value{
                 let array = r.deref();
-                if let JObjectData::Array(_, v) = &*array.data.read().unwrap(){
-                    let bytes = unwrap_bytes(v);
-                    let bytes: Vec<u16> = bytes
-                        .chunks_exact(2)
-                        .into_iter()
-                        .map(|a| u16::from_be_bytes([a[0], a[1]]))
-                        .collect();
-                    return String::from_utf16(&bytes).unwrap();
+                if let JObjectData::Array(ArrayData::Byte(bytes)) = &*array.data.read().unwrap(){
+                    return if coder == JValue::Int(LATIN1){
+                        bytes.iter().map(|b| *b as u8 as u16).collect()
+                    }else{
+                        bytes.chunks_exact(2)
+                            .map(|a| u16::from_be_bytes([a[0] as u8, a[1] as u8]))
+                            .collect()
+                    };
                 };
             }
         };
@@ -70,42 +184,6 @@ pub fn java_string_to_rust_string(jstring: JValue) -> String{
 // implementation
 // all panic rather than erroring
 
-fn wrap_bytes(ints: Vec<u8>) -> Vec<JValue>{
-    return ints.iter().map(|i| JValue::Int(*i as i32)).collect();
-}
-
-fn unwrap_bytes(ints: &Vec<JValue>) -> Vec<u8>{
-    let mut ret = Vec::with_capacity(ints.len());
-    for v in ints{
-        if let JValue::Int(i) = v{
-            ret.push(*i as u8);
-        }else{
-            panic!("Tried to call objects::unwrap_bytes on a vec with non-ints!");
-        }
-    }
-    return ret;
-}
-
-fn as_utf16(string: &str) -> Vec<u8>{
-    let mut ret = Vec::with_capacity(string.len() * 2);
-    for i in string.encode_utf16(){
-        for b in i.to_be_bytes(){
-            ret.push(b);
-        }
-    }
-    return ret;
-}
-
-fn array_of(objects: Vec<JValue>) -> JValue{
-    let class = if objects.len() == 0{
-        heap::bt_class_by_desc("Ljava/lang/Object;".to_owned()).unwrap()
-    }else{
-        objects[0].class()
-    };
-    let class = Arc::new(classes::array_class(&class));
-    return heap::add_ref(JObject::new(class, JObjectData::Array(objects.len(), objects)));
-}
-
 pub fn force_init_class(desc: &str) -> ClassRef{
     return heap::get_or_create_bt_class(desc.to_string())
         .expect(format!("Could not parse {}!", desc).as_str())
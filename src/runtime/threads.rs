@@ -0,0 +1,310 @@
+// Java threads, each running on its own OS thread with its own interpreter stack.
+
+use std::{cell::Cell, sync::{Arc, Condvar, Mutex}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use crate::parser::classfile_structs::NameAndType;
+use crate::runtime::{jvalue::{JValue, JObjectData}, interpreter::{self, MethodResult, StackTrace}, heap::JRef, objects};
+
+// values of Thread.threadStatus, as JVMTI thread states
+const STATUS_RUNNABLE: i32 = 0x0005; // alive and runnable
+const STATUS_TERMINATED: i32 = 0x0002;
+
+/// A thread that has started and not yet finished.
+struct LiveThread{
+    thread: JRef,
+    daemon: bool,
+    parker: Arc<Parker>,
+    waiting_on: Option<JRef> // the object whose monitor it's waiting on, if any
+}
+
+/// What a thread blocks on when parked or sleeping.
+struct Parker{
+    permit: Mutex<bool>, // given by unpark, and taken by park
+    wakeup: Condvar // signalled when unparked or interrupted
+}
+
+static LIVE_THREADS: Mutex<Vec<LiveThread>> = Mutex::new(Vec::new());
+static THREAD_FINISHED: Condvar = Condvar::new();
+
+thread_local!{
+    /// The Thread object for the Java thread running on this OS thread.
+    static CURRENT_THREAD: Cell<Option<JRef>> = const { Cell::new(None) };
+    /// The virtual thread mounted on this OS thread by `Thread.setCurrentThread`, if any.
+    static MOUNTED_THREAD: Cell<Option<JRef>> = const { Cell::new(None) };
+    /// The cache of `ScopedValue` bindings for the thread running here, which virtual threads swap out when unmounted.
+    static SCOPED_VALUE_CACHE: Cell<Option<JRef>> = const { Cell::new(None) };
+}
+
+/// Returns the platform thread running on this OS thread, which is the carrier of any virtual thread mounted on it.
+/// Parking, sleeping, and waiting happen on the carrier.
+pub fn current_thread() -> Option<JRef>{
+    return CURRENT_THREAD.get();
+}
+
+pub fn set_current_thread(thread: JRef){
+    CURRENT_THREAD.set(Some(thread));
+}
+
+pub fn mounted_thread() -> Option<JRef>{
+    return MOUNTED_THREAD.get();
+}
+
+/// Mounts a virtual thread on this OS thread, or unmounts it if None.
+pub fn set_mounted_thread(thread: Option<JRef>){
+    MOUNTED_THREAD.set(thread);
+}
+
+pub fn scoped_value_cache() -> Option<JRef>{
+    return SCOPED_VALUE_CACHE.get();
+}
+
+pub fn set_scoped_value_cache(cache: Option<JRef>){
+    SCOPED_VALUE_CACHE.set(cache);
+}
+
+/// Marks a thread as started, adding it to the live threads.
+pub fn register(thread: JRef){
+    let daemon = get_thread_field(thread, "daemon") == Some(JValue::Int(1));
+    set_thread_field(thread, "eetop", JValue::Long(1)); // any non-zero value, for Thread.isAlive
+    set_thread_field(thread, "threadStatus", JValue::Int(STATUS_RUNNABLE));
+    LIVE_THREADS.lock().unwrap().push(LiveThread{
+        thread,
+        daemon,
+        parker: Arc::new(Parker{ permit: Mutex::new(false), wakeup: Condvar::new() }),
+        waiting_on: None
+    });
+}
+
+/// Starts running a Thread's `run` method on a new OS thread.
+pub fn start(thread: JRef){
+    register(thread);
+    let name = thread_name(thread);
+    std::thread::Builder::new()
+        .name(name.clone())
+        .spawn(move || {
+            set_current_thread(thread);
+            let class = thread.deref().class.clone();
+            let run = NameAndType{ name: "run".to_string(), descriptor: "()V".to_string() };
+            let (method, owner) = class.resolve_method(&run).expect("Thread has no run method");
+            match interpreter::execute(owner, method, vec![JValue::Reference(Some(thread))], StackTrace::new()){
+                MethodResult::ThrowObject(s, e) => eprintln!("Exception in thread \"{}\" {}\n{}", name, e.deref().class.name, s),
+                MethodResult::Throw(s, e) => eprintln!("Exception in thread \"{}\" {}\n{}", name, e, s),
+                MethodResult::MachineError(e) => eprintln!("Machine error in thread \"{}\": {}", name, e),
+                _ => {}
+            }
+            finish(thread);
+        })
+        .expect("Could not spawn thread");
+}
+
+/// Lets a thread clean up with `Thread.exit`, removes it from the live threads, and wakes threads joining it.
+pub fn finish(thread: JRef){
+    let class = objects::force_init_class("Ljava/lang/Thread;");
+    let exit = NameAndType{ name: "exit".to_string(), descriptor: "()V".to_string() };
+    if let Some(method) = class.declared_method(&exit){
+        interpreter::execute(&class, method, vec![JValue::Reference(Some(thread))], StackTrace::new());
+    }
+    set_thread_field(thread, "threadStatus", JValue::Int(STATUS_TERMINATED));
+    set_thread_field(thread, "eetop", JValue::Long(0));
+    LIVE_THREADS.lock().unwrap().retain(|t| t.thread != thread);
+    THREAD_FINISHED.notify_all();
+
+    let monitor = &thread.deref().monitor;
+    monitor.enter();
+    monitor.notify(true).unwrap();
+    monitor.exit().unwrap();
+}
+
+/// Blocks until every non-daemon thread other than this one has finished, so the VM can exit.
+pub fn wait_for_non_daemon_threads(){
+    let current = current_thread();
+    let mut live = LIVE_THREADS.lock().unwrap();
+    while live.iter().any(|t| !t.daemon && Some(t.thread) != current){
+        live = THREAD_FINISHED.wait(live).unwrap();
+    }
+}
+
+pub fn is_alive(thread: JRef) -> bool{
+    return LIVE_THREADS.lock().unwrap().iter().any(|t| t.thread == thread);
+}
+
+pub fn live_threads() -> Vec<JRef>{
+    return LIVE_THREADS.lock().unwrap().iter().map(|t| t.thread).collect();
+}
+
+// Interruption
+// Thread.interrupt sets the interrupted field itself, then calls interrupt0 to wake the thread up.
+
+/// Wakes the thread up if it's sleeping or waiting, so it can see it's been interrupted.
+pub fn interrupt(thread: JRef){
+    let live = LIVE_THREADS.lock().unwrap();
+    if let Some(t) = live.iter().find(|t| t.thread == thread){
+        let _permit = t.parker.permit.lock().unwrap();
+        t.parker.wakeup.notify_all();
+        if let Some(obj) = t.waiting_on{
+            obj.deref().monitor.wake_waiters();
+        }
+    }
+}
+
+/// Returns whether the current thread has been interrupted, clearing its interrupted status if so.
+pub fn take_interrupt() -> bool{
+    let Some(thread) = current_thread() else { return false };
+    if get_thread_field(thread, "interrupted") == Some(JValue::Int(1)){
+        set_thread_field(thread, "interrupted", JValue::Int(0));
+        return true;
+    }
+    return false;
+}
+
+fn is_interrupted(thread: Option<JRef>) -> bool{
+    return thread.is_some_and(|t| get_thread_field(t, "interrupted") == Some(JValue::Int(1)));
+}
+
+/// Sleeps for the given duration, returning false if interrupted first.
+pub fn sleep(duration: Duration) -> bool{
+    let Some(parker) = current_parker() else {
+        std::thread::sleep(duration);
+        return true;
+    };
+    let deadline = Instant::now() + duration;
+    let mut permit = parker.permit.lock().unwrap();
+    loop{
+        if take_interrupt(){
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline{
+            return true;
+        }
+        // being unparked doesn't end a sleep
+        permit = parker.wakeup.wait_timeout(permit, deadline - now).unwrap().0;
+    }
+}
+
+// Parking (LockSupport)
+
+/// When a parked thread should stop waiting, if it isn't unparked or interrupted first.
+pub enum ParkTimeout{
+    Never,
+    After(Duration),
+    At(SystemTime)
+}
+
+/// Blocks until the current thread is given its permit by `unpark`, is interrupted, or the timeout passes, and takes
+/// the permit. Returns immediately if the permit was already given.
+pub fn park(timeout: ParkTimeout){
+    let deadline = match timeout{
+        ParkTimeout::Never => None,
+        ParkTimeout::After(duration) => Some(Instant::now() + duration),
+        // past deadlines give a remaining time of zero
+        ParkTimeout::At(time) => Some(Instant::now() + time.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+    };
+    // a thread without a Thread object can't be unparked, and parking is allowed to return spuriously
+    let Some(parker) = current_parker() else { return };
+    let mut permit = parker.permit.lock().unwrap();
+    loop{
+        if *permit{
+            *permit = false;
+            return;
+        }
+        if is_interrupted(current_thread()){
+            return;
+        }
+        match deadline{
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline{
+                    return;
+                }
+                permit = parker.wakeup.wait_timeout(permit, deadline - now).unwrap().0;
+            },
+            None => permit = parker.wakeup.wait(permit).unwrap()
+        }
+    }
+}
+
+/// Gives a thread its permit, unparking it if it's parked. Does nothing if the thread hasn't started or has finished.
+pub fn unpark(thread: JRef){
+    let live = LIVE_THREADS.lock().unwrap();
+    if let Some(t) = live.iter().find(|t| t.thread == thread){
+        *t.parker.permit.lock().unwrap() = true;
+        t.parker.wakeup.notify_all();
+    }
+}
+
+/// Converts a time in milliseconds since the epoch, as used by `Unsafe.park`, into a time.
+pub fn epoch_millis(millis: i64) -> SystemTime{
+    return UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64);
+}
+
+fn current_parker() -> Option<Arc<Parker>>{
+    let current = current_thread()?;
+    return LIVE_THREADS.lock().unwrap().iter()
+        .find(|t| t.thread == current)
+        .map(|t| t.parker.clone());
+}
+
+/// Waits on an object's monitor, which the current thread must own, until notified, interrupted, or the timeout
+/// passes. Errors are the names of the exception to throw.
+pub fn wait(obj: JRef, timeout: Option<Duration>) -> Result<(), &'static str>{
+    let current = current_thread();
+    set_waiting_on(current, Some(obj));
+    let result = obj.deref().monitor.wait(timeout, || is_interrupted(current));
+    set_waiting_on(current, None);
+    if result.is_ok() && take_interrupt(){
+        return Err("InterruptedException");
+    }
+    return result;
+}
+
+fn set_waiting_on(thread: Option<JRef>, obj: Option<JRef>){
+    if let Some(thread) = thread
+    && let Some(t) = LIVE_THREADS.lock().unwrap().iter_mut().find(|t| t.thread == thread){
+        t.waiting_on = obj;
+    }
+}
+
+/// Creates an InterruptedException to throw.
+pub fn interrupted_exception() -> JRef{
+    let class = objects::force_init_class("Ljava/lang/InterruptedException;");
+    let JValue::Reference(Some(exception)) = objects::create_new(class.clone()) else { unreachable!() };
+    let init = NameAndType{ name: "<init>".to_string(), descriptor: "()V".to_string() };
+    let (method, owner) = class.special_method(&init).unwrap();
+    interpreter::execute(owner, method, vec![JValue::Reference(Some(exception))], StackTrace::new());
+    return exception;
+}
+
+// Thread fields
+// newer JDKs keep some of these in a FieldHolder, in Thread.holder
+
+fn thread_name(thread: JRef) -> String{
+    return match get_thread_field(thread, "name"){
+        Some(name @ JValue::Reference(Some(_))) => objects::java_string_to_rust_string(name),
+        _ => "Thread".to_string()
+    };
+}
+
+fn get_thread_field(thread: JRef, name: &str) -> Option<JValue>{
+    let (obj, offset) = thread_field_slot(thread, name)?;
+    let JObjectData::Fields(fields) = &*obj.deref().data.read().unwrap() else { return None };
+    return Some(fields[offset]);
+}
+
+fn set_thread_field(thread: JRef, name: &str, value: JValue){
+    let Some((obj, offset)) = thread_field_slot(thread, name) else { return };
+    let JObjectData::Fields(fields) = &mut *obj.deref().data.write().unwrap() else { return };
+    fields[offset] = value;
+}
+
+/// Finds the object and slot holding a field of a thread, which is either the Thread or its holder.
+fn thread_field_slot(thread: JRef, name: &str) -> Option<(JRef, usize)>{
+    let class = objects::force_init_class("Ljava/lang/Thread;");
+    if let Some(offset) = class.declared_field_offset(name){
+        return Some((thread, offset));
+    }
+    let holder_offset = class.declared_field_offset("holder")?;
+    let JObjectData::Fields(fields) = &*thread.deref().data.read().unwrap() else { return None };
+    let JValue::Reference(Some(holder)) = fields[holder_offset] else { return None };
+    return Some((holder, holder.deref().class.declared_field_offset(name)?));
+}
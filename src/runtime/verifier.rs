@@ -0,0 +1,1002 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::constants;
+use crate::parser::classfile_structs::{Attribute, Classfile, Code, ConstantEntry, Instruction, MemberRef, MethodInfo, StackMapFrame, VerificationType};
+
+use super::heap;
+
+// Bytecode verification (JVMS 4.10)
+// errors are messages starting with VerifyError, saying which method and bytecode offset it failed at
+
+/// Verifies the code of every method of a classfile.
+pub fn verify(classfile: &Classfile) -> Result<(), String>{
+    let mut hierarchy = Hierarchy{ classfile, supers: HashMap::new() };
+    for method in &classfile.methods{
+        for attribute in &method.attributes{
+            if let Attribute::Code(code) = attribute{
+                let mut verifier = MethodVerifier{
+                    hierarchy: &mut hierarchy, method, code,
+                    frames: HashMap::new(), inferring: false, worklist: BTreeSet::new(), subroutines: HashMap::new()
+                };
+                // older classfiles don't have stack maps to check against, and version 50 ones fall back to not using them (JVMS 4.10)
+                let result = match classfile.major_ver{
+                    ..50 => verifier.infer(),
+                    50 => verifier.type_check().or_else(|_| verifier.infer()),
+                    _ => verifier.type_check()
+                };
+                result.map_err(|(idx, e)| {
+                    let offset = code.bytecode.get(idx).map_or(code.bytecode.len(), |(offset, _)| *offset);
+                    format!("VerifyError: {} (in {}.{}{} at offset {})", e, classfile.name, method.name, method_descriptor(method), offset)
+                })?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// The type of a local variable or stack entry (JVMS 4.10.1.2). Longs and doubles take one stack entry, but two locals.
+#[derive(Debug, Clone, PartialEq)]
+enum VType{
+    Top, Int, Float, Long, Double, Null,
+    UninitializedThis,
+    Uninitialized(usize),   // index of the new instruction that made it
+    Reference(String),      // descriptor
+    ReturnAddress(usize)    // index of the subroutine a jsr called
+}
+
+impl VType{
+    fn from_descriptor(desc: &str) -> VType{
+        return match desc{
+            "Z" | "B" | "S" | "C" | "I" => VType::Int,
+            "F" => VType::Float,
+            "J" => VType::Long,
+            "D" => VType::Double,
+            _ => VType::Reference(desc.to_owned())
+        };
+    }
+
+    fn size(&self) -> usize{
+        return if matches!(self, VType::Long | VType::Double){ 2 }else{ 1 };
+    }
+
+    fn is_reference(&self) -> bool{
+        return matches!(self, VType::Null | VType::UninitializedThis | VType::Uninitialized(_) | VType::Reference(_));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Frame{
+    locals: Vec<VType>,
+    stack: Vec<VType>,
+    this_uninit: bool   // in a constructor that hasn't called its super or this constructor yet
+}
+
+/// Answers subtyping questions for the class being verified, which isn't loaded yet.
+struct Hierarchy<'a>{
+    classfile: &'a Classfile,
+    supers: HashMap<String, (Option<String>, bool)> // internal name -> superclass, whether it's an interface
+}
+
+impl Hierarchy<'_>{
+    fn lookup(&mut self, name: &str) -> (Option<String>, bool){
+        if name == self.classfile.name{
+            return (self.classfile.super_class.clone(), constants::bit_set(self.classfile.flags, constants::CLASS_ACC_INTERFACE));
+        }
+        if let Some(known) = self.supers.get(name){
+            return known.clone();
+        }
+        // only the classfile is needed, so this doesn't link (or verify) anything
+        let desc = format!("L{};", name);
+        let found = match heap::bt_class_by_desc(desc.clone()){
+            Some(class) => (class.super_class.as_ref().map(|s| s.name.replace('.', "/")), class.is_interface()),
+            None => match heap::get_or_create_bt_class(desc).ok().and_then(|_| heap::classfile_by_name(constants::BOOTSTRAP_LOADER_NAME.to_owned(), name.to_owned())){
+                Some(classfile) => (classfile.super_class, constants::bit_set(classfile.flags, constants::CLASS_ACC_INTERFACE)),
+                None => (None, false)
+            }
+        };
+        self.supers.insert(name.to_owned(), found.clone());
+        return found;
+    }
+
+    /// Whether a value of one reference type can be used as another (JVMS 4.10.1.2); interfaces are treated like Object.
+    fn reference_assignable(&mut self, from: &str, to: &str) -> bool{
+        if from == to || to == "Ljava/lang/Object;"{
+            return true;
+        }
+        if let Some(to_component) = to.strip_prefix('['){
+            let Some(from_component) = from.strip_prefix('[') else { return false };
+            let is_ref = |d: &str| d.starts_with('L') || d.starts_with('[');
+            return is_ref(from_component) && is_ref(to_component) && self.reference_assignable(from_component, to_component);
+        }
+        if from.starts_with('['){
+            return to == "Ljava/lang/Cloneable;" || to == "Ljava/io/Serializable;";
+        }
+        let to_name = &to[1..to.len() - 1];
+        if self.lookup(to_name).1{
+            return true;
+        }
+        let mut current = Some(from[1..from.len() - 1].to_owned());
+        while let Some(name) = current{
+            if name == to_name{
+                return true;
+            }
+            current = self.lookup(&name).0;
+        }
+        return false;
+    }
+
+    fn assignable(&mut self, from: &VType, to: &VType) -> bool{
+        return match (from, to){
+            (_, VType::Top) => true,
+            (a, b) if a == b => true,
+            (VType::Null, VType::Reference(_)) => true,
+            (VType::Reference(a), VType::Reference(b)) => self.reference_assignable(a, b),
+            _ => false
+        };
+    }
+
+    /// The most specific type both references can be used as, treating interfaces like Object (JVMS 4.10.2.2).
+    fn common_supertype(&mut self, a: &str, b: &str) -> String{
+        if let (Some(a_component), Some(b_component)) = (a.strip_prefix('['), b.strip_prefix('[')){
+            let is_ref = |d: &str| d.starts_with('L') || d.starts_with('[');
+            if is_ref(a_component) && is_ref(b_component){
+                return format!("[{}", self.common_supertype(a_component, b_component));
+            }
+            return "Ljava/lang/Object;".to_owned();
+        }
+        if a.starts_with('[') || b.starts_with('['){
+            return "Ljava/lang/Object;".to_owned();
+        }
+        let mut a_supers = Vec::new();
+        let mut current = Some(a[1..a.len() - 1].to_owned());
+        while let Some(name) = current{
+            current = self.lookup(&name).0;
+            a_supers.push(name);
+        }
+        let mut current = Some(b[1..b.len() - 1].to_owned());
+        while let Some(name) = current{
+            if a_supers.contains(&name){
+                return format!("L{};", name);
+            }
+            current = self.lookup(&name).0;
+        }
+        return "Ljava/lang/Object;".to_owned();
+    }
+
+    /// The type of a value that could be either of two types, or None if they can't be used as the same thing.
+    fn merge(&mut self, a: &VType, b: &VType) -> Option<VType>{
+        return match (a, b){
+            (a, b) if a == b => Some(a.clone()),
+            (VType::Null, VType::Reference(_)) => Some(b.clone()),
+            (VType::Reference(_), VType::Null) => Some(a.clone()),
+            (VType::Reference(a), VType::Reference(b)) => Some(VType::Reference(self.common_supertype(a, b))),
+            _ => None
+        };
+    }
+
+    /// Merges the frames of two paths to an instruction; locals that don't match become unusable, but the stacks must match.
+    fn merge_frames(&mut self, a: &Frame, b: &Frame) -> Result<Frame, String>{
+        if a.stack.len() != b.stack.len(){
+            return Err(format!("Inconsistent stack height {} != {}", a.stack.len(), b.stack.len()));
+        }
+        let locals = a.locals.iter().zip(&b.locals).map(|(x, y)| self.merge(x, y).unwrap_or(VType::Top)).collect();
+        let mut stack = Vec::with_capacity(a.stack.len());
+        for (x, y) in a.stack.iter().zip(&b.stack){
+            let Some(merged) = self.merge(x, y) else { return Err(format!("Inconsistent types on the stack: {:?} and {:?}", x, y)) };
+            stack.push(merged);
+        }
+        return Ok(Frame{ locals, stack, this_uninit: a.this_uninit || b.this_uninit });
+    }
+
+    fn frame_assignable(&mut self, from: &Frame, to: &Frame) -> bool{
+        return from.locals.len() == to.locals.len()
+            && from.stack.len() == to.stack.len()
+            && (!from.this_uninit || to.this_uninit)
+            && from.locals.iter().zip(&to.locals).all(|(a, b)| self.assignable(a, b))
+            && from.stack.iter().zip(&to.stack).all(|(a, b)| self.assignable(a, b));
+    }
+}
+
+type VerifyResult<T> = Result<T, (usize, String)>; // instruction index, what's wrong
+
+/// The locals a subroutine stores to, and the rets that return from it.
+#[derive(Debug, Clone, Default)]
+struct Subroutine{
+    written: BTreeSet<usize>,
+    rets: Vec<usize>
+}
+
+struct MethodVerifier<'a, 'b>{
+    hierarchy: &'a mut Hierarchy<'b>,
+    method: &'a MethodInfo,
+    code: &'a Code,
+    frames: HashMap<usize, Frame>, // stack map frames, or inferred frames, by instruction index
+    inferring: bool,
+    worklist: BTreeSet<usize>,  // instructions whose inferred frame has changed
+    subroutines: HashMap<usize, Subroutine>
+}
+
+impl MethodVerifier<'_, '_>{
+    /// Verifies by checking the types at each instruction against the StackMapTable (JVMS 4.10.1).
+    fn type_check(&mut self) -> VerifyResult<()>{
+        let mut initial = self.initial_frame();
+        self.expand_stack_map(&initial)?;
+        self.pad_locals(&mut initial)?;
+        let return_type = self.method.desc.last().unwrap().clone();
+
+        let mut frame = Some(initial);
+        for (i, (_, instr)) in self.code.bytecode.iter().enumerate(){
+            // where there's a stack map frame, it's what we have from here, as long as what falls through matches it
+            frame = match (frame, self.frames.get(&i)){
+                (Some(current), Some(mapped)) => {
+                    if !self.hierarchy.frame_assignable(&current, mapped){
+                        return Err((i, format!("Current frame is not assignable to stack map frame: {:?} to {:?}", current, mapped)));
+                    }
+                    Some(mapped.clone())
+                },
+                (None, Some(mapped)) => Some(mapped.clone()),
+                (Some(current), None) => Some(current),
+                (None, None) => return Err((i, "Expected a stack map frame after an unconditional branch".to_owned()))
+            };
+            let mut current = frame.take().unwrap();
+
+            self.handlers(i, &current).map_err(|e| (i, e))?;
+            let falls_through = self.execute(i, instr, &mut current, &return_type).map_err(|e| (i, e))?;
+            if falls_through{
+                frame = Some(current);
+            }
+        }
+        if frame.is_some(){
+            return Err((self.code.bytecode.len(), "Control flow falls off the end of the code".to_owned()));
+        }
+        return Ok(());
+    }
+
+    /// Verifies by inferring the types at each instruction from every way it can be reached (JVMS 4.10.2).
+    fn infer(&mut self) -> VerifyResult<()>{
+        self.frames.clear();
+        self.inferring = true;
+        let mut initial = self.initial_frame();
+        self.pad_locals(&mut initial)?;
+        let return_type = self.method.desc.last().unwrap().clone();
+
+        let code = self.code;
+        self.frames.insert(0, initial);
+        self.worklist.insert(0);
+        while let Some(i) = self.worklist.pop_first(){
+            let mut current = self.frames[&i].clone();
+            self.handlers(i, &current).map_err(|e| (i, e))?;
+            let falls_through = self.execute(i, &code.bytecode[i].1, &mut current, &return_type).map_err(|e| (i, e))?;
+            // the handlers can also see what this instruction stored
+            self.handlers(i, &current).map_err(|e| (i, e))?;
+            if falls_through{
+                if i + 1 >= code.bytecode.len(){
+                    return Err((code.bytecode.len(), "Control flow falls off the end of the code".to_owned()));
+                }
+                self.merge_into(i + 1, &current).map_err(|e| (i, e))?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn pad_locals(&self, frame: &mut Frame) -> VerifyResult<()>{
+        if frame.locals.len() > self.code.max_locals as usize{
+            return Err((0, "Parameters don't fit in max_locals".to_owned()));
+        }
+        frame.locals.resize(self.code.max_locals as usize, VType::Top);
+        return Ok(());
+    }
+
+    fn initial_frame(&self) -> Frame{
+        let mut locals = Vec::new();
+        let mut this_uninit = false;
+        if !constants::bit_set(self.method.flags, constants::ACC_STATIC){
+            let this_name = &self.hierarchy.classfile.name;
+            if self.method.name == "<init>" && this_name != "java/lang/Object"{
+                locals.push(VType::UninitializedThis);
+                this_uninit = true;
+            }else{
+                locals.push(VType::Reference(format!("L{};", this_name)));
+            }
+        }
+        for param in &self.method.desc[..self.method.desc.len() - 1]{
+            push_local(&mut locals, VType::from_descriptor(param));
+        }
+        return Frame{ locals, stack: Vec::new(), this_uninit };
+    }
+
+    /// Works out the full frame at each entry of the StackMapTable, which are each given relative to the previous one.
+    fn expand_stack_map(&mut self, initial: &Frame) -> VerifyResult<()>{
+        let Some(table) = self.code.attributes.iter().find_map(|a| if let Attribute::StackMapTable(t) = a{ Some(t) }else{ None }) else {
+            return Ok(());
+        };
+        // locals as they're declared, with longs and doubles taking one entry
+        let mut declared: Vec<VType> = Vec::new();
+        let mut skip = false;
+        for local in &initial.locals{
+            if !skip{
+                declared.push(local.clone());
+            }
+            skip = !skip && local.size() == 2;
+        }
+        let mut offset: Option<usize> = None;
+        for entry in table{
+            let delta = match entry{
+                StackMapFrame::Same{ offset_delta } | StackMapFrame::SameLocals1StackItem{ offset_delta, .. } | StackMapFrame::Chop{ offset_delta, .. }
+                | StackMapFrame::Append{ offset_delta, .. } | StackMapFrame::Full{ offset_delta, .. } => *offset_delta as usize
+            };
+            // offsets are deltas from one past the previous frame's, except for the first
+            let at = offset.map_or(delta, |o| o + delta + 1);
+            offset = Some(at);
+            let Some(idx) = self.instruction_at(at) else {
+                return Err((0, format!("Stack map frame at offset {} is not at an instruction", at)));
+            };
+            let expand = |types: &[VerificationType]| types.iter().map(|t| self.expand_type(t)).collect::<Result<Vec<_>, _>>().map_err(|e| (idx, e));
+
+            let stack = match entry{
+                StackMapFrame::Same{ .. } => Vec::new(),
+                StackMapFrame::SameLocals1StackItem{ stack, .. } => expand(std::slice::from_ref(stack))?,
+                StackMapFrame::Chop{ count, .. } => {
+                    if (*count as usize) > declared.len(){
+                        return Err((idx, "Chop frame removes more locals than there are".to_owned()));
+                    }
+                    declared.truncate(declared.len() - *count as usize);
+                    Vec::new()
+                },
+                StackMapFrame::Append{ locals, .. } => {
+                    declared.extend(expand(locals)?);
+                    Vec::new()
+                },
+                StackMapFrame::Full{ locals, stack, .. } => {
+                    declared = expand(locals)?;
+                    expand(stack)?
+                }
+            };
+
+            let mut locals = Vec::new();
+            for local in &declared{
+                push_local(&mut locals, local.clone());
+            }
+            if locals.len() > self.code.max_locals as usize{
+                return Err((idx, "Stack map frame has more locals than max_locals".to_owned()));
+            }
+            locals.resize(self.code.max_locals as usize, VType::Top);
+            if stack.iter().map(VType::size).sum::<usize>() > self.code.max_stack as usize{
+                return Err((idx, "Stack map frame has a deeper stack than max_stack".to_owned()));
+            }
+            let this_uninit = locals.contains(&VType::UninitializedThis);
+            self.frames.insert(idx, Frame{ locals, stack, this_uninit });
+        }
+        return Ok(());
+    }
+
+    fn expand_type(&self, t: &VerificationType) -> Result<VType, String>{
+        return Ok(match t{
+            VerificationType::Top => VType::Top,
+            VerificationType::Integer => VType::Int,
+            VerificationType::Float => VType::Float,
+            VerificationType::Double => VType::Double,
+            VerificationType::Long => VType::Long,
+            VerificationType::Null => VType::Null,
+            VerificationType::UninitializedThis => VType::UninitializedThis,
+            VerificationType::Object(name) => VType::Reference(class_desc(name)),
+            VerificationType::Uninitialized(offset) => match self.instruction_at(*offset as usize){
+                Some(idx) if matches!(self.code.bytecode[idx].1, Instruction::New(_)) => VType::Uninitialized(idx),
+                _ => return Err(format!("Uninitialized type refers to offset {}, which is not a new instruction", offset))
+            }
+        });
+    }
+
+    fn instruction_at(&self, offset: usize) -> Option<usize>{
+        return self.code.bytecode.binary_search_by_key(&offset, |(o, _)| *o).ok();
+    }
+
+    /// Merges a frame into the one inferred for an instruction, which is revisited if that changes it.
+    fn merge_into(&mut self, target: usize, frame: &Frame) -> Result<(), String>{
+        let merged = match self.frames.get(&target){
+            Some(existing) => self.hierarchy.merge_frames(existing, frame)?,
+            None => frame.clone()
+        };
+        if self.frames.get(&target) != Some(&merged){
+            self.frames.insert(target, merged);
+            self.worklist.insert(target);
+        }
+        return Ok(());
+    }
+
+    /// The handlers covering an instruction can be reached with its locals, and the exception.
+    fn handlers(&mut self, i: usize, frame: &Frame) -> Result<(), String>{
+        let code = self.code;
+        for handler in &code.exception_handlers{
+            if handler.start_idx <= i && i < handler.end_idx{
+                let catch_type = handler.catch_type.as_ref().map_or("Ljava/lang/Throwable;".to_owned(), |t| format!("L{};", t));
+                let exception_frame = Frame{ locals: frame.locals.clone(), stack: vec![VType::Reference(catch_type)], this_uninit: frame.this_uninit };
+                self.branch(i, &exception_frame, handler.handler_idx)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Checks that a branch to the given instruction is allowed with the current frame.
+    fn check_target(&mut self, i: usize, frame: &Frame, target: usize) -> VerifyResult<()>{
+        let Some(mapped) = self.frames.get(&target) else {
+            return Err((i, format!("Expected a stack map frame at branch target {}", self.code.bytecode.get(target).map_or(0, |(o, _)| *o))));
+        };
+        if !self.hierarchy.frame_assignable(frame, mapped){
+            return Err((i, format!("Frame is not assignable to the stack map frame at branch target: {:?} to {:?}", frame, mapped)));
+        }
+        return Ok(());
+    }
+
+    /// Applies an instruction to the frame, checking its operands; returns whether execution can continue to the next instruction.
+    fn execute(&mut self, i: usize, instr: &Instruction, f: &mut Frame, return_type: &str) -> Result<bool, String>{
+        let max_locals = self.code.max_locals as usize;
+        let max_stack = self.code.max_stack as usize;
+        let mut ops = Ops{ hierarchy: &mut *self.hierarchy, f, max_locals, max_stack };
+        match instr{
+            Instruction::Nop => {},
+            Instruction::IConst(_) => ops.push(VType::Int)?,
+            Instruction::LConst(_) => ops.push(VType::Long)?,
+            Instruction::FConst(_) => ops.push(VType::Float)?,
+            Instruction::DConst(_) => ops.push(VType::Double)?,
+            Instruction::AConstNull => ops.push(VType::Null)?,
+
+            Instruction::Ldc(constant) => ops.push(match constant{
+                ConstantEntry::Integer(_) => VType::Int,
+                ConstantEntry::Float(_) => VType::Float,
+                ConstantEntry::Long(_) => VType::Long,
+                ConstantEntry::Double(_) => VType::Double,
+                ConstantEntry::StringConst(_) => VType::Reference("Ljava/lang/String;".to_owned()),
+                ConstantEntry::Class(_) => VType::Reference("Ljava/lang/Class;".to_owned()),
+                ConstantEntry::MethodType(_) => VType::Reference("Ljava/lang/invoke/MethodType;".to_owned()),
+                ConstantEntry::MethodHandle(..) => VType::Reference("Ljava/lang/invoke/MethodHandle;".to_owned()),
+                ConstantEntry::Dynamic(d) => VType::from_descriptor(&d.value.descriptor),
+                _ => return Err("Invalid constant for ldc".to_owned())
+            })?,
+
+            Instruction::ILoad(idx) => ops.load(*idx, VType::Int)?,
+            Instruction::LLoad(idx) => ops.load(*idx, VType::Long)?,
+            Instruction::FLoad(idx) => ops.load(*idx, VType::Float)?,
+            Instruction::DLoad(idx) => ops.load(*idx, VType::Double)?,
+            Instruction::ALoad(idx) => {
+                let local = ops.local(*idx)?;
+                if !local.is_reference(){
+                    return Err(format!("Expected a reference in local {}, found {:?}", idx, local));
+                }
+                ops.push(local)?;
+            },
+            Instruction::IStore(idx) => { ops.pop(&VType::Int)?; ops.store(*idx, VType::Int)?; },
+            Instruction::LStore(idx) => { ops.pop(&VType::Long)?; ops.store(*idx, VType::Long)?; },
+            Instruction::FStore(idx) => { ops.pop(&VType::Float)?; ops.store(*idx, VType::Float)?; },
+            Instruction::DStore(idx) => { ops.pop(&VType::Double)?; ops.store(*idx, VType::Double)?; },
+            Instruction::AStore(idx) => {
+                // subroutines keep their return address in a local
+                let value = match ops.f.stack.last(){
+                    Some(VType::ReturnAddress(_)) => ops.f.stack.pop().unwrap(),
+                    _ => ops.pop_reference()?
+                };
+                ops.store(*idx, value)?;
+            },
+
+            Instruction::IALoad => { ops.pop(&VType::Int)?; ops.pop_array(&["I"])?; ops.push(VType::Int)?; },
+            Instruction::BALoad => { ops.pop(&VType::Int)?; ops.pop_array(&["B", "Z"])?; ops.push(VType::Int)?; },
+            Instruction::CALoad => { ops.pop(&VType::Int)?; ops.pop_array(&["C"])?; ops.push(VType::Int)?; },
+            Instruction::SALoad => { ops.pop(&VType::Int)?; ops.pop_array(&["S"])?; ops.push(VType::Int)?; },
+            Instruction::LALoad => { ops.pop(&VType::Int)?; ops.pop_array(&["J"])?; ops.push(VType::Long)?; },
+            Instruction::FALoad => { ops.pop(&VType::Int)?; ops.pop_array(&["F"])?; ops.push(VType::Float)?; },
+            Instruction::DALoad => { ops.pop(&VType::Int)?; ops.pop_array(&["D"])?; ops.push(VType::Double)?; },
+            Instruction::AALoad => {
+                ops.pop(&VType::Int)?;
+                let component = ops.pop_array(&[])?;
+                ops.push(component.map_or(VType::Null, VType::Reference))?;
+            },
+            Instruction::IAStore => { ops.pop(&VType::Int)?; ops.pop(&VType::Int)?; ops.pop_array(&["I"])?; },
+            Instruction::BAStore => { ops.pop(&VType::Int)?; ops.pop(&VType::Int)?; ops.pop_array(&["B", "Z"])?; },
+            Instruction::CAStore => { ops.pop(&VType::Int)?; ops.pop(&VType::Int)?; ops.pop_array(&["C"])?; },
+            Instruction::SAStore => { ops.pop(&VType::Int)?; ops.pop(&VType::Int)?; ops.pop_array(&["S"])?; },
+            Instruction::LAStore => { ops.pop(&VType::Long)?; ops.pop(&VType::Int)?; ops.pop_array(&["J"])?; },
+            Instruction::FAStore => { ops.pop(&VType::Float)?; ops.pop(&VType::Int)?; ops.pop_array(&["F"])?; },
+            Instruction::DAStore => { ops.pop(&VType::Double)?; ops.pop(&VType::Int)?; ops.pop_array(&["D"])?; },
+            Instruction::AAStore => {
+                // whether the value fits the array is checked when it's stored
+                ops.pop(&VType::Reference("Ljava/lang/Object;".to_owned()))?;
+                ops.pop(&VType::Int)?;
+                ops.pop_array(&[])?;
+            },
+
+            // the stack shuffles work on words, and mustn't split a long or double
+            Instruction::Pop => { ops.pop_words(1)?; },
+            Instruction::Pop2 => { ops.pop_words(2)?; },
+            Instruction::Dup => { let a = ops.pop_words(1)?; ops.push_all(&[&a, &a])?; },
+            Instruction::DupX1 => { let a = ops.pop_words(1)?; let b = ops.pop_words(1)?; ops.push_all(&[&a, &b, &a])?; },
+            Instruction::DupX2 => { let a = ops.pop_words(1)?; let b = ops.pop_words(2)?; ops.push_all(&[&a, &b, &a])?; },
+            Instruction::Dup2 => { let a = ops.pop_words(2)?; ops.push_all(&[&a, &a])?; },
+            Instruction::Dup2X1 => { let a = ops.pop_words(2)?; let b = ops.pop_words(1)?; ops.push_all(&[&a, &b, &a])?; },
+            Instruction::Dup2X2 => { let a = ops.pop_words(2)?; let b = ops.pop_words(2)?; ops.push_all(&[&a, &b, &a])?; },
+            Instruction::Swap => { let a = ops.pop_words(1)?; let b = ops.pop_words(1)?; ops.push_all(&[&a, &b])?; },
+
+            Instruction::IAdd | Instruction::ISub | Instruction::IMul | Instruction::IDiv | Instruction::IRem
+            | Instruction::IShl | Instruction::IShr | Instruction::IUshr | Instruction::IAnd | Instruction::IOr | Instruction::IXor
+                => ops.binary(VType::Int, VType::Int, VType::Int)?,
+            Instruction::LAdd | Instruction::LSub | Instruction::LMul | Instruction::LDiv | Instruction::LRem
+            | Instruction::LAnd | Instruction::LOr | Instruction::LXor
+                => ops.binary(VType::Long, VType::Long, VType::Long)?,
+            Instruction::LShl | Instruction::LShr | Instruction::LUshr => ops.binary(VType::Long, VType::Int, VType::Long)?,
+            Instruction::FAdd | Instruction::FSub | Instruction::FMul | Instruction::FDiv | Instruction::FRem
+                => ops.binary(VType::Float, VType::Float, VType::Float)?,
+            Instruction::DAdd | Instruction::DSub | Instruction::DMul | Instruction::DDiv | Instruction::DRem
+                => ops.binary(VType::Double, VType::Double, VType::Double)?,
+            Instruction::LCmp => ops.binary(VType::Long, VType::Long, VType::Int)?,
+            Instruction::FCmpL | Instruction::FCmpG => ops.binary(VType::Float, VType::Float, VType::Int)?,
+            Instruction::DCmpL | Instruction::DCmpG => ops.binary(VType::Double, VType::Double, VType::Int)?,
+
+            Instruction::INeg | Instruction::I2B | Instruction::I2C | Instruction::I2S => ops.convert(VType::Int, VType::Int)?,
+            Instruction::LNeg => ops.convert(VType::Long, VType::Long)?,
+            Instruction::FNeg => ops.convert(VType::Float, VType::Float)?,
+            Instruction::DNeg => ops.convert(VType::Double, VType::Double)?,
+            Instruction::I2L => ops.convert(VType::Int, VType::Long)?,
+            Instruction::I2F => ops.convert(VType::Int, VType::Float)?,
+            Instruction::I2D => ops.convert(VType::Int, VType::Double)?,
+            Instruction::L2I => ops.convert(VType::Long, VType::Int)?,
+            Instruction::L2F => ops.convert(VType::Long, VType::Float)?,
+            Instruction::L2D => ops.convert(VType::Long, VType::Double)?,
+            Instruction::F2I => ops.convert(VType::Float, VType::Int)?,
+            Instruction::F2L => ops.convert(VType::Float, VType::Long)?,
+            Instruction::F2D => ops.convert(VType::Float, VType::Double)?,
+            Instruction::D2I => ops.convert(VType::Double, VType::Int)?,
+            Instruction::D2L => ops.convert(VType::Double, VType::Long)?,
+            Instruction::D2F => ops.convert(VType::Double, VType::Float)?,
+
+            Instruction::IInc(idx, _) => { ops.load(*idx, VType::Int)?; ops.pop(&VType::Int)?; },
+
+            Instruction::Goto(target) => {
+                self.branch(i, f, *target)?;
+                return Ok(false);
+            },
+            Instruction::Jsr(target) => {
+                if !self.inferring{
+                    return Err("jsr and ret aren't allowed in classfiles of version 51 or later".to_owned());
+                }
+                ops.push(VType::ReturnAddress(*target))?;
+                self.branch(i, f, *target)?;
+                // the subroutine returns here too, so its rets need to see this frame
+                for ret in self.subroutine(*target).rets{
+                    if self.frames.contains_key(&ret){
+                        self.worklist.insert(ret);
+                    }
+                }
+                return Ok(false);
+            },
+            Instruction::Ret(idx) => {
+                if !self.inferring{
+                    return Err("jsr and ret aren't allowed in classfiles of version 51 or later".to_owned());
+                }
+                let VType::ReturnAddress(entry) = ops.local(*idx)? else {
+                    return Err(format!("Expected a return address in local {}", idx));
+                };
+                let subroutine = self.subroutine(entry);
+                let code = self.code;
+                for (j, (_, instr)) in code.bytecode.iter().enumerate(){
+                    if *instr == Instruction::Jsr(entry) && let Some(caller) = self.frames.get(&j).cloned(){
+                        // locals the subroutine doesn't store to are as they were before the jsr
+                        let mut after = Frame{ locals: caller.locals, stack: f.stack.clone(), this_uninit: f.this_uninit };
+                        for local in &subroutine.written{
+                            after.locals[*local] = f.locals[*local].clone();
+                        }
+                        self.merge_into(j + 1, &after)?;
+                    }
+                }
+                return Ok(false);
+            },
+            Instruction::TableSwitch(default, _, _, targets) => {
+                ops.pop(&VType::Int)?;
+                self.branch(i, f, *default)?;
+                for target in targets{
+                    self.branch(i, f, *target)?;
+                }
+                return Ok(false);
+            },
+            Instruction::LookupSwitch(default, pairs) => {
+                ops.pop(&VType::Int)?;
+                self.branch(i, f, *default)?;
+                for (_, target) in pairs{
+                    self.branch(i, f, *target)?;
+                }
+                return Ok(false);
+            },
+            Instruction::IfEq(target) | Instruction::IfNe(target) | Instruction::IfLt(target)
+            | Instruction::IfGe(target) | Instruction::IfGt(target) | Instruction::IfLe(target) => {
+                ops.pop(&VType::Int)?;
+                self.branch(i, f, *target)?;
+            },
+            Instruction::IfICmpEq(target) | Instruction::IfICmpNe(target) | Instruction::IfICmpLt(target)
+            | Instruction::IfICmpGe(target) | Instruction::IfICmpGt(target) | Instruction::IfICmpLe(target) => {
+                ops.pop(&VType::Int)?;
+                ops.pop(&VType::Int)?;
+                self.branch(i, f, *target)?;
+            },
+            Instruction::IfACmpEq(target) | Instruction::IfACmpNe(target) => {
+                ops.pop_reference()?;
+                ops.pop_reference()?;
+                self.branch(i, f, *target)?;
+            },
+            Instruction::IfNull(target) | Instruction::IfNonnull(target) => {
+                ops.pop_reference()?;
+                self.branch(i, f, *target)?;
+            },
+
+            Instruction::IReturn | Instruction::LReturn | Instruction::FReturn | Instruction::DReturn | Instruction::AReturn => {
+                if return_type == "V"{
+                    return Err("Returning a value from a void method".to_owned());
+                }
+                let expected = VType::from_descriptor(return_type);
+                let matches = match instr{
+                    Instruction::IReturn => expected == VType::Int,
+                    Instruction::LReturn => expected == VType::Long,
+                    Instruction::FReturn => expected == VType::Float,
+                    Instruction::DReturn => expected == VType::Double,
+                    _ => expected.is_reference()
+                };
+                if !matches{
+                    return Err(format!("Wrong return instruction for return type {}", return_type));
+                }
+                ops.pop(&expected)?;
+                return Ok(false);
+            },
+            Instruction::Return => {
+                if return_type != "V"{
+                    return Err("Returning no value from a non-void method".to_owned());
+                }
+                if f.this_uninit{
+                    return Err("Constructor returns without calling another constructor".to_owned());
+                }
+                return Ok(false);
+            },
+            Instruction::AThrow => {
+                ops.pop(&VType::Reference("Ljava/lang/Throwable;".to_owned()))?;
+                return Ok(false);
+            },
+
+            Instruction::GetStatic(field) => ops.push(VType::from_descriptor(&field.name_and_type.descriptor))?,
+            Instruction::PutStatic(field) => { ops.pop(&VType::from_descriptor(&field.name_and_type.descriptor))?; },
+            Instruction::GetField(field) => {
+                ops.pop(&VType::Reference(class_desc(&field.owner_name)))?;
+                ops.push(VType::from_descriptor(&field.name_and_type.descriptor))?;
+            },
+            Instruction::PutField(field) => {
+                ops.pop(&VType::from_descriptor(&field.name_and_type.descriptor))?;
+                // constructors can set their own class's fields before calling super
+                let receiver = ops.pop_reference()?;
+                let own_field = receiver == VType::UninitializedThis && field.owner_name == ops.hierarchy.classfile.name;
+                if !own_field && !ops.hierarchy.assignable(&receiver, &VType::Reference(class_desc(&field.owner_name))){
+                    return Err(format!("Bad receiver for putfield {}: {:?}", field.name_and_type.name, receiver));
+                }
+            },
+
+            Instruction::InvokeVirtual(target) | Instruction::InvokeInterface(target) | Instruction::InvokeStatic(target) => {
+                ops.pop_arguments(&target.name_and_type.descriptor)?;
+                if !matches!(instr, Instruction::InvokeStatic(_)){
+                    ops.pop(&VType::Reference(class_desc(&target.owner_name)))?;
+                }
+                ops.push_return(&target.name_and_type.descriptor)?;
+            },
+            Instruction::InvokeSpecial(target) => {
+                ops.pop_arguments(&target.name_and_type.descriptor)?;
+                if target.name_and_type.name == "<init>"{
+                    let receiver = ops.pop_reference()?;
+                    self.initialize(receiver, target, f)?;
+                }else{
+                    let current = VType::Reference(format!("L{};", self.hierarchy.classfile.name));
+                    let mut ops = Ops{ hierarchy: &mut *self.hierarchy, f, max_locals, max_stack };
+                    ops.pop(&current)?;
+                    ops.push_return(&target.name_and_type.descriptor)?;
+                }
+            },
+            Instruction::InvokeDynamic(call) => {
+                ops.pop_arguments(&call.value.descriptor)?;
+                ops.push_return(&call.value.descriptor)?;
+            },
+
+            Instruction::ArrayLength => {
+                let array = ops.pop_reference()?;
+                if !matches!(&array, VType::Null) && !matches!(&array, VType::Reference(d) if d.starts_with('[')){
+                    return Err(format!("Expected an array for arraylength, found {:?}", array));
+                }
+                ops.push(VType::Int)?;
+            },
+            Instruction::New(class) => {
+                if class.starts_with('['){
+                    return Err("Can't use new to create an array".to_owned());
+                }
+                // the same uninitialized object can't be around twice
+                if f.stack.contains(&VType::Uninitialized(i)){
+                    return Err("Uninitialized object from this new is already on the stack".to_owned());
+                }
+                for local in &mut f.locals{
+                    if *local == VType::Uninitialized(i){
+                        *local = VType::Top;
+                    }
+                }
+                let mut ops = Ops{ hierarchy: &mut *self.hierarchy, f, max_locals, max_stack };
+                ops.push(VType::Uninitialized(i))?;
+            },
+            Instruction::NewArray(component) => {
+                ops.pop(&VType::Int)?;
+                ops.push(VType::Reference(format!("[{}", component)))?;
+            },
+            Instruction::MultiANewArray(desc, dimensions) => {
+                if *dimensions == 0 || desc.chars().take_while(|c| *c == '[').count() < *dimensions as usize{
+                    return Err("Too many dimensions for multianewarray".to_owned());
+                }
+                for _ in 0..*dimensions{
+                    ops.pop(&VType::Int)?;
+                }
+                ops.push(VType::Reference(desc.clone()))?;
+            },
+            Instruction::CheckCast(class) => {
+                ops.pop_reference()?;
+                ops.push(VType::Reference(class_desc(class)))?;
+            },
+            Instruction::InstanceOf(_) => {
+                ops.pop_reference()?;
+                ops.push(VType::Int)?;
+            },
+            Instruction::MonitorEnter | Instruction::MonitorExit => { ops.pop_reference()?; }
+        }
+        return Ok(true);
+    }
+
+    fn branch(&mut self, i: usize, f: &Frame, target: usize) -> Result<(), String>{
+        if self.inferring{
+            return self.merge_into(target, f);
+        }
+        return self.check_target(i, f, target).map_err(|(_, e)| e);
+    }
+
+    fn subroutine(&mut self, entry: usize) -> Subroutine{
+        if let Some(known) = self.subroutines.get(&entry){
+            return known.clone();
+        }
+        // in case it calls itself
+        self.subroutines.insert(entry, Subroutine::default());
+        let code = self.code;
+        let mut subroutine = Subroutine::default();
+        let mut seen = HashSet::new();
+        let mut pending = vec![entry];
+        while let Some(i) = pending.pop(){
+            if i >= code.bytecode.len() || !seen.insert(i){
+                continue;
+            }
+            match &code.bytecode[i].1{
+                Instruction::IStore(idx) | Instruction::FStore(idx) | Instruction::AStore(idx) | Instruction::IInc(idx, _) => {
+                    subroutine.written.insert(*idx as usize);
+                },
+                Instruction::LStore(idx) | Instruction::DStore(idx) => {
+                    subroutine.written.insert(*idx as usize);
+                    subroutine.written.insert(*idx as usize + 1);
+                },
+                Instruction::Ret(_) => {
+                    subroutine.rets.push(i);
+                    continue;
+                },
+                // a nested subroutine returns to after its jsr
+                Instruction::Jsr(nested) => {
+                    let nested = self.subroutine(*nested);
+                    subroutine.written.extend(nested.written);
+                    pending.push(i + 1);
+                    continue;
+                },
+                _ => {}
+            }
+            pending.extend(successors(&code.bytecode[i].1, i));
+        }
+        self.subroutines.insert(entry, subroutine.clone());
+        return subroutine;
+    }
+
+    /// Calls a constructor on an uninitialized object, which makes every copy of it initialized (JVMS 4.10.1.9.invokespecial).
+    fn initialize(&mut self, receiver: VType, target: &MemberRef, f: &mut Frame) -> Result<(), String>{
+        let this_name = self.hierarchy.classfile.name.clone();
+        let initialized = match &receiver{
+            VType::UninitializedThis => {
+                // a constructor calls one of its own class or its superclass
+                if target.owner_name != this_name && Some(&target.owner_name) != self.hierarchy.classfile.super_class.as_ref(){
+                    return Err(format!("Constructor calls a constructor of {}, which isn't its class or superclass", target.owner_name));
+                }
+                f.this_uninit = false;
+                format!("L{};", this_name)
+            },
+            VType::Uninitialized(idx) => {
+                let Instruction::New(class) = &self.code.bytecode[*idx].1 else { unreachable!() };
+                if *class != target.owner_name{
+                    return Err(format!("Calls a constructor of {} on a new {}", target.owner_name, class));
+                }
+                class_desc(class)
+            },
+            _ => return Err(format!("Calls a constructor on an initialized object: {:?}", receiver))
+        };
+        let initialized = VType::Reference(initialized);
+        for t in f.locals.iter_mut().chain(f.stack.iter_mut()){
+            if *t == receiver{
+                *t = initialized.clone();
+            }
+        }
+        if target.name_and_type.descriptor.ends_with(")V"){
+            return Ok(());
+        }
+        return Err("Constructor must return void".to_owned());
+    }
+}
+
+/// Operations on a frame, checking types and limits.
+struct Ops<'a, 'b>{
+    hierarchy: &'a mut Hierarchy<'b>,
+    f: &'a mut Frame,
+    max_locals: usize,
+    max_stack: usize
+}
+
+impl Ops<'_, '_>{
+    fn push(&mut self, t: VType) -> Result<(), String>{
+        self.f.stack.push(t);
+        if self.f.stack.iter().map(VType::size).sum::<usize>() > self.max_stack{
+            return Err("Operand stack overflow".to_owned());
+        }
+        return Ok(());
+    }
+
+    fn pop(&mut self, expected: &VType) -> Result<VType, String>{
+        let Some(top) = self.f.stack.pop() else { return Err(format!("Operand stack underflow, expected {:?}", expected)) };
+        if !self.hierarchy.assignable(&top, expected){
+            return Err(format!("Expected {:?} on the stack, found {:?}", expected, top));
+        }
+        return Ok(top);
+    }
+
+    fn pop_reference(&mut self) -> Result<VType, String>{
+        let Some(top) = self.f.stack.pop() else { return Err("Operand stack underflow, expected a reference".to_owned()) };
+        if !top.is_reference(){
+            return Err(format!("Expected a reference on the stack, found {:?}", top));
+        }
+        return Ok(top);
+    }
+
+    /// Pops an array whose component is one of the given primitive types, or any reference type if none are given.
+    /// Returns the component type, or None for null.
+    fn pop_array(&mut self, components: &[&str]) -> Result<Option<String>, String>{
+        let array = self.pop_reference()?;
+        return match &array{
+            VType::Null => Ok(None),
+            VType::Reference(desc) if let Some(component) = desc.strip_prefix('[') => {
+                let fits = if components.is_empty(){
+                    component.starts_with('L') || component.starts_with('[')
+                }else{
+                    components.contains(&component)
+                };
+                if fits{ Ok(Some(component.to_owned())) }else{ Err(format!("Wrong array type {}", desc)) }
+            },
+            _ => Err(format!("Expected an array on the stack, found {:?}", array))
+        };
+    }
+
+    /// Pops values making up exactly the given number of words, in stack order.
+    fn pop_words(&mut self, words: usize) -> Result<Vec<VType>, String>{
+        let mut popped = Vec::new();
+        let mut size = 0;
+        while size < words{
+            let Some(top) = self.f.stack.pop() else { return Err("Operand stack underflow".to_owned()) };
+            size += top.size();
+            popped.insert(0, top);
+        }
+        if size != words{
+            return Err("Stack instruction would split a long or double".to_owned());
+        }
+        return Ok(popped);
+    }
+
+    fn push_all(&mut self, groups: &[&Vec<VType>]) -> Result<(), String>{
+        for group in groups.iter().rev(){
+            for t in group.iter(){
+                self.push(t.clone())?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn binary(&mut self, left: VType, right: VType, result: VType) -> Result<(), String>{
+        self.pop(&right)?;
+        self.pop(&left)?;
+        return self.push(result);
+    }
+
+    fn convert(&mut self, from: VType, to: VType) -> Result<(), String>{
+        self.pop(&from)?;
+        return self.push(to);
+    }
+
+    fn local(&self, idx: u16) -> Result<VType, String>{
+        return self.f.locals.get(idx as usize).cloned().ok_or(format!("Local {} is past max_locals", idx));
+    }
+
+    fn load(&mut self, idx: u16, expected: VType) -> Result<(), String>{
+        let local = self.local(idx)?;
+        if !self.hierarchy.assignable(&local, &expected){
+            return Err(format!("Expected {:?} in local {}, found {:?}", expected, idx, local));
+        }
+        return self.push(expected);
+    }
+
+    fn store(&mut self, idx: u16, t: VType) -> Result<(), String>{
+        let idx = idx as usize;
+        if idx + t.size() > self.max_locals{
+            return Err(format!("Local {} is past max_locals", idx));
+        }
+        // overwriting the second half of a long or double ruins it
+        if idx > 0 && self.f.locals[idx - 1].size() == 2{
+            self.f.locals[idx - 1] = VType::Top;
+        }
+        if t.size() == 2{
+            self.f.locals[idx + 1] = VType::Top;
+        }
+        self.f.locals[idx] = t;
+        return Ok(());
+    }
+
+    fn pop_arguments(&mut self, desc: &str) -> Result<(), String>{
+        let types = descriptor_types(desc)?;
+        for param in types[..types.len() - 1].iter().rev(){
+            self.pop(&VType::from_descriptor(param))?;
+        }
+        return Ok(());
+    }
+
+    fn push_return(&mut self, desc: &str) -> Result<(), String>{
+        let types = descriptor_types(desc)?;
+        let ret = types.last().unwrap();
+        if ret != "V"{
+            self.push(VType::from_descriptor(ret))?;
+        }
+        return Ok(());
+    }
+}
+
+/// The instructions that can run after one, not counting exception handlers or returning from subroutines.
+fn successors(instr: &Instruction, i: usize) -> Vec<usize>{
+    return match instr{
+        Instruction::Goto(target) | Instruction::Jsr(target) => vec![*target],
+        Instruction::IfEq(target) | Instruction::IfNe(target) | Instruction::IfLt(target)
+        | Instruction::IfGe(target) | Instruction::IfGt(target) | Instruction::IfLe(target)
+        | Instruction::IfICmpEq(target) | Instruction::IfICmpNe(target) | Instruction::IfICmpLt(target)
+        | Instruction::IfICmpGe(target) | Instruction::IfICmpGt(target) | Instruction::IfICmpLe(target)
+        | Instruction::IfACmpEq(target) | Instruction::IfACmpNe(target)
+        | Instruction::IfNull(target) | Instruction::IfNonnull(target) => vec![*target, i + 1],
+        Instruction::TableSwitch(default, _, _, targets) => targets.iter().copied().chain([*default]).collect(),
+        Instruction::LookupSwitch(default, pairs) => pairs.iter().map(|(_, t)| *t).chain([*default]).collect(),
+        Instruction::IReturn | Instruction::LReturn | Instruction::FReturn | Instruction::DReturn | Instruction::AReturn
+        | Instruction::Return | Instruction::AThrow | Instruction::Ret(_) => Vec::new(),
+        _ => vec![i + 1]
+    };
+}
+
+/// Adds a local to a list of locals, with the extra slot a long or double takes.
+fn push_local(locals: &mut Vec<VType>, t: VType){
+    let wide = t.size() == 2;
+    locals.push(t);
+    if wide{
+        locals.push(VType::Top);
+    }
+}
+
+fn descriptor_types(desc: &str) -> Result<Vec<String>, String>{
+    return crate::parser::classfile_parser::parse_method_descriptor(desc.to_owned());
+}
+
+fn method_descriptor(method: &MethodInfo) -> String{
+    let (ret, params) = method.desc.split_last().unwrap();
+    return format!("({}){}", params.concat(), ret);
+}
+
+/// The descriptor for a class named in the constant pool, which names arrays by their descriptor.
+fn class_desc(name: &str) -> String{
+    return if name.starts_with('['){ name.to_owned() }else{ format!("L{};", name) };
+}